@@ -0,0 +1,40 @@
+/* C interface to http_client, from the library built with the ffi feature:
+ *
+ *     cargo rustc --lib --release --features ffi --crate-type cdylib
+ */
+#ifndef HTTP_CLIENT_H
+#define HTTP_CLIENT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Called as bytes arrive, possibly from several threads at once */
+typedef void (*shc_progress_fn)(void *user_data, uint64_t downloaded, uint64_t total_size);
+
+typedef struct shc_options {
+    unsigned connections;          /* parallel connections, 0 for 1 */
+    unsigned timeout_ms;           /* connect and read timeout, 0 for none */
+    const char *expected_sha256;   /* hex digest to verify, or NULL */
+    shc_progress_fn progress;      /* or NULL */
+    void *user_data;               /* passed to progress */
+} shc_options;
+
+typedef struct shc_result {
+    uint64_t size;
+    char sha256[65];               /* hex, NUL terminated */
+    char error[256];               /* empty on success */
+} shc_result;
+
+/* Download url to path. options and result may be NULL.
+ * Returns 0 on success and -1 on failure. */
+int shc_download(const char *url, const char *path, const shc_options *options, shc_result *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,480 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+
+use crate::inflate::{self, GzDecoder, Inflater};
+
+// Longest GNU long name or pax header read into memory; the size comes from
+// the archive, which may be out to exhaust it
+const MAX_HEADER_EXTENSION: u64 = 1024 * 1024;
+// Longest symlink target read from a zip entry
+const MAX_LINK_TARGET: u64 = 4096;
+
+// Archive formats --extract understands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+// What an extraction created
+#[derive(Default)]
+pub struct ExtractStats {
+    pub files: usize,
+    pub directories: usize,
+    pub links: usize,
+    pub skipped: usize,
+}
+
+// Work out the archive format from the content
+pub fn detect(data: &[u8]) -> Option<Kind> {
+    if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        return Some(Kind::Zip);
+    }
+    if inflate::is_gzip(data) {
+        let mut header = [0u8; 512];
+        let mut decoder = GzDecoder::new(data).ok()?;
+        decoder.read_exact(&mut header).ok()?;
+        return is_tar_header(&header).then_some(Kind::TarGz);
+    }
+    data.get(..512).filter(|header| is_tar_header(header)).map(|_| Kind::Tar)
+}
+
+// Unpack an archive into a directory, creating it if needed. Entries whose
+// paths (or link targets) would end up outside the directory are refused.
+// Tar archives are read through once; a zip is read from its central
+// directory at the end, so the input has to be seekable.
+pub fn extract<R: Read + Seek>(input: R, kind: Kind, dest: &Path) -> std::io::Result<ExtractStats> {
+    std::fs::create_dir_all(dest)?;
+
+    match kind {
+        Kind::Tar => extract_tar(input, dest),
+        Kind::TarGz => extract_tar(GzDecoder::new(input)?, dest),
+        Kind::Zip => extract_zip(input, dest),
+    }
+}
+
+fn is_tar_header(header: &[u8]) -> bool {
+    header.len() >= 512 && header.iter().any(|&b| b != 0) && checksum_matches(header)
+}
+
+// The header checksum is the sum of all header bytes, with the checksum
+// field itself counted as spaces
+fn checksum_matches(header: &[u8]) -> bool {
+    let expected = parse_octal(&header[148..156]);
+    let sum: u64 = header[..512].iter().enumerate()
+        .map(|(i, &b)| if (148..156).contains(&i) { b' ' as u64 } else { b as u64 })
+        .sum();
+    expected == Some(sum)
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    // GNU tar stores large numbers in base 256, flagged by the high bit
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let value = field[1..].iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        return Some(value);
+    }
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() { Some(0) } else { u64::from_str_radix(text, 8).ok() }
+}
+
+fn field_str(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+fn extract_tar<R: Read>(mut input: R, dest: &Path) -> std::io::Result<ExtractStats> {
+    let mut stats = ExtractStats::default();
+    // Names and sizes from GNU long name entries and pax headers, for the next entry
+    let mut long_name: Option<String> = None;
+    let mut long_link: Option<String> = None;
+    let mut pax_size: Option<u64> = None;
+
+    loop {
+        let mut header = [0u8; 512];
+        match input.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+        // A zero block marks the end of the archive
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        if !checksum_matches(&header) {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "Bad tar header checksum"));
+        }
+
+        let size = pax_size.take()
+            .or(parse_octal(&header[124..136]))
+            .ok_or(std::io::Error::new(ErrorKind::InvalidData, "Bad tar entry size"))?;
+        let padded = size.div_ceil(512) * 512;
+        let mode = parse_octal(&header[100..108]).unwrap_or(0o644) as u32;
+
+        let mut name = field_str(&header[..100]);
+        if &header[257..262] == b"ustar" {
+            let prefix = field_str(&header[345..500]);
+            if !prefix.is_empty() {
+                name = format!("{}/{}", prefix, name);
+            }
+        }
+        let name = long_name.take().unwrap_or(name);
+        let link = long_link.take().unwrap_or_else(|| field_str(&header[157..257]));
+
+        match header[156] {
+            b'0' | 0 | b'7' => {
+                let path = safe_path(dest, &name)?;
+                let mut file = create_file(&path)?;
+                std::io::copy(&mut (&mut input).take(size), &mut file)?;
+                set_mode(&path, mode)?;
+                skip(&mut input, padded - size)?;
+                stats.files += 1;
+                continue;
+            }
+            b'5' => {
+                let path = safe_path(dest, &name)?;
+                std::fs::create_dir_all(&path)?;
+                set_mode(&path, mode)?;
+                stats.directories += 1;
+            }
+            b'2' => {
+                let path = safe_path(dest, &name)?;
+                create_symlink(dest, &path, &link)?;
+                stats.links += 1;
+            }
+            b'1' => {
+                let path = safe_path(dest, &name)?;
+                let target = safe_path(dest, &link)?;
+                create_parents(&path)?;
+                remove_existing(&path)?;
+                std::fs::hard_link(target, &path)?;
+                stats.links += 1;
+            }
+            b'L' | b'K' => {
+                let value = read_extension(&mut input, size)?;
+                skip(&mut input, padded - size)?;
+                if header[156] == b'L' { long_name = Some(field_str(&value)) } else { long_link = Some(field_str(&value)) }
+                continue;
+            }
+            b'x' => {
+                let records = read_extension(&mut input, size)?;
+                skip(&mut input, padded - size)?;
+                for (key, value) in parse_pax(&records) {
+                    match key.as_str() {
+                        "path" => long_name = Some(value),
+                        "linkpath" => long_link = Some(value),
+                        "size" => pax_size = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+                continue;
+            }
+            // Global pax headers, devices, FIFOs and the like
+            _ => stats.skipped += 1,
+        }
+
+        skip(&mut input, padded)?;
+    }
+
+    Ok(stats)
+}
+
+// Records in a pax extended header: "<length> <key>=<value>\n"
+fn parse_pax(records: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(records);
+    let mut rest = text.as_ref();
+    let mut pairs = Vec::new();
+
+    while let Some((len, _)) = rest.split_once(' ') {
+        let Some(record) = len.parse::<usize>().ok().and_then(|len| rest.get(..len)) else { break };
+        let Some(body) = record.get(len.len() + 1..) else { break };
+        let body = body.trim_end_matches('\n');
+        if let Some((key, value)) = body.split_once('=') {
+            pairs.push((key.to_string(), value.to_string()));
+        }
+        rest = &rest[record.len()..];
+    }
+
+    pairs
+}
+
+fn extract_zip<R: Read + Seek>(mut input: R, dest: &Path) -> std::io::Result<ExtractStats> {
+    let invalid = |message: &str| std::io::Error::new(ErrorKind::InvalidData, format!("Invalid zip archive: {}", message));
+    let u16_at = |data: &[u8], pos: usize| data.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+    let u32_at = |data: &[u8], pos: usize| data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    // The end of central directory record sits at the end, before a comment
+    let len = input.seek(SeekFrom::End(0))?;
+    let tail_start = len.saturating_sub(22 + 65535);
+    input.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = Vec::new();
+    input.by_ref().read_to_end(&mut tail)?;
+    let eocd = (0..tail.len().saturating_sub(21)).rev()
+        .find(|&i| tail[i..].starts_with(b"PK\x05\x06"))
+        .ok_or(invalid("no end of central directory"))?;
+    let entries = u16_at(&tail, eocd + 10).ok_or(invalid("truncated"))?;
+    let directory_size = u32_at(&tail, eocd + 12).ok_or(invalid("truncated"))?;
+    let directory_start = u32_at(&tail, eocd + 16).ok_or(invalid("truncated"))?;
+    if entries == 0xffff || directory_start == u32::MAX {
+        return Err(std::io::Error::new(ErrorKind::Unsupported, "zip64 archives are not supported"));
+    }
+
+    // The central directory is read whole; it can be no longer than the file
+    input.seek(SeekFrom::Start(directory_start as u64))?;
+    let mut data = Vec::new();
+    input.by_ref().take(directory_size as u64).read_to_end(&mut data)?;
+
+    let mut stats = ExtractStats::default();
+    let mut pos = 0;
+
+    for _ in 0..entries {
+        if !data.get(pos..).is_some_and(|d| d.starts_with(b"PK\x01\x02")) {
+            return Err(invalid("bad central directory entry"));
+        }
+        let made_by = u16_at(&data, pos + 4).ok_or(invalid("truncated"))?;
+        let flags = u16_at(&data, pos + 8).ok_or(invalid("truncated"))?;
+        let method = u16_at(&data, pos + 10).ok_or(invalid("truncated"))?;
+        let crc = u32_at(&data, pos + 16).ok_or(invalid("truncated"))?;
+        let compressed = u32_at(&data, pos + 20).ok_or(invalid("truncated"))? as u64;
+        let name_len = u16_at(&data, pos + 28).ok_or(invalid("truncated"))?;
+        let extra_len = u16_at(&data, pos + 30).ok_or(invalid("truncated"))?;
+        let comment_len = u16_at(&data, pos + 32).ok_or(invalid("truncated"))?;
+        let attributes = u32_at(&data, pos + 38).ok_or(invalid("truncated"))?;
+        let local = u32_at(&data, pos + 42).ok_or(invalid("truncated"))? as u64;
+        let name = data.get(pos + 46..pos + 46 + name_len).ok_or(invalid("truncated"))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        pos += 46 + name_len + extra_len + comment_len;
+
+        if flags & 1 != 0 {
+            return Err(std::io::Error::new(ErrorKind::Unsupported, format!("{} is encrypted", name)));
+        }
+
+        // Unix permissions live in the high half of the external attributes
+        let unix_mode = (made_by >> 8 == 3).then_some(attributes >> 16).filter(|&mode| mode != 0);
+        let path = safe_path(dest, &name)?;
+        if name.ends_with('/') {
+            std::fs::create_dir_all(&path)?;
+            stats.directories += 1;
+            continue;
+        }
+
+        let mut header = [0u8; 30];
+        input.seek(SeekFrom::Start(local))?;
+        input.read_exact(&mut header).map_err(|_| invalid("entry data out of bounds"))?;
+        let skip = u16_at(&header, 26).ok_or(invalid("truncated"))? + u16_at(&header, 28).ok_or(invalid("truncated"))?;
+        input.seek(SeekFrom::Current(skip as i64))?;
+        let raw = input.by_ref().take(compressed);
+        let input: Box<dyn Read + '_> = match method {
+            0 => Box::new(raw),
+            8 => Box::new(Inflater::new(raw)),
+            _ => return Err(std::io::Error::new(ErrorKind::Unsupported, format!("{} uses zip method {}", name, method))),
+        };
+        let mut content = Crc32Reader { input, crc: 0 };
+
+        match unix_mode {
+            Some(mode) if mode & 0o170000 == 0o120000 => {
+                let mut target = Vec::new();
+                content.by_ref().take(MAX_LINK_TARGET).read_to_end(&mut target)?;
+                if content.crc != crc {
+                    return Err(invalid(&format!("CRC mismatch for {}", name)));
+                }
+                create_symlink(dest, &path, &String::from_utf8_lossy(&target))?;
+                stats.links += 1;
+            }
+            _ => {
+                let mut file = create_file(&path)?;
+                let copied = std::io::copy(&mut content, &mut file);
+                if copied.is_err() || content.crc != crc {
+                    drop(file);
+                    let _ = std::fs::remove_file(&path);
+                    copied?;
+                    return Err(invalid(&format!("CRC mismatch for {}", name)));
+                }
+                if let Some(mode) = unix_mode {
+                    set_mode(&path, mode)?;
+                }
+                stats.files += 1;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+// Works out the CRC-32 of what is read through it
+struct Crc32Reader<R> {
+    input: R,
+    crc: u32,
+}
+
+impl<R: Read> Read for Crc32Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.input.read(buf)?;
+        self.crc = inflate::crc32(self.crc, &buf[..n]);
+        Ok(n)
+    }
+}
+
+// Join an archive entry name onto the destination, refusing anything that
+// would leave it, either by name or by going through a symlink
+fn safe_path(dest: &Path, name: &str) -> std::io::Result<PathBuf> {
+    let refuse = || std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("Refusing to extract {}: it points outside the target directory", name),
+    );
+    let mut path = dest.to_path_buf();
+    for component in Path::new(name).components() {
+        if path != dest && std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink()) {
+            return Err(refuse());
+        }
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return Err(refuse()),
+        }
+    }
+    Ok(path)
+}
+
+// Create a symlink, as long as its target stays inside the destination
+fn create_symlink(dest: &Path, path: &Path, target: &str) -> std::io::Result<()> {
+    let relative = path.strip_prefix(dest).unwrap_or(path);
+    let mut depth = relative.components().count().saturating_sub(1) as isize;
+    for component in Path::new(target).components() {
+        depth += match component {
+            Component::Normal(_) => 1,
+            Component::CurDir => 0,
+            Component::ParentDir => -1,
+            _ => -1000,
+        };
+        if depth < 0 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Refusing to create {} -> {}: the link leaves the target directory", path.display(), target),
+            ));
+        }
+    }
+
+    create_parents(path)?;
+    remove_existing(path)?;
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, path);
+    #[cfg(not(unix))]
+    return Err(std::io::Error::new(ErrorKind::Unsupported, "symlinks are not supported on this platform"));
+}
+
+fn create_parents(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+// Remove a file or link already at the path, so we never write through a symlink
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => std::fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+fn create_file(path: &Path) -> std::io::Result<File> {
+    create_parents(path)?;
+    remove_existing(path)?;
+    File::create(path)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+// The body of a long name entry or pax header, refused when it is longer
+// than MAX_HEADER_EXTENSION
+fn read_extension<R: Read>(input: &mut R, size: u64) -> std::io::Result<Vec<u8>> {
+    if size > MAX_HEADER_EXTENSION {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Tar header extension of {} bytes is over the limit of {}", size, MAX_HEADER_EXTENSION),
+        ));
+    }
+    let mut value = vec![0u8; size as usize];
+    input.read_exact(&mut value)?;
+    Ok(value)
+}
+
+fn skip<R: Read>(input: &mut R, count: u64) -> std::io::Result<()> {
+    let skipped = std::io::copy(&mut input.take(count), &mut std::io::sink())?;
+    if skipped == count { Ok(()) } else { Err(ErrorKind::UnexpectedEof.into()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::testing;
+
+    // A tar header block for an entry of the given type and size
+    fn header(name: &str, kind: u8, size: u64) -> Vec<u8> {
+        let mut header = vec![0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[156] = kind;
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u64 = header.iter().map(|&b| b as u64).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        header
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = testing::temp_path(&format!("archive_{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn oversized_long_name_is_refused() {
+        let tar = header("././@LongLink", b'L', 1 << 32);
+        let dir = scratch("long_name");
+        let error = extract(Cursor::new(&tar), Kind::Tar, &dir).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn oversized_pax_header_is_refused() {
+        let tar = header("pax", b'x', MAX_HEADER_EXTENSION + 1);
+        let dir = scratch("pax");
+        let error = extract(Cursor::new(&tar), Kind::Tar, &dir).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn long_name_within_the_limit_names_the_next_entry() {
+        let long = "d/".repeat(60) + "file.txt";
+        let mut tar = header("././@LongLink", b'L', long.len() as u64 + 1);
+        let mut name = long.clone().into_bytes();
+        name.resize(512, 0);
+        tar.extend(name);
+        tar.extend(header("short", b'0', 2));
+        let mut body = b"hi".to_vec();
+        body.resize(512, 0);
+        tar.extend(body);
+        tar.extend([0u8; 1024]);
+        let dir = scratch("ok");
+        extract(Cursor::new(&tar), Kind::Tar, &dir).unwrap();
+        assert_eq!(std::fs::read(dir.join(&long)).unwrap(), b"hi");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
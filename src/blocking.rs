@@ -0,0 +1,9 @@
+use std::path::Path;
+
+use crate::download::{Download, Summary};
+
+// Download a URL to a file on the calling thread. For more control use
+// Download::builder and run().
+pub fn download(url: &str, output: impl AsRef<Path>) -> std::io::Result<Summary> {
+    Download::builder(url).output(output.as_ref()).build()?.run()
+}
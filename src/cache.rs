@@ -0,0 +1,288 @@
+// A private HTTP cache on disk, after RFC 9111: full 200 responses are kept
+// under a hash of their URL and reused for as long as they are fresh.
+//
+// Each entry is two files, <key>.head with the URL, when the response arrived
+// and its status and headers, and <key>.body with the body. When a response
+// has a Vary header, the request headers it names are hashed into the key as
+// well, so each variant has its own entry, and the names are kept in
+// <url key>.vary for the next lookup (RFC 9111 4.1).
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use http_client::http::ResponseHead;
+use http_client::time;
+use http_client::url::Url;
+
+// A stored response
+pub struct Entry {
+    pub head: ResponseHead,
+    // When the response was received
+    pub stored: SystemTime,
+    head_path: PathBuf,
+    body_path: PathBuf,
+}
+
+impl Entry {
+    // Whether the entry can be used without asking the server (RFC 9111 4.2)
+    pub fn is_fresh(&self, now: SystemTime) -> bool {
+        freshness_lifetime(&self.head) > current_age(&self.head, self.stored, now)
+    }
+
+    pub fn open_body(&self) -> std::io::Result<File> {
+        File::open(&self.body_path)
+    }
+
+    // Request headers that ask the server to answer 304 if the stored
+    // response is still current (RFC 9110 13.1); none if it can't be checked
+    pub fn validators(&self) -> Vec<(String, String)> {
+        let mut validators = Vec::new();
+        if let Some(etag) = self.head.header("ETag") {
+            validators.push(("If-None-Match".to_string(), etag.to_string()));
+        }
+        if let Some(modified) = self.head.header("Last-Modified") {
+            validators.push(("If-Modified-Since".to_string(), modified.to_string()));
+        }
+        validators
+    }
+
+    // The stored head brought up to date by a 304: its headers replace the
+    // stored ones of the same name (RFC 9111 4.3.4), apart from
+    // Content-Length, which on a 304 doesn't describe the stored body
+    pub fn revalidated(&self, not_modified: &ResponseHead) -> ResponseHead {
+        let replaced = |name: &str| {
+            !name.eq_ignore_ascii_case("Content-Length")
+                && not_modified.headers.iter().any(|(new, _)| new.eq_ignore_ascii_case(name))
+        };
+        let mut headers: Vec<(String, String)> = self.head.headers.iter()
+            .filter(|(name, _)| !replaced(name))
+            .cloned()
+            .collect();
+        headers.extend(not_modified.headers.iter()
+            .filter(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"))
+            .cloned());
+        ResponseHead { status: self.head.status, headers }
+    }
+
+    // Replace the stored head, after a 304 said the body is current
+    pub fn update(&self, url: &Url, head: &ResponseHead, received: SystemTime) -> std::io::Result<()> {
+        write_head(&self.head_path, url, head, received)
+    }
+}
+
+// Exit status when --offline can't serve a download from the cache, so
+// scripts can tell a cold cache from a failed download
+pub const OFFLINE_EXIT_CODE: i32 = 3;
+
+// Why --offline couldn't serve a download. Carried inside the io::Error so
+// main can exit with OFFLINE_EXIT_CODE.
+#[derive(Debug)]
+pub enum Offline {
+    Missing(String),
+    Stale(String),
+}
+
+impl Offline {
+    pub fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::NotFound, self)
+    }
+}
+
+impl std::fmt::Display for Offline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Offline::Missing(url) => write!(f, "{} isn't in the cache, and --offline forbids downloading it", url),
+            Offline::Stale(url) => write!(f, "The cached copy of {} is stale, and --offline forbids revalidating it", url),
+        }
+    }
+}
+
+impl std::error::Error for Offline {}
+
+// Exit status to leave with when a download failed because of --offline
+pub fn exit_code(error: &std::io::Error) -> Option<i32> {
+    error.get_ref()?.downcast_ref::<Offline>().map(|_| OFFLINE_EXIT_CODE)
+}
+
+// The stored response for a URL, if there is a usable one for a request
+// with these headers
+pub fn lookup(dir: &Path, url: &Url, request_headers: &[(String, String)]) -> std::io::Result<Option<Entry>> {
+    let vary = match std::fs::read_to_string(vary_path(dir, url)) {
+        Ok(text) => text.lines().map(str::to_string).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let Some((head_path, body_path)) = paths(dir, url, &vary, request_headers) else {
+        return Ok(None);
+    };
+    let text = match std::fs::read_to_string(&head_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let mut lines = text.lines();
+    // A different URL with the same hash, or a damaged entry, counts as missing
+    if lines.next() != Some(url.to_string().as_str()) {
+        return Ok(None);
+    }
+    let Some(stored) = lines.next().and_then(|secs| secs.parse().ok()) else {
+        return Ok(None);
+    };
+    let Some(status) = lines.next().and_then(|status| status.parse().ok()) else {
+        return Ok(None);
+    };
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let head = ResponseHead { status, headers };
+
+    let size = std::fs::metadata(&body_path).map(|metadata| metadata.len()).ok();
+    if size.is_none() || head.content_length() != size {
+        return Ok(None);
+    }
+    Ok(Some(Entry { head, stored: UNIX_EPOCH + Duration::from_secs(stored), head_path, body_path }))
+}
+
+// Keep a complete response to a request with these headers, unless it may
+// not be stored. Returns whether it was.
+pub fn store(
+    dir: &Path,
+    url: &Url,
+    request_headers: &[(String, String)],
+    head: &ResponseHead,
+    received: SystemTime,
+    body: &mut dyn Read,
+) -> std::io::Result<bool> {
+    let vary: Vec<String> = head.headers.iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Vary"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    let Some((head_path, body_path)) = paths(dir, url, &vary, request_headers) else {
+        return Ok(false);
+    };
+    if head.status != 200 || directive(head, "no-store").is_some() {
+        return Ok(false);
+    }
+
+    std::fs::create_dir_all(dir)?;
+    if vary.is_empty() {
+        match std::fs::remove_file(vary_path(dir, url)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    } else {
+        write_replacing(&vary_path(dir, url), &mut vary.join("\n").as_bytes())?;
+    }
+    // The head goes last, so a half-written entry is never taken for a whole one
+    let _ = std::fs::remove_file(&head_path);
+    write_replacing(&body_path, body)?;
+    write_head(&head_path, url, head, received)?;
+    Ok(true)
+}
+
+fn write_head(path: &Path, url: &Url, head: &ResponseHead, received: SystemTime) -> std::io::Result<()> {
+    let secs = received.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut text = format!("{}\n{}\n{}\n", url, secs, head.status);
+    for (name, value) in &head.headers {
+        text.push_str(&format!("{}: {}\n", name, value));
+    }
+    write_replacing(path, &mut text.as_bytes())
+}
+
+fn url_key(url: &Url) -> String {
+    format!("{:x}", Sha256::digest(url.to_string().as_bytes()))
+}
+
+fn vary_path(dir: &Path, url: &Url) -> PathBuf {
+    dir.join(format!("{}.vary", url_key(url)))
+}
+
+// The head and body files for the variant of a URL that a request with these
+// headers selects. None for "Vary: *", which no stored response can satisfy.
+fn paths(dir: &Path, url: &Url, vary: &[String], request_headers: &[(String, String)]) -> Option<(PathBuf, PathBuf)> {
+    if vary.iter().any(|name| name == "*") {
+        return None;
+    }
+    let key = if vary.is_empty() {
+        url_key(url)
+    } else {
+        let mut selecting = url.to_string();
+        for name in vary {
+            let values: Vec<&str> = request_headers.iter()
+                .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+                .collect();
+            selecting.push_str(&format!("\n{}: {}", name, values.join(", ")));
+        }
+        format!("{:x}", Sha256::digest(selecting.as_bytes()))
+    };
+    Some((dir.join(format!("{}.head", key)), dir.join(format!("{}.body", key))))
+}
+
+// Write through a temporary file and rename it over the old one
+fn write_replacing(path: &Path, data: &mut dyn Read) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    if let Err(e) = std::io::copy(data, &mut File::create(&temp)?) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, path)
+}
+
+// A Cache-Control directive of the response: Some("") when given without a
+// value, quotes removed from one that has a value
+fn directive<'a>(head: &'a ResponseHead, name: &str) -> Option<&'a str> {
+    head.headers.iter()
+        .filter(|(header, _)| header.eq_ignore_ascii_case("Cache-Control"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|directive| directive.trim())
+        .find_map(|directive| {
+            let (key, value) = directive.split_once('=').unwrap_or((directive, ""));
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"'))
+        })
+}
+
+// How long the response stays fresh after it was generated (RFC 9111 4.2.1)
+fn freshness_lifetime(head: &ResponseHead) -> Duration {
+    if directive(head, "no-cache").is_some() {
+        return Duration::ZERO;
+    }
+    if let Some(max_age) = directive(head, "max-age") {
+        return Duration::from_secs(max_age.parse().unwrap_or(0));
+    }
+
+    let date = head.header("Date").and_then(time::parse_http_date);
+    if let Some(expires) = head.header("Expires") {
+        // An invalid Expires, such as "0", means already expired
+        return match (time::parse_http_date(expires), date) {
+            (Some(expires), Some(date)) => expires.duration_since(date).unwrap_or_default(),
+            _ => Duration::ZERO,
+        };
+    }
+
+    // Otherwise the usual heuristic, a tenth of the time since it last changed
+    match (head.header("Last-Modified").and_then(time::parse_http_date), date) {
+        (Some(modified), Some(date)) => date.duration_since(modified).unwrap_or_default() / 10,
+        _ => Duration::ZERO,
+    }
+}
+
+// How old the response is now (RFC 9111 4.2.3)
+fn current_age(head: &ResponseHead, received: SystemTime, now: SystemTime) -> Duration {
+    let apparent_age = head.header("Date")
+        .and_then(time::parse_http_date)
+        .and_then(|date| received.duration_since(date).ok())
+        .unwrap_or_default();
+    let age = Duration::from_secs(head.header("Age").and_then(|age| age.trim().parse().ok()).unwrap_or(0));
+    let resident_time = now.duration_since(received).unwrap_or_default();
+    apparent_age.max(age) + resident_time
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+// Lets another thread stop a download, or a deadline. Clones share the same
+// flag and deadline, so keep one and hand a clone to the download.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    deadline: Arc<OnceLock<Instant>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // Count as cancelled from the deadline on. Only the first deadline set
+    // on a token holds.
+    pub fn cancel_at(&self, deadline: Instant) {
+        let _ = self.deadline.set(deadline);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.is_expired()
+    }
+
+    // Whether the deadline has passed
+    pub fn is_expired(&self) -> bool {
+        self.deadline().is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.get().copied()
+    }
+}
+
+// The error a cancelled download ends with, carried inside an io::Error of
+// kind Interrupted. Use Cancelled::from_error to get at it.
+#[derive(Debug)]
+pub struct Cancelled {
+    // Bytes that had arrived when the download stopped
+    pub downloaded: u64,
+    pub total_size: u64,
+}
+
+impl Cancelled {
+    pub fn from_error(error: &std::io::Error) -> Option<&Cancelled> {
+        error.get_ref()?.downcast_ref::<Cancelled>()
+    }
+
+    pub(crate) fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Interrupted, self)
+    }
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Download cancelled after {} of {} bytes", self.downloaded, self.total_size)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+// The error a download ends with when it runs out of the time it was given,
+// carried inside an io::Error of kind TimedOut. Use TimeLimitExceeded::from_error
+// to get at it.
+#[derive(Debug)]
+pub struct TimeLimitExceeded {
+    pub limit: Duration,
+    // Bytes that had arrived when the download stopped
+    pub downloaded: u64,
+    // None if the server hadn't said yet
+    pub total_size: Option<u64>,
+}
+
+impl TimeLimitExceeded {
+    pub fn from_error(error: &std::io::Error) -> Option<&TimeLimitExceeded> {
+        error.get_ref()?.downcast_ref::<TimeLimitExceeded>()
+    }
+
+    pub fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, self)
+    }
+}
+
+impl std::fmt::Display for TimeLimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Time limit of {} s reached after {}", self.limit.as_secs_f64(), self.downloaded)?;
+        match self.total_size {
+            Some(total_size) => write!(f, " of {} bytes", total_size),
+            None => write!(f, " bytes"),
+        }
+    }
+}
+
+impl std::error::Error for TimeLimitExceeded {}
@@ -0,0 +1,1236 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use http_client::dns;
+use http_client::oauth2::ClientCredentials;
+use http_client::proxy::{Proxy, ProxyAuth};
+use http_client::retry::{Jitter, RetryPolicy};
+use http_client::sigv4::{Credentials, Signer};
+
+use crate::commands;
+use crate::completions;
+use crate::compress;
+use crate::curl;
+use crate::http;
+use crate::integrity::{self, ExpectedDigest};
+use crate::output::{self, Existing, SkipCheck, Template};
+use crate::script::Script;
+use crate::speedtest;
+use crate::split;
+use crate::url::Url;
+use crate::writeout::WriteOut;
+
+// URL downloaded when none is given on the command line
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8080/";
+
+// How long a connection may stay under --speed-limit when --speed-time isn't given
+const DEFAULT_SPEED_TIME: std::time::Duration = std::time::Duration::from_secs(30);
+
+// What --compressed asks for: the encodings the body can be decoded from
+const COMPRESSED_ENCODINGS: &str = "gzip, deflate";
+
+// A command line flag. The parser and the usage text are both driven by FLAGS.
+pub struct Flag {
+    pub long: &'static str,
+    pub short: Option<char>,
+    pub value: Value,
+    pub help: &'static str,
+}
+
+// Whether a flag takes a value, with the placeholder shown in the usage text
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    None,
+    Required(&'static str),
+    // Only accepted inline, as --flag=VALUE
+    Optional(&'static str),
+}
+
+pub const FLAGS: &[Flag] = &[
+    Flag {
+        long: "output",
+        short: Some('o'),
+        value: Value::Required("FILE"),
+        help: "Write the download to FILE (only with a single URL)",
+    },
+    Flag {
+        long: "output-document",
+        short: Some('O'),
+        value: Value::Required("FILE"),
+        help: "Same as --output (wget's spelling)",
+    },
+    Flag {
+        long: "output-template",
+        short: None,
+        value: Value::Required("TEMPLATE"),
+        help: "Derive output paths from a template, e.g. \"{host}/{path}/{filename}\"",
+    },
+    Flag {
+        long: "exec",
+        short: None,
+        value: Value::Required("COMMAND"),
+        help: "Pipe the verified body into a shell command instead of saving it",
+    },
+    Flag {
+        long: "pre-hook",
+        short: None,
+        value: Value::Required("COMMAND"),
+        help: "Run a shell command before each download; if it fails the download is skipped",
+    },
+    Flag {
+        long: "post-hook",
+        short: None,
+        value: Value::Required("COMMAND"),
+        help: "Run a shell command after each download, with HTTP_CLIENT_* variables describing it",
+    },
+    Flag {
+        long: "refresh-cmd",
+        short: None,
+        value: Value::Required("COMMAND"),
+        help: "When ranges are refused with 403, run a shell command that prints a fresh signed URL and carry on",
+    },
+    Flag {
+        long: "tee",
+        short: None,
+        value: Value::None,
+        help: "Also write the body to stdout (status messages go to stderr)",
+    },
+    Flag {
+        long: "compress-output",
+        short: None,
+        value: Value::Required("gz|zst"),
+        help: "Compress the file on the fly (hashes are still taken of the original bytes)",
+    },
+    Flag {
+        long: "split",
+        short: None,
+        value: Value::Required("SIZE"),
+        help: "Save as FILE.part001, FILE.part002, ... of at most SIZE (e.g. 1G) with a hash manifest",
+    },
+    Flag {
+        long: "extract",
+        short: None,
+        value: Value::Optional("DIR"),
+        help: "Unpack a verified tar, tar.gz or zip download (into DIR, or next to the archive)",
+    },
+    Flag {
+        long: "adjust-extension",
+        short: Some('E'),
+        value: Value::None,
+        help: "Add the extension the Content-Type, or else the first bytes, call for to derived names",
+    },
+    Flag {
+        long: "directory-prefix",
+        short: Some('P'),
+        value: Value::Required("DIR"),
+        help: "Save files under DIR instead of the current directory",
+    },
+    Flag {
+        long: "no-clobber",
+        short: None,
+        value: Value::None,
+        help: "Fail instead of overwriting an existing output file",
+    },
+    Flag {
+        long: "skip-existing",
+        short: None,
+        value: Value::Optional("size|hash|time"),
+        help: "Don't download if the output file exists (and has the remote size, expected hash or a recent time)",
+    },
+    Flag {
+        long: "timestamping",
+        short: Some('N'),
+        value: Value::None,
+        help: "Only download files newer than the local copy, keeping the remote time (like wget -N)",
+    },
+    Flag {
+        long: "continue",
+        short: Some('c'),
+        value: Value::None,
+        help: "Same as the resume subcommand (wget's spelling)",
+    },
+    Flag {
+        long: "numbered",
+        short: None,
+        value: Value::None,
+        help: "Save as FILE.1, FILE.2, ... when the output file exists",
+    },
+    Flag {
+        long: "wait-for-lock",
+        short: None,
+        value: Value::None,
+        help: "Wait for another download of the same output file to finish instead of failing",
+    },
+    Flag {
+        long: "zsync",
+        short: None,
+        value: Value::Required("CONTROL"),
+        help: "Update an existing output file using a zsync control file (path or URL)",
+    },
+    Flag {
+        long: "remote-time",
+        short: Some('R'),
+        value: Value::None,
+        help: "Set the output file's modification time from Last-Modified",
+    },
+    Flag {
+        long: "mode",
+        short: None,
+        value: Value::Required("MODE"),
+        help: "Permissions for the output file, in octal (e.g. 0644)",
+    },
+    Flag {
+        long: "executable",
+        short: None,
+        value: Value::None,
+        help: "Make the output file executable",
+    },
+    Flag {
+        long: "write-checksums",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Append a sha256sum-compatible line to FILE for every completed download",
+    },
+    Flag {
+        long: "xattr",
+        short: None,
+        value: Value::None,
+        help: "Record the source URL, ETag and SHA-256 in the file's extended attributes",
+    },
+    Flag {
+        long: "sha256",
+        short: None,
+        value: Value::Required("HEX"),
+        help: "Expected SHA-256 of the download, as hex",
+    },
+    Flag {
+        long: "integrity",
+        short: None,
+        value: Value::Required("SRI"),
+        help: "Expected digest in Subresource Integrity form, e.g. sha256-<base64>",
+    },
+    Flag {
+        long: "algorithm",
+        short: Some('a'),
+        value: Value::Required("NAME"),
+        help: "Hash with md5, sha1, sha256 (default), sha384 or sha512 (hash only)",
+    },
+    Flag {
+        long: "limit-rate",
+        short: None,
+        value: Value::Required("RATE"),
+        help: "Keep the average speed under RATE bytes per second, e.g. 200K or 1M",
+    },
+    Flag {
+        long: "max-time",
+        short: Some('m'),
+        value: Value::Required("SECS"),
+        help: "Stop every download still going after SECS seconds, keeping the .part files",
+    },
+    Flag {
+        long: "speed-limit",
+        short: Some('Y'),
+        value: Value::Required("RATE"),
+        help: "Drop and retry a connection slower than RATE bytes per second for --speed-time",
+    },
+    Flag {
+        long: "speed-time",
+        short: Some('y'),
+        value: Value::Required("SECS"),
+        help: "How long a connection may stay under --speed-limit (default 30)",
+    },
+    Flag {
+        long: "retry",
+        short: None,
+        value: Value::Required("N"),
+        help: "Try a failed range N more times before giving up (default 2)",
+    },
+    Flag {
+        long: "retry-delay",
+        short: None,
+        value: Value::Required("SECS"),
+        help: "Wait before the first retry, e.g. 0.5 (default 0.25)",
+    },
+    Flag {
+        long: "retry-multiplier",
+        short: None,
+        value: Value::Required("X"),
+        help: "Multiply the wait by X after each retry (default 2)",
+    },
+    Flag {
+        long: "retry-max-delay",
+        short: None,
+        value: Value::Required("SECS"),
+        help: "Never wait longer than SECS between tries (default 10)",
+    },
+    Flag {
+        long: "retry-jitter",
+        short: None,
+        value: Value::Required("MODE"),
+        help: "Randomise the wait: none, full (default) or equal",
+    },
+    Flag {
+        long: "network-wait",
+        short: None,
+        value: Value::Required("SECS"),
+        help: "Wait up to SECS for the network when every connection fails, 0 not to (default 120)",
+    },
+    Flag {
+        long: "quiet",
+        short: Some('q'),
+        value: Value::None,
+        help: "Print nothing but errors",
+    },
+    Flag {
+        long: "verbose",
+        short: Some('v'),
+        value: Value::None,
+        help: "Show a map of which parts of the body are in, being fetched or still to come",
+    },
+    Flag {
+        long: "tui",
+        short: None,
+        value: Value::None,
+        help: "Show a full-screen dashboard of the downloads, with keys to pause and cancel them",
+    },
+    Flag {
+        long: "notify",
+        short: None,
+        value: Value::None,
+        help: "Raise a desktop notification when each download finishes or fails",
+    },
+    Flag {
+        long: "notify-webhook",
+        short: None,
+        value: Value::Required("URL"),
+        help: "POST a JSON report of each download to URL as it ends",
+    },
+    Flag {
+        long: "write-out",
+        short: Some('w'),
+        value: Value::Required("FORMAT"),
+        help: "Print FORMAT to stdout after each download, with variables like %{http_code} and %{sha256}",
+    },
+    Flag {
+        long: "json",
+        short: None,
+        value: Value::None,
+        help: "Print a JSON summary of each download, timings included, to stdout",
+    },
+    Flag {
+        long: "mirror",
+        short: None,
+        value: Value::Required("URL"),
+        help: "Another place to fetch the same file from; can be given more than once",
+    },
+    Flag {
+        long: "script",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Run the JavaScript callbacks in FILE (rewrite_url, request_headers, on_response, on_chunk, choose_mirror)",
+    },
+    Flag {
+        long: "pipeline",
+        short: None,
+        value: Value::Required("DEPTH"),
+        help: "Keep up to DEPTH range requests out at once on one kept-alive connection (not with --mirror)",
+    },
+    Flag {
+        long: "connections",
+        short: Some('x'),
+        value: Value::Required("N"),
+        help: "Fetch ranges over N connections at once, writing each where it belongs",
+    },
+    Flag {
+        long: "min-split-size",
+        short: None,
+        value: Value::Required("SIZE"),
+        help: "Open only as many --connections as leave each at least SIZE of the body (default 1M)",
+    },
+    Flag {
+        long: "io-uring",
+        short: None,
+        value: Value::None,
+        help: "Drive the --connections from one thread through io_uring (Linux only)",
+    },
+    Flag {
+        long: "http2-prior-knowledge",
+        short: None,
+        value: Value::None,
+        help: "Speak HTTP/2 over plain TCP without an upgrade, fetching ranges as streams of one connection",
+    },
+    Flag {
+        long: "speed-test",
+        short: None,
+        value: Value::Optional("LIMIT"),
+        help: "Measure throughput with 1 to 8 connections for LIMIT (e.g. 10s, the default, or 100M) each, saving nothing",
+    },
+    Flag {
+        long: "cache-dir",
+        short: None,
+        value: Value::Required("DIR"),
+        help: "Keep responses in DIR and reuse them while they are fresh",
+    },
+    Flag {
+        long: "offline",
+        short: None,
+        value: Value::None,
+        help: "Serve downloads only from fresh --cache-dir entries, never the network",
+    },
+    Flag {
+        long: "store",
+        short: None,
+        value: Value::Required("DIR"),
+        help: "Keep verified downloads in DIR by digest, and take any whose --sha256 or --integrity is there from it",
+    },
+    Flag {
+        long: "header",
+        short: Some('H'),
+        value: Value::Required("HEADER"),
+        help: "Send \"Name: value\" with every request; can be given more than once",
+    },
+    Flag {
+        long: "accept",
+        short: None,
+        value: Value::Required("TYPES"),
+        help: "Send TYPES as the Accept header, e.g. \"application/json\"",
+    },
+    Flag {
+        long: "compressed",
+        short: None,
+        value: Value::None,
+        help: "Ask for a gzip or deflate encoded body and save it decoded",
+    },
+    Flag {
+        long: "no-compression",
+        short: None,
+        value: Value::None,
+        help: "Ask for the body without any Content-Encoding (Accept-Encoding: identity)",
+    },
+    Flag {
+        long: "from-curl",
+        short: None,
+        value: Value::Required("COMMAND"),
+        help: "Take the URLs, headers and output from a pasted curl command line",
+    },
+    Flag {
+        long: "trace",
+        short: None,
+        value: Value::None,
+        help: "Print the request and response headers of every connection to stderr",
+    },
+    Flag {
+        long: "trace-ascii",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Like --trace, also dumping everything sent and received, bodies included, to FILE",
+    },
+    Flag {
+        long: "har",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Record every request and response to FILE in HTTP Archive format",
+    },
+    Flag {
+        long: "cookies",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Send the cookies in FILE, a Netscape cookies.txt as curl and wget write",
+    },
+    Flag {
+        long: "cookie-jar",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Save the cookies there are at the end to FILE, in the same format",
+    },
+    Flag {
+        long: "netrc-file",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Take logins for Basic auth from FILE instead of ~/.netrc",
+    },
+    Flag {
+        long: "keyring",
+        short: None,
+        value: Value::None,
+        help: "Send the credentials stored for each host with the auth subcommand",
+    },
+    Flag {
+        long: "ipv4",
+        short: Some('4'),
+        value: Value::None,
+        help: "Resolve names to and connect over IPv4 only",
+    },
+    Flag {
+        long: "ipv6",
+        short: Some('6'),
+        value: Value::None,
+        help: "Resolve names to and connect over IPv6 only",
+    },
+    Flag {
+        long: "tcp-nodelay",
+        short: None,
+        value: Value::None,
+        help: "Turn off Nagle's algorithm on every connection",
+    },
+    Flag {
+        long: "tcp-fastopen",
+        short: None,
+        value: Value::None,
+        help: "Send each request in the TCP handshake with Fast Open (Linux only)",
+    },
+    Flag {
+        long: "tcp-keepalive",
+        short: None,
+        value: Value::Required("SECS"),
+        help: "Send TCP keepalive probes after SECS idle, and every SECS after that",
+    },
+    Flag {
+        long: "read-buffer",
+        short: None,
+        value: Value::Required("SIZE"),
+        help: "Read up to SIZE bytes from a connection at a time (default 256K)",
+    },
+    Flag {
+        long: "recv-buffer",
+        short: None,
+        value: Value::Required("SIZE"),
+        help: "Ask for a SIZE socket receive buffer (SO_RCVBUF), e.g. 4M",
+    },
+    Flag {
+        long: "send-buffer",
+        short: None,
+        value: Value::Required("SIZE"),
+        help: "Ask for a SIZE socket send buffer (SO_SNDBUF)",
+    },
+    Flag {
+        long: "connect-to",
+        short: None,
+        value: Value::Required("HOST:PORT:OTHERHOST:OTHERPORT"),
+        help: "Connect to OTHERHOST:OTHERPORT for requests to HOST:PORT, keeping the URL and Host; can be given more than once",
+    },
+    Flag {
+        long: "hosts-file",
+        short: None,
+        value: Value::Required("FILE"),
+        help: "Look host names up in FILE, in the /etc/hosts format, before DNS",
+    },
+    Flag {
+        long: "dns-server",
+        short: None,
+        value: Value::Required("ADDRESS"),
+        help: "Look host names up with this DNS server (or several, comma-separated) instead of the system's",
+    },
+    Flag {
+        long: "dot",
+        short: None,
+        value: Value::Required("ADDRESS"),
+        help: "Look host names up with this DNS server over TLS, ADDRESS[:PORT][#NAME] (or several, comma-separated); needs the tls feature",
+    },
+    Flag {
+        long: "resolve",
+        short: None,
+        value: Value::Required("HOST:PORT:ADDRESS"),
+        help: "Use ADDRESS (or several, comma-separated) for HOST:PORT instead of DNS; can be given more than once",
+    },
+    Flag {
+        long: "proxy",
+        short: None,
+        value: Value::Required("URL"),
+        help: "Send requests through the HTTP proxy at URL, e.g. http://proxy.example:3128",
+    },
+    Flag {
+        long: "proxy-pac",
+        short: None,
+        value: Value::Required("URL|FILE"),
+        help: "Pick the proxy for each URL with the FindProxyForURL function of a PAC script",
+    },
+    Flag {
+        long: "proxy-user",
+        short: None,
+        value: Value::Required("USER:PASSWORD"),
+        help: "Log in to the proxy with Basic auth",
+    },
+    Flag {
+        long: "proxytunnel",
+        short: None,
+        value: Value::None,
+        help: "Have the proxy CONNECT to each host instead of forwarding the requests",
+    },
+    Flag {
+        long: "aws-sigv4",
+        short: None,
+        value: Value::Required("REGION/SERVICE"),
+        help: "Sign requests with AWS Signature V4, with credentials from the environment or ~/.aws/credentials",
+    },
+    Flag {
+        long: "oauth2-token-url",
+        short: None,
+        value: Value::Required("URL"),
+        help: "Send bearer tokens from this OAuth2 endpoint, for OAUTH2_CLIENT_ID and OAUTH2_CLIENT_SECRET",
+    },
+    Flag {
+        long: "s3",
+        short: None,
+        value: Value::None,
+        help: "Fetch multipart S3 objects part by part, in parallel, and check the body against the ETag",
+    },
+    Flag {
+        long: "dry-run",
+        short: None,
+        value: Value::None,
+        help: "Probe each URL and print the download plan without fetching the body",
+    },
+    Flag {
+        long: "in-order",
+        short: None,
+        value: Value::None,
+        help: "Replay the requests one at a time, in the order they were recorded (replay only)",
+    },
+    Flag {
+        long: "original-headers",
+        short: None,
+        value: Value::None,
+        help: "Send the recorded request headers along (replay only)",
+    },
+    Flag {
+        long: "help",
+        short: Some('h'),
+        value: Value::None,
+        help: "Print this help and exit",
+    },
+];
+
+// A subcommand, its arguments and what it does; without one, the arguments are for get
+pub struct Subcommand {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub help: &'static str,
+}
+
+pub const COMMANDS: &[Subcommand] = &[
+    Subcommand { name: "get", args: "<URL>...", help: "Download URLs (the default)" },
+    Subcommand { name: "resume", args: "<URL>...", help: "Continue interrupted downloads from their .part files" },
+    Subcommand { name: "head", args: "<URL>", help: "Print the response status and headers" },
+    Subcommand { name: "upload", args: "<FILE> <URL>", help: "PUT a file to a URL, printing the answer" },
+    Subcommand { name: "verify", args: "<FILE> <URL>", help: "Check a file against what the server advertises" },
+    Subcommand { name: "hash", args: "<FILE>...", help: "Print the hashes of local files" },
+    Subcommand { name: "replay", args: "<HAR>", help: "Send the GET requests in a HAR file again" },
+    Subcommand { name: "bench", args: "<URL> [LIMIT]", help: "Time range sizes and connection counts, for LIMIT (default 2s) each" },
+    Subcommand { name: "auth", args: "add|rm <HOST>", help: "Store a host's credentials in the system keyring, or remove them" },
+    Subcommand { name: "completions", args: "<SHELL>", help: "Print a completion script for bash, zsh, fish or powershell" },
+];
+
+// Flags every subcommand that makes requests accepts, for how they are sent
+const CONNECTION_FLAGS: &[&str] = &["header", "accept", "compressed", "no-compression", "trace", "trace-ascii", "har", "cookies", "cookie-jar", "netrc-file", "http2-prior-knowledge", "max-time", "speed-limit", "speed-time", "ipv4", "ipv6", "tcp-nodelay", "tcp-fastopen", "tcp-keepalive", "read-buffer", "recv-buffer", "send-buffer", "connect-to", "resolve", "hosts-file", "dns-server", "dot", "proxy", "proxy-pac", "proxy-user", "proxytunnel", "aws-sigv4", "oauth2-token-url"];
+
+// The flags each subcommand that doesn't download accepts on top of those,
+// besides --help. Replay's URLs come from the HAR, so it has no use for
+// --from-curl or --keyring.
+const VERIFY_FLAGS: &[&str] = &["sha256", "integrity", "from-curl", "keyring"];
+const HEAD_FLAGS: &[&str] = &["from-curl", "keyring"];
+const UPLOAD_FLAGS: &[&str] = &["from-curl", "keyring"];
+const HASH_FLAGS: &[&str] = &["algorithm"];
+const REPLAY_FLAGS: &[&str] = &["in-order", "original-headers"];
+const BENCH_FLAGS: &[&str] = &["from-curl", "keyring"];
+const AUTH_FLAGS: &[&str] = &[];
+const COMPLETIONS_FLAGS: &[&str] = &[];
+
+// Flags only those subcommands take, refused when downloading
+const SUBCOMMAND_ONLY_FLAGS: &[&str] = &["algorithm", "in-order", "original-headers"];
+
+// What the invocation asks for
+#[derive(Default)]
+pub enum Command {
+    // Download every URL in Options::urls
+    #[default]
+    Download,
+    // Print the response head for a URL
+    Head { url: Url },
+    // PUT a file to a URL
+    Upload { file: PathBuf, url: Url },
+    // Check an existing file against what the server advertises for a URL
+    Verify { file: PathBuf, url: Url },
+    // Hash local files
+    Hash { files: Vec<PathBuf> },
+    // Send the GET requests recorded in a HAR file again
+    Replay { har: PathBuf },
+    // Find the fastest range size and connection count for a URL
+    Bench { url: Url, limit: speedtest::Limit },
+    // Add or remove a host's credentials in the keyring
+    Auth { action: commands::AuthAction, host: String },
+    // Print a shell completion script
+    Completions { shell: completions::Shell },
+}
+
+impl Command {
+    // The URL a subcommand works on, for those that take one
+    pub fn url(&self) -> Option<&Url> {
+        match self {
+            Command::Head { url } | Command::Upload { url, .. } | Command::Verify { url, .. } | Command::Bench { url, .. } => Some(url),
+            _ => None,
+        }
+    }
+}
+
+// Everything the command line configures
+#[derive(Default)]
+pub struct Options {
+    pub command: Command,
+    pub urls: Vec<Url>,
+    pub output: Option<String>,
+    pub output_template: Option<Template>,
+    pub exec: Option<String>,
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    pub refresh_cmd: Option<String>,
+    pub tee: bool,
+    pub compress: Option<compress::Format>,
+    pub split: Option<u64>,
+    pub extract: bool,
+    pub extract_dir: Option<PathBuf>,
+    pub directory_prefix: Option<PathBuf>,
+    pub accept: Option<String>,
+    pub compressed: bool,
+    pub no_compression: bool,
+    pub adjust_extension: bool,
+    pub existing: Existing,
+    pub wait_for_lock: bool,
+    pub zsync: Option<String>,
+    pub remote_time: bool,
+    pub mode: Option<u32>,
+    pub executable: bool,
+    pub xattr: bool,
+    pub write_checksums: Option<PathBuf>,
+    pub expected: Vec<ExpectedDigest>,
+    pub resume: bool,
+    pub dry_run: bool,
+    pub quiet: bool,
+    pub verbose: bool,
+    pub tui: bool,
+    pub notify: bool,
+    pub notify_webhook: Option<Url>,
+    pub limit_rate: Option<u64>,
+    pub max_time: Option<std::time::Duration>,
+    pub speed_limit: Option<u64>,
+    pub speed_time: Option<std::time::Duration>,
+    pub retry: RetryPolicy,
+    pub network_wait: Option<std::time::Duration>,
+    pub write_out: Option<WriteOut>,
+    pub json: bool,
+    pub mirrors: Vec<Url>,
+    pub script_file: Option<PathBuf>,
+    pub script: Option<Script>,
+    pub pipeline: Option<usize>,
+    pub connections: Option<usize>,
+    pub min_split_size: Option<u64>,
+    pub io_uring: bool,
+    pub speed_test: Option<speedtest::Limit>,
+    pub cache_dir: Option<PathBuf>,
+    pub offline: bool,
+    pub store: Option<PathBuf>,
+    pub trace: bool,
+    pub trace_ascii: Option<PathBuf>,
+    pub har: Option<PathBuf>,
+    pub cookies: Option<PathBuf>,
+    pub cookie_jar: Option<PathBuf>,
+    pub netrc_file: Option<PathBuf>,
+    pub hosts_file: Option<PathBuf>,
+    pub keyring: bool,
+    pub proxy_pac: Option<String>,
+    pub proxy_user: Option<ProxyAuth>,
+    pub proxytunnel: bool,
+    pub s3: bool,
+    pub in_order: bool,
+    pub original_headers: bool,
+    pub algorithm: commands::HashAlgorithm,
+    pub http: http::Settings,
+    pub help: bool,
+}
+
+// Parse the arguments following the program name
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args: VecDeque<String> = args.into_iter().collect();
+    let mut positional_only = false;
+    let mut positional = Vec::new();
+    let mut given = Vec::new();
+
+    while let Some(arg) = args.pop_front() {
+        if positional_only || !arg.starts_with('-') {
+            positional.push(arg);
+            continue;
+        }
+        if arg == "--" {
+            positional_only = true;
+            continue;
+        }
+
+        let (flag, inline_value) = lookup(&arg)?;
+
+        let value = match (flag.value, inline_value) {
+            (Value::None, Some(_)) => return Err(format!("--{} does not take a value", flag.long)),
+            (Value::Required(_), None) => Some(args.pop_front().ok_or(format!("--{} requires a value", flag.long))?),
+            (_, value) => value,
+        };
+        given.push(flag.long);
+        
+        // A pasted curl command stands for the arguments it translates to
+        if flag.long == "from-curl" {
+            let translated = curl::translate(&value.expect("--from-curl takes a value"))?;
+            for arg in translated.into_iter().rev() {
+                args.push_front(arg);
+            }
+            continue;
+        }
+
+        apply(&mut options, flag.long, value)?;
+    }
+
+    let command = COMMANDS.iter()
+        .map(|command| command.name)
+        .find(|name| positional.first().map(String::as_str) == Some(*name));
+    if command.is_some() {
+        positional.remove(0);
+    }
+
+    // Each subcommand has its own set of flags, and the connection's if it
+    // makes requests
+    let accepted: Option<&[&[&str]]> = match command {
+        Some("head") => Some(&[CONNECTION_FLAGS, HEAD_FLAGS]),
+        Some("upload") => Some(&[CONNECTION_FLAGS, UPLOAD_FLAGS]),
+        Some("verify") => Some(&[CONNECTION_FLAGS, VERIFY_FLAGS]),
+        Some("hash") => Some(&[HASH_FLAGS]),
+        Some("replay") => Some(&[CONNECTION_FLAGS, REPLAY_FLAGS]),
+        Some("bench") => Some(&[CONNECTION_FLAGS, BENCH_FLAGS]),
+        Some("auth") => Some(&[AUTH_FLAGS]),
+        Some("completions") => Some(&[COMPLETIONS_FLAGS]),
+        _ => None,
+    };
+    let misplaced = given.iter().find(|&&flag| match accepted {
+        Some(accepted) => flag != "help" && !accepted.iter().any(|flags| flags.contains(&flag)),
+        None => SUBCOMMAND_ONLY_FLAGS.contains(&flag),
+    });
+    if let Some(flag) = misplaced {
+        return Err(format!("--{} can't be used with {}", flag, command.unwrap_or("get")));
+    }
+    if options.help {
+        return Ok(options);
+    }
+    #[cfg(feature = "tls")]
+    if !options.http.dot_servers.is_empty() && !options.http.dns_servers.is_empty() {
+        return Err("--dot and --dns-server can't be combined".to_string());
+    }
+    // The proxy's settings may come before or after it
+    if options.http.proxy.is_some() && options.proxy_pac.is_some() {
+        return Err("--proxy and --proxy-pac can't be combined".to_string());
+    }
+    match &mut options.http.proxy {
+        Some(proxy) => {
+            proxy.auth = options.proxy_user.take();
+            proxy.tunnel = options.proxytunnel;
+        }
+        None if options.proxy_pac.is_none() && (options.proxy_user.is_some() || options.proxytunnel) => {
+            return Err("--proxy-user and --proxytunnel need --proxy or --proxy-pac".to_string());
+        }
+        None => {}
+    }
+    // Either one alone turns the check on, with curl's default for the other
+    if options.speed_limit.is_some() || options.speed_time.is_some() {
+        options.http.low_speed = Some(http::SpeedLimit {
+            bytes_per_second: options.speed_limit.unwrap_or(1),
+            time: options.speed_time.unwrap_or(DEFAULT_SPEED_TIME),
+        });
+    }
+    // Content negotiation, unless --header already asks for the same
+    if options.compressed && options.no_compression {
+        return Err("--compressed and --no-compression can't be combined".to_string());
+    }
+    let encoding = if options.compressed {
+        Some(COMPRESSED_ENCODINGS)
+    } else {
+        options.no_compression.then_some("identity")
+    };
+    let negotiated = [("Accept", options.accept.take()), ("Accept-Encoding", encoding.map(str::to_string))];
+    for (name, value) in negotiated {
+        if let Some(value) = value
+            && !options.http.headers.iter().any(|(given, _)| given.eq_ignore_ascii_case(name))
+        {
+            options.http.headers.push((name.to_string(), value));
+        }
+    }
+
+    match command {
+        Some("head") => {
+            let [url] = positional.as_slice() else {
+                return Err(format!("head needs a URL\n\n{}", usage()));
+            };
+            options.command = Command::Head { url: Url::parse(url)? };
+            return Ok(options);
+        }
+        Some("upload") => {
+            let [file, url] = positional.as_slice() else {
+                return Err(format!("upload needs a file and a URL\n\n{}", usage()));
+            };
+            options.command = Command::Upload { file: PathBuf::from(file), url: Url::parse(url)? };
+            return Ok(options);
+        }
+        Some("verify") => {
+            let [file, url] = positional.as_slice() else {
+                return Err(format!("verify needs a file and a URL\n\n{}", usage()));
+            };
+            options.command = Command::Verify { file: PathBuf::from(file), url: Url::parse(url)? };
+            return Ok(options);
+        }
+        Some("hash") => {
+            if positional.is_empty() {
+                return Err(format!("hash needs at least one file\n\n{}", usage()));
+            }
+            options.command = Command::Hash { files: positional.iter().map(PathBuf::from).collect() };
+            return Ok(options);
+        }
+        Some("replay") => {
+            let [har] = positional.as_slice() else {
+                return Err(format!("replay needs a HAR file\n\n{}", usage()));
+            };
+            options.command = Command::Replay { har: PathBuf::from(har) };
+            return Ok(options);
+        }
+        Some("bench") => {
+            let (url, limit) = match positional.as_slice() {
+                [url] => (url, speedtest::Limit::Duration(std::time::Duration::from_secs(2))),
+                [url, limit] => (url, speedtest::Limit::parse_given(limit)?),
+                _ => return Err(format!("bench needs a URL\n\n{}", usage())),
+            };
+            options.command = Command::Bench { url: Url::parse(url)?, limit };
+            return Ok(options);
+        }
+        Some("auth") => {
+            let [action, host] = positional.as_slice() else {
+                return Err(format!("auth needs add or rm and a host\n\n{}", usage()));
+            };
+            options.command = Command::Auth { action: commands::AuthAction::parse(action)?, host: host.clone() };
+            return Ok(options);
+        }
+        Some("completions") => {
+            let [shell] = positional.as_slice() else {
+                return Err(format!("completions needs a shell\n\n{}", usage()));
+            };
+            options.command = Command::Completions { shell: completions::Shell::parse(shell)? };
+            return Ok(options);
+        }
+        Some("resume") => options.resume = true,
+        _ => {}
+    }
+
+    for arg in &positional {
+        options.urls.push(Url::parse(arg)?);
+    }
+    if options.urls.is_empty() {
+        options.urls.push(Url::parse(DEFAULT_URL)?);
+    }
+    if options.output.is_some() && options.urls.len() > 1 {
+        return Err("--output can only be used with a single URL".to_string());
+    }
+    if options.offline && options.cache_dir.is_none() && options.store.is_none() {
+        return Err("--offline needs --cache-dir or --store".to_string());
+    }
+    if !options.mirrors.is_empty() && options.urls.len() > 1 {
+        return Err("--mirror can only be used with a single URL".to_string());
+    }
+    if options.s3 {
+        let ranged = [
+            ("--resume", options.resume),
+            ("--zsync", options.zsync.is_some()),
+            ("--mirror", !options.mirrors.is_empty()),
+            ("--tee", options.tee),
+            ("--compress-output", options.compress.is_some()),
+        ];
+        if let Some((flag, _)) = ranged.iter().find(|(_, set)| *set) {
+            return Err(format!("--s3 fetches objects by part, so it can't be combined with {}", flag));
+        }
+    }
+    // The rebuilt file goes straight into the .part file, and the seed is
+    // the file as saved
+    if options.zsync.is_some() && (options.tee || options.compress.is_some()) {
+        return Err("--zsync rebuilds the file in place, so it can't be combined with --tee or --compress-output".to_string());
+    }
+    if options.exec.is_some() {
+        let saving = [
+            ("--output", options.output.is_some()),
+            ("--output-template", options.output_template.is_some()),
+            ("--compress-output", options.compress.is_some()),
+            ("--split", options.split.is_some()),
+            ("--extract", options.extract),
+            ("--zsync", options.zsync.is_some()),
+            ("--write-checksums", options.write_checksums.is_some()),
+            ("--xattr", options.xattr),
+            ("--mode", options.mode.is_some()),
+            ("--executable", options.executable),
+            ("--remote-time", options.remote_time),
+        ];
+        if let Some((flag, _)) = saving.iter().find(|(_, set)| *set) {
+            return Err(format!("--exec doesn't save a file, so it can't be combined with {}", flag));
+        }
+    }
+    if options.tui && (options.tee || options.exec.is_some()) {
+        return Err("--tui draws on the terminal, so it can't be combined with --tee or --exec".to_string());
+    }
+    if options.compressed && (options.tee || options.compress.is_some() || options.zsync.is_some()) {
+        return Err("--compressed decodes the body once it is in, so it can't be combined with --tee, --compress-output or --zsync".to_string());
+    }
+    if options.resume && (options.compress.is_some() || options.zsync.is_some() || options.exec.is_some() || options.tee) {
+        return Err("resume can't be combined with --compress-output, --zsync, --exec or --tee".to_string());
+    }
+    if options.connections.is_some() {
+        let in_order = [
+            ("--tee", options.tee),
+            ("--compress-output", options.compress.is_some()),
+            ("--limit-rate", options.limit_rate.is_some()),
+            ("--mirror", !options.mirrors.is_empty()),
+            ("--script", options.script_file.is_some()),
+        ];
+        if let Some((flag, _)) = in_order.iter().find(|(_, set)| *set) {
+            return Err(format!("--connections fetches ranges out of order, so it can't be combined with {}", flag));
+        }
+    } else if options.min_split_size.is_some() || options.io_uring {
+        let flag = if options.io_uring { "--io-uring" } else { "--min-split-size" };
+        return Err(format!("{} needs --connections", flag));
+    }
+    // io_uring reads the answers straight off the connections, one range at
+    // a time over HTTP/1.1
+    if options.io_uring {
+        let unsupported = [
+            ("--http2-prior-knowledge", options.http.http2),
+            ("--pipeline", options.pipeline.is_some()),
+            ("--speed-limit", options.speed_limit.is_some()),
+            ("--speed-time", options.speed_time.is_some()),
+        ];
+        if let Some((flag, _)) = unsupported.iter().find(|(_, set)| *set) {
+            return Err(format!("--io-uring can't be combined with {}", flag));
+        }
+    }
+    if options.split.is_some() && (options.xattr || options.write_checksums.is_some()) {
+        return Err("--split can't be combined with --xattr or --write-checksums (parts are listed in their own manifest)".to_string());
+    }
+    if options.existing == Existing::Skip(SkipCheck::Hash) && options.expected.is_empty() {
+        return Err("--skip-existing=hash needs --sha256 or --integrity".to_string());
+    }
+
+    Ok(options)
+}
+
+// Find the flag an argument refers to, splitting off an inline "=value"
+fn lookup(arg: &str) -> Result<(&'static Flag, Option<String>), String> {
+    let unknown = || format!("Unknown argument: {}\n\n{}", arg, usage());
+
+    if let Some(rest) = arg.strip_prefix("--") {
+        let (name, value) = match rest.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (rest, None),
+        };
+        let flag = FLAGS.iter().find(|f| f.long == name).ok_or_else(unknown)?;
+        return Ok((flag, value));
+    }
+
+    let mut chars = arg.chars();
+    if let (Some('-'), Some(short)) = (chars.next(), chars.next()) {
+        let flag = FLAGS.iter().find(|f| f.short == Some(short)).ok_or_else(unknown)?;
+        let rest = chars.as_str();
+        return Ok((flag, (!rest.is_empty()).then(|| rest.to_string())));
+    }
+
+    Err(unknown())
+}
+
+fn apply(options: &mut Options, flag: &str, value: Option<String>) -> Result<(), String> {
+    let value = value.unwrap_or_default();
+
+    match flag {
+        "output" | "output-document" => options.output = Some(value),
+        "output-template" => options.output_template = Some(Template::parse(&value)?),
+        "exec" => options.exec = Some(value),
+        "pre-hook" => options.pre_hook = Some(value),
+        "post-hook" => options.post_hook = Some(value),
+        "refresh-cmd" => options.refresh_cmd = Some(value),
+        "tee" => options.tee = true,
+        "compress-output" => options.compress = Some(compress::Format::parse(&value)?),
+        "split" => options.split = Some(split::parse_size(&value)?),
+        "extract" => {
+            options.extract = true;
+            options.extract_dir = (!value.is_empty()).then(|| PathBuf::from(value));
+        }
+        "directory-prefix" => options.directory_prefix = Some(PathBuf::from(value)),
+        "adjust-extension" => options.adjust_extension = true,
+        "accept" => options.accept = Some(value),
+        "compressed" => options.compressed = true,
+        "no-compression" => options.no_compression = true,
+        "no-clobber" => options.existing = Existing::Fail,
+        "skip-existing" => options.existing = Existing::Skip(SkipCheck::parse(&value)?),
+        "numbered" => options.existing = Existing::Numbered,
+        "wait-for-lock" => options.wait_for_lock = true,
+        "timestamping" => {
+            options.existing = Existing::Skip(SkipCheck::Time);
+            options.remote_time = true;
+        }
+        "continue" => options.resume = true,
+        "zsync" => options.zsync = Some(value),
+        "remote-time" => options.remote_time = true,
+        "mode" => options.mode = Some(output::parse_mode(&value)?),
+        "executable" => options.executable = true,
+        "xattr" => options.xattr = true,
+        "write-checksums" => options.write_checksums = Some(PathBuf::from(value)),
+        "sha256" => options.expected.push(integrity::parse_hex_sha256(&value)?),
+        "integrity" => options.expected.extend(integrity::parse_sri(&value)?),
+        "algorithm" => options.algorithm = commands::HashAlgorithm::parse(&value)?,
+        "header" => options.http.headers.push(parse_header(&value)?),
+        "trace" => options.trace = true,
+        "trace-ascii" => options.trace_ascii = Some(PathBuf::from(value)),
+        "har" => options.har = Some(PathBuf::from(value)),
+        "cookies" => options.cookies = Some(PathBuf::from(value)),
+        "cookie-jar" => options.cookie_jar = Some(PathBuf::from(value)),
+        "netrc-file" => options.netrc_file = Some(PathBuf::from(value)),
+        "keyring" => options.keyring = true,
+        "ipv4" => options.http.ip_family = Some(http::IpFamily::V4),
+        "ipv6" => options.http.ip_family = Some(http::IpFamily::V6),
+        "tcp-nodelay" => options.http.nodelay = true,
+        "tcp-fastopen" => options.http.fast_open = true,
+        "http2-prior-knowledge" => options.http.http2 = true,
+        "tcp-keepalive" => {
+            let secs = value.parse::<u64>().ok().filter(|secs| *secs > 0)
+                .ok_or(format!("Invalid --tcp-keepalive value: {} (expected whole seconds)", value))?;
+            options.http.keepalive = Some(std::time::Duration::from_secs(secs));
+        }
+        "read-buffer" => options.http.read_buffer = Some(split::parse_size(&value)? as usize),
+        "recv-buffer" => options.http.recv_buffer = Some(split::parse_size(&value)? as usize),
+        "send-buffer" => options.http.send_buffer = Some(split::parse_size(&value)? as usize),
+        "connect-to" => options.http.connect_to.push(http::ConnectTo::parse(&value)?),
+        "hosts-file" => options.hosts_file = Some(PathBuf::from(value)),
+        "dns-server" => {
+            for server in value.split(',') {
+                options.http.dns_servers.push(dns::parse_server(server.trim())?);
+            }
+        }
+        "dot" => {
+            #[cfg(feature = "tls")]
+            for server in value.split(',') {
+                options.http.dot_servers.push(dns::TlsServer::parse(server.trim())?);
+            }
+            #[cfg(not(feature = "tls"))]
+            return Err("--dot needs TLS, which this build doesn't have (build with --features tls)".to_string());
+        }
+        "resolve" => options.http.resolve.push(http::Resolve::parse(&value)?),
+        "proxy" => options.http.proxy = Some(Proxy::parse(&value)?),
+        "proxy-pac" => options.proxy_pac = Some(value),
+        "proxy-user" => options.proxy_user = Some(ProxyAuth::parse_basic(&value)?),
+        "proxytunnel" => options.proxytunnel = true,
+        "aws-sigv4" => options.http.sigv4 = Some(Signer::parse(&value, Credentials::load()?)?),
+        "oauth2-token-url" => options.http.oauth2 = Some(ClientCredentials::from_env(Url::parse(&value)?)?),
+        "s3" => options.s3 = true,
+        "dry-run" => options.dry_run = true,
+        "quiet" => options.quiet = true,
+        "verbose" => options.verbose = true,
+        "tui" => options.tui = true,
+        "notify" => options.notify = true,
+        "notify-webhook" => options.notify_webhook = Some(Url::parse(&value)?),
+        "limit-rate" => options.limit_rate = Some(split::parse_size(&value)?),
+        "max-time" => {
+            let limit = value.parse::<f64>().ok()
+                .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+                .filter(|limit| !limit.is_zero())
+                .ok_or(format!("Invalid --max-time value: {} (expected seconds)", value))?;
+            options.max_time = Some(limit);
+        }
+        "speed-limit" => options.speed_limit = Some(split::parse_size(&value)?),
+        "speed-time" => {
+            let secs = value.parse::<u64>().ok().filter(|secs| *secs > 0)
+                .ok_or(format!("Invalid --speed-time value: {} (expected whole seconds)", value))?;
+            options.speed_time = Some(std::time::Duration::from_secs(secs));
+        }
+        "retry" => {
+            let retries = value.parse::<usize>().ok().and_then(|retries| retries.checked_add(1))
+                .ok_or(format!("Invalid --retry value: {} (expected a number of retries)", value))?;
+            options.retry.max_attempts = retries;
+        }
+        "retry-delay" => options.retry.initial_delay = parse_delay("--retry-delay", &value)?,
+        "retry-max-delay" => options.retry.max_delay = parse_delay("--retry-max-delay", &value)?,
+        "retry-multiplier" => {
+            options.retry.multiplier = value.parse::<f64>().ok()
+                .filter(|multiplier| multiplier.is_finite() && *multiplier >= 1.0)
+                .ok_or(format!("Invalid --retry-multiplier value: {} (expected a number of at least 1)", value))?;
+        }
+        "retry-jitter" => options.retry.jitter = Jitter::parse(&value)?,
+        "network-wait" => {
+            let secs = value.parse::<u64>()
+                .map_err(|_| format!("Invalid --network-wait value: {} (expected whole seconds)", value))?;
+            options.network_wait = Some(std::time::Duration::from_secs(secs));
+        }
+        "write-out" => options.write_out = Some(WriteOut::parse(&value)?),
+        "json" => options.json = true,
+        "mirror" => options.mirrors.push(Url::parse(&value)?),
+        "script" => options.script_file = Some(PathBuf::from(value)),
+        "pipeline" => {
+            let depth = value.parse::<usize>().ok().filter(|depth| *depth > 0)
+                .ok_or(format!("Invalid --pipeline value: {} (expected a depth of at least 1)", value))?;
+            options.pipeline = Some(depth);
+        }
+        "connections" => {
+            let connections = value.parse::<usize>().ok().filter(|connections| *connections > 0)
+                .ok_or(format!("Invalid --connections value: {} (expected at least 1)", value))?;
+            options.connections = Some(connections);
+        }
+        "min-split-size" => options.min_split_size = Some(split::parse_size(&value)?),
+        "io-uring" => options.io_uring = true,
+        "speed-test" => options.speed_test = Some(speedtest::Limit::parse(&value)?),
+        "cache-dir" => options.cache_dir = Some(PathBuf::from(value)),
+        "offline" => options.offline = true,
+        "store" => options.store = Some(PathBuf::from(value)),
+        "in-order" => options.in_order = true,
+        "original-headers" => options.original_headers = true,
+        "help" => options.help = true,
+        _ => unreachable!("flag --{} has no handler", flag),
+    }
+
+    Ok(())
+}
+
+// Parse a wait between retries given in seconds, fractions allowed
+fn parse_delay(flag: &str, value: &str) -> Result<std::time::Duration, String> {
+    value.parse::<f64>().ok()
+        .and_then(|secs| std::time::Duration::try_from_secs_f64(secs).ok())
+        .ok_or(format!("Invalid {} value: {} (expected seconds)", flag, value))
+}
+
+// Parse a request header given as "Name: value"
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header.split_once(':')
+        .ok_or_else(|| format!("Invalid header (expected \"Name: value\"): {}", header))?;
+    let name = name.trim();
+    if name.is_empty() || name.contains(char::is_whitespace) || header.contains(['\r', '\n']) {
+        return Err(format!("Invalid header: {}", header));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+// Usage text listing every flag
+pub fn usage() -> String {
+    let mut text = String::from("Usage: http_client [COMMAND] [OPTIONS] [ARGS]\n\nCommands:\n");
+    for command in COMMANDS {
+        let spec = format!("{} {}", command.name, command.args);
+        text.push_str(&format!("  {:<28} {}\n", spec, command.help));
+    }
+    text.push_str(&format!("\nURL defaults to {}\n\nOptions:\n", DEFAULT_URL));
+
+    for flag in FLAGS {
+        let short = flag.short.map(|c| format!("-{}, ", c)).unwrap_or_default();
+        let value = match flag.value {
+            Value::None => String::new(),
+            Value::Required(v) => format!(" <{}>", v),
+            Value::Optional(v) => format!("[=<{}>]", v),
+        };
+        let spec = format!("{}--{}{}", short, flag.long, value);
+        text.push_str(&format!("  {:<28} {}\n", spec, flag.help));
+    }
+
+    text
+}
@@ -0,0 +1,165 @@
+use std::fs::File;
+use std::io::{IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::http;
+use crate::integrity::{Algorithm, Hasher};
+use crate::keyring;
+use crate::md5;
+use crate::sha1;
+use crate::url::Url;
+
+// Algorithms the hash subcommand can print, the legacy ones included for
+// comparing against old checksum files
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha2(Algorithm),
+}
+
+impl HashAlgorithm {
+    pub fn parse(name: &str) -> Result<HashAlgorithm, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            name => Algorithm::from_name(name)
+                .map(HashAlgorithm::Sha2)
+                .ok_or_else(|| format!("Unknown hash algorithm: {} (use md5, sha1, sha256, sha384 or sha512)", name)),
+        }
+    }
+
+    // Hash everything input has, a buffer at a time
+    fn hash(self, input: impl Read) -> std::io::Result<Vec<u8>> {
+        match self {
+            HashAlgorithm::Md5 => {
+                let mut md5 = md5::Md5::new();
+                crate::read_pieces(input, |piece| {
+                    md5.update(piece);
+                    Ok(())
+                })?;
+                Ok(md5.finish().to_vec())
+            }
+            HashAlgorithm::Sha1 => {
+                let mut sha1 = sha1::Sha1::new();
+                crate::read_pieces(input, |piece| {
+                    sha1.update(piece);
+                    Ok(())
+                })?;
+                Ok(sha1.finish().to_vec())
+            }
+            HashAlgorithm::Sha2(algorithm) => {
+                let mut hasher = Hasher::new(algorithm);
+                crate::read_pieces(input, |piece| {
+                    hasher.update(piece);
+                    Ok(())
+                })?;
+                Ok(hasher.finish())
+            }
+        }
+    }
+}
+
+impl Default for HashAlgorithm {
+    fn default() -> HashAlgorithm {
+        HashAlgorithm::Sha2(Algorithm::Sha256)
+    }
+}
+
+// Print the status line and headers of a HEAD request. Returns whether the
+// server answered with something other than an error.
+pub fn head(url: &Url, settings: &http::Settings) -> std::io::Result<bool> {
+    let head = http::head(url, settings)?;
+    println!("{}", head.status);
+    for (name, value) in &head.headers {
+        println!("{}: {}", name, value);
+    }
+    Ok(head.status < 400)
+}
+
+// PUT the file to the URL, read as it goes out, and print the answer: its
+// status on stderr and its body on stdout. Returns whether the server took
+// the file.
+pub fn upload(file: &Path, url: &Url, settings: &http::Settings) -> std::io::Result<bool> {
+    let mut body = File::open(file)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read {}: {}", file.display(), e)))?;
+    let length = body.metadata()?.len();
+    let (head, answer) = http::upload("PUT", url, &[], &mut body, length, settings)?;
+    eprintln!("{} answered {} to {} bytes of {}", url, head.status, length, file.display());
+    std::io::stdout().write_all(&answer)?;
+    Ok((200..300).contains(&head.status))
+}
+
+// Print a sha256sum-style line for each file. Files that can't be read are
+// reported and skipped; returns whether all of them could be hashed.
+pub fn hash(files: &[PathBuf], algorithm: HashAlgorithm) -> bool {
+    let mut all_read = true;
+    for file in files {
+        match File::open(file).and_then(|input| algorithm.hash(input)) {
+            Ok(digest) => {
+                let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+                println!("{}  {}", hex, file.display());
+            }
+            Err(e) => {
+                eprintln!("Error reading {}: {}", file.display(), e);
+                all_read = false;
+            }
+        }
+    }
+    all_read
+}
+
+// What auth does with a host's entry in the keyring
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthAction {
+    Add,
+    Remove,
+}
+
+impl AuthAction {
+    pub fn parse(action: &str) -> Result<AuthAction, String> {
+        match action {
+            "add" => Ok(AuthAction::Add),
+            "rm" | "remove" => Ok(AuthAction::Remove),
+            _ => Err(format!("Unknown auth action: {} (expected add or rm)", action)),
+        }
+    }
+}
+
+// Store the credentials read from stdin for the host, or remove the stored ones
+pub fn auth(action: AuthAction, host: &str) -> std::io::Result<()> {
+    if action == AuthAction::Remove {
+        keyring::remove(host)?;
+        eprintln!("Removed the credentials for {}", host);
+        return Ok(());
+    }
+
+    let terminal = std::io::stdin().is_terminal();
+    if terminal {
+        eprint!("Credentials for {} (user:password or Bearer TOKEN): ", host);
+        set_echo(false);
+    }
+    let mut line = String::new();
+    let read = std::io::stdin().read_line(&mut line);
+    if terminal {
+        set_echo(true);
+        eprintln!();
+    }
+    read?;
+    let authorization = keyring::authorization(line.trim_end_matches(['\r', '\n']))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    keyring::store(host, &authorization)?;
+    eprintln!("Stored the credentials for {} in the keyring", host);
+    Ok(())
+}
+
+// Keep what is typed at the terminal from showing, where stty can do that
+fn set_echo(on: bool) {
+    #[cfg(unix)]
+    let _ = std::process::Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .stdin(std::process::Stdio::inherit())
+        .status();
+    #[cfg(not(unix))]
+    let _ = on;
+}
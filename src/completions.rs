@@ -0,0 +1,283 @@
+// Shell completion scripts, generated from the same FLAGS and COMMANDS
+// tables as the parser and the usage text, so they can't fall behind.
+
+use crate::cli::{COMMANDS, FLAGS, Flag, Value};
+
+const PROGRAM: &str = "http_client";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+// What a flag's value can be completed with
+enum Completion {
+    Nothing,
+    Files,
+    Directories,
+    Choices(Vec<&'static str>),
+}
+
+impl Shell {
+    const NAMES: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+    pub fn parse(name: &str) -> Result<Shell, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "powershell" | "pwsh" => Ok(Shell::Powershell),
+            _ => Err(format!("Unknown shell: {} (use {})", name, Shell::NAMES.join(", "))),
+        }
+    }
+
+    // The completion script, to be sourced or saved where the shell looks
+    pub fn script(self) -> String {
+        match self {
+            Shell::Bash => bash(),
+            Shell::Zsh => zsh(),
+            Shell::Fish => fish(),
+            Shell::Powershell => powershell(),
+        }
+    }
+}
+
+// Placeholders like gz|zst list the accepted values; FILE and DIR are paths
+fn completion(flag: &Flag) -> Completion {
+    let placeholder = match flag.value {
+        Value::None => return Completion::Nothing,
+        Value::Required(placeholder) | Value::Optional(placeholder) => placeholder,
+    };
+    match placeholder {
+        "FILE" | "CONTROL" => Completion::Files,
+        "DIR" => Completion::Directories,
+        _ if placeholder.contains('|') => Completion::Choices(placeholder.split('|').collect()),
+        _ => Completion::Nothing,
+    }
+}
+
+// Every spelling of a flag, long first
+fn spellings(flag: &Flag) -> Vec<String> {
+    let mut names = vec![format!("--{}", flag.long)];
+    names.extend(flag.short.map(|c| format!("-{}", c)));
+    names
+}
+
+fn bash() -> String {
+    let all_flags: Vec<String> = FLAGS.iter().flat_map(spellings).collect();
+    let commands: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+
+    let mut cases = String::new();
+    for flag in FLAGS.iter().filter(|flag| matches!(flag.value, Value::Required(_))) {
+        let reply = match completion(flag) {
+            Completion::Nothing => "return".to_string(),
+            Completion::Files => "COMPREPLY=($(compgen -f -- \"$cur\")); return".to_string(),
+            Completion::Directories => "COMPREPLY=($(compgen -d -- \"$cur\")); return".to_string(),
+            Completion::Choices(choices) => {
+                format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return", choices.join(" "))
+            }
+        };
+        cases.push_str(&format!("        {})\n            {} ;;\n", spellings(flag).join("|"), reply));
+    }
+
+    format!(
+        r#"_{program}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+
+    if [[ $COMP_CWORD -eq 2 && "$prev" == completions ]]; then
+        COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+        return
+    fi
+    case "$prev" in
+{cases}    esac
+
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{flags}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{commands}" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+
+complete -o filenames -F _{program} {program}
+"#,
+        program = PROGRAM,
+        shells = Shell::NAMES.join(" "),
+        cases = cases,
+        flags = all_flags.join(" "),
+        commands = commands.join(" "),
+    )
+}
+
+// Quote for a single-quoted zsh or bash word
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn zsh() -> String {
+    let mut specs = String::new();
+    for flag in FLAGS {
+        let description = format!("[{}]", flag.help.replace('[', "\\[").replace(']', "\\]"));
+        let action = match completion(flag) {
+            Completion::Nothing => " ".to_string(),
+            Completion::Files => "_files".to_string(),
+            Completion::Directories => "_files -/".to_string(),
+            Completion::Choices(choices) => format!("({})", choices.join(" ")),
+        };
+        // Optional values have to be given as --flag=VALUE
+        let (equals, value) = match flag.value {
+            Value::None => ("", String::new()),
+            Value::Required(placeholder) => ("", format!(":{}:{}", placeholder, action)),
+            Value::Optional(placeholder) => ("=-", format!("::{}:{}", placeholder, action)),
+        };
+        let spec = match flag.short {
+            Some(short) => format!(
+                "{}{{-{},--{}{}}}{}",
+                sh_quote(&format!("(-{} --{})", short, flag.long)),
+                short, flag.long, equals,
+                sh_quote(&format!("{}{}", description, value)),
+            ),
+            None => sh_quote(&format!("--{}{}{}{}", flag.long, equals, description, value)),
+        };
+        specs.push_str(&format!("        {} \\\n", spec));
+    }
+
+    let commands: Vec<String> = COMMANDS.iter()
+        .map(|command| format!("        {}", sh_quote(&format!("{}:{}", command.name, command.help))))
+        .collect();
+
+    format!(
+        r#"#compdef {program}
+
+_{program}() {{
+    local state
+    _arguments -s -S \
+{specs}        '1: :->first' \
+        '*: :->rest'
+
+    case $state in
+        first)
+            local -a commands
+            commands=(
+{commands}
+            )
+            _describe -t commands command commands
+            _files
+            ;;
+        rest)
+            if [[ ${{words[2]}} == completions ]]; then
+                _values shell {shells}
+            else
+                _files
+            fi
+            ;;
+    esac
+}}
+
+_{program} "$@"
+"#,
+        program = PROGRAM,
+        specs = specs,
+        commands = commands.join("\n"),
+        shells = Shell::NAMES.join(" "),
+    )
+}
+
+// Quote for a single-quoted fish word
+fn fish_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish() -> String {
+    let mut script = format!("complete -c {} -f\n", PROGRAM);
+
+    for command in COMMANDS {
+        script.push_str(&format!(
+            "complete -c {} -n __fish_use_subcommand -a {} -d {}\n",
+            PROGRAM, command.name, fish_quote(command.help)
+        ));
+    }
+    script.push_str(&format!(
+        "complete -c {} -n '__fish_seen_subcommand_from completions' -a {}\n",
+        PROGRAM, fish_quote(&Shell::NAMES.join(" "))
+    ));
+    script.push_str(&format!("complete -c {} -n '__fish_seen_subcommand_from upload verify hash' -F\n", PROGRAM));
+
+    for flag in FLAGS {
+        let mut line = format!("complete -c {} -l {}", PROGRAM, flag.long);
+        if let Some(short) = flag.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        line.push_str(&format!(" -d {}", fish_quote(flag.help)));
+        // fish has no optional values, so those are completed as plain flags
+        if let Value::Required(_) = flag.value {
+            match completion(flag) {
+                Completion::Nothing => line.push_str(" -x"),
+                Completion::Files => line.push_str(" -r -F"),
+                Completion::Directories => line.push_str(" -x -a '(__fish_complete_directories)'"),
+                Completion::Choices(choices) => line.push_str(&format!(" -x -a {}", fish_quote(&choices.join(" ")))),
+            }
+        }
+        script.push_str(&line);
+        script.push('\n');
+    }
+    script
+}
+
+// Quote for a single-quoted Powershell string
+fn ps_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+fn powershell() -> String {
+    let entries = |items: Vec<(String, &str)>| -> String {
+        items.iter()
+            .map(|(text, help)| format!("        @({}, {})", ps_quote(text), ps_quote(help)))
+            .collect::<Vec<_>>()
+            .join(",\n")
+    };
+    let commands = entries(COMMANDS.iter().map(|command| (command.name.to_string(), command.help)).collect());
+    let flags = entries(FLAGS.iter().flat_map(|flag| spellings(flag).into_iter().map(|name| (name, flag.help))).collect());
+    let shells = entries(Shell::NAMES.iter().map(|name| (name.to_string(), "Shell")).collect());
+
+    format!(
+        r#"Register-ArgumentCompleter -Native -CommandName {program} -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+
+    $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    $position = if ($wordToComplete) {{ $words.Count - 1 }} else {{ $words.Count }}
+    $commands = @(
+{commands}
+    )
+    $flags = @(
+{flags}
+    )
+    $shells = @(
+{shells}
+    )
+
+    $candidates = if ($wordToComplete.StartsWith('-')) {{
+        $flags
+    }} elseif ($position -eq 1) {{
+        $commands
+    }} elseif ($position -eq 2 -and $words[1] -eq 'completions') {{
+        $shells
+    }} else {{
+        @()
+    }}
+    $candidates | Where-Object {{ $_[0] -like "$wordToComplete*" }} | ForEach-Object {{
+        [System.Management.Automation.CompletionResult]::new($_[0], $_[0], 'ParameterValue', $_[1])
+    }}
+}}
+"#,
+        program = PROGRAM,
+        commands = commands,
+        flags = flags,
+        shells = shells,
+    )
+}
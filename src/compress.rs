@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+// Formats the output can be compressed into on the fly. Compression is done
+// by the gzip and zstd programs, which have to be installed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Result<Format, String> {
+        match value {
+            "gz" | "gzip" => Ok(Format::Gzip),
+            "zst" | "zstd" => Ok(Format::Zstd),
+            _ => Err(format!("Unknown compression format: {} (expected gz or zst)", value)),
+        }
+    }
+
+    // Extension appended to derived output names
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Gzip => "gz",
+            Format::Zstd => "zst",
+        }
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Zstd => "zstd",
+        }
+    }
+}
+
+// A writer that compresses everything written to it into a file, by piping
+// it through the compressor program
+pub struct Compressor {
+    format: Format,
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl Compressor {
+    pub fn spawn(format: Format, output: &File) -> std::io::Result<Compressor> {
+        let mut child = Command::new(format.program())
+            .args(["-c", "-q"])
+            .stdin(Stdio::piped())
+            .stdout(output.try_clone()?)
+            .spawn()
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", format.program(), e)))?;
+        let stdin = child.stdin.take();
+
+        Ok(Compressor { format, child, stdin })
+    }
+
+    // Close the compressor's input and wait for it to write the last of its output
+    pub fn finish(mut self) -> std::io::Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!("{} failed: {}", self.format.program(), status)))
+        }
+    }
+}
+
+impl Write for Compressor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stdin.as_mut().expect("compressor input is open until finish").write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdin.as_mut().expect("compressor input is open until finish").flush()
+    }
+}
+
+// A reader of what a compressed output holds, for reading the body back once
+// it is written, by piping the file through the program the other way
+pub struct Decompressor {
+    format: Format,
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Decompressor {
+    pub fn spawn(format: Format, input: File) -> std::io::Result<Decompressor> {
+        let mut child = Command::new(format.program())
+            .args(["-d", "-c", "-q"])
+            .stdin(input)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", format.program(), e)))?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        Ok(Decompressor { format, child, stdout })
+    }
+}
+
+impl Read for Decompressor {
+    // The end of the output only counts once the program says it succeeded
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(std::io::Error::other(format!("{} -d failed: {}", self.format.program(), status)));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Decompressor {
+    // Stop a program whose output is no longer wanted
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,90 @@
+// A record of which chunks of a parallel download are already in its .part
+// file, kept beside it as <output>.part.ctrl, after aria2's control files.
+// It is rewritten as the download goes and left behind when it fails, so a
+// later run fetches only the chunks that are missing rather than starting
+// over. It is only ever saved once the file has been synced, so every chunk
+// it lists is really on disk.
+//
+// Four lines: the URL, the body's size and the chunk size, the body's ETag or
+// Last-Modified ("-" if it had neither) and the finished chunks as runs of
+// indexes, such as "0-11 14 20-31".
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::engine::CHUNK_SIZE;
+use crate::http::ResponseHead;
+use crate::url::Url;
+
+pub(crate) struct ControlFile {
+    path: PathBuf,
+    // The first three lines, which have to match for a record to be used
+    header: String,
+}
+
+impl ControlFile {
+    pub(crate) fn new(part_path: &Path, url: &Url, head: &ResponseHead, total_size: u64) -> ControlFile {
+        let mut name = part_path.as_os_str().to_owned();
+        name.push(".ctrl");
+        let validator = head.header("ETag").or(head.header("Last-Modified")).unwrap_or("-");
+        ControlFile {
+            path: PathBuf::from(name),
+            header: format!("{}\n{} {}\n{}\n", url, total_size, CHUNK_SIZE, validator),
+        }
+    }
+
+    // The chunks an earlier run finished, if it left a record for the same
+    // body. A damaged record counts as none.
+    pub(crate) fn load(&self, chunks: usize) -> std::io::Result<Option<Vec<bool>>> {
+        let text = match std::fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(text.strip_prefix(&self.header).and_then(|done| parse_runs(done.trim(), chunks)))
+    }
+
+    // Replace the record with one of these finished chunks, in one step so
+    // a crash leaves either the old record or the new one
+    pub(crate) fn save(&self, done: &[bool]) -> std::io::Result<()> {
+        let mut temp_name = self.path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+        let temp_path = PathBuf::from(temp_name);
+        let mut file = std::fs::File::create(&temp_path)?;
+        file.write_all(format!("{}{}\n", self.header, format_runs(done)).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&temp_path, &self.path)
+    }
+
+    pub(crate) fn remove(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn format_runs(done: &[bool]) -> String {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < done.len() {
+        if !done[i] {
+            i += 1;
+            continue;
+        }
+        let end = (i..done.len()).find(|&j| !done[j]).unwrap_or(done.len()) - 1;
+        runs.push(if end == i { i.to_string() } else { format!("{}-{}", i, end) });
+        i = end + 1;
+    }
+    runs.join(" ")
+}
+
+fn parse_runs(text: &str, chunks: usize) -> Option<Vec<bool>> {
+    let mut done = vec![false; chunks];
+    for run in text.split_whitespace() {
+        let (first, last) = run.split_once('-').unwrap_or((run, run));
+        let (first, last): (usize, usize) = (first.parse().ok()?, last.parse().ok()?);
+        if first > last || last >= chunks {
+            return None;
+        }
+        done[first..=last].fill(true);
+    }
+    Some(done)
+}
@@ -0,0 +1,233 @@
+// Cookies kept across requests, loaded from and saved to the Netscape
+// cookies.txt format that curl and wget read and write.
+//
+// Each line of the file is a cookie: domain, whether subdomains match
+// (TRUE/FALSE), path, whether it is secure only, expiry in Unix seconds (0 for
+// a session cookie), name and value, separated by tabs. Lines starting with #
+// are comments, except that a "#HttpOnly_" prefix marks an HttpOnly cookie.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::http::ResponseHead;
+use crate::time;
+use crate::url::Url;
+
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+// The cookies so far. Clones share them, so one jar can be given to every
+// connection of a session.
+#[derive(Clone, Debug, Default)]
+pub struct CookieJar {
+    cookies: Arc<Mutex<Vec<Cookie>>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cookie {
+    // Lowercase, without a leading dot
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    // Unix seconds, 0 for a cookie that lasts for the session
+    expires: u64,
+    name: String,
+    value: String,
+}
+
+impl CookieJar {
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    // Read a cookies.txt file. Lines that aren't cookies are skipped.
+    pub fn load(path: &Path) -> std::io::Result<CookieJar> {
+        let text = std::fs::read_to_string(path)?;
+        let now = unix_now();
+        let cookies = text.lines()
+            .filter_map(parse_line)
+            .filter(|cookie| !cookie.expired(now))
+            .collect();
+        Ok(CookieJar { cookies: Arc::new(Mutex::new(cookies)) })
+    }
+
+    // The jar in cookies.txt form
+    pub fn to_text(&self) -> String {
+        let cookies = self.cookies.lock().expect("no thread panics holding the cookie jar");
+        let now = unix_now();
+        let mut text = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in cookies.iter().filter(|cookie| !cookie.expired(now)) {
+            let flag = |set: bool| if set { "TRUE" } else { "FALSE" };
+            let domain = if cookie.include_subdomains { format!(".{}", cookie.domain) } else { cookie.domain.clone() };
+            text.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only { HTTP_ONLY_PREFIX } else { "" }, domain, flag(cookie.include_subdomains),
+                cookie.path, flag(cookie.secure), cookie.expires, cookie.name, cookie.value
+            ));
+        }
+        text
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    // Value for the Cookie header of a request to the URL, if any cookies
+    // apply. Longer paths go first (RFC 6265 5.4). Secure cookies are never
+    // sent, since connections are plain HTTP.
+    pub(crate) fn header(&self, url: &Url) -> Option<String> {
+        let cookies = self.cookies.lock().expect("no thread panics holding the cookie jar");
+        let now = unix_now();
+        let path = request_path(url);
+        let mut matching: Vec<&Cookie> = cookies.iter()
+            .filter(|cookie| !cookie.expired(now) && !cookie.secure)
+            .filter(|cookie| cookie.matches_domain(&url.host) && path_matches(&path, &cookie.path))
+            .collect();
+        if matching.is_empty() {
+            return None;
+        }
+        matching.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+        let pairs: Vec<String> = matching.iter().map(|cookie| format!("{}={}", cookie.name, cookie.value)).collect();
+        Some(pairs.join("; "))
+    }
+
+    // Take in the Set-Cookie headers of a response to the URL
+    pub(crate) fn receive(&self, url: &Url, head: &ResponseHead) {
+        let set_cookies: Vec<Cookie> = head.headers.iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Set-Cookie"))
+            .filter_map(|(_, value)| parse_set_cookie(url, value))
+            .collect();
+        if set_cookies.is_empty() {
+            return;
+        }
+
+        let mut cookies = self.cookies.lock().expect("no thread panics holding the cookie jar");
+        let now = unix_now();
+        for cookie in set_cookies {
+            // A new cookie replaces the one with the same name, domain and path;
+            // one that has already expired just removes it
+            cookies.retain(|old| (&old.name, &old.domain, &old.path) != (&cookie.name, &cookie.domain, &cookie.path));
+            if !cookie.expired(now) {
+                cookies.push(cookie);
+            }
+        }
+    }
+}
+
+impl Cookie {
+    fn expired(&self, now: u64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    fn matches_domain(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        host == self.domain
+            || (self.include_subdomains && host.strip_suffix(&self.domain).is_some_and(|rest| rest.ends_with('.')))
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+fn parse_line(line: &str) -> Option<Cookie> {
+    let (http_only, line) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+        Some(rest) => (true, rest),
+        None if line.starts_with('#') => return None,
+        None => (false, line),
+    };
+    let [domain, include_subdomains, path, secure, expires, name, value] =
+        line.trim_end_matches(['\r', '\n']).splitn(7, '\t').collect::<Vec<_>>()[..]
+    else {
+        return None;
+    };
+    Some(Cookie {
+        domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+        include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+        path: path.to_string(),
+        secure: secure.eq_ignore_ascii_case("TRUE"),
+        http_only,
+        expires: expires.parse().ok()?,
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+// A Set-Cookie header (RFC 6265 5.2), or None if it is malformed or sets a
+// cookie for a domain the URL's host isn't in
+fn parse_set_cookie(url: &Url, header: &str) -> Option<Cookie> {
+    let mut parts = header.split(';');
+    let (name, value) = parts.next()?.split_once('=')?;
+    let name = name.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut cookie = Cookie {
+        domain: url.host.to_ascii_lowercase(),
+        include_subdomains: false,
+        path: default_path(url),
+        secure: false,
+        http_only: false,
+        expires: 0,
+        name: name.to_string(),
+        value: value.trim().to_string(),
+    };
+    let mut max_age = None;
+    for attribute in parts {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let value = value.trim();
+        match key.trim().to_ascii_lowercase().as_str() {
+            "domain" if !value.is_empty() => {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                cookie.include_subdomains = true;
+                cookie.domain = domain;
+                if !cookie.matches_domain(&url.host) {
+                    return None;
+                }
+            }
+            "path" if value.starts_with('/') => cookie.path = value.to_string(),
+            "secure" => cookie.secure = true,
+            "httponly" => cookie.http_only = true,
+            "expires" => {
+                if let Some(expires) = time::parse_http_date(value) {
+                    cookie.expires = expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs().max(1);
+                }
+            }
+            "max-age" => max_age = value.parse::<i64>().ok(),
+            _ => {}
+        }
+    }
+    // Max-Age wins over Expires; zero or less expires the cookie at once
+    if let Some(max_age) = max_age {
+        cookie.expires = match u64::try_from(max_age) {
+            Ok(secs) if secs > 0 => (SystemTime::now() + Duration::from_secs(secs))
+                .duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            _ => 1,
+        };
+    }
+    Some(cookie)
+}
+
+// The URL's path without the query
+fn request_path(url: &Url) -> String {
+    let path = url.path.split('?').next().unwrap_or("/");
+    if path.is_empty() { "/".to_string() } else { path.to_string() }
+}
+
+// The directory of the request path, used when Set-Cookie has no Path (RFC 6265 5.1.4)
+fn default_path(url: &Url) -> String {
+    let path = request_path(url);
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(slash) => path[..slash].to_string(),
+    }
+}
+
+// RFC 6265 5.1.4: the cookie path is the request path or a directory of it
+fn path_matches(request: &str, cookie: &str) -> bool {
+    request == cookie
+        || (request.starts_with(cookie) && (cookie.ends_with('/') || request[cookie.len()..].starts_with('/')))
+}
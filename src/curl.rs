@@ -0,0 +1,381 @@
+// Translation of a pasted curl command line, like the ones browsers produce
+// with "Copy as cURL", into this client's own arguments.
+
+use crate::integrity;
+
+// curl options that don't change anything here: redirects, progress and
+// error display, TLS and protocol choices this client makes on its own
+const IGNORED: &[&str] = &[
+    "-L", "--location", "-s", "--silent", "-S", "--show-error", "-f", "--fail", "-k", "--insecure",
+    "-g", "--globoff", "-#", "--progress-bar", "-O", "--remote-name", "--http1.1",
+    "--http2", "--no-buffer", "-N",
+];
+
+// Options that need a value, so they can be written as -Xvalue
+const SHORT_WITH_VALUE: &str = "HoXdAeubcCrTwxUYym";
+
+// Turn `curl [options] URL...` into arguments for parse_args
+pub fn translate(command: &str) -> Result<Vec<String>, String> {
+    let words = split_words(command)?;
+    let mut words = words.into_iter().peekable();
+    if words.peek().is_some_and(|word| word == "curl" || word.ends_with("/curl")) {
+        words.next();
+    }
+
+    let mut subcommand = None;
+    let mut args = Vec::new();
+    let mut urls = Vec::new();
+    // -T's file, put ahead of the URL for the upload subcommand
+    let mut upload = None;
+    let mut method = None;
+    let unsupported = |option: &str, why: &str| format!("--from-curl: {} isn't supported, {}", option, why);
+
+    while let Some(word) = words.next() {
+        if !word.starts_with('-') || word == "-" {
+            urls.push(word);
+            continue;
+        }
+        if IGNORED.contains(&word.as_str()) {
+            continue;
+        }
+
+        // Bundled short options like -sSL, or a value stuck to its option like -ofile
+        let (option, attached) = match word.strip_prefix('-') {
+            Some(rest) if !rest.starts_with('-') && rest.len() > 1 => {
+                let first = rest.chars().next().expect("rest is not empty");
+                if SHORT_WITH_VALUE.contains(first) {
+                    (format!("-{}", first), Some(rest[first.len_utf8()..].to_string()))
+                } else {
+                    for flag in rest.chars() {
+                        let flag = format!("-{}", flag);
+                        if !IGNORED.contains(&flag.as_str()) && flag != "-I" && flag != "-v" {
+                            return Err(unsupported(&flag, "it can't be bundled with other options here"));
+                        }
+                        match flag.as_str() {
+                            "-I" => subcommand = Some("head"),
+                            "-v" => args.push("--trace".to_string()),
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+            }
+            _ => match word.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option.to_string(), Some(value.to_string())),
+                _ => (word, None),
+            },
+        };
+        let mut value = || attached.clone().or_else(|| words.next()).ok_or_else(|| format!("--from-curl: {} needs a value", option));
+
+        match option.as_str() {
+            "-H" | "--header" => {
+                args.push("--header".to_string());
+                args.push(value()?);
+            }
+            "-A" | "--user-agent" => {
+                args.push("--header".to_string());
+                args.push(format!("User-Agent: {}", value()?));
+            }
+            "-e" | "--referer" => {
+                args.push("--header".to_string());
+                args.push(format!("Referer: {}", value()?));
+            }
+            "-b" | "--cookie" => {
+                // Without '=' the value names a cookie file, as with curl
+                let cookies = value()?;
+                if cookies.contains('=') {
+                    args.push("--header".to_string());
+                    args.push(format!("Cookie: {}", cookies));
+                } else {
+                    args.push("--cookies".to_string());
+                    args.push(cookies);
+                }
+            }
+            "-c" | "--cookie-jar" => {
+                args.push("--cookie-jar".to_string());
+                args.push(value()?);
+            }
+            "-u" | "--user" => {
+                let credentials = value()?;
+                args.push("--header".to_string());
+                args.push(format!("Authorization: Basic {}", integrity::encode_base64(credentials.as_bytes())));
+            }
+            // .netrc is always read
+            "-n" | "--netrc" | "--netrc-optional" => {}
+            "--netrc-file" => {
+                args.push("--netrc-file".to_string());
+                args.push(value()?);
+            }
+            "-4" | "--ipv4" => args.push("--ipv4".to_string()),
+            "-6" | "--ipv6" => args.push("--ipv6".to_string()),
+            "--tcp-nodelay" => args.push("--tcp-nodelay".to_string()),
+            "--tcp-fastopen" => args.push("--tcp-fastopen".to_string()),
+            "--keepalive-time" => {
+                args.push("--tcp-keepalive".to_string());
+                args.push(value()?);
+            }
+            "-m" | "--max-time" => {
+                args.push("--max-time".to_string());
+                args.push(value()?);
+            }
+            "-Y" | "--speed-limit" => {
+                args.push("--speed-limit".to_string());
+                args.push(value()?);
+            }
+            "-y" | "--speed-time" => {
+                args.push("--speed-time".to_string());
+                args.push(value()?);
+            }
+            "--retry" => {
+                args.push("--retry".to_string());
+                args.push(value()?);
+            }
+            // curl waits the same time before every retry once this is given
+            "--retry-delay" => {
+                args.push("--retry-delay".to_string());
+                args.push(value()?);
+                args.push("--retry-multiplier".to_string());
+                args.push("1".to_string());
+                args.push("--retry-jitter".to_string());
+                args.push("none".to_string());
+            }
+            "--dns-servers" => {
+                args.push("--dns-server".to_string());
+                args.push(value()?);
+            }
+            "--resolve" => {
+                args.push("--resolve".to_string());
+                args.push(value()?);
+            }
+            "--connect-to" => {
+                args.push("--connect-to".to_string());
+                args.push(value()?);
+            }
+            "-x" | "--proxy" => {
+                args.push("--proxy".to_string());
+                args.push(value()?);
+            }
+            "-U" | "--proxy-user" => {
+                args.push("--proxy-user".to_string());
+                args.push(value()?);
+            }
+            "-p" | "--proxytunnel" => args.push("--proxytunnel".to_string()),
+            "--compressed" => args.push("--compressed".to_string()),
+            "-o" | "--output" => {
+                args.push("--output".to_string());
+                args.push(value()?);
+            }
+            "-w" | "--write-out" => {
+                args.push("--write-out".to_string());
+                args.push(value()?);
+            }
+            "--url" => urls.push(value()?),
+            "-X" | "--request" => method = Some(value()?),
+            "-T" | "--upload-file" => {
+                upload = Some(value()?);
+                subcommand = Some("upload");
+            }
+            "-d" | "--data" | "--data-raw" | "--data-binary" | "--data-urlencode" | "-F" | "--form" => {
+                return Err(unsupported(&option, "downloads don't send a request body, and -T is the only upload"));
+            }
+            "-I" | "--head" => subcommand = Some("head"),
+            "-v" | "--verbose" => args.push("--trace".to_string()),
+            "-C" | "--continue-at" => {
+                if value()? != "-" {
+                    return Err(unsupported(&option, "only -C - is, to resume where the .part file ends"));
+                }
+                subcommand = Some("resume");
+            }
+            _ => return Err(unsupported(&option, "this client has no equivalent")),
+        }
+    }
+
+    // Downloads are GETs and uploads PUTs, whatever else curl could send
+    let expected = if upload.is_some() { "PUT" } else { "GET" };
+    if let Some(method) = method.filter(|method| !method.eq_ignore_ascii_case(expected)) {
+        let why = if upload.is_some() { "uploads are always PUT requests" } else { "downloads are always GET requests" };
+        return Err(unsupported(&format!("-X {}", method), why));
+    }
+    if urls.is_empty() {
+        return Err("--from-curl: the curl command has no URL".to_string());
+    }
+    let mut translated: Vec<String> = subcommand.map(str::to_string).into_iter().collect();
+    translated.extend(args);
+    translated.extend(upload);
+    translated.extend(urls);
+    Ok(translated)
+}
+
+// Split a command line into words the way a POSIX shell would, with single
+// and double quotes, backslash escapes, line continuations and the $'...'
+// strings some browsers use
+fn split_words(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            '\\' => match chars.next() {
+                // A line continuation
+                Some('\n') => {}
+                Some(c) => {
+                    word.push(c);
+                    in_word = true;
+                }
+                None => return Err("--from-curl: the command ends with a backslash".to_string()),
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("--from-curl: unterminated ' quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("--from-curl: unterminated \" quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("--from-curl: unterminated \" quote".to_string()),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => word.push('\n'),
+                            Some('t') => word.push('\t'),
+                            Some('r') => word.push('\r'),
+                            Some(c) => word.push(c),
+                            None => return Err("--from-curl: unterminated $' quote".to_string()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("--from-curl: unterminated $' quote".to_string()),
+                    }
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(command: &str) -> Vec<String> {
+        split_words(command).unwrap()
+    }
+
+    #[test]
+    fn words_split_as_a_shell_would() {
+        assert_eq!(words("  curl \t'a b'  \"c d\"e\n"), ["curl", "a b", "c de"]);
+        // Nothing is special inside single quotes
+        assert_eq!(words(r#"'$x \n "y"'"#), [r#"$x \n "y""#]);
+        // Inside double quotes only ", \, $ and ` are escaped, and a
+        // backslash before anything else stays
+        assert_eq!(words(r#""a\"b" "c\\d" "\$e" "f\g""#), ["a\"b", "c\\d", "$e", r"f\g"]);
+        // Outside quotes a backslash escapes anything, and one before a line
+        // break continues the line
+        assert_eq!(words("a\\ b c\\\n d"), ["a b", "c", "d"]);
+        assert_eq!(words("\"a\\\nb\""), ["ab"]);
+    }
+
+    #[test]
+    fn ansi_c_strings() {
+        assert_eq!(words(r"$'a\tb\nc\rd' $'it\'s'"), ["a\tb\nc\rd", "it's"]);
+        // An empty quoted word is still a word
+        assert_eq!(words("a '' $'' \"\""), ["a", "", "", ""]);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_errors() {
+        assert_eq!(split_words("'a").unwrap_err(), "--from-curl: unterminated ' quote");
+        assert_eq!(split_words("\"a").unwrap_err(), "--from-curl: unterminated \" quote");
+        assert_eq!(split_words("\"a\\").unwrap_err(), "--from-curl: unterminated \" quote");
+        assert_eq!(split_words("$'a").unwrap_err(), "--from-curl: unterminated $' quote");
+        assert_eq!(split_words("a \\").unwrap_err(), "--from-curl: the command ends with a backslash");
+    }
+
+    #[test]
+    fn headers_and_credentials_become_headers() {
+        let translated = translate("curl 'http://example.com/f' -H 'Accept: */*' --header=X-A:\\ 1 -HX-B:2 -u user:pass -A agent").unwrap();
+        assert_eq!(translated, [
+            "--header", "Accept: */*",
+            "--header", "X-A: 1",
+            "--header", "X-B:2",
+            "--header", "Authorization: Basic dXNlcjpwYXNz",
+            "--header", "User-Agent: agent",
+            "http://example.com/f",
+        ]);
+        // A cookie string is a header, anything else a cookie file
+        assert_eq!(translate("curl -b 'a=1; b=2' -b jar.txt http://h/").unwrap(), ["--header", "Cookie: a=1; b=2", "--cookies", "jar.txt", "http://h/"]);
+        assert_eq!(translate("curl -u").unwrap_err(), "--from-curl: -u needs a value");
+    }
+
+    #[test]
+    fn bundled_short_options() {
+        assert_eq!(translate("curl -sSLI http://h/").unwrap(), ["head", "http://h/"]);
+        assert_eq!(translate("/usr/bin/curl -sv -ofile http://h/").unwrap(), ["--trace", "--output", "file", "http://h/"]);
+        assert_eq!(translate("curl -sn http://h/").unwrap_err(), "--from-curl: -n isn't supported, it can't be bundled with other options here");
+    }
+
+    #[test]
+    fn subcommands_and_methods() {
+        assert_eq!(translate("curl -T f.bin -X put http://h/f").unwrap(), ["upload", "f.bin", "http://h/f"]);
+        assert_eq!(translate("curl -C - http://h/f").unwrap(), ["resume", "http://h/f"]);
+        assert_eq!(translate("curl -X GET --url http://h/f").unwrap(), ["http://h/f"]);
+        assert_eq!(translate("curl -X POST http://h/f").unwrap_err(), "--from-curl: -X POST isn't supported, downloads are always GET requests");
+        assert_eq!(translate("curl -T f -X POST http://h/f").unwrap_err(), "--from-curl: -X POST isn't supported, uploads are always PUT requests");
+        assert_eq!(translate("curl -C 100 http://h/f").unwrap_err(), "--from-curl: -C isn't supported, only -C - is, to resume where the .part file ends");
+        assert_eq!(translate("curl -sL").unwrap_err(), "--from-curl: the curl command has no URL");
+    }
+
+    #[test]
+    fn request_bodies_and_unknown_options_are_refused() {
+        for data in ["-d a=1", "--data-raw 'a=1'", "--data-binary @f", "-F f=@x", "--data=a"] {
+            let error = translate(&format!("curl {} http://h/", data)).unwrap_err();
+            assert!(error.ends_with("isn't supported, downloads don't send a request body, and -T is the only upload"), "{}", error);
+        }
+        assert_eq!(translate("curl --tlsv1.3 http://h/").unwrap_err(), "--from-curl: --tlsv1.3 isn't supported, this client has no equivalent");
+        assert_eq!(translate("curl -Z http://h/").unwrap_err(), "--from-curl: -Z isn't supported, this client has no equivalent");
+    }
+
+    #[test]
+    fn retry_delay_keeps_the_wait_fixed() {
+        let translated = translate("curl --retry 3 --retry-delay 2 http://h/").unwrap();
+        assert_eq!(translated, ["--retry", "3", "--retry-delay", "2", "--retry-multiplier", "1", "--retry-jitter", "none", "http://h/"]);
+    }
+}
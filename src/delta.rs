@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::http;
+use crate::integrity::Mismatch;
+use crate::md4;
+use crate::sha1;
+use crate::url::Url;
+
+// Runs of missing blocks asked for in one request, kept under the limits
+// servers put on the ranges in a Range header
+const RANGES_PER_REQUEST: usize = 64;
+
+// Block checksums from a zsync control file, as written by zsyncmake. Each
+// block has a weak rolling checksum ("rsum") and a truncated MD4 sum.
+pub struct ControlFile {
+    pub block_size: usize,
+    pub length: u64,
+    // Number of consecutive blocks that have to match before a match is trusted
+    seq_matches: usize,
+    rsum_bytes: usize,
+    checksum_bytes: usize,
+    sha1: Option<Vec<u8>>,
+    blocks: Vec<BlockSum>,
+}
+
+struct BlockSum {
+    rsum: u32,
+    checksum: Vec<u8>,
+}
+
+// What a delta download reused and what it had to fetch
+pub struct DeltaStats {
+    pub reused_blocks: usize,
+    pub total_blocks: usize,
+    pub fetched_bytes: u64,
+    pub ranges: usize,
+    pub requests: usize,
+}
+
+impl ControlFile {
+    // Load a control file from a local path or an http:// URL
+    pub fn load(location: &str, settings: &http::Settings) -> std::io::Result<ControlFile> {
+        let data = if location.starts_with("http://") {
+            let url = Url::parse(location).map_err(std::io::Error::other)?;
+            http::fetch(&url, None, settings)?
+        } else {
+            std::fs::read(location)?
+        };
+
+        ControlFile::parse(&data).map_err(|e| std::io::Error::other(format!("{}: {}", location, e)))
+    }
+
+    pub fn parse(data: &[u8]) -> Result<ControlFile, String> {
+        // "Key: value" header lines, then a blank line, then the block sums
+        let header_end = data.windows(2).position(|w| w == b"\n\n").ok_or("No end of zsync header")?;
+        let header = String::from_utf8_lossy(&data[..header_end]);
+        let sums = &data[header_end + 2..];
+
+        let fields: HashMap<String, String> = header.lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        let number = |key: &str| -> Result<u64, String> {
+            fields.get(key)
+                .ok_or(format!("Missing {} in zsync header", key))?
+                .parse()
+                .map_err(|_| format!("Invalid {} in zsync header", key))
+        };
+
+        if !fields.contains_key("zsync") {
+            return Err("Not a zsync control file".to_string());
+        }
+        if fields.contains_key("z-url") && !fields.contains_key("url") {
+            return Err("Control files for compressed downloads are not supported".to_string());
+        }
+
+        let block_size = usize::try_from(number("blocksize")?).map_err(|_| "Invalid blocksize in zsync header".to_string())?;
+        let length = number("length")?;
+        let (seq_matches, rsum_bytes, checksum_bytes) = match fields.get("hash-lengths") {
+            Some(lengths) => {
+                let parts: Vec<usize> = lengths.split(',').filter_map(|n| n.trim().parse().ok()).collect();
+                match parts.as_slice() {
+                    [seq, rsum, checksum] => (*seq, *rsum, *checksum),
+                    _ => return Err(format!("Invalid Hash-Lengths in zsync header: {}", lengths)),
+                }
+            }
+            None => (1, 4, 16),
+        };
+        if block_size == 0 || !(1..=4).contains(&rsum_bytes) || !(1..=16).contains(&checksum_bytes) {
+            return Err("Unsupported block size or hash lengths in zsync header".to_string());
+        }
+
+        let block_count = usize::try_from(length.div_ceil(block_size as u64))
+            .map_err(|_| "Too many blocks in zsync header".to_string())?;
+        let entry = rsum_bytes + checksum_bytes;
+        if block_count.checked_mul(entry).is_none_or(|needed| sums.len() < needed) {
+            return Err(format!("Expected {} block checksums, control file is truncated", block_count));
+        }
+
+        // The rsum is stored as the last rsum_bytes of its big-endian a:b form
+        let blocks = sums.chunks_exact(entry)
+            .take(block_count)
+            .map(|entry| {
+                let mut rsum = [0u8; 4];
+                rsum[4 - rsum_bytes..].copy_from_slice(&entry[..rsum_bytes]);
+                BlockSum { rsum: u32::from_be_bytes(rsum), checksum: entry[rsum_bytes..].to_vec() }
+            })
+            .collect();
+
+        let sha1 = fields.get("sha-1")
+            .map(|hex| crate::integrity::decode_hex(hex).ok_or("Invalid SHA-1 in zsync header"))
+            .transpose()?;
+
+        Ok(ControlFile { block_size, length, seq_matches, rsum_bytes, checksum_bytes, sha1, blocks })
+    }
+
+    fn rsum_mask(&self) -> u32 {
+        if self.rsum_bytes == 4 { u32::MAX } else { (1 << (8 * self.rsum_bytes)) - 1 }
+    }
+
+    fn checksum(&self, window: &[u8]) -> Vec<u8> {
+        md4::digest(window)[..self.checksum_bytes].to_vec()
+    }
+}
+
+// Rolling checksum over one block, as zsync computes it: a is the sum of the
+// bytes, b weights each byte by its distance from the end; both mod 2^16
+fn rsum(window: &[u8]) -> (u16, u16) {
+    let len = window.len();
+    window.iter().enumerate().fold((0u16, 0u16), |(a, b), (i, &c)| {
+        (a.wrapping_add(c as u16), b.wrapping_add(((len - i) as u16).wrapping_mul(c as u16)))
+    })
+}
+
+// Rebuild the remote file from a stale local copy into out, which is opened
+// for reading too: blocks found anywhere in the local file are copied from
+// it, and only the rest is fetched by range, each written at its offset.
+pub fn reconstruct(url: &Url, control: &ControlFile, seed: &Path, out: &File, settings: &http::Settings) -> std::io::Result<DeltaStats> {
+    let block_size = control.block_size as u64;
+    let found = find_blocks(control, File::open(seed)?)?;
+
+    // Copy what we have, then fetch the runs of missing blocks
+    out.set_len(control.length)?;
+    let mut local = File::open(seed)?;
+    let mut block = vec![0u8; control.block_size];
+    let mut stats = DeltaStats { reused_blocks: 0, total_blocks: found.len(), fetched_bytes: 0, ranges: 0, requests: 0 };
+    let mut runs = Vec::new();
+    let mut i = 0;
+
+    while i < found.len() {
+        let start = i as u64 * block_size;
+        if let Some(from) = found[i] {
+            let end = (start + block_size).min(control.length);
+            let block = &mut block[..(end - start) as usize];
+            read_at(&mut local, from, block)?;
+            write_at(out, start, block)?;
+            stats.reused_blocks += 1;
+            i += 1;
+            continue;
+        }
+
+        let run_end = (i..found.len()).find(|&j| found[j].is_some()).unwrap_or(found.len());
+        let end = (run_end as u64 * block_size).min(control.length);
+        runs.push((start, end));
+        stats.fetched_bytes += end - start;
+        stats.ranges += 1;
+        i = run_end;
+    }
+    for batch in runs.chunks(RANGES_PER_REQUEST) {
+        stats.requests += fetch_runs(url, out, control.length, batch, settings)?;
+    }
+
+    if let Some(expected) = &control.sha1 {
+        let mut sha1 = sha1::Sha1::new();
+        let mut written = out;
+        written.seek(SeekFrom::Start(0))?;
+        crate::read_pieces(written, |piece| {
+            sha1.update(piece);
+            Ok(())
+        })?;
+        if sha1.finish().as_slice() != expected.as_slice() {
+            return Err(Mismatch { algorithm: "sha1", reason: "Delta download doesn't match the SHA-1 in the control file".to_string() }.into_error());
+        }
+    }
+
+    Ok(stats)
+}
+
+// Where in the local file each remote block can be copied from, found by
+// rolling the weak checksum over it a byte at a time
+fn find_blocks(control: &ControlFile, local: File) -> std::io::Result<Vec<Option<u64>>> {
+    let block_size = control.block_size;
+    let mask = control.rsum_mask();
+    let mut index: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in control.blocks.iter().enumerate() {
+        index.entry(block.rsum & mask).or_default().push(i);
+    }
+
+    let mut local = Seed::new(local, block_size);
+    let mut found: Vec<Option<u64>> = vec![None; control.blocks.len()];
+    let mut offset = 0;
+    let Some(first) = local.get(0, block_size)? else { return Ok(found) };
+    let (mut a, mut b) = rsum(first);
+
+    loop {
+        local.forget_before(offset);
+        let Some(window) = local.get(offset, block_size)? else { break };
+        let mut matched = false;
+
+        if let Some(candidates) = index.get(&((((a as u32) << 16) | b as u32) & mask)) {
+            let checksum = control.checksum(window);
+            // That of the following block, worked out when first needed
+            let mut next: Option<Option<Vec<u8>>> = None;
+            for &i in candidates {
+                if found[i].is_some() || control.blocks[i].checksum != checksum {
+                    continue;
+                }
+                // Short checksums need the following block to match as well
+                if control.seq_matches >= 2 && i + 1 < control.blocks.len() {
+                    if next.is_none() {
+                        next = Some(local.get(offset + block_size as u64, block_size)?.map(|block| control.checksum(block)));
+                    }
+                    if next.as_ref().and_then(Option::as_ref) != Some(&control.blocks[i + 1].checksum) {
+                        continue;
+                    }
+                }
+                found[i] = Some(offset);
+                matched = true;
+            }
+        }
+
+        if matched {
+            offset += block_size as u64;
+            if let Some(window) = local.get(offset, block_size)? {
+                (a, b) = rsum(window);
+            }
+        } else {
+            // The byte after the window rolls in as the first rolls out
+            let Some(window) = local.get(offset, block_size + 1)? else { break };
+            let (old, new) = (window[0] as u16, window[block_size] as u16);
+            a = a.wrapping_sub(old).wrapping_add(new);
+            b = b.wrapping_sub((block_size as u16).wrapping_mul(old)).wrapping_add(a);
+            offset += 1;
+        }
+    }
+    Ok(found)
+}
+
+// The local file as the scan moves through it, read a buffer at a time and
+// dropped once passed. A block of zeros is added at the end so its last
+// partial block can match the remote's, which zsync checksums padded with
+// zeros.
+struct Seed {
+    file: File,
+    block_size: usize,
+    bytes: Vec<u8>,
+    // Where in the file bytes starts
+    start: u64,
+    ended: bool,
+}
+
+impl Seed {
+    fn new(file: File, block_size: usize) -> Seed {
+        Seed { file, block_size, bytes: Vec::new(), start: 0, ended: false }
+    }
+
+    // The len bytes at offset, or None if the padded file ends before them
+    fn get(&mut self, offset: u64, len: usize) -> std::io::Result<Option<&[u8]>> {
+        let from = (offset - self.start) as usize;
+        while self.bytes.len() < from + len && !self.ended {
+            let have = self.bytes.len();
+            self.bytes.resize(have + crate::READ_SIZE, 0);
+            match self.file.read(&mut self.bytes[have..]) {
+                Ok(0) => {
+                    self.bytes.truncate(have);
+                    self.bytes.resize(have + self.block_size, 0);
+                    self.ended = true;
+                }
+                Ok(n) => self.bytes.truncate(have + n),
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => self.bytes.truncate(have),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(self.bytes.get(from..from + len))
+    }
+
+    // Let go of what comes before offset, once there is a buffer's worth
+    fn forget_before(&mut self, offset: u64) {
+        let passed = (offset - self.start) as usize;
+        if passed >= crate::READ_SIZE {
+            self.bytes.drain(..passed);
+            self.start = offset;
+        }
+    }
+}
+
+// Fill buffer with the local bytes at offset, zeros past the end of the file
+// as the scan padded it
+fn read_at(file: &mut File, offset: u64, buffer: &mut [u8]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    buffer[filled..].fill(0);
+    Ok(())
+}
+
+fn write_at(mut out: &File, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+    out.seek(SeekFrom::Start(offset))?;
+    out.write_all(bytes)
+}
+
+// Fetch runs of the output, each from start to end (exclusive), in one
+// multi-range request. Runs the answer leaves out are asked for again one at
+// a time. Returns how many requests it took.
+fn fetch_runs(url: &Url, out: &File, length: u64, runs: &[(u64, u64)], settings: &http::Settings) -> std::io::Result<usize> {
+    if let [(start, end)] = runs {
+        return fetch_into(url, out, *start, *end, settings).map(|()| 1);
+    }
+    let ranges: Vec<(u64, u64)> = runs.iter().map(|&(start, end)| (start, end - 1)).collect();
+    let parts = http::download_ranges(url, &ranges, settings)?;
+    let mut covered = vec![false; runs.len()];
+    for (start, bytes) in parts {
+        let end = start.saturating_add(bytes.len() as u64);
+        if end > length {
+            return Err(std::io::Error::other(format!("Server sent bytes {}-{}, past the end of the file", start, end - 1)));
+        }
+        write_at(out, start, &bytes)?;
+        for (run, covered) in runs.iter().zip(&mut covered) {
+            *covered |= start <= run.0 && run.1 <= end;
+        }
+    }
+    let mut requests = 1;
+    for (&(start, end), _) in runs.iter().zip(&covered).filter(|(_, covered)| !**covered) {
+        fetch_into(url, out, start, end, settings)?;
+        requests += 1;
+    }
+    Ok(requests)
+}
+
+// Write the remote bytes from start to end (exclusive) to the same place in out
+fn fetch_into(url: &Url, out: &File, start: u64, end: u64, settings: &http::Settings) -> std::io::Result<()> {
+    let mut filled = start;
+    let mut empty_responses = 0;
+
+    while filled < end {
+        let chunk = http::download_range(url, filled, end - 1, settings)?;
+
+        if chunk.is_empty() {
+            empty_responses += 1;
+            if empty_responses == 3 {
+                return Err(std::io::Error::other(format!("No data for range starting at {}", filled)));
+            }
+            continue;
+        }
+        if chunk.len() as u64 > end - filled {
+            return Err(std::io::Error::other("Server ignored the Range header, delta download isn't possible"));
+        }
+
+        write_at(out, filled, &chunk)?;
+        filled += chunk.len() as u64;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::testing;
+
+    // A control file for the data, as zsyncmake writes one
+    fn control(data: &[u8], block_size: usize, hash_lengths: (usize, usize, usize), sha1: Option<&[u8]>) -> Vec<u8> {
+        let (seq, rsum_bytes, checksum_bytes) = hash_lengths;
+        let mut file = format!(
+            "zsync: 0.6.2\nFilename: f\nBlocksize: {}\nLength: {}\nHash-Lengths: {},{},{}\nURL: f\n",
+            block_size, data.len(), seq, rsum_bytes, checksum_bytes
+        );
+        if let Some(sha1) = sha1 {
+            let hex: String = sha1.iter().map(|b| format!("{:02x}", b)).collect();
+            file.push_str(&format!("SHA-1: {}\n", hex));
+        }
+        let mut file = (file + "\n").into_bytes();
+        for block in data.chunks(block_size) {
+            let mut block = block.to_vec();
+            block.resize(block_size, 0);
+            let (a, b) = rsum(&block);
+            file.extend_from_slice(&[a.to_be_bytes(), b.to_be_bytes()].concat()[4 - rsum_bytes..]);
+            file.extend_from_slice(&md4::digest(&block)[..checksum_bytes]);
+        }
+        file
+    }
+
+    // Bytes that don't repeat within a block, so each block matches once
+    fn remote() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect()
+    }
+
+    fn seed(name: &str, data: &[u8]) -> PathBuf {
+        let path = testing::temp_path(&format!("delta_{}", name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    // Rebuild into a file of its own, returning what ended up in it
+    fn rebuild(url: &Url, control: &ControlFile, seed: &Path) -> std::io::Result<(Vec<u8>, DeltaStats)> {
+        let path = seed.with_extension("out");
+        let out = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path)?;
+        let rebuilt = reconstruct(url, control, seed, &out, &http::Settings::default()).map(|stats| (std::fs::read(&path).unwrap(), stats));
+        let _ = std::fs::remove_file(path);
+        rebuilt
+    }
+
+    // Serves the ranges asked for of the data, a single range as itself and
+    // several as multipart/byteranges
+    fn serve(data: Vec<u8>) -> Url {
+        let url = testing::serve(move |head| {
+            let spec = head.lines().find_map(|line| line.strip_prefix("Range: bytes=")).unwrap();
+            let ranges: Vec<(usize, usize)> = spec.split(',').map(|range| {
+                let (start, end) = range.split_once('-').unwrap();
+                (start.parse().unwrap(), end.parse().unwrap())
+            }).collect();
+            match ranges.as_slice() {
+                [(start, end)] => {
+                    let content_range = format!("Content-Range: bytes {}-{}/{}\r\n", start, end, data.len());
+                    testing::response("206 Partial Content", &content_range, &data[*start..=*end])
+                }
+                _ => {
+                    let mut body = Vec::new();
+                    for (start, end) in &ranges {
+                        body.extend(format!("\r\n--sep\r\nContent-Range: bytes {}-{}/{}\r\n\r\n", start, end, data.len()).into_bytes());
+                        body.extend_from_slice(&data[*start..=*end]);
+                    }
+                    body.extend_from_slice(b"\r\n--sep--\r\n");
+                    testing::response("206 Partial Content", "Content-Type: multipart/byteranges; boundary=sep\r\n", &body)
+                }
+            }
+        });
+        Url::parse(&url).unwrap()
+    }
+
+    #[test]
+    fn rsum_weights_bytes_by_their_distance_from_the_end() {
+        assert_eq!(rsum(b"abc"), (294, 3 * 97 + 2 * 98 + 99));
+        assert_eq!(rsum(&[0xff; 300]), ((300 * 255) as u16, (255 * 300 * 301 / 2) as u16));
+    }
+
+    #[test]
+    fn control_files_parse() {
+        let data = remote();
+        let parsed = ControlFile::parse(&control(&data, 1024, (2, 3, 5), Some(&sha1::digest(&data)))).unwrap();
+        assert_eq!((parsed.block_size, parsed.length, parsed.seq_matches), (1024, 10_000, 2));
+        assert_eq!((parsed.rsum_bytes, parsed.checksum_bytes, parsed.blocks.len()), (3, 5, 10));
+        assert_eq!(parsed.sha1.as_deref(), Some(&sha1::digest(&data)[..]));
+        assert_eq!(parsed.rsum_mask(), 0xff_ffff);
+        let (a, b) = rsum(&data[..1024]);
+        assert_eq!(parsed.blocks[0].rsum, (((a as u32) << 16) | b as u32) & 0xff_ffff);
+        assert_eq!(parsed.blocks[0].checksum, md4::digest(&data[..1024])[..5]);
+
+        // Without Hash-Lengths, a whole rsum and MD4 sum per block
+        let text = b"zsync: 0.6.2\nBlocksize: 4\nLength: 5\n\n".to_vec();
+        let parsed = ControlFile::parse(&[text, vec![0; 40]].concat()).unwrap();
+        assert_eq!((parsed.seq_matches, parsed.rsum_bytes, parsed.checksum_bytes, parsed.blocks.len()), (1, 4, 16, 2));
+    }
+
+    #[test]
+    fn bad_control_files_are_refused() {
+        let error = |text: &str| ControlFile::parse(text.as_bytes()).err().unwrap();
+        assert_eq!(error("zsync: 0.6.2\nBlocksize: 4\n"), "No end of zsync header");
+        assert_eq!(error("Blocksize: 4\nLength: 4\n\n"), "Not a zsync control file");
+        assert_eq!(error("zsync: 1\nZ-URL: f.gz\nBlocksize: 4\nLength: 4\n\n"), "Control files for compressed downloads are not supported");
+        assert_eq!(error("zsync: 1\nLength: 4\n\n"), "Missing blocksize in zsync header");
+        assert_eq!(error("zsync: 1\nBlocksize: four\nLength: 4\n\n"), "Invalid blocksize in zsync header");
+        assert_eq!(error("zsync: 1\nBlocksize: 4\nLength: 4\nHash-Lengths: 1,2\n\n"), "Invalid Hash-Lengths in zsync header: 1,2");
+        assert_eq!(error("zsync: 1\nBlocksize: 4\nLength: 4\nHash-Lengths: 1,5,8\n\n"), "Unsupported block size or hash lengths in zsync header");
+        assert_eq!(error("zsync: 1\nBlocksize: 4\nLength: 9\n\n0123"), "Expected 3 block checksums, control file is truncated");
+        assert_eq!(error("zsync: 1\nBlocksize: 4\nLength: 0\nSHA-1: xyz\n\n"), "Invalid SHA-1 in zsync header");
+    }
+
+    #[test]
+    fn an_up_to_date_copy_needs_no_requests() {
+        let data = remote();
+        let control = ControlFile::parse(&control(&data, 512, (1, 4, 16), Some(&sha1::digest(&data)))).unwrap();
+        let seed = seed("same", &data);
+        // Nothing listens here, and nothing needs to
+        let url = Url::parse("http://127.0.0.1:9/f").unwrap();
+        let (rebuilt, stats) = rebuild(&url, &control, &seed).unwrap();
+        assert!(rebuilt == data);
+        assert_eq!((stats.reused_blocks, stats.total_blocks, stats.fetched_bytes, stats.requests), (20, 20, 0, 0));
+        let _ = std::fs::remove_file(seed);
+    }
+
+    #[test]
+    fn moved_and_changed_blocks() {
+        let data = remote();
+        // Bytes put in front, so every block is found at another offset, and
+        // blocks 1 and 4 changed. Two matches in a row are needed, so blocks
+        // 0 and 3 can't be trusted either, and the two runs come in one
+        // multi-range request.
+        let mut local = b"new bytes at the start".to_vec();
+        local.extend_from_slice(&data);
+        local[22 + 1500] ^= 1;
+        local[22 + 5000] ^= 1;
+        let control = ControlFile::parse(&control(&data, 1024, (2, 2, 4), Some(&sha1::digest(&data)))).unwrap();
+        let seed = seed("moved", &local);
+        let (rebuilt, stats) = rebuild(&serve(data.clone()), &control, &seed).unwrap();
+        assert!(rebuilt == data);
+        assert_eq!((stats.reused_blocks, stats.total_blocks), (6, 10));
+        assert_eq!((stats.fetched_bytes, stats.ranges, stats.requests), (4096, 2, 1));
+        let _ = std::fs::remove_file(seed);
+    }
+
+    #[test]
+    fn a_rebuild_that_doesnt_match_the_sha1_fails() {
+        let data = remote();
+        let control = ControlFile::parse(&control(&data, 512, (1, 4, 16), Some(&[0; 20]))).unwrap();
+        let seed = seed("sha1", &data);
+        let url = Url::parse("http://127.0.0.1:9/f").unwrap();
+        let error = rebuild(&url, &control, &seed).err().unwrap();
+        assert_eq!(error.to_string(), "Delta download doesn't match the SHA-1 in the control file");
+        let _ = std::fs::remove_file(seed);
+    }
+
+    #[test]
+    fn seeds_bigger_than_a_read_are_scanned_as_they_are_read() {
+        // Blocks on both sides of every buffer the seed is read in, a last
+        // partial block, and a shifted copy that has to be rolled through
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let mut local = vec![7; 1000];
+        local.extend_from_slice(&data[..150_000]);
+        local.extend_from_slice(b"changed");
+        local.extend_from_slice(&data[150_007..]);
+        let control = ControlFile::parse(&control(&data, 2000, (1, 4, 16), Some(&sha1::digest(&data)))).unwrap();
+        let seed = seed("big", &local);
+        let (rebuilt, stats) = rebuild(&serve(data.clone()), &control, &seed).unwrap();
+        assert!(rebuilt == data);
+        assert_eq!((stats.reused_blocks, stats.total_blocks, stats.fetched_bytes), (149, 150, 2000));
+        let _ = std::fs::remove_file(seed);
+    }
+}
@@ -0,0 +1,1375 @@
+use std::fs::File;
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cancel::{CancellationToken, Cancelled, TimeLimitExceeded};
+use crate::control::ControlFile;
+use crate::engine::{self, CHUNK_SIZE, Chunk, ChunkSizer, Scheduler, Step};
+use crate::events::{DownloadEvent, EventSender};
+use crate::future::DownloadFuture;
+use crate::handle::DownloadHandle;
+use crate::http;
+use crate::integrity::{self, Algorithm, ExpectedDigest, Hasher};
+use crate::lock::OutputLock;
+use crate::middleware::{Middleware, Stack};
+use crate::network::{DEFAULT_NETWORK_WAIT, NetworkWatch};
+use crate::progress::ProgressObserver;
+use crate::refresh::UrlRefresh;
+use crate::retry::RetryPolicy;
+use crate::splice;
+use crate::uring::{Ring, Timespec};
+use crate::url::Url;
+
+// File name used when neither the builder nor the URL gives one
+const DEFAULT_FILE_NAME: &str = "downloaded_data.bin";
+
+// Room in each io_uring buffer for the response head, besides the chunk
+const RING_HEAD_ROOM: usize = 16 * 1024;
+
+// How much of the file is read at a time to hash it
+const CHECK_BUFFER: usize = 1024 * 1024;
+
+// How often a resumable download records which chunks are in
+const CONTROL_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+// How often a wait before a retry looks for cancellation
+const BACKOFF_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// How often an io_uring transfer looks for cancellation and resumption
+const RING_TICK: Duration = Duration::from_millis(100);
+
+// A transfer of one URL to a file, configured with Download::builder:
+//
+//     let summary = Download::builder("http://example.com/file.iso")
+//         .output("file.iso")
+//         .connections(8)
+//         .expected_sha256("9f86d081...")
+//         .timeout(Duration::from_secs(30))
+//         .build()?
+//         .run()?;
+pub struct Download {
+    url: Url,
+    // Where the same body is fetched from once the URL fails, in turn
+    mirrors: Vec<Url>,
+    // Which of the URL (0) and the mirrors requests go to now
+    source: AtomicUsize,
+    output: PathBuf,
+    connections: usize,
+    min_split_size: u64,
+    pipeline: usize,
+    io_uring: bool,
+    resumable: bool,
+    wait_for_lock: bool,
+    expected: Vec<ExpectedDigest>,
+    max_time: Option<Duration>,
+    retry: RetryPolicy,
+    network_wait: Duration,
+    settings: http::Settings,
+    observers: Vec<Arc<dyn ProgressObserver>>,
+    state: Arc<State>,
+}
+
+// Collects the settings for a Download. Nothing is checked until build().
+pub struct DownloadBuilder {
+    url: String,
+    mirrors: Vec<String>,
+    output: Option<PathBuf>,
+    connections: usize,
+    min_split_size: u64,
+    pipeline: usize,
+    http2: bool,
+    io_uring: bool,
+    resumable: bool,
+    wait_for_lock: bool,
+    expected_sha256: Option<String>,
+    timeout: Option<Duration>,
+    max_time: Option<Duration>,
+    retry: RetryPolicy,
+    network_wait: Duration,
+    low_speed: Option<http::SpeedLimit>,
+    cancel: Option<CancellationToken>,
+    refresh: Option<UrlRefresh>,
+    middleware: Stack,
+    settings: http::Settings,
+    observers: Vec<Arc<dyn ProgressObserver>>,
+}
+
+// State the connections of one transfer share
+struct Shared {
+    file: Mutex<File>,
+    // A second handle that bodies are spliced into, on Linux. Splicing at an
+    // offset leaves the file position alone, so it takes no lock.
+    spliced: Option<File>,
+    // Cleared for good once the file or a connection won't splice
+    splicing: AtomicBool,
+    scheduler: Scheduler,
+    // How long the probe's round trip took, which chunks are sized from
+    round_trip: Duration,
+    network: NetworkWatch,
+}
+
+// Live state of a download, shared with its DownloadHandle
+#[derive(Default)]
+pub(crate) struct State {
+    pub(crate) paused: Mutex<bool>,
+    // Signalled when the download is resumed or cancelled
+    pub(crate) resumed: Condvar,
+    pub(crate) downloaded: AtomicU64,
+    // Zero until the server has told us
+    pub(crate) total_size: AtomicU64,
+}
+
+// What a finished download produced
+#[derive(Debug)]
+pub struct Summary {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Download {
+    pub fn builder(url: &str) -> DownloadBuilder {
+        DownloadBuilder {
+            url: url.to_string(),
+            mirrors: Vec::new(),
+            output: None,
+            connections: 1,
+            min_split_size: engine::DEFAULT_MIN_SPLIT_SIZE,
+            pipeline: 1,
+            http2: false,
+            io_uring: false,
+            resumable: false,
+            wait_for_lock: false,
+            expected_sha256: None,
+            timeout: None,
+            max_time: None,
+            retry: RetryPolicy::default(),
+            network_wait: DEFAULT_NETWORK_WAIT,
+            low_speed: None,
+            cancel: None,
+            refresh: None,
+            middleware: Stack::default(),
+            settings: http::Settings::default(),
+            observers: Vec::new(),
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    // Receive the download's events over a channel as it runs
+    pub fn subscribe(&mut self) -> Receiver<DownloadEvent> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.observers.push(Arc::new(EventSender(sender)));
+        receiver
+    }
+
+    // Run the download without blocking, as a future that any executor can
+    // drive. Dropping the future cancels the download.
+    pub fn run_async(mut self) -> DownloadFuture {
+        let cancel = self.settings.cancel.get_or_insert_with(CancellationToken::new).clone();
+        DownloadFuture::spawn(self, cancel)
+    }
+
+    // Run the download on a background thread, returning a handle to pause,
+    // resume or cancel it and to watch its progress
+    pub fn start(mut self) -> DownloadHandle {
+        let cancel = self.settings.cancel.get_or_insert_with(CancellationToken::new).clone();
+        let state = self.state.clone();
+        let thread = std::thread::spawn(move || self.run());
+        DownloadHandle::new(state, cancel, thread)
+    }
+
+    // Fetch the body into a .part file next to the output, check it and move
+    // it into place. Nothing is left behind if the download fails. The output
+    // is locked meanwhile, against other downloads of the same path.
+    pub fn run(&self) -> std::io::Result<Summary> {
+        if let (Some(limit), Some(cancel)) = (self.max_time, &self.settings.cancel) {
+            cancel.cancel_at(Instant::now() + limit);
+        }
+        let result = self.transfer().map_err(|e| self.timed_out(e));
+        for observer in &self.observers {
+            observer.on_finished(result.as_ref());
+        }
+        result
+    }
+
+    fn transfer(&self) -> std::io::Result<Summary> {
+        let _lock = OutputLock::acquire(&self.output, self.wait_for_lock)?;
+        // A source that can't be reached or refuses is given up on for the
+        // next mirror
+        let (head, phases) = loop {
+            let (source, url) = self.source();
+            match http::probe_timed(url, &[], &self.settings) {
+                Ok((head, _)) if head.status >= 400 && self.switch_source(source) => continue,
+                Err(_) if !self.cancelled() && self.switch_source(source) => continue,
+                probed => break probed?,
+            }
+        };
+        let total_size = head.content_length()
+            .ok_or(std::io::Error::other("No Content-Length header"))?;
+
+        let mut part_name = self.output.as_os_str().to_owned();
+        part_name.push(".part");
+        let part_path = PathBuf::from(part_name);
+
+        self.fetch_part(&part_path, &head, phases.round_trip())?;
+        let sha256 = match self.check(&part_path, total_size) {
+            Ok(sha256) => sha256,
+            Err(e) => {
+                let _ = std::fs::remove_file(&part_path);
+                return Err(e);
+            }
+        };
+
+        std::fs::rename(&part_path, &self.output)?;
+        Ok(Summary { path: self.output.clone(), size: total_size, sha256 })
+    }
+
+    // Fetch the body the probe's head describes into the part file, without
+    // checking it or moving it into place, for callers that do those
+    // themselves as the command line does. The output isn't locked. Returns
+    // how many bytes came over the network, which for a resumed download
+    // leaves out those the part file had already.
+    pub fn fetch_part(&self, part_path: &Path, head: &http::ResponseHead, round_trip: Duration) -> std::io::Result<u64> {
+        let total_size = head.content_length()
+            .ok_or(std::io::Error::other("No Content-Length header"))?;
+
+        // A resumable download picks up the chunks an earlier run left in the
+        // .part file, if its control file is for the same body
+        let chunks = Scheduler::chunk_count(total_size).filter(|_| self.resumable);
+        let control = chunks.map(|_| ControlFile::new(part_path, &self.url, head, total_size));
+        let previous = match (&control, chunks) {
+            (Some(control), Some(chunks)) if part_path.metadata().is_ok_and(|metadata| metadata.len() == total_size) => {
+                control.load(chunks)?
+            }
+            _ => None,
+        };
+        let resumed = previous.is_some();
+        let scheduler = match chunks {
+            Some(chunks) => Scheduler::resuming(total_size, previous.unwrap_or_else(|| vec![false; chunks])),
+            None => Scheduler::new(total_size),
+        };
+        let scheduler = scheduler.with_retry_policy(self.retry);
+
+        let fetched = total_size - scheduler.finished_size();
+
+        self.state.total_size.store(total_size, Ordering::Relaxed);
+        self.state.downloaded.store(scheduler.finished_size(), Ordering::Relaxed);
+        for observer in &self.observers {
+            observer.on_started(self.source().1, total_size);
+        }
+
+        if let Err(e) = self.fetch_to(part_path, scheduler, round_trip, resumed, control.as_ref()) {
+            if control.is_none() {
+                let _ = std::fs::remove_file(part_path);
+            }
+            return Err(e);
+        }
+        // Every chunk is in, so there is nothing left to resume
+        if let Some(control) = &control {
+            control.remove();
+        }
+        Ok(fetched)
+    }
+
+    // Fetch every chunk into the file, spreading them over the connections.
+    // With a control file, which chunks are in is saved now and then while
+    // that goes on, and once more if it fails.
+    fn fetch_to(&self, path: &Path, scheduler: Scheduler, round_trip: Duration, resumed: bool, control: Option<&ControlFile>) -> std::io::Result<()> {
+        let total_size = scheduler.total_size();
+        let file = if resumed {
+            std::fs::OpenOptions::new().write(true).open(path)?
+        } else {
+            let file = File::create(path)?;
+            file.set_len(total_size)?;
+            file
+        };
+        let synced = control.map(|_| file.try_clone()).transpose()?;
+        let remaining = total_size - scheduler.finished_size();
+        let workers = engine::connections_for(remaining, self.connections, self.min_split_size);
+        let shared = Shared {
+            spliced: if splice::SUPPORTED { Some(file.try_clone()?) } else { None },
+            // Spliced bodies would go round the middleware, and past the
+            // trace, HAR and timing logs
+            splicing: AtomicBool::new(
+                self.settings.middleware.is_empty()
+                    && self.settings.trace.is_none()
+                    && self.settings.har.is_none()
+                    && self.settings.timing.is_none(),
+            ),
+            file: Mutex::new(file),
+            scheduler,
+            round_trip,
+            network: NetworkWatch::new(workers, self.retry.max_attempts, self.network_wait),
+        };
+
+        // Over HTTP/2 the connections become streams on one connection
+        let (threads, depth) = if self.settings.http2 { (1, workers * self.pipeline) } else { (workers, self.pipeline) };
+
+        let save = || match (control, &synced) {
+            (Some(control), Some(file)) => save_progress(control, file, &shared.scheduler),
+            _ => Ok(()),
+        };
+        let result = std::thread::scope(|scope| {
+            // Saving can fail without harm to the download, which then only
+            // has more to fetch again if it is resumed
+            let (stop, stopped) = std::sync::mpsc::channel::<()>();
+            let save = &save;
+            let saver = control.is_some().then(|| scope.spawn(move || {
+                while stopped.recv_timeout(CONTROL_SAVE_INTERVAL) == Err(RecvTimeoutError::Timeout) {
+                    let _ = save();
+                }
+            }));
+
+            let result = if self.io_uring {
+                let file = shared.file.lock().expect("no worker holds the file yet");
+                RingTransfer::new(self, &file, &shared.scheduler, workers).and_then(RingTransfer::run)
+            } else {
+                let fetch = || if depth > 1 || self.settings.http2 { self.fetch_pipelined(&shared, depth) } else { self.fetch_chunks(&shared) };
+                let handles: Vec<_> = (0..threads).map(|_| scope.spawn(fetch)).collect();
+                handles.into_iter().try_for_each(|handle| handle.join().expect("download worker panicked"))
+            };
+            drop(stop);
+            if let Some(saver) = saver {
+                saver.join().expect("control file saver panicked");
+            }
+            result
+        });
+        let result = result.and_then(|()| {
+            if self.cancelled() {
+                let downloaded = self.state.downloaded.load(Ordering::Relaxed).min(total_size);
+                return Err(Cancelled { downloaded, total_size }.into_error());
+            }
+            Ok(())
+        });
+        if result.is_err() {
+            let _ = save();
+            return result;
+        }
+
+        shared.file.into_inner().expect("no worker holds the file").sync_all()
+    }
+
+    // Whatever a download ends with once its time is up, it stopped for that
+    fn timed_out(&self, error: std::io::Error) -> std::io::Error {
+        match (self.max_time, &self.settings.cancel) {
+            (Some(limit), Some(cancel)) if cancel.is_expired() => {
+                let total_size = self.state.total_size.load(Ordering::Relaxed);
+                let downloaded = self.state.downloaded.load(Ordering::Relaxed);
+                TimeLimitExceeded { limit, downloaded, total_size: (total_size > 0).then_some(total_size) }.into_error()
+            }
+            _ => error,
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.settings.cancel.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    // Paused downloads finish the chunks they have, then wait here. The wait
+    // wakes up now and then, since a token cancelled by the embedder itself
+    // doesn't signal the condition variable.
+    fn wait_while_paused(&self) {
+        let mut paused = self.state.paused.lock().expect("no thread panics holding the pause flag");
+        while *paused && !self.cancelled() {
+            paused = self.state.resumed
+                .wait_timeout(paused, Duration::from_millis(100))
+                .expect("no thread panics holding the pause flag")
+                .0;
+        }
+    }
+
+    // Worker loop: claim the next chunk, fetch it and write it at its offset.
+    // Each worker sizes its chunks to how fast its own requests come back.
+    // On cancellation the worker just stops; fetch_to reports it.
+    fn fetch_chunks(&self, shared: &Shared) -> std::io::Result<()> {
+        let mut sizer = ChunkSizer::new(shared.round_trip);
+        loop {
+            self.wait_while_paused();
+            if self.cancelled() {
+                return Ok(());
+            }
+            let Some(mut chunk) = shared.scheduler.claim(sizer.size()) else {
+                return Ok(());
+            };
+            let result = self.fetch_chunk(shared, &mut chunk, &mut sizer);
+            // What did arrive counts even if the rest didn't
+            shared.scheduler.finish(&chunk);
+            result?;
+            if !chunk.is_done() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Worker loop for a pipelined connection: keep up to depth chunks
+    // requested and take the answers as they come. A chunk that comes back
+    // short or not at all puts the pipeline out of step, so it is started
+    // again from that chunk. Chunks are sized to the rate answers arrive at.
+    fn fetch_pipelined(&self, shared: &Shared, depth: usize) -> std::io::Result<()> {
+        let (mut source, mut pipeline) = self.source_pipeline(depth);
+        let mut sizer = ChunkSizer::new(shared.round_trip);
+        // Chunks with a request out, in the order they were sent
+        let mut queued: VecDeque<Chunk> = VecDeque::new();
+        let mut answered = Instant::now();
+        let mut generation = shared.network.generation();
+        loop {
+            // Once the network has been checked, the requests out go again
+            // on a new connection, and once the download has moved to
+            // another mirror, to that mirror
+            if shared.network.generation() != generation {
+                generation = shared.network.generation();
+                pipeline.reset();
+                self.resend(&mut pipeline, &queued)?;
+            }
+            if self.source.load(Ordering::Relaxed) != source {
+                (source, pipeline) = self.source_pipeline(depth);
+                self.resend(&mut pipeline, &queued)?;
+            }
+            if queued.is_empty() {
+                self.wait_while_paused();
+            }
+            while !pipeline.is_full() && !self.cancelled() && !*self.state.paused.lock().expect("no thread panics holding the pause flag") {
+                let Some(chunk) = shared.scheduler.claim(sizer.size()) else {
+                    break;
+                };
+                let (start, end) = chunk.range();
+                self.requesting(start, end);
+                pipeline.send(start, end)?;
+                queued.push_back(chunk);
+            }
+            let Some(mut chunk) = queued.pop_front() else {
+                return Ok(());
+            };
+            let step = match pipeline.receive() {
+                Ok(body) => {
+                    shared.network.on_success();
+                    sizer.observe(body.len() as u64, answered.elapsed());
+                    self.apply(shared, chunk.on_response(&shared.scheduler, &body))
+                }
+                Err(_) if self.cancelled() => return Ok(()),
+                Err(e) => self.apply(shared, self.on_error(&shared.network, source, &mut chunk, e)),
+            };
+            answered = Instant::now();
+            shared.scheduler.finish(&chunk);
+            step?;
+            if !chunk.is_done() {
+                if self.source.load(Ordering::Relaxed) != source {
+                    (source, pipeline) = self.source_pipeline(depth);
+                } else {
+                    pipeline.reset();
+                }
+                queued.push_front(chunk);
+                self.resend(&mut pipeline, &queued)?;
+            }
+        }
+    }
+
+    // Ask for the queued chunks again, in order, on a pipeline just reset
+    fn resend(&self, pipeline: &mut http::Pipeline, queued: &VecDeque<Chunk>) -> std::io::Result<()> {
+        for chunk in queued {
+            let (start, end) = chunk.range();
+            self.requesting(start, end);
+            pipeline.send(start, end)?;
+        }
+        Ok(())
+    }
+
+    // Fetch one chunk, stopping early only if cancelled
+    fn fetch_chunk(&self, shared: &Shared, chunk: &mut Chunk, sizer: &mut ChunkSizer) -> std::io::Result<()> {
+        while !chunk.is_done() {
+            let (start, end) = chunk.range();
+            let (source, url) = self.source();
+            self.requesting(start, end);
+            let started = Instant::now();
+            if let Some(file) = shared.spliced.as_ref().filter(|_| shared.splicing.load(Ordering::Relaxed)) {
+                match http::splice_range(url, start, end, file, start, &self.settings) {
+                    Ok(Some(len)) => {
+                        shared.network.on_success();
+                        sizer.observe(len, started.elapsed());
+                        chunk.on_stored(len);
+                        self.written(shared.scheduler.total_size(), start, len);
+                        continue;
+                    }
+                    // Any other answer is fetched again the usual way
+                    Ok(None) => {}
+                    Err(_) if self.cancelled() => return Ok(()),
+                    Err(e) if matches!(e.kind(), std::io::ErrorKind::Unsupported | std::io::ErrorKind::InvalidInput) => {
+                        shared.splicing.store(false, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        self.apply(shared, self.on_error(&shared.network, source, chunk, e))?;
+                        continue;
+                    }
+                }
+            }
+            match http::download_range(url, start, end, &self.settings) {
+                Ok(body) => {
+                    shared.network.on_success();
+                    sizer.observe(body.len() as u64, started.elapsed());
+                    self.apply(shared, chunk.on_response(&shared.scheduler, &body))?
+                }
+                Err(_) if self.cancelled() => return Ok(()),
+                Err(e) => self.apply(shared, self.on_error(&shared.network, source, chunk, e))?,
+            }
+        }
+        Ok(())
+    }
+
+    // What to do about a failed request for the chunk to the source. Once
+    // every connection has been failing the network is checked first, and a
+    // chunk whose request failed for want of one is asked for again without
+    // the try counting. A source given up on moves the download to the next
+    // mirror, where the chunk is asked for again.
+    fn on_error<'a>(&self, network: &NetworkWatch, source: usize, chunk: &mut Chunk, error: std::io::Error) -> Step<'a> {
+        if let Some(generation) = network.on_failure(&error) {
+            let probe = || http::probe(self.source_url(source), &self.settings).map(drop);
+            match network.recover(generation, probe, || self.cancelled()) {
+                Ok(true) => return chunk.on_reconnect(&error),
+                Ok(false) => {}
+                Err(e) => return Step::Fail(e),
+            }
+        }
+        match chunk.on_error(error) {
+            Step::Fail(e) if !self.cancelled() && self.switch_source(source) => chunk.on_new_source(&e),
+            step => step,
+        }
+    }
+
+    // The URL for source 0, and the mirrors after it
+    fn source_url(&self, source: usize) -> &Url {
+        match source.checked_sub(1) {
+            Some(mirror) => &self.mirrors[mirror],
+            None => &self.url,
+        }
+    }
+
+    // The source requests go to now, and its URL
+    fn source(&self) -> (usize, &Url) {
+        let source = self.source.load(Ordering::Relaxed);
+        (source, self.source_url(source))
+    }
+
+    // A pipeline to the source requests go to now
+    fn source_pipeline(&self, depth: usize) -> (usize, http::Pipeline<'_>) {
+        let (source, url) = self.source();
+        (source, http::Pipeline::new(url, depth, &self.settings))
+    }
+
+    // Give up on a source for the next mirror, if there is one, telling the
+    // observers. Connections failing on the same source at once move the
+    // download on only once. Returns whether there was a source after it.
+    fn switch_source(&self, failed: usize) -> bool {
+        if failed >= self.mirrors.len() {
+            return false;
+        }
+        if self.source.compare_exchange(failed, failed + 1, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            for observer in &self.observers {
+                observer.on_mirror_switched(self.source_url(failed), self.source_url(failed + 1));
+            }
+        }
+        true
+    }
+
+    // Carry out what the engine decided about a response
+    fn apply(&self, shared: &Shared, step: Step) -> std::io::Result<()> {
+        match step {
+            Step::Write { offset, data } => self.write(shared, offset, data),
+            Step::Retry { offset, attempt, reason, delay } => {
+                self.retrying(offset, attempt, &reason, delay);
+                Ok(())
+            }
+            Step::Fail(e) => Err(e),
+        }
+    }
+
+    fn write(&self, shared: &Shared, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        let mut file = shared.file.lock().expect("no worker panics holding the file");
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        drop(file);
+
+        self.written(shared.scheduler.total_size(), offset, data.len() as u64);
+        Ok(())
+    }
+
+    // Tell the observers about a request for the bytes from start to end
+    // (inclusive)
+    fn requesting(&self, start: u64, end: u64) {
+        for observer in &self.observers {
+            observer.on_request(start, end - start + 1);
+        }
+    }
+
+    // Count the len bytes at offset as downloaded and tell the observers
+    fn written(&self, total_size: u64, offset: u64, len: u64) {
+        let downloaded = self.state.downloaded.fetch_add(len, Ordering::Relaxed).saturating_add(len);
+        for observer in &self.observers {
+            observer.on_chunk(offset, len as usize, downloaded.min(total_size), total_size);
+        }
+    }
+
+    // Tell the observers a range is being asked for again, then wait out the
+    // delay the retry policy gave, or until the download is cancelled
+    fn retrying(&self, offset: u64, attempt: usize, reason: &str, delay: Duration) {
+        for observer in &self.observers {
+            observer.on_retry(offset, attempt, reason);
+        }
+        let until = Instant::now() + delay;
+        while !self.cancelled() {
+            let left = until.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            std::thread::sleep(left.min(BACKOFF_CHECK_INTERVAL));
+        }
+    }
+
+    // Hash what was downloaded and check it against the expected digests,
+    // reading the file a piece at a time however big it is
+    fn check(&self, path: &Path, total_size: u64) -> std::io::Result<String> {
+        let mut algorithms = vec![Algorithm::Sha256];
+        for digest in &self.expected {
+            if !algorithms.contains(&digest.algorithm) {
+                algorithms.push(digest.algorithm);
+            }
+        }
+        let mut hashers: Vec<Hasher> = algorithms.into_iter().map(Hasher::new).collect();
+
+        let mut file = File::open(path)?;
+        let mut buffer = vec![0; CHECK_BUFFER];
+        let mut size: u64 = 0;
+        loop {
+            let read = match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            size += read as u64;
+            for hasher in &mut hashers {
+                hasher.update(&buffer[..read]);
+            }
+        }
+        if size != total_size {
+            return Err(std::io::Error::other(format!(
+                "Downloaded {} bytes but expected {} bytes", size, total_size
+            )));
+        }
+
+        let digests: Vec<(Algorithm, Vec<u8>)> = hashers.into_iter().map(|hasher| (hasher.algorithm(), hasher.finish())).collect();
+        let digest = |algorithm| digests.iter()
+            .find(|(hashed, _)| *hashed == algorithm)
+            .map(|(_, digest)| digest.clone())
+            .expect("every expected algorithm was hashed");
+        integrity::check(&self.expected, digest)?;
+        Ok(digest(Algorithm::Sha256).iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+}
+
+// Record the chunks finished so far, once they are safely in the file
+fn save_progress(control: &ControlFile, file: &File, scheduler: &Scheduler) -> std::io::Result<()> {
+    let done = scheduler.done();
+    file.sync_data()?;
+    control.save(&done)
+}
+
+// Tags for the operations of each slot of a RingTransfer; the slot's index is
+// in the bits above them
+const TAG_READ: u64 = 0;
+const TAG_READ_TIMEOUT: u64 = 1;
+const TAG_WRITE: u64 = 2;
+const TAG_TICK: u64 = u64::MAX;
+const TAG_TICK_REMOVED: u64 = u64::MAX - 1;
+
+// Every connection of a download driven from one thread through io_uring.
+// Each connection has a buffer registered with the kernel; a range's response
+// is read into it and its body written to the file straight from there.
+// Connecting and sending the requests still block, and so does anything that
+// isn't a plain 200 or 206 that fits the buffer, which goes through http
+// instead.
+struct RingTransfer<'a> {
+    download: &'a Download,
+    file: &'a File,
+    scheduler: &'a Scheduler,
+    network: NetworkWatch,
+    // Dropped before the buffers it writes into
+    ring: Ring,
+    buffers: Vec<Box<[u8]>>,
+    slots: Vec<Slot>,
+    read_timeout: Option<Timespec>,
+    tick: Timespec,
+    ticking: bool,
+}
+
+// One connection of a RingTransfer and the chunk it is fetching
+#[derive(Default)]
+struct Slot {
+    chunk: Option<Chunk>,
+    // The source the chunk was last asked of
+    source: usize,
+    conn: Option<TcpStream>,
+    // Bytes of the response in the buffer
+    filled: usize,
+    // Set once the head is in: where the response ends, if it says
+    end: Option<Option<usize>>,
+    writing: Option<Pending>,
+    // Operations the kernel has yet to finish
+    in_flight: usize,
+    // Set once there are no chunks left to claim
+    finished: bool,
+}
+
+// Body bytes in a slot's buffer on their way to the file
+struct Pending {
+    offset: u64,
+    start: usize,
+    len: usize,
+    written: usize,
+}
+
+impl<'a> RingTransfer<'a> {
+    fn new(download: &'a Download, file: &'a File, scheduler: &'a Scheduler, workers: usize) -> std::io::Result<RingTransfer<'a>> {
+        let unavailable = |e: std::io::Error| std::io::Error::new(e.kind(), format!("io_uring is not available: {}", e));
+        // A read and its timeout per connection, and the tick
+        let ring = Ring::new(workers as u32 * 2 + 1).map_err(unavailable)?;
+        let mut buffers: Vec<Box<[u8]>> = (0..workers)
+            .map(|_| vec![0; CHUNK_SIZE + RING_HEAD_ROOM].into_boxed_slice())
+            .collect();
+        // SAFETY: the boxes stay put while the transfer owns them, the ring
+        // is dropped before them, and run() waits for every operation
+        unsafe { ring.register_buffers(&mut buffers) }.map_err(unavailable)?;
+        Ok(RingTransfer {
+            download,
+            file,
+            scheduler,
+            network: NetworkWatch::new(workers, download.retry.max_attempts, download.network_wait),
+            ring,
+            buffers,
+            slots: (0..workers).map(|_| Slot::default()).collect(),
+            read_timeout: download.settings.timeout.map(Timespec::from),
+            tick: Timespec::from(RING_TICK),
+            ticking: false,
+        })
+    }
+
+    // Fetch until every chunk is in, the download is cancelled or it fails.
+    // Whatever the kernel is still doing is finished before returning.
+    fn run(mut self) -> std::io::Result<()> {
+        let result = self.drive();
+        self.drain();
+        result
+    }
+
+    fn drive(&mut self) -> std::io::Result<()> {
+        for index in 0..self.slots.len() {
+            self.start(index)?;
+        }
+        while !self.slots.iter().all(|slot| slot.finished) {
+            if !self.ticking {
+                // SAFETY: the tick lives as long as the ring
+                unsafe { self.ring.timeout(&self.tick, TAG_TICK)? };
+                self.ticking = true;
+            }
+            self.ring.submit_and_wait(1)?;
+            let mut result = Ok(());
+            for completion in self.ring.completions() {
+                self.account(completion.tag);
+                if result.is_ok() {
+                    result = self.on_completion(completion.tag, completion.result);
+                }
+            }
+            result?;
+            if self.download.cancelled() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    // Have every read end and wait for all the operations still in flight,
+    // so none of them touches a buffer after it is gone. If the ring itself
+    // fails the buffers are leaked instead.
+    fn drain(&mut self) {
+        for conn in self.slots.iter().filter_map(|slot| slot.conn.as_ref()) {
+            let _ = conn.shutdown(std::net::Shutdown::Both);
+        }
+        // Failing that, the tick is over within RING_TICK anyway
+        if self.ticking {
+            let _ = self.ring.remove_timeout(TAG_TICK, TAG_TICK_REMOVED);
+        }
+        while self.ticking || self.slots.iter().any(|slot| slot.in_flight > 0) {
+            if self.ring.submit_and_wait(1).is_err() {
+                std::mem::forget(std::mem::take(&mut self.buffers));
+                return;
+            }
+            for completion in self.ring.completions() {
+                self.account(completion.tag);
+            }
+        }
+    }
+
+    fn account(&mut self, tag: u64) {
+        if tag == TAG_TICK {
+            self.ticking = false;
+        } else if tag != TAG_TICK_REMOVED {
+            self.slots[(tag >> 2) as usize].in_flight -= 1;
+        }
+    }
+
+    fn on_completion(&mut self, tag: u64, result: std::io::Result<usize>) -> std::io::Result<()> {
+        if tag == TAG_TICK {
+            // Connections left idle by a pause pick up where they left off
+            if !*self.download.state.paused.lock().expect("no thread panics holding the pause flag") {
+                for index in 0..self.slots.len() {
+                    let slot = &self.slots[index];
+                    if !slot.finished && slot.chunk.is_none() && slot.in_flight == 0 {
+                        self.start(index)?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+        let index = (tag >> 2) as usize;
+        match tag & 3 {
+            TAG_READ => self.on_read(index, result),
+            TAG_WRITE => self.on_write(index, result),
+            _ => Ok(()),
+        }
+    }
+
+    // Request the slot's chunk, claiming the next one if it has none. A
+    // paused or cancelled download leaves the slot idle.
+    fn start(&mut self, index: usize) -> std::io::Result<()> {
+        loop {
+            let slot = &mut self.slots[index];
+            if slot.chunk.is_none() {
+                let paused = *self.download.state.paused.lock().expect("no thread panics holding the pause flag");
+                if paused || self.download.cancelled() {
+                    return Ok(());
+                }
+                match self.scheduler.claim(CHUNK_SIZE as u64) {
+                    Some(chunk) => slot.chunk = Some(chunk),
+                    None => {
+                        slot.finished = true;
+                        return Ok(());
+                    }
+                }
+            }
+            let chunk = slot.chunk.as_mut().expect("the slot has a chunk");
+            let (start, end) = chunk.range();
+            let (source, url) = self.download.source();
+            slot.source = source;
+            self.download.requesting(start, end);
+            match http::request_range(url, start, end, &self.download.settings) {
+                Ok(conn) => {
+                    slot.conn = Some(conn);
+                    slot.filled = 0;
+                    slot.end = None;
+                    return self.read(index);
+                }
+                Err(_) if self.download.cancelled() => return Ok(()),
+                Err(e) => self.retry(index, e)?,
+            }
+        }
+    }
+
+    // Give up on the slot's connection, and on the download once the
+    // engine says so. The caller asks for the chunk again.
+    fn retry(&mut self, index: usize, error: std::io::Error) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        slot.conn = None;
+        let chunk = slot.chunk.as_mut().expect("the slot has a chunk");
+        match self.download.on_error(&self.network, slot.source, chunk, error) {
+            Step::Retry { offset, attempt, reason, delay } => {
+                self.download.retrying(offset, attempt, &reason, delay);
+                Ok(())
+            }
+            Step::Fail(e) => Err(e),
+            Step::Write { .. } => unreachable!("on_error never asks for a write"),
+        }
+    }
+
+    fn read(&mut self, index: usize) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        let conn = slot.conn.as_ref().expect("a reading slot has a connection");
+        let timeout = self.read_timeout.as_ref().map(|timeout| (timeout, (index as u64) << 2 | TAG_READ_TIMEOUT));
+        // SAFETY: the rest of the slot's registered buffer, which nothing
+        // touches until the read completes; the timeout lives as long as the
+        // ring
+        unsafe {
+            self.ring.read_fixed(conn, &mut self.buffers[index][slot.filled..], index as u16, (index as u64) << 2 | TAG_READ, timeout)?;
+        }
+        slot.in_flight += 1 + usize::from(timeout.is_some());
+        Ok(())
+    }
+
+    fn on_read(&mut self, index: usize, result: std::io::Result<usize>) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        let read = match result {
+            Ok(read) => read,
+            Err(_) if self.download.cancelled() => return Ok(()),
+            Err(e) => {
+                let e = if crate::uring::timed_out(&e) {
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "Timed out waiting for data")
+                } else {
+                    e
+                };
+                self.retry(index, e)?;
+                return self.start(index);
+            }
+        };
+        slot.filled += read;
+        if slot.end.is_none() {
+            slot.end = http::split_response(&self.buffers[index][..slot.filled]).map(|(_, _, end)| end);
+        }
+        match slot.end.flatten() {
+            Some(end) if slot.filled >= end => {
+                slot.filled = end;
+                self.on_response(index)
+            }
+            _ if read == 0 => self.on_response(index),
+            _ if slot.filled == self.buffers[index].len() => self.fall_back(index),
+            _ => self.read(index),
+        }
+    }
+
+    // The whole response is in the buffer
+    fn on_response(&mut self, index: usize) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        slot.conn = None;
+        let response = &self.buffers[index][..slot.filled];
+        let (start, end) = slot.chunk.as_ref().expect("a reading slot has a chunk").range();
+        // An answer for other bytes goes through http too, which refuses it
+        let url = self.download.source_url(slot.source);
+        let parts = http::split_response(response).filter(|(head, _, _)| {
+            matches!(head.status, 200 | 206) && http::check_range(url, head, start, end).is_ok()
+        });
+        let Some((_, body_start, body_end)) = parts else {
+            return self.fall_back(index);
+        };
+        let body = &response[body_start..body_end.unwrap_or(response.len()).min(response.len())];
+        self.network.on_success();
+        match slot.chunk.as_mut().expect("a reading slot has a chunk").on_response(self.scheduler, body) {
+            Step::Write { offset, data } => {
+                let start = data.as_ptr() as usize - response.as_ptr() as usize;
+                slot.writing = Some(Pending { offset, start, len: data.len(), written: 0 });
+                self.write(index)
+            }
+            Step::Retry { offset, attempt, reason, delay } => {
+                self.download.retrying(offset, attempt, &reason, delay);
+                self.start(index)
+            }
+            Step::Fail(e) => Err(e),
+        }
+    }
+
+    fn write(&mut self, index: usize) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        let pending = slot.writing.as_ref().expect("a writing slot has data");
+        let data = &self.buffers[index][pending.start + pending.written..pending.start + pending.len];
+        let offset = pending.offset + pending.written as u64;
+        // SAFETY: part of the slot's registered buffer, left alone until the
+        // write completes
+        unsafe { self.ring.write_fixed(self.file, data, index as u16, offset, (index as u64) << 2 | TAG_WRITE)? };
+        slot.in_flight += 1;
+        Ok(())
+    }
+
+    fn on_write(&mut self, index: usize, result: std::io::Result<usize>) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        let pending = slot.writing.as_mut().expect("a writing slot has data");
+        match result? {
+            0 => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "Could not write to the file")),
+            written => pending.written += written,
+        }
+        if pending.written < pending.len {
+            return self.write(index);
+        }
+        let Pending { offset, len, .. } = slot.writing.take().expect("a writing slot has data");
+        self.download.written(self.scheduler.total_size(), offset, len as u64);
+        if let Some(chunk) = slot.chunk.take_if(|chunk| chunk.is_done()) {
+            self.scheduler.finish(&chunk);
+        }
+        self.start(index)
+    }
+
+    // A response the ring doesn't handle, such as a refusal or one too big
+    // for the buffer, is asked for again through http, which knows what to
+    // make of it, and written out from there
+    fn fall_back(&mut self, index: usize) -> std::io::Result<()> {
+        let slot = &mut self.slots[index];
+        slot.conn = None;
+        let chunk = slot.chunk.as_mut().expect("a reading slot has a chunk");
+        let (start, end) = chunk.range();
+        let (source, url) = self.download.source();
+        slot.source = source;
+        let body = match http::download_range(url, start, end, &self.download.settings) {
+            Ok(body) => {
+                self.network.on_success();
+                body
+            }
+            Err(_) if self.download.cancelled() => return Ok(()),
+            Err(e) => {
+                self.retry(index, e)?;
+                return self.start(index);
+            }
+        };
+        match chunk.on_response(self.scheduler, &body) {
+            Step::Write { offset, data } => {
+                let mut file = self.file;
+                file.seek(SeekFrom::Start(offset))?;
+                file.write_all(data)?;
+                self.download.written(self.scheduler.total_size(), offset, data.len() as u64);
+                if chunk.is_done() {
+                    self.scheduler.finish(chunk);
+                    slot.chunk = None;
+                }
+            }
+            Step::Retry { offset, attempt, reason, delay } => self.download.retrying(offset, attempt, &reason, delay),
+            Step::Fail(e) => return Err(e),
+        }
+        self.start(index)
+    }
+}
+
+impl DownloadBuilder {
+    // Where to save the file. Defaults to the last segment of the URL's path.
+    pub fn output(mut self, path: impl Into<PathBuf>) -> DownloadBuilder {
+        self.output = Some(path.into());
+        self
+    }
+
+    // Another URL for the same body, moved on to once the URL and the
+    // mirrors added before this one fail for good: the probe can't get an
+    // answer or is refused, or the retry policy gives up on a range
+    pub fn mirror(mut self, url: &str) -> DownloadBuilder {
+        self.mirrors.push(url.to_string());
+        self
+    }
+
+    // Number of ranges fetched at the same time, each over its own connection
+    pub fn connections(mut self, connections: usize) -> DownloadBuilder {
+        self.connections = connections;
+        self
+    }
+
+    // Use fewer connections than asked for when there would be less than
+    // this many bytes for each, so a small body comes over one instead of
+    // paying for handshakes and loading the server with ranges. 1M unless
+    // set; 0 always splits.
+    pub fn min_split_size(mut self, bytes: u64) -> DownloadBuilder {
+        self.min_split_size = bytes;
+        self
+    }
+
+    // Keep up to depth range requests out on each connection at once, over
+    // HTTP keep-alive, so the next is already on its way while an answer
+    // arrives. 1, the default, waits for each answer before asking again,
+    // on a new connection. Not used with io_uring.
+    pub fn pipeline(mut self, depth: usize) -> DownloadBuilder {
+        self.pipeline = depth.max(1);
+        self
+    }
+
+    // Speak HTTP/2 to the server without asking first, as it has to be told
+    // over plain TCP, and carry every connection's requests as streams of a
+    // single connection, as many at once as the server allows. With a
+    // pipeline deeper than 1 each connection counts for that many streams.
+    pub fn http2(mut self, enabled: bool) -> DownloadBuilder {
+        self.http2 = enabled;
+        self
+    }
+
+    // Drive all the connections from one thread through io_uring, reading
+    // into buffers registered with the kernel and writing the file from
+    // them. Linux only; elsewhere, or where the kernel doesn't allow it, the
+    // download fails saying so.
+    pub fn io_uring(mut self, enabled: bool) -> DownloadBuilder {
+        self.io_uring = enabled;
+        self
+    }
+
+    // Keep the .part file when the download fails or is cancelled, with a
+    // control file beside it saying which chunks it has, and fetch only the
+    // missing ones when a download of the same body is run again
+    pub fn resumable(mut self, enabled: bool) -> DownloadBuilder {
+        self.resumable = enabled;
+        self
+    }
+
+    // Wait for another download holding the output's lock to finish, rather
+    // than failing with lock::Locked straight away
+    pub fn wait_for_lock(mut self, enabled: bool) -> DownloadBuilder {
+        self.wait_for_lock = enabled;
+        self
+    }
+
+    // Hex SHA-256 the body has to match before it is moved into place
+    pub fn expected_sha256(mut self, hex: &str) -> DownloadBuilder {
+        self.expected_sha256 = Some(hex.to_string());
+        self
+    }
+
+    // Stop the download when the token is cancelled. It is checked between
+    // reads and chunks, and the download fails with a Cancelled error.
+    pub fn cancellation(mut self, token: CancellationToken) -> DownloadBuilder {
+        self.cancel = Some(token);
+        self
+    }
+
+    // Ask the callback for a new URL when ranges start being refused with
+    // 403, as they are once a signed URL expires, and carry on from the same
+    // offset with the URL it returns
+    pub fn refresh_url(mut self, mint: impl Fn(&Url) -> std::io::Result<Url> + Send + Sync + 'static) -> DownloadBuilder {
+        self.refresh = Some(UrlRefresh::new(mint));
+        self
+    }
+
+    // Report progress to an observer while the download runs. Can be given
+    // more than once.
+    pub fn observer(mut self, observer: impl ProgressObserver + 'static) -> DownloadBuilder {
+        self.observers.push(Arc::new(observer));
+        self
+    }
+
+    // Limit on connecting, and on every read or write once connected
+    pub fn timeout(mut self, timeout: Duration) -> DownloadBuilder {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    // Give up once the download has run this long, counted from when it
+    // starts, with a TimeLimitExceeded error. A resumable download keeps what
+    // it has for next time. The cancellation token, if one was given, counts
+    // as cancelled from then on.
+    pub fn max_time(mut self, limit: Duration) -> DownloadBuilder {
+        self.max_time = Some(limit);
+        self
+    }
+
+    // How many times a failed range is tried and how long is waited in
+    // between, RetryPolicy::default() if not set
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> DownloadBuilder {
+        self.retry = policy;
+        self
+    }
+
+    // How long to wait for the network when every connection is failing,
+    // DEFAULT_NETWORK_WAIT if not set; zero gives up as soon as the retries do
+    pub fn network_wait(mut self, wait: Duration) -> DownloadBuilder {
+        self.network_wait = wait;
+        self
+    }
+
+    // Drop a connection whose average speed stays under bytes_per_second for
+    // the length of time, and fetch its range again on a new one. Not used
+    // with io_uring.
+    pub fn low_speed_limit(mut self, bytes_per_second: u64, time: Duration) -> DownloadBuilder {
+        self.low_speed = Some(http::SpeedLimit { bytes_per_second, time });
+        self
+    }
+
+    // Send every request through the layer, inside any added before it.
+    // Pipelined and io_uring ranges don't go through layers.
+    pub fn layer(mut self, layer: impl Middleware + 'static) -> DownloadBuilder {
+        self.middleware.push(layer);
+        self
+    }
+
+    // Connection settings to start from, such as a proxy, cookies or extra
+    // headers. What the other methods set goes on top, and layers added with
+    // layer() go inside the settings' own.
+    pub fn settings(mut self, settings: http::Settings) -> DownloadBuilder {
+        self.settings = settings;
+        self
+    }
+
+    pub fn build(self) -> std::io::Result<Download> {
+        let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+
+        let url = Url::parse(&self.url).map_err(invalid)?;
+        let mirrors = self.mirrors.iter()
+            .map(|mirror| Url::parse(mirror).map_err(invalid))
+            .collect::<std::io::Result<Vec<Url>>>()?;
+        let mut middleware = self.settings.middleware;
+        middleware.append(self.middleware);
+        let settings = http::Settings {
+            timeout: self.timeout.or(self.settings.timeout),
+            low_speed: self.low_speed.or(self.settings.low_speed),
+            cancel: self.cancel.or(self.settings.cancel).or_else(|| self.max_time.map(|_| CancellationToken::new())),
+            refresh: self.refresh.or(self.settings.refresh),
+            http2: self.http2 || self.settings.http2,
+            middleware,
+            ..self.settings
+        };
+        if self.connections == 0 {
+            return Err(invalid("connections must be at least 1".to_string()));
+        }
+        if settings.http2 && self.io_uring {
+            return Err(invalid("io_uring transfers only speak HTTP/1.1".to_string()));
+        }
+        if self.retry.max_attempts == 0 {
+            return Err(invalid("the retry policy has to allow at least one attempt".to_string()));
+        }
+        if !(self.retry.multiplier.is_finite() && self.retry.multiplier >= 1.0) {
+            return Err(invalid("the retry multiplier must be at least 1".to_string()));
+        }
+        if settings.low_speed.is_some() && self.io_uring {
+            return Err(invalid("io_uring transfers don't watch for a low speed".to_string()));
+        }
+        if settings.low_speed.is_some_and(|limit| limit.time.is_zero()) {
+            return Err(invalid("the low speed time must be more than zero".to_string()));
+        }
+        let expected = match &self.expected_sha256 {
+            Some(hex) => vec![integrity::parse_hex_sha256(hex).map_err(invalid)?],
+            None => Vec::new(),
+        };
+        let output = self.output
+            .unwrap_or_else(|| {
+                let name = url.file_name().filter(|name| !matches!(*name, "." | ".."));
+                PathBuf::from(name.unwrap_or(DEFAULT_FILE_NAME))
+            });
+
+        Ok(Download {
+            url,
+            mirrors,
+            source: AtomicUsize::new(0),
+            output,
+            connections: self.connections,
+            min_split_size: self.min_split_size,
+            pipeline: self.pipeline,
+            io_uring: self.io_uring,
+            resumable: self.resumable,
+            wait_for_lock: self.wait_for_lock,
+            expected,
+            max_time: self.max_time,
+            retry: self.retry,
+            network_wait: self.network_wait,
+            settings,
+            observers: self.observers,
+            state: Arc::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, response};
+    use std::net::TcpListener;
+
+    // A body that doesn't repeat within a chunk, so misplaced ranges show
+    fn body() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    // How the test server answers a range
+    #[derive(Clone, Copy, PartialEq)]
+    enum Ranges {
+        Served,
+        Refused,
+        // With a 206 of the right length, but from the start of the body
+        Misplaced,
+    }
+
+    // Answers a GET without a range with the whole body, and one with a
+    // range as ranges says
+    fn serve(body: Vec<u8>, ranges: Ranges) -> String {
+        testing::serve(move |head| {
+            let range = head.lines()
+                .find_map(|line| line.strip_prefix("Range: bytes="))
+                .and_then(|range| range.split_once('-'))
+                .map(|(start, end)| (start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+            match range {
+                None => response("200 OK", "", &body),
+                Some(_) if ranges == Ranges::Refused => response("404 Not Found", "", b""),
+                Some((start, end)) if ranges == Ranges::Misplaced => {
+                    let len = end.min(body.len() - 1) - start + 1;
+                    response("206 Partial Content", &format!("Content-Range: bytes 0-{}/{}\r\n", len - 1, body.len()), &body[..len])
+                }
+                Some((start, end)) => {
+                    let end = end.min(body.len() - 1);
+                    response("206 Partial Content", &format!("Content-Range: bytes {}-{}/{}\r\n", start, end, body.len()), &body[start..=end])
+                }
+            }
+        })
+    }
+
+    fn output(name: &str) -> PathBuf {
+        testing::temp_path(&format!("download_{}", name))
+    }
+
+    fn switches(events: &Receiver<DownloadEvent>) -> Vec<DownloadEvent> {
+        events.try_iter().filter(|event| matches!(event, DownloadEvent::MirrorSwitched { .. })).collect()
+    }
+
+    #[test]
+    fn refused_ranges_move_to_the_next_mirror() {
+        let primary = serve(body(), Ranges::Refused);
+        let mirror = serve(body(), Ranges::Served);
+        let path = output("refused");
+        let mut download = Download::builder(&primary).mirror(&mirror).output(&path).connections(2).min_split_size(64 * 1024).build().unwrap();
+        let events = download.subscribe();
+        download.run().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), body());
+        let _ = std::fs::remove_file(&path);
+        // Both connections fail on the URL, but it is left only once
+        assert_eq!(switches(&events), vec![DownloadEvent::MirrorSwitched { from: primary, to: mirror }]);
+    }
+
+    #[test]
+    fn an_unreachable_url_is_given_up_on_at_the_probe() {
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let primary = format!("http://{}/f.bin", closed);
+        let mirror = serve(body(), Ranges::Served);
+        let path = output("unreachable");
+        let mut download = Download::builder(&primary).mirror(&mirror).output(&path).build().unwrap();
+        let events = download.subscribe();
+        download.run().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), body());
+        let _ = std::fs::remove_file(&path);
+        let events: Vec<DownloadEvent> = events.try_iter().collect();
+        assert_eq!(events[0], DownloadEvent::MirrorSwitched { from: primary, to: mirror.clone() });
+        assert_eq!(events[1], DownloadEvent::Connected { url: mirror, total_size: 200_000 });
+    }
+
+    #[test]
+    fn the_download_fails_once_every_mirror_has() {
+        let primary = serve(body(), Ranges::Refused);
+        let first = serve(body(), Ranges::Refused);
+        let second = serve(body(), Ranges::Refused);
+        let path = output("failing");
+        let mut download = Download::builder(&primary).mirror(&first).mirror(&second).output(&path).build().unwrap();
+        let events = download.subscribe();
+        assert!(download.run().is_err());
+        assert!(!path.exists());
+        assert_eq!(switches(&events), vec![
+            DownloadEvent::MirrorSwitched { from: primary, to: first.clone() },
+            DownloadEvent::MirrorSwitched { from: first, to: second },
+        ]);
+    }
+
+    #[test]
+    fn io_uring_answers_for_other_bytes_are_refused() {
+        let url = serve(body(), Ranges::Misplaced);
+        let path = output("misplaced");
+        let once = RetryPolicy { max_attempts: 1, ..RetryPolicy::default() };
+        let download = Download::builder(&url).output(&path).io_uring(true).retry_policy(once).build().unwrap();
+        let error = download.run().unwrap_err();
+        assert!(error.to_string().contains("was for another range"), "{}", error);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,258 @@
+// The transfer logic without any I/O: which ranges to ask for, what each
+// response means and when to give up. Front-ends do the requests and the
+// writing, so the blocking and async entry points share all of this.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::retry::{self, RetryPolicy};
+
+// Size of the blocks the body is split into and kept track of in, and of
+// the first range each connection asks for
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+// Largest range a connection asks for at once
+pub const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+// A range is sized to take about this many round trips to arrive, so the
+// time spent asking is small next to the time spent receiving, but no more
+// than MAX_CHUNK_TIME so a retry doesn't lose much
+const CHUNK_ROUND_TRIPS: u32 = 16;
+const MAX_CHUNK_TIME: Duration = Duration::from_secs(2);
+
+// Tries per range before the download gives up
+pub const MAX_ATTEMPTS: usize = 3;
+
+// Bodies smaller than this aren't split across connections
+pub const DEFAULT_MIN_SPLIT_SIZE: u64 = 1024 * 1024;
+
+// How many connections to fetch size bytes over: as many as asked for, but
+// no more than leaves each at least min_split_size bytes, or has a chunk for
+// each. Always at least one.
+pub fn connections_for(size: u64, connections: usize, min_split_size: u64) -> usize {
+    let most = (size / min_split_size.max(1)).min(size.div_ceil(CHUNK_SIZE as u64)).max(1);
+    usize::try_from(most).map_or(connections, |most| connections.min(most))
+}
+
+// Picks how much one connection asks for at a time from how long a round
+// trip to the server takes and how fast the connection has been going
+pub struct ChunkSizer {
+    target: Duration,
+    size: u64,
+}
+
+// Hands out the chunks of a body, in order, to any number of workers. A
+// chunk is a run of CHUNK_SIZE blocks, as many as the worker asks for.
+pub struct Scheduler {
+    // Offset of the next block to claim
+    next: AtomicU64,
+    total_size: u64,
+    // Which blocks are in, for downloads that keep track so they can be
+    // resumed; empty otherwise
+    done: Vec<AtomicBool>,
+    // Handed to every chunk claimed
+    retry: RetryPolicy,
+}
+
+// One chunk being fetched, possibly over several responses
+pub struct Chunk {
+    // The first block in the chunk
+    index: usize,
+    filled: u64,
+    end: u64,
+    attempt: usize,
+    retry: RetryPolicy,
+    // Set when the response was the whole body rather than the range
+    whole: bool,
+}
+
+// What a front-end should do after a response or a failed request
+pub enum Step<'a> {
+    // Write the data at the offset in the output
+    Write { offset: u64, data: &'a [u8] },
+    // Ask for the chunk's range again, after waiting for the delay
+    Retry { offset: u64, attempt: usize, reason: String, delay: Duration },
+    // Give up on the download
+    Fail(std::io::Error),
+}
+
+impl ChunkSizer {
+    pub fn new(round_trip: Duration) -> ChunkSizer {
+        ChunkSizer { target: (round_trip * CHUNK_ROUND_TRIPS).min(MAX_CHUNK_TIME), size: CHUNK_SIZE as u64 }
+    }
+
+    // How many bytes to ask for next
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    // A range of len bytes took elapsed to arrive. The next is as large as
+    // the connection would fetch in the target time at that speed, in whole
+    // blocks, growing no more than fourfold at a time so one fast response
+    // doesn't overshoot.
+    pub fn observe(&mut self, len: u64, elapsed: Duration) {
+        if len == 0 {
+            return;
+        }
+        let rate = len as f64 / elapsed.as_secs_f64().max(0.001);
+        let wanted = (rate * self.target.as_secs_f64()) as u64;
+        let block = CHUNK_SIZE as u64;
+        self.size = (wanted.min(self.size.saturating_mul(4)).clamp(block, MAX_CHUNK_SIZE) / block) * block;
+    }
+}
+
+impl Scheduler {
+    pub fn new(total_size: u64) -> Scheduler {
+        Scheduler { next: AtomicU64::new(0), total_size, done: Vec::new(), retry: RetryPolicy::default() }
+    }
+
+    // A scheduler that keeps track of the chunks finished, and skips those
+    // already marked in done, one flag per chunk
+    pub fn resuming(total_size: u64, done: Vec<bool>) -> Scheduler {
+        Scheduler { next: AtomicU64::new(0), total_size, done: done.into_iter().map(AtomicBool::new).collect(), retry: RetryPolicy::default() }
+    }
+
+    // Retry the chunks claimed from now on by this policy, rather than the default
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Scheduler {
+        self.retry = retry;
+        self
+    }
+
+    // How many blocks a body of this size is split into, if it can be counted
+    pub fn chunk_count(total_size: u64) -> Option<usize> {
+        usize::try_from(total_size.div_ceil(CHUNK_SIZE as u64)).ok()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    // The next chunk nobody has claimed yet, if any are left: up to len bytes
+    // of blocks, starting at the first not already done and stopping short
+    // of the next that is
+    pub fn claim(&self, len: u64) -> Option<Chunk> {
+        let block = CHUNK_SIZE as u64;
+        let is_done = |offset: u64| self.done.get((offset / block) as usize).is_some_and(|done| done.load(Ordering::Relaxed));
+        let first = |mut offset: u64| {
+            while offset < self.total_size && is_done(offset) {
+                offset += block;
+            }
+            offset
+        };
+        // Never moved past the end, so it can't wrap around however many
+        // workers keep asking. The last run of the closure is the one that
+        // took effect.
+        let mut claimed = (0, 0);
+        self.next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            let start = first(next);
+            if start >= self.total_size {
+                return None;
+            }
+            let limit = start.saturating_add(len.max(block)).min(self.total_size);
+            let mut end = start.saturating_add(block).min(self.total_size);
+            while end < limit && !is_done(end) {
+                end = end.saturating_add(block).min(limit);
+            }
+            claimed = (start, end);
+            Some(end)
+        }).ok()?;
+        let (start, end) = claimed;
+        Some(Chunk { index: (start / block) as usize, filled: start, end, attempt: 1, retry: self.retry, whole: false })
+    }
+
+    // Note that the chunk's blocks are written out as far as it has got, and
+    // with them the whole body if that is what came back
+    pub fn finish(&self, chunk: &Chunk) {
+        if chunk.whole {
+            self.done.iter().for_each(|done| done.store(true, Ordering::Relaxed));
+            return;
+        }
+        let block = CHUNK_SIZE as u64;
+        let filled = if chunk.filled >= self.total_size { self.done.len() } else { (chunk.filled / block) as usize };
+        for done in self.done.iter().take(filled).skip(chunk.index) {
+            done.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // Which blocks are finished so far
+    pub fn done(&self) -> Vec<bool> {
+        self.done.iter().map(|done| done.load(Ordering::Relaxed)).collect()
+    }
+
+    // Bytes in the blocks finished so far
+    pub fn finished_size(&self) -> u64 {
+        let last = self.done.len().saturating_sub(1);
+        self.done.iter()
+            .enumerate()
+            .filter(|(_, done)| done.load(Ordering::Relaxed))
+            .map(|(index, _)| if index == last { self.total_size - last as u64 * CHUNK_SIZE as u64 } else { CHUNK_SIZE as u64 })
+            .sum()
+    }
+}
+
+impl Chunk {
+    pub fn is_done(&self) -> bool {
+        self.filled >= self.end
+    }
+
+    // The inclusive byte range to request next
+    pub fn range(&self) -> (u64, u64) {
+        (self.filled, self.end - 1)
+    }
+
+    // Work out what the body of a response to range() means
+    pub fn on_response<'a>(&mut self, scheduler: &Scheduler, body: &'a [u8]) -> Step<'a> {
+        if body.is_empty() {
+            let error = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("No data for range starting at {}", self.filled));
+            return self.on_error(error);
+        }
+        self.attempt = 1;
+
+        // A server that ignores Range sends everything, which will do as well
+        let wanted = self.end - self.filled;
+        if body.len() as u64 > wanted && body.len() as u64 == scheduler.total_size {
+            scheduler.next.store(scheduler.total_size, Ordering::Relaxed);
+            self.filled = self.end;
+            self.whole = true;
+            return Step::Write { offset: 0, data: body };
+        }
+
+        let offset = self.filled;
+        let data = &body[..usize::try_from(wanted).map_or(body.len(), |wanted| body.len().min(wanted))];
+        self.filled += data.len() as u64;
+        Step::Write { offset, data }
+    }
+
+    // The front-end put len bytes of a response to range() in place at the
+    // chunk's offset itself, having checked they were no more than asked for
+    pub fn on_stored(&mut self, len: u64) {
+        self.attempt = 1;
+        self.filled += len;
+    }
+
+    // A request for range() failed
+    pub fn on_error<'a>(&mut self, error: std::io::Error) -> Step<'a> {
+        if !self.retry.allows(self.attempt) || !retry::is_retryable(&error) {
+            return Step::Fail(error);
+        }
+        let delay = self.retry.delay(self.attempt);
+        self.attempt += 1;
+        Step::Retry { offset: self.filled, attempt: self.attempt - 1, reason: error.to_string(), delay }
+    }
+
+    // The requests for range() failed for good on one source and the
+    // download has another: ask it at once, the tries so far forgiven
+    pub fn on_new_source<'a>(&mut self, error: &std::io::Error) -> Step<'a> {
+        self.attempt = 1;
+        let reason = format!("Moving to the next mirror after {}", error);
+        Step::Retry { offset: self.filled, attempt: 1, reason, delay: Duration::ZERO }
+    }
+
+    // A request for range() failed because the network went away, and it is
+    // back: ask again at once, the tries so far forgiven
+    pub fn on_reconnect<'a>(&mut self, error: &std::io::Error) -> Step<'a> {
+        self.attempt = 1;
+        let reason = format!("The network came back after {}", error);
+        Step::Retry { offset: self.filled, attempt: 1, reason, delay: Duration::ZERO }
+    }
+}
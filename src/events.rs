@@ -0,0 +1,48 @@
+use std::sync::mpsc::Sender;
+
+use crate::download::Summary;
+use crate::progress::ProgressObserver;
+use crate::url::Url;
+
+// What Download::subscribe reports, in the order it happens
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DownloadEvent {
+    // The server answered and told us the size
+    Connected { url: String, total_size: u64 },
+    ChunkFinished { offset: u64, len: usize },
+    // A range request failed or came back empty and is being retried
+    Stalled { offset: u64, attempt: usize, reason: String },
+    // The source failed for good and the download moved to the next mirror
+    MirrorSwitched { from: String, to: String },
+    Completed { sha256: String },
+    Failed { error: String },
+}
+
+// Forwards observer calls as events. Sends fail only once the receiver is
+// gone, and then nobody is listening anyway.
+pub(crate) struct EventSender(pub(crate) Sender<DownloadEvent>);
+
+impl ProgressObserver for EventSender {
+    fn on_started(&self, url: &Url, total_size: u64) {
+        let _ = self.0.send(DownloadEvent::Connected { url: url.to_string(), total_size });
+    }
+
+    fn on_chunk(&self, offset: u64, len: usize, _downloaded: u64, _total_size: u64) {
+        let _ = self.0.send(DownloadEvent::ChunkFinished { offset, len });
+    }
+
+    fn on_retry(&self, offset: u64, attempt: usize, reason: &str) {
+        let _ = self.0.send(DownloadEvent::Stalled { offset, attempt, reason: reason.to_string() });
+    }
+
+    fn on_mirror_switched(&self, from: &Url, to: &Url) {
+        let _ = self.0.send(DownloadEvent::MirrorSwitched { from: from.to_string(), to: to.to_string() });
+    }
+
+    fn on_finished(&self, result: Result<&Summary, &std::io::Error>) {
+        let _ = self.0.send(match result {
+            Ok(summary) => DownloadEvent::Completed { sha256: summary.sha256.clone() },
+            Err(e) => DownloadEvent::Failed { error: e.to_string() },
+        });
+    }
+}
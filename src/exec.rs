@@ -0,0 +1,72 @@
+use std::io::{ErrorKind, Read};
+use std::process::{Command, Stdio};
+
+// A piped-to command that didn't succeed. Carried inside the io::Error so
+// main can exit with the command's own status.
+#[derive(Debug)]
+pub struct Failed {
+    pub command: String,
+    pub code: Option<i32>,
+}
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} exited with status {}", self.command, code),
+            None => write!(f, "{} was killed by a signal", self.command),
+        }
+    }
+}
+
+impl std::error::Error for Failed {}
+
+// Exit status to leave with when a download failed because of its command
+pub fn exit_code(error: &std::io::Error) -> Option<i32> {
+    let failed = error.get_ref()?.downcast_ref::<Failed>()?;
+    Some(failed.code.unwrap_or(1))
+}
+
+// Run a command through the shell
+pub fn shell(command: &str) -> Command {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+// Run a shell command with what data reads on its stdin. A command that
+// stops reading early (like head) isn't an error by itself; only its exit
+// status counts.
+pub fn pipe(command: &str, data: &mut dyn Read) -> std::io::Result<()> {
+    let mut child = shell(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", command, e)))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    match std::io::copy(data, &mut stdin) {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => {
+            drop(stdin);
+            let _ = child.wait();
+            return Err(e);
+        }
+        _ => drop(stdin),
+    }
+
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(Failed { command: command.to_string(), code: status.code() }))
+    }
+}
@@ -0,0 +1,131 @@
+// The exit status of a failed run, one number per cause so scripts can branch
+// on it. The numbers are curl's where curl has the same failure, so a script
+// written for curl reads them the same way; a body that hashed wrong has no
+// curl number and gets one past the end of curl's table. A run with several
+// failed downloads exits with the status of the last.
+
+use std::io::ErrorKind;
+
+use http_client::http::{Forbidden, RangeIgnored, RangeNotSatisfiable, TooSlow, UnexpectedStatus, Unresolved};
+use http_client::integrity::Mismatch;
+use http_client::proxy::ProxyAuthRequired;
+use http_client::{Cancelled, TimeLimitExceeded};
+
+use crate::{cache, exec};
+
+// Anything without a number of its own
+pub const FAILED: i32 = 1;
+// A URL this build can't fetch, https:// among them as there is no TLS;
+// curl's "unsupported protocol", which a curl built without TLS gives too
+pub const UNSUPPORTED_PROTOCOL: i32 = 1;
+// The command line didn't parse
+pub const USAGE: i32 = 2;
+// The host name didn't resolve
+pub const RESOLVE: i32 = 6;
+// The server couldn't be connected to, or the network went away
+pub const CONNECT: i32 = 7;
+// The server answered with an error status, as curl --fail exits
+pub const HTTP_ERROR: i32 = 22;
+// --max-time ran out, or the transfer stalled under --speed-limit or
+// a read timed out
+pub const TIMEOUT: i32 = 28;
+// The download was cancelled; curl's "aborted by callback"
+pub const CANCELLED: i32 = 42;
+// The body didn't match the digest it was expected to
+pub const HASH_MISMATCH: i32 = 110;
+
+// The status to exit with for a command line that didn't parse
+pub fn for_usage(message: &str) -> i32 {
+    if message.contains(http_client::url::UNSUPPORTED_SCHEME) { UNSUPPORTED_PROTOCOL } else { USAGE }
+}
+
+// Why a download or command failed, each cause with its own status
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cause {
+    // A failed --exec command, with its status
+    Exec(i32),
+    // --offline and the URL isn't cached
+    Offline,
+    HashMismatch,
+    Timeout,
+    Cancelled,
+    Resolve,
+    Connect,
+    HttpError,
+    Other,
+}
+
+impl Cause {
+    pub fn of(error: &std::io::Error) -> Cause {
+        if let Some(code) = exec::exit_code(error) {
+            return Cause::Exec(code);
+        }
+        if cache::exit_code(error).is_some() {
+            return Cause::Offline;
+        }
+        if Mismatch::from_error(error).is_some() {
+            return Cause::HashMismatch;
+        }
+        if TimeLimitExceeded::from_error(error).is_some() || TooSlow::from_error(error).is_some() {
+            return Cause::Timeout;
+        }
+        if Cancelled::from_error(error).is_some() {
+            return Cause::Cancelled;
+        }
+        if Unresolved::from_error(error).is_some() {
+            return Cause::Resolve;
+        }
+        let answered = UnexpectedStatus::from_error(error).is_some()
+            || Forbidden::from_error(error).is_some()
+            || RangeNotSatisfiable::from_error(error).is_some()
+            || RangeIgnored::from_error(error).is_some()
+            || ProxyAuthRequired::from_error(error).is_some();
+        if answered {
+            return Cause::HttpError;
+        }
+        match error.kind() {
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Cause::Timeout,
+            ErrorKind::Interrupted => Cause::Cancelled,
+            ErrorKind::ConnectionRefused
+            | ErrorKind::HostUnreachable
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::NetworkDown
+            | ErrorKind::AddrNotAvailable => Cause::Connect,
+            _ => Cause::Other,
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Cause::Exec(code) => code,
+            Cause::Offline => cache::OFFLINE_EXIT_CODE,
+            Cause::HashMismatch => HASH_MISMATCH,
+            Cause::Timeout => TIMEOUT,
+            Cause::Cancelled => CANCELLED,
+            Cause::Resolve => RESOLVE,
+            Cause::Connect => CONNECT,
+            Cause::HttpError => HTTP_ERROR,
+            Cause::Other => FAILED,
+        }
+    }
+
+    // How --json names the cause
+    pub fn name(self) -> &'static str {
+        match self {
+            Cause::Exec(_) => "exec",
+            Cause::Offline => "offline",
+            Cause::HashMismatch => "hash_mismatch",
+            Cause::Timeout => "timeout",
+            Cause::Cancelled => "cancelled",
+            Cause::Resolve => "resolve",
+            Cause::Connect => "connect",
+            Cause::HttpError => "http",
+            Cause::Other => "other",
+        }
+    }
+}
+
+// The status to exit with for a failure
+pub fn for_error(error: &std::io::Error) -> i32 {
+    Cause::of(error).exit_code()
+}
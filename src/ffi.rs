@@ -0,0 +1,133 @@
+// C bindings, enabled with the ffi feature. include/http_client.h declares
+// them; build the shared library with
+//
+//     cargo rustc --lib --release --features ffi --crate-type cdylib
+//
+// The safety requirements are in the comment above each function.
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::{CStr, c_char, c_uint, c_void};
+use std::time::Duration;
+
+use crate::download::Download;
+use crate::progress::ProgressObserver;
+
+// Called as bytes arrive, possibly from several threads at once
+pub type ProgressCallback = extern "C" fn(user_data: *mut c_void, downloaded: u64, total_size: u64);
+
+#[repr(C)]
+pub struct Options {
+    // Parallel connections, 0 for the default of 1
+    pub connections: c_uint,
+    // Timeout in milliseconds for connecting and each read, 0 for none
+    pub timeout_ms: c_uint,
+    // Hex SHA-256 the body has to match, or null
+    pub expected_sha256: *const c_char,
+    pub progress: Option<ProgressCallback>,
+    pub user_data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct DownloadResult {
+    pub size: u64,
+    // Hex SHA-256, NUL terminated
+    pub sha256: [c_char; 65],
+    // Why the download failed, NUL terminated and possibly cut short
+    pub error: [c_char; 256],
+}
+
+// Hands progress to the C callback. The caller vouches for user_data being
+// usable from the download's threads.
+struct CallbackObserver {
+    callback: ProgressCallback,
+    user_data: *mut c_void,
+}
+
+unsafe impl Send for CallbackObserver {}
+unsafe impl Sync for CallbackObserver {}
+
+impl ProgressObserver for CallbackObserver {
+    fn on_chunk(&self, _offset: u64, _len: usize, downloaded: u64, total_size: u64) {
+        (self.callback)(self.user_data, downloaded, total_size);
+    }
+}
+
+// Download url to path, verifying it against options->expected_sha256 if
+// set. Returns 0 on success and -1 on failure, with result (if not null)
+// filled in either way.
+//
+// # Safety
+// url and path must be NUL-terminated strings. options and result may be
+// null; otherwise they must point to valid structs, and
+// options->expected_sha256 must be null or a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_download(
+    url: *const c_char,
+    path: *const c_char,
+    options: *const Options,
+    result: *mut DownloadResult,
+) -> i32 {
+    let outcome = unsafe { run(url, path, options) };
+    let result = unsafe { result.as_mut() };
+
+    match outcome {
+        Ok(summary) => {
+            if let Some(result) = result {
+                result.size = summary.size;
+                copy_str(&mut result.sha256, &summary.sha256);
+                copy_str(&mut result.error, "");
+            }
+            0
+        }
+        Err(e) => {
+            if let Some(result) = result {
+                result.size = 0;
+                copy_str(&mut result.sha256, "");
+                copy_str(&mut result.error, &e.to_string());
+            }
+            -1
+        }
+    }
+}
+
+unsafe fn run(url: *const c_char, path: *const c_char, options: *const Options) -> std::io::Result<crate::Summary> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidInput, message.to_string());
+    let url = unsafe { string(url) }.ok_or_else(|| invalid("url is null or not UTF-8"))?;
+    let path = unsafe { string(path) }.ok_or_else(|| invalid("path is null or not UTF-8"))?;
+
+    let mut builder = Download::builder(url).output(path);
+    if let Some(options) = unsafe { options.as_ref() } {
+        if options.connections > 0 {
+            builder = builder.connections(options.connections as usize);
+        }
+        if options.timeout_ms > 0 {
+            builder = builder.timeout(Duration::from_millis(options.timeout_ms as u64));
+        }
+        if !options.expected_sha256.is_null() {
+            let hex = unsafe { string(options.expected_sha256) }
+                .ok_or_else(|| invalid("expected_sha256 is not UTF-8"))?;
+            builder = builder.expected_sha256(hex);
+        }
+        if let Some(callback) = options.progress {
+            builder = builder.observer(CallbackObserver { callback, user_data: options.user_data });
+        }
+    }
+
+    builder.build()?.run()
+}
+
+unsafe fn string<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().ok()
+}
+
+// Copy as much of text as fits, always NUL terminating
+fn copy_str(out: &mut [c_char], text: &str) {
+    let len = text.len().min(out.len() - 1);
+    for (slot, &byte) in out.iter_mut().zip(&text.as_bytes()[..len]) {
+        *slot = byte as c_char;
+    }
+    out[len] = 0;
+}
@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::cancel::CancellationToken;
+use crate::download::{Download, Summary};
+
+// Download a URL to a file without blocking the calling task. Works with any
+// executor: the transfer runs on its own thread and wakes the task when done.
+pub async fn download(url: &str, output: impl AsRef<Path>) -> std::io::Result<Summary> {
+    Download::builder(url).output(output.as_ref()).build()?.run_async().await
+}
+
+// The result of Download::run_async. Dropping it before it completes
+// cancels the download.
+pub struct DownloadFuture {
+    shared: Arc<Mutex<Completion>>,
+    cancel: CancellationToken,
+}
+
+#[derive(Default)]
+struct Completion {
+    result: Option<std::io::Result<Summary>>,
+    waker: Option<Waker>,
+}
+
+impl DownloadFuture {
+    pub(crate) fn spawn(download: Download, cancel: CancellationToken) -> DownloadFuture {
+        let shared = Arc::new(Mutex::new(Completion::default()));
+        let completion = shared.clone();
+
+        std::thread::spawn(move || {
+            let result = download.run();
+            let mut completion = completion.lock().expect("no thread panics holding the completion");
+            completion.result = Some(result);
+            if let Some(waker) = completion.waker.take() {
+                waker.wake();
+            }
+        });
+
+        DownloadFuture { shared, cancel }
+    }
+}
+
+impl Future for DownloadFuture {
+    type Output = std::io::Result<Summary>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut completion = self.shared.lock().expect("no thread panics holding the completion");
+        match completion.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                completion.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for DownloadFuture {
+    fn drop(&mut self) {
+        let finished = self.shared.lock().map(|completion| completion.result.is_some()).unwrap_or(true);
+        if !finished {
+            self.cancel.cancel();
+        }
+    }
+}
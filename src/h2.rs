@@ -0,0 +1,552 @@
+// HTTP/2 over plain TCP with prior knowledge (RFC 9113): the connection opens
+// with the HTTP/2 preface rather than an upgrade, as curl's
+// --http2-prior-knowledge does, since without TLS there is no ALPN to agree
+// on h2 with. Every request is a stream, so one connection carries many at
+// once. Answers are handed back as HTTP/1.1-style bytes, a status line and
+// headers then the body, so the rest of the client reads them as it always
+// has.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpStream;
+
+use crate::cancel::CancellationToken;
+use crate::engine::MAX_CHUNK_SIZE;
+use crate::hpack;
+use crate::http::{Settings, ReadWatch};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+// Frame flags; ACK shares its bit with END_STREAM
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+// Settings
+const ENABLE_PUSH: u16 = 0x2;
+const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const INITIAL_WINDOW_SIZE: u16 = 0x4;
+const MAX_FRAME_SIZE: u16 = 0x5;
+
+// Error codes
+const REFUSED_STREAM: u32 = 0x7;
+const CANCEL: u32 = 0x8;
+
+// What each stream may be sent before the server waits for more room: the
+// largest range there is, so a range never waits on a window update. The
+// connection as a whole gets room for several.
+const STREAM_WINDOW: u32 = MAX_CHUNK_SIZE as u32;
+const CONNECTION_WINDOW: u32 = 4 * STREAM_WINDOW;
+
+// Largest frame the server may send us
+const MAX_FRAME: usize = 1 << 20;
+
+// What HTTP/2 starts every connection with until the server says otherwise
+const DEFAULT_WINDOW: i64 = 65_535;
+const DEFAULT_FRAME: usize = 16_384;
+
+pub(crate) struct Connection {
+    conn: TcpStream,
+    cancel: Option<CancellationToken>,
+    watch: ReadWatch,
+    decoder: hpack::Decoder,
+    next_stream: u32,
+    streams: HashMap<u32, Stream>,
+    // What the server allows: streams open at once, the largest frame it
+    // takes, and the room it starts each stream with
+    max_streams: usize,
+    max_frame: usize,
+    initial_window: i64,
+    // Room left to send request bodies in, over the whole connection
+    send_window: i64,
+    // Bytes received that haven't been given back to the window yet
+    unacknowledged: u32,
+    // Set by GOAWAY: streams past this one won't be answered
+    last_stream: Option<u32>,
+    // Whether the server's first frame is in
+    started: bool,
+}
+
+#[derive(Default)]
+struct Stream {
+    // The final header fields, once they are in
+    head: Option<Vec<(String, String)>>,
+    body: Vec<u8>,
+    ended: bool,
+    // Reset by the server, with this error code
+    reset: Option<u32>,
+    send_window: i64,
+    unacknowledged: u32,
+}
+
+fn protocol_error(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("HTTP/2: {}", message))
+}
+
+impl Connection {
+    // Start HTTP/2 on a fresh connection. The server's own settings are
+    // taken as they arrive, rather than waited for.
+    pub(crate) fn handshake(mut conn: TcpStream, settings: &Settings) -> std::io::Result<Connection> {
+        let mut start = PREFACE.to_vec();
+        let mut ours = Vec::new();
+        for (id, value) in [(ENABLE_PUSH, 0), (INITIAL_WINDOW_SIZE, STREAM_WINDOW), (MAX_FRAME_SIZE, MAX_FRAME as u32)] {
+            ours.extend_from_slice(&id.to_be_bytes());
+            ours.extend_from_slice(&value.to_be_bytes());
+        }
+        start.extend(frame(SETTINGS, 0, 0, &ours));
+        start.extend(frame(WINDOW_UPDATE, 0, 0, &(CONNECTION_WINDOW - DEFAULT_WINDOW as u32).to_be_bytes()));
+        conn.write_all(&start)?;
+        Ok(Connection {
+            conn,
+            cancel: settings.cancel.clone(),
+            watch: ReadWatch::new(settings),
+            decoder: hpack::Decoder::new(),
+            next_stream: 1,
+            streams: HashMap::new(),
+            max_streams: usize::MAX,
+            max_frame: DEFAULT_FRAME,
+            initial_window: DEFAULT_WINDOW,
+            send_window: DEFAULT_WINDOW,
+            unacknowledged: 0,
+            last_stream: None,
+            started: false,
+        })
+    }
+
+    // How many streams the server lets be open at once
+    pub(crate) fn max_streams(&self) -> usize {
+        self.max_streams
+    }
+
+    // Start a request on a new stream, sending the body as the server makes
+    // room for it. authority and path stand in for Host and the request
+    // target; fields are the other headers.
+    pub(crate) fn open(&mut self, method: &str, authority: &str, path: &str, fields: &[(&str, &str)], body: &[u8]) -> std::io::Result<u32> {
+        if self.last_stream.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "HTTP/2: the server is closing the connection"));
+        }
+        let id = self.next_stream;
+        self.next_stream += 2;
+        let mut all = vec![(":method", method), (":scheme", "http"), (":authority", authority), (":path", path)];
+        all.extend_from_slice(fields);
+        let block = hpack::encode(&all);
+
+        let mut pieces = block.chunks(self.max_frame).peekable();
+        let mut frames = Vec::new();
+        let mut kind = HEADERS;
+        let first_flags = if body.is_empty() { END_STREAM } else { 0 };
+        loop {
+            let piece = pieces.next().unwrap_or_default();
+            let last = pieces.peek().is_none();
+            let flags = if kind == HEADERS { first_flags } else { 0 } | if last { END_HEADERS } else { 0 };
+            frames.extend(frame(kind, flags, id, piece));
+            if last {
+                break;
+            }
+            kind = CONTINUATION;
+        }
+        self.conn.write_all(&frames)?;
+        self.streams.insert(id, Stream { send_window: self.initial_window, ..Stream::default() });
+
+        let mut sent = 0;
+        while sent < body.len() {
+            let room = self.streams.get(&id).map_or(0, |stream| stream.send_window).min(self.send_window);
+            if room <= 0 {
+                self.read_frame()?;
+                if self.streams.get(&id).is_none_or(|stream| stream.reset.is_some()) {
+                    return Ok(id);
+                }
+                continue;
+            }
+            let len = (body.len() - sent).min(room as usize).min(self.max_frame);
+            let flags = if sent + len == body.len() { END_STREAM } else { 0 };
+            self.conn.write_all(&frame(DATA, flags, id, &body[sent..sent + len]))?;
+            self.send_window -= len as i64;
+            if let Some(stream) = self.streams.get_mut(&id) {
+                stream.send_window -= len as i64;
+            }
+            sent += len;
+        }
+        Ok(id)
+    }
+
+    // The head of the stream's answer, leaving the body to come. None if the
+    // server turned the stream away unanswered, so it can be tried again.
+    pub(crate) fn head(&mut self, id: u32) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            let stream = self.streams.get(&id).ok_or_else(|| protocol_error("no such stream"))?;
+            if let Some(fields) = &stream.head {
+                return Ok(Some(http1_head(fields)));
+            }
+            if let Some(refused) = self.refused(id)? {
+                return Ok(refused);
+            }
+            self.read_frame()?;
+        }
+    }
+
+    // The stream's whole answer, head and body. None as for head.
+    pub(crate) fn response(&mut self, id: u32) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            let stream = self.streams.get(&id).ok_or_else(|| protocol_error("no such stream"))?;
+            if stream.ended {
+                let stream = self.streams.remove(&id).expect("looked up above");
+                let fields = stream.head.ok_or_else(|| protocol_error("stream ended without a response"))?;
+                let mut response = http1_head(&fields);
+                response.extend(stream.body);
+                return Ok(Some(response));
+            }
+            if let Some(refused) = self.refused(id)? {
+                return Ok(refused);
+            }
+            self.read_frame()?;
+        }
+    }
+
+    // Stop the stream, if the server hasn't finished it already
+    pub(crate) fn cancel(&mut self, id: u32) -> std::io::Result<()> {
+        if self.streams.remove(&id).is_some_and(|stream| !stream.ended) {
+            self.conn.write_all(&frame(RST_STREAM, 0, id, &CANCEL.to_be_bytes()))?;
+        }
+        Ok(())
+    }
+
+    // Some(None) when the server won't answer the stream but another try
+    // might do; an error when it reset the stream for any other reason
+    fn refused(&mut self, id: u32) -> std::io::Result<Option<Option<Vec<u8>>>> {
+        let stream = &self.streams[&id];
+        let past_goaway = self.last_stream.is_some_and(|last| id > last);
+        match stream.reset {
+            _ if past_goaway => {}
+            Some(REFUSED_STREAM) => {}
+            Some(code) => return Err(protocol_error(&format!("the server reset the stream (error {})", code))),
+            None => return Ok(None),
+        }
+        self.streams.remove(&id);
+        Ok(Some(None))
+    }
+
+    // Read one frame and act on it, along with any CONTINUATION frames
+    // finishing a header block
+    fn read_frame(&mut self) -> std::io::Result<()> {
+        if self.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        let (kind, flags, id, payload) = self.read_raw()?;
+        match kind {
+            DATA => {
+                let data = unpad(flags, &payload)?;
+                let len = payload.len() as u32;
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.body.extend_from_slice(data);
+                    stream.ended |= flags & END_STREAM != 0;
+                    stream.unacknowledged += len;
+                    if !stream.ended && stream.unacknowledged >= STREAM_WINDOW / 2 {
+                        let increment = std::mem::take(&mut stream.unacknowledged);
+                        self.conn.write_all(&frame(WINDOW_UPDATE, 0, id, &increment.to_be_bytes()))?;
+                    }
+                }
+                self.unacknowledged += len;
+                if self.unacknowledged >= CONNECTION_WINDOW / 2 {
+                    let increment = std::mem::take(&mut self.unacknowledged);
+                    self.conn.write_all(&frame(WINDOW_UPDATE, 0, 0, &increment.to_be_bytes()))?;
+                }
+            }
+            HEADERS => {
+                let mut block = unpad(flags, &payload)?;
+                if flags & PRIORITY != 0 {
+                    block = block.get(5..).ok_or_else(|| protocol_error("short HEADERS frame"))?;
+                }
+                let mut block = block.to_vec();
+                let mut done = flags & END_HEADERS != 0;
+                while !done {
+                    let (kind, more_flags, more_id, more) = self.read_raw()?;
+                    if kind != CONTINUATION || more_id != id {
+                        return Err(protocol_error("header block interrupted"));
+                    }
+                    block.extend(more);
+                    done = more_flags & END_HEADERS != 0;
+                }
+                // Decoded even for streams that are gone, to keep the table in step
+                let fields = self.decoder.decode(&block)?;
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    let status = fields.iter().find(|(name, _)| name == ":status").map(|(_, value)| value.as_str());
+                    let interim = status.is_some_and(|status| status.starts_with('1') && status != "101");
+                    if stream.head.is_none() && !interim {
+                        stream.head = Some(fields);
+                    }
+                    stream.ended |= flags & END_STREAM != 0;
+                }
+            }
+            RST_STREAM => {
+                let code = payload.get(..4).ok_or_else(|| protocol_error("short RST_STREAM frame"))?;
+                if let Some(stream) = self.streams.get_mut(&id) {
+                    stream.reset = Some(u32::from_be_bytes(code.try_into().expect("four bytes")));
+                }
+            }
+            SETTINGS if flags & ACK == 0 => {
+                for setting in payload.chunks_exact(6) {
+                    let value = u32::from_be_bytes(setting[2..6].try_into().expect("four bytes"));
+                    match u16::from_be_bytes([setting[0], setting[1]]) {
+                        MAX_CONCURRENT_STREAMS => self.max_streams = value.max(1) as usize,
+                        INITIAL_WINDOW_SIZE => {
+                            let change = i64::from(value) - self.initial_window;
+                            self.initial_window = i64::from(value);
+                            for stream in self.streams.values_mut() {
+                                stream.send_window += change;
+                            }
+                        }
+                        MAX_FRAME_SIZE => self.max_frame = value as usize,
+                        _ => {}
+                    }
+                }
+                self.conn.write_all(&frame(SETTINGS, ACK, 0, &[]))?;
+            }
+            PING if flags & ACK == 0 => self.conn.write_all(&frame(PING, ACK, 0, &payload))?,
+            GOAWAY => {
+                let last = payload.get(..4).ok_or_else(|| protocol_error("short GOAWAY frame"))?;
+                self.last_stream = Some(u32::from_be_bytes(last.try_into().expect("four bytes")) & 0x7fff_ffff);
+            }
+            WINDOW_UPDATE => {
+                let increment = payload.get(..4).ok_or_else(|| protocol_error("short WINDOW_UPDATE frame"))?;
+                let increment = i64::from(u32::from_be_bytes(increment.try_into().expect("four bytes")) & 0x7fff_ffff);
+                match self.streams.get_mut(&id) {
+                    _ if id == 0 => self.send_window += increment,
+                    Some(stream) => stream.send_window += increment,
+                    None => {}
+                }
+            }
+            PUSH_PROMISE => return Err(protocol_error("server push was turned off")),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // The next frame's type, flags, stream and payload
+    fn read_raw(&mut self) -> std::io::Result<(u8, u8, u32, Vec<u8>)> {
+        let mut header = [0; 9];
+        self.fill(&mut header)?;
+        // A server's side of the connection opens with its SETTINGS
+        if !self.started && header[3] != SETTINGS {
+            return Err(protocol_error("the server did not answer in HTTP/2"));
+        }
+        self.started = true;
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        if len > MAX_FRAME {
+            return Err(protocol_error("frame larger than allowed"));
+        }
+        let id = u32::from_be_bytes(header[5..9].try_into().expect("four bytes")) & 0x7fff_ffff;
+        let mut payload = vec![0; len];
+        self.fill(&mut payload)?;
+        Ok((header[3], header[4], id, payload))
+    }
+
+    // Read exactly enough to fill the buffer, watching the speed
+    fn fill(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match self.watch.read(&mut self.conn, &mut buffer[filled..])? {
+                0 => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed in the middle of a frame")),
+                n => filled += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn frame(kind: u8, flags: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(9 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+    frame.extend_from_slice(&[kind, flags]);
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+// A DATA or HEADERS payload without its padding
+fn unpad(flags: u8, payload: &[u8]) -> std::io::Result<&[u8]> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let pad = *payload.first().ok_or_else(|| protocol_error("empty padded frame"))? as usize;
+    payload.get(1..payload.len().saturating_sub(pad)).filter(|_| pad < payload.len()).ok_or_else(|| protocol_error("too much padding"))
+}
+
+// The fields as an HTTP/1.1-style head, "HTTP/2 206" and all
+fn http1_head(fields: &[(String, String)]) -> Vec<u8> {
+    let status = fields.iter().find(|(name, _)| name == ":status").map_or("0", |(_, value)| value.as_str());
+    let mut head = format!("HTTP/2 {}\r\n", status);
+    for (name, value) in fields.iter().filter(|(name, _)| !name.starts_with(':')) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
+
+// How a request goes out, for the trace and the HAR log: an HTTP/1.1-style
+// head with the pseudo-headers as a request line and Host
+pub(crate) fn describe(method: &str, authority: &str, path: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut text = format!("{} {} HTTP/2\r\nhost: {}\r\n", method, path, authority);
+    for (name, value) in fields {
+        text.push_str(&format!("{}: {}\r\n", name.to_ascii_lowercase(), value));
+    }
+    text.push_str("\r\n");
+    text.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    // A connection to a server that sends the frames, then reads whatever
+    // the client sends until it hangs up. What the client sent comes back
+    // from the thread.
+    fn serve(frames: Vec<Vec<u8>>) -> (Connection, std::thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            conn.write_all(&frames.concat()).unwrap();
+            let mut received = Vec::new();
+            conn.read_to_end(&mut received).unwrap();
+            received
+        });
+        let connection = Connection::handshake(TcpStream::connect(address).unwrap(), &Settings::default()).unwrap();
+        (connection, server)
+    }
+
+    fn settings(pairs: &[(u16, u32)]) -> Vec<u8> {
+        let payload: Vec<u8> = pairs.iter().flat_map(|(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()].concat()).collect();
+        frame(SETTINGS, 0, 0, &payload)
+    }
+
+    // The frames in bytes sent, in order, after the preface
+    fn frames(mut bytes: &[u8]) -> Vec<(u8, u8, u32, Vec<u8>)> {
+        bytes = bytes.strip_prefix(PREFACE).unwrap();
+        let mut frames = Vec::new();
+        while bytes.len() >= 9 {
+            let len = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+            let id = u32::from_be_bytes(bytes[5..9].try_into().unwrap());
+            frames.push((bytes[3], bytes[4], id, bytes[9..9 + len].to_vec()));
+            bytes = &bytes[9 + len..];
+        }
+        frames
+    }
+
+    #[test]
+    fn frames_have_a_nine_byte_header() {
+        assert_eq!(frame(PING, ACK, 0, b"12345678"), b"\x00\x00\x08\x06\x01\x00\x00\x00\x0012345678");
+        assert_eq!(frame(DATA, END_STREAM, 3, b""), b"\x00\x00\x00\x00\x01\x00\x00\x00\x03");
+    }
+
+    #[test]
+    fn padding_comes_off() {
+        assert_eq!(unpad(0, b"\x02ab").unwrap(), b"\x02ab");
+        assert_eq!(unpad(PADDED, b"\x02abcd").unwrap(), b"ab");
+        assert_eq!(unpad(PADDED, b"\x00ab").unwrap(), b"ab");
+        assert!(unpad(PADDED, b"").is_err());
+        assert!(unpad(PADDED, b"\x03ab").is_err());
+    }
+
+    #[test]
+    fn heads_read_as_http1() {
+        let fields = vec![(":status".to_string(), "206".to_string()), ("content-range".to_string(), "bytes 0-1/2".to_string())];
+        assert_eq!(http1_head(&fields), b"HTTP/2 206\r\ncontent-range: bytes 0-1/2\r\n\r\n");
+        assert_eq!(describe("GET", "example.com", "/a", &[("Range", "bytes=0-")]), b"GET /a HTTP/2\r\nhost: example.com\r\nrange: bytes=0-\r\n\r\n");
+    }
+
+    #[test]
+    fn a_request_and_its_answer() {
+        let head = hpack::encode(&[(":status", "200"), ("content-type", "text/plain")]);
+        let (split_at, padded) = (3, [&[4][..], b"hello", &[0; 4]].concat());
+        let (mut connection, server) = serve(vec![
+            settings(&[(MAX_CONCURRENT_STREAMS, 10)]),
+            frame(HEADERS, 0, 1, &head[..split_at]),
+            frame(CONTINUATION, END_HEADERS, 1, &head[split_at..]),
+            frame(DATA, PADDED, 1, &padded),
+            frame(DATA, END_STREAM, 1, b" world"),
+        ]);
+        let id = connection.open("GET", "example.com", "/", &[("Accept", "*/*")], b"").unwrap();
+        assert_eq!(id, 1);
+        let response = connection.response(id).unwrap().unwrap();
+        assert_eq!(response, b"HTTP/2 200\r\ncontent-type: text/plain\r\n\r\nhello world");
+        assert_eq!(connection.max_streams(), 10);
+        drop(connection);
+
+        let sent = frames(&server.join().unwrap());
+        assert_eq!(sent[0].0, SETTINGS);
+        assert_eq!(sent[1].0, WINDOW_UPDATE);
+        let (kind, flags, stream, block) = &sent[2];
+        assert_eq!((*kind, *flags, *stream), (HEADERS, END_STREAM | END_HEADERS, 1));
+        let request = hpack::Decoder::new().decode(block).unwrap();
+        let request: Vec<(&str, &str)> = request.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        assert_eq!(request, [(":method", "GET"), (":scheme", "http"), (":authority", "example.com"), (":path", "/"), ("accept", "*/*")]);
+        assert_eq!(&sent[3], &(SETTINGS, ACK, 0, Vec::new()));
+    }
+
+    #[test]
+    fn request_bodies_wait_for_window() {
+        let (mut connection, server) = serve(vec![
+            settings(&[]),
+            frame(WINDOW_UPDATE, 0, 0, &10_000u32.to_be_bytes()),
+            frame(WINDOW_UPDATE, 0, 1, &10_000u32.to_be_bytes()),
+            frame(HEADERS, END_HEADERS | END_STREAM, 1, &hpack::encode(&[(":status", "204")])),
+        ]);
+        let body = vec![b'x'; 70_000];
+        let id = connection.open("PUT", "example.com", "/", &[], &body).unwrap();
+        assert_eq!(connection.response(id).unwrap().unwrap(), b"HTTP/2 204\r\n\r\n");
+        drop(connection);
+
+        // The default window and frame size, then the rest once let
+        let data: Vec<_> = frames(&server.join().unwrap()).into_iter().filter(|frame| frame.0 == DATA).collect();
+        let sizes: Vec<_> = data.iter().map(|frame| frame.3.len()).collect();
+        assert_eq!(sizes, [16_384, 16_384, 16_384, 16_383, 4_465]);
+        let flags: Vec<_> = data.iter().map(|frame| frame.1).collect();
+        assert_eq!(flags, [0, 0, 0, 0, END_STREAM]);
+    }
+
+    #[test]
+    fn refused_streams_can_be_tried_again() {
+        let (mut connection, _server) = serve(vec![settings(&[]), frame(RST_STREAM, 0, 1, &REFUSED_STREAM.to_be_bytes())]);
+        let id = connection.open("GET", "example.com", "/", &[], b"").unwrap();
+        assert_eq!(connection.head(id).unwrap(), None);
+
+        let (mut connection, _server) = serve(vec![settings(&[]), frame(RST_STREAM, 0, 1, &CANCEL.to_be_bytes())]);
+        let id = connection.open("GET", "example.com", "/", &[], b"").unwrap();
+        assert_eq!(connection.head(id).unwrap_err().to_string(), "HTTP/2: the server reset the stream (error 8)");
+    }
+
+    #[test]
+    fn goaway_turns_away_later_streams() {
+        let (mut connection, _server) = serve(vec![settings(&[]), frame(GOAWAY, 0, 0, &[0, 0, 0, 0, 0, 0, 0, 0])]);
+        let id = connection.open("GET", "example.com", "/", &[], b"").unwrap();
+        assert_eq!(connection.response(id).unwrap(), None);
+        assert_eq!(connection.open("GET", "example.com", "/", &[], b"").unwrap_err().kind(), std::io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn servers_that_dont_speak_http2_are_refused() {
+        let (mut connection, _server) = serve(vec![b"HTTP/1.1 400 Bad Request\r\n\r\n".to_vec()]);
+        let id = connection.open("GET", "example.com", "/", &[], b"").unwrap();
+        assert_eq!(connection.head(id).unwrap_err().to_string(), "HTTP/2: the server did not answer in HTTP/2");
+
+        let (mut connection, _server) = serve(vec![settings(&[]), frame(PUSH_PROMISE, END_HEADERS, 1, &[0, 0, 0, 2])]);
+        let id = connection.open("GET", "example.com", "/", &[], b"").unwrap();
+        assert_eq!(connection.head(id).unwrap_err().to_string(), "HTTP/2: server push was turned off");
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::download::{State, Summary};
+
+// Control over a download running in the background, from Download::start
+pub struct DownloadHandle {
+    state: Arc<State>,
+    cancel: CancellationToken,
+    started: Instant,
+    thread: JoinHandle<std::io::Result<Summary>>,
+}
+
+// A snapshot of how a running download is doing
+#[derive(Clone, Debug)]
+pub struct Stats {
+    pub downloaded: u64,
+    // None until the server has sent the size
+    pub total_size: Option<u64>,
+    pub elapsed: Duration,
+    pub paused: bool,
+}
+
+impl Stats {
+    // Average speed since the download started, pauses included
+    pub fn bytes_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 { self.downloaded as f64 / seconds } else { 0.0 }
+    }
+}
+
+impl DownloadHandle {
+    pub(crate) fn new(
+        state: Arc<State>,
+        cancel: CancellationToken,
+        thread: JoinHandle<std::io::Result<Summary>>,
+    ) -> DownloadHandle {
+        DownloadHandle { state, cancel, started: Instant::now(), thread }
+    }
+
+    // Stop requesting new chunks. Requests already on the wire complete, and
+    // everything received so far is kept.
+    pub fn pause(&self) {
+        self.set_paused(true);
+    }
+
+    pub fn resume(&self) {
+        self.set_paused(false);
+    }
+
+    // Stop the download; join() then returns a Cancelled error
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+        // Wake paused workers so they notice
+        let _paused = self.state.paused.lock().expect("no thread panics holding the pause flag");
+        self.state.resumed.notify_all();
+    }
+
+    pub fn stats(&self) -> Stats {
+        let total_size = self.state.total_size.load(Ordering::Relaxed);
+        Stats {
+            downloaded: self.state.downloaded.load(Ordering::Relaxed),
+            total_size: (total_size > 0).then_some(total_size),
+            elapsed: self.started.elapsed(),
+            paused: *self.state.paused.lock().expect("no thread panics holding the pause flag"),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    // Wait for the download to end and return its result
+    pub fn join(self) -> std::io::Result<Summary> {
+        self.thread.join().expect("download thread panicked")
+    }
+
+    fn set_paused(&self, paused: bool) {
+        *self.state.paused.lock().expect("no thread panics holding the pause flag") = paused;
+        self.state.resumed.notify_all();
+    }
+}
@@ -0,0 +1,167 @@
+// Recording of every request and response in HTTP Archive (HAR 1.2) form,
+// which browser devtools and HAR viewers can open.
+
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::http::ResponseHead;
+use crate::json;
+use crate::time;
+use crate::timing::{Clock, Phases};
+use crate::url::Url;
+
+// The entries recorded so far. Clones share them, so one log can be given to
+// every connection of a session.
+#[derive(Clone, Debug, Default)]
+pub struct HarLog {
+    entries: Arc<Mutex<Vec<Entry>>>,
+}
+
+// One request and its response
+#[derive(Debug)]
+struct Entry {
+    started: SystemTime,
+    url: String,
+    query: Vec<(String, String)>,
+    request: Message,
+    response: Message,
+    status: u16,
+    status_text: String,
+    mime_type: String,
+    timings: Phases,
+}
+
+// The head of a request or response and the size of what followed it
+#[derive(Debug)]
+struct Message {
+    first_line: String,
+    headers: Vec<(String, String)>,
+    headers_size: usize,
+    body_size: usize,
+}
+
+impl HarLog {
+    pub fn new() -> HarLog {
+        HarLog::default()
+    }
+
+    // Add an exchange, given the request and response as they went over the wire
+    pub(crate) fn record(&self, url: &Url, request: &[u8], response: &[u8], clock: &Clock) {
+        let request = Message::parse(request);
+        let head = ResponseHead::parse(response);
+        let response = Message::parse(response);
+
+        let status_text = response.first_line.splitn(3, ' ').nth(2).unwrap_or_default().to_string();
+        let query = match url.path.split_once('?') {
+            Some((_, query)) => query.split('&')
+                .filter(|pair| !pair.is_empty())
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                    (name.to_string(), value.to_string())
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let entry = Entry {
+            started: clock.started,
+            url: url.to_string(),
+            query,
+            status: head.status,
+            status_text,
+            mime_type: head.header("Content-Type").unwrap_or_default().to_string(),
+            request,
+            response,
+            timings: clock.phases(),
+        };
+        self.entries.lock().expect("no thread panics holding the HAR log").push(entry);
+    }
+
+    // The log as HAR 1.2 JSON
+    pub fn to_json(&self) -> String {
+        let entries = self.entries.lock().expect("no thread panics holding the HAR log");
+        let mut json = format!(
+            "{{\"log\":{{\"version\":\"1.2\",\"creator\":{{\"name\":\"http_client\",\"version\":\"{}\"}},\"entries\":[",
+            env!("CARGO_PKG_VERSION")
+        );
+        for (n, entry) in entries.iter().enumerate() {
+            if n > 0 {
+                json.push(',');
+            }
+            json.push('\n');
+            entry.write_json(&mut json);
+        }
+        json.push_str("\n]}}\n");
+        json
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
+
+impl Message {
+    // Read the head of a request or response, up to the blank line
+    fn parse(message: &[u8]) -> Message {
+        let (head_len, headers_size) = match message.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => (end, end + 4),
+            None => (message.len(), message.len()),
+        };
+        let text = String::from_utf8_lossy(&message[..head_len]);
+        let mut lines = text.split("\r\n");
+        let first_line = lines.next().unwrap_or_default().to_string();
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        Message { first_line, headers, headers_size, body_size: message.len() - headers_size }
+    }
+
+    // HTTP version from the request line or status line
+    fn version(&self, is_request: bool) -> &str {
+        let mut words = self.first_line.split(' ');
+        let version = if is_request { words.nth(2) } else { words.next() };
+        version.unwrap_or("HTTP/1.1")
+    }
+}
+
+impl Entry {
+    fn write_json(&self, json: &mut String) {
+        let ms = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        let t = &self.timings;
+        let method = self.request.first_line.split(' ').next().unwrap_or("GET");
+
+        json.push_str(&format!(
+            "{{\"startedDateTime\":{},\"time\":{},",
+            json::quote(&time::format_iso8601(self.started)), ms(t.total())
+        ));
+        json.push_str(&format!(
+            "\"request\":{{\"method\":{},\"url\":{},\"httpVersion\":{},\"cookies\":[],\"headers\":{},\
+             \"queryString\":{},\"headersSize\":{},\"bodySize\":{}}},",
+            json::quote(method), json::quote(&self.url), json::quote(self.request.version(true)),
+            pairs(&self.request.headers), pairs(&self.query),
+            self.request.headers_size, self.request.body_size
+        ));
+        json.push_str(&format!(
+            "\"response\":{{\"status\":{},\"statusText\":{},\"httpVersion\":{},\"cookies\":[],\"headers\":{},\
+             \"content\":{{\"size\":{},\"mimeType\":{}}},\"redirectURL\":{},\"headersSize\":{},\"bodySize\":{}}},",
+            self.status, json::quote(&self.status_text), json::quote(self.response.version(false)),
+            pairs(&self.response.headers), self.response.body_size, json::quote(&self.mime_type),
+            json::quote(self.response.headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("Location")).map_or("", |(_, value)| value)),
+            self.response.headers_size, self.response.body_size
+        ));
+        json.push_str(&format!(
+            "\"cache\":{{}},\"timings\":{{\"blocked\":-1,\"dns\":{},\"ssl\":-1,\"connect\":{},\"send\":{},\"wait\":{},\"receive\":{}}}}}",
+            ms(t.dns), ms(t.connect), ms(t.send), ms(t.wait), ms(t.transfer)
+        ));
+    }
+}
+
+// Name/value pairs as a JSON array of objects
+fn pairs(pairs: &[(String, String)]) -> String {
+    let objects: Vec<String> = pairs.iter()
+        .map(|(name, value)| format!("{{\"name\":{},\"value\":{}}}", json::quote(name), json::quote(value)))
+        .collect();
+    format!("[{}]", objects.join(","))
+}
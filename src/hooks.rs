@@ -0,0 +1,74 @@
+use std::process::Stdio;
+use std::sync::atomic::Ordering;
+
+use crate::exec;
+use crate::url::Url;
+use crate::Outcome;
+
+// Run the pre-download hook. It sees HTTP_CLIENT_EVENT=pre and HTTP_CLIENT_URL.
+pub fn pre(command: &str, url: &Url) -> std::io::Result<()> {
+    run(command, &[("HTTP_CLIENT_EVENT", "pre".to_string()), ("HTTP_CLIENT_URL", url.to_string())])
+        .map_err(|e| std::io::Error::new(e.kind(), format!("pre-hook {}", e)))
+}
+
+// Run the post-download hook, describing how the download went:
+// HTTP_CLIENT_STATUS is saved, piped, skipped or failed, and HTTP_CLIENT_OUTPUT,
+// HTTP_CLIENT_SHA256 and HTTP_CLIENT_ERROR are set when they apply.
+pub fn post(command: &str, url: &Url, result: &std::io::Result<Outcome>) -> std::io::Result<()> {
+    let mut env = vec![("HTTP_CLIENT_EVENT", "post".to_string()), ("HTTP_CLIENT_URL", url.to_string())];
+
+    match result {
+        Ok(Outcome::Saved { path, sha256 }) => {
+            env.push(("HTTP_CLIENT_STATUS", "saved".to_string()));
+            env.push(("HTTP_CLIENT_OUTPUT", path.display().to_string()));
+            env.push(("HTTP_CLIENT_SHA256", sha256.clone()));
+        }
+        Ok(Outcome::Piped { sha256 }) => {
+            env.push(("HTTP_CLIENT_STATUS", "piped".to_string()));
+            env.push(("HTTP_CLIENT_SHA256", sha256.clone()));
+        }
+        Ok(Outcome::Skipped) => env.push(("HTTP_CLIENT_STATUS", "skipped".to_string())),
+        Err(e) => {
+            env.push(("HTTP_CLIENT_STATUS", "failed".to_string()));
+            env.push(("HTTP_CLIENT_ERROR", e.to_string()));
+        }
+    }
+
+    run(command, &env)
+}
+
+// Run the --refresh-cmd command for a new URL in place of one that is refused.
+// It sees HTTP_CLIENT_EVENT=refresh and HTTP_CLIENT_URL, the URL as first
+// given, and prints the new URL.
+pub fn refresh(command: &str, url: &Url) -> std::io::Result<Url> {
+    let output = exec::shell(command)
+        .envs([("HTTP_CLIENT_EVENT", "refresh".to_string()), ("HTTP_CLIENT_URL", url.to_string())])
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", command, e)))?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(exec::Failed { command: command.to_string(), code: output.status.code() }.to_string()));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.lines().map(str::trim).find(|line| !line.is_empty())
+        .ok_or(std::io::Error::other(format!("{} printed no URL", command)))?;
+    Url::parse(line).map_err(std::io::Error::other)
+}
+
+fn run(command: &str, env: &[(&str, String)]) -> std::io::Result<()> {
+    let mut shell = exec::shell(command);
+    shell.envs(env.iter().map(|(key, value)| (key, value)));
+    // Keep the hook's output out of a body that is going to stdout
+    if crate::STATUS_TO_STDERR.load(Ordering::Relaxed) {
+        shell.stdout(Stdio::from(std::io::stderr()));
+    }
+
+    let status = shell.status()
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", command, e)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        // Not passed on as the exit status, that is only done for --exec
+        Err(std::io::Error::other(exec::Failed { command: command.to_string(), code: status.code() }.to_string()))
+    }
+}
@@ -0,0 +1,39 @@
+// Host names mapped to addresses in the /etc/hosts format: an address, then
+// the names it is for, with "#" starting a comment. Lines that don't parse
+// are skipped, as resolvers skip them.
+
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct Hosts {
+    entries: Vec<(String, IpAddr)>,
+}
+
+impl Hosts {
+    pub fn parse(text: &str) -> Hosts {
+        let mut entries = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(Ok(address)) = fields.next().map(str::parse::<IpAddr>) else {
+                continue;
+            };
+            entries.extend(fields.map(|name| (name.to_ascii_lowercase(), address)));
+        }
+        Hosts { entries }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Hosts> {
+        Ok(Hosts::parse(&std::fs::read_to_string(path)?))
+    }
+
+    // Every address listed for the host, in the order of the file; empty
+    // if it isn't there
+    pub fn addresses(&self, host: &str) -> Vec<IpAddr> {
+        self.entries.iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, address)| *address)
+            .collect()
+    }
+}
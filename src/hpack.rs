@@ -0,0 +1,453 @@
+// HPACK (RFC 7541), the header compression of HTTP/2. Requests are written
+// as literals the server is asked not to index, which needs no state; the
+// server's headers are read with the static and dynamic tables and Huffman
+// coding it may use.
+
+use std::collections::VecDeque;
+use std::sync::OnceLock;
+
+// Indexes 1 to 61
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+// Bit lengths of the Huffman codes for bytes 0 to 255 and for the end of a
+// string, 256. The code is canonical: codes of one length are consecutive,
+// in symbol order, so these are all it takes to rebuild it.
+const HUFFMAN_LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+// Symbols in code order, and how many codes there are of each length
+struct Huffman {
+    symbols: Vec<u16>,
+    counts: [u32; 31],
+}
+
+fn huffman() -> &'static Huffman {
+    static HUFFMAN: OnceLock<Huffman> = OnceLock::new();
+    HUFFMAN.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..257).collect();
+        symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[symbol as usize]);
+        let mut counts = [0; 31];
+        for &length in &HUFFMAN_LENGTHS {
+            counts[length as usize] += 1;
+        }
+        Huffman { symbols, counts }
+    })
+}
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Bad HPACK header block: {}", message))
+}
+
+// A header block of the fields, each a literal the server shouldn't add to
+// its table, names in lower case as HTTP/2 wants
+pub(crate) fn encode(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in fields {
+        block.push(0);
+        encode_string(&mut block, name.to_ascii_lowercase().as_bytes());
+        encode_string(&mut block, value.as_bytes());
+    }
+    block
+}
+
+// A string literal without Huffman coding
+fn encode_string(block: &mut Vec<u8>, bytes: &[u8]) {
+    encode_integer(block, 7, 0, bytes.len());
+    block.extend_from_slice(bytes);
+}
+
+// An integer in the low prefix bits of a byte starting with flags, then in
+// 7-bit groups as needed
+fn encode_integer(block: &mut Vec<u8>, prefix: u32, flags: u8, mut value: usize) {
+    let max = (1 << prefix) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+    block.push(flags | max as u8);
+    value -= max;
+    while value >= 128 {
+        block.push((value % 128) as u8 | 128);
+        value /= 128;
+    }
+    block.push(value as u8);
+}
+
+// Reads the header blocks of one connection, keeping its dynamic table
+pub(crate) struct Decoder {
+    table: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Decoder {
+    pub(crate) fn new() -> Decoder {
+        Decoder { table: VecDeque::new(), size: 0, max_size: 4096 }
+    }
+
+    // The name and value of every field in the block, in order
+    pub(crate) fn decode(&mut self, block: &[u8]) -> std::io::Result<Vec<(String, String)>> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < block.len() {
+            let byte = block[pos];
+            if byte & 0x80 != 0 {
+                // Indexed field
+                let index = decode_integer(block, &mut pos, 7)?;
+                fields.push(self.entry(index)?);
+            } else if byte & 0xe0 == 0x20 {
+                // Dynamic table size update
+                self.max_size = decode_integer(block, &mut pos, 5)?;
+                self.evict(0);
+            } else {
+                // Literal, added to the table (01), or not (0000 and 0001)
+                let indexed = byte & 0x40 != 0;
+                let index = decode_integer(block, &mut pos, if indexed { 6 } else { 4 })?;
+                let name = match index {
+                    0 => decode_string(block, &mut pos)?,
+                    index => self.entry(index)?.0,
+                };
+                let value = decode_string(block, &mut pos)?;
+                if indexed {
+                    self.insert(name.clone(), value.clone());
+                }
+                fields.push((name, value));
+            }
+        }
+        Ok(fields)
+    }
+
+    fn entry(&self, index: usize) -> std::io::Result<(String, String)> {
+        match index {
+            0 => Err(invalid("index 0")),
+            1..=61 => {
+                let (name, value) = STATIC_TABLE[index - 1];
+                Ok((name.to_string(), value.to_string()))
+            }
+            _ => self.table.get(index - 62).cloned().ok_or_else(|| invalid("index past the table")),
+        }
+    }
+
+    // Entries count their name and value and 32 bytes besides
+    fn insert(&mut self, name: String, value: String) {
+        let size = name.len() + value.len() + 32;
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.table.push_front((name, value));
+        }
+    }
+
+    // Drop the oldest entries until there is room for size more bytes
+    fn evict(&mut self, size: usize) {
+        while self.size + size > self.max_size {
+            let Some((name, value)) = self.table.pop_back() else {
+                break;
+            };
+            self.size -= name.len() + value.len() + 32;
+        }
+    }
+}
+
+fn decode_integer(block: &[u8], pos: &mut usize, prefix: u32) -> std::io::Result<usize> {
+    let max = (1usize << prefix) - 1;
+    let first = *block.get(*pos).ok_or_else(|| invalid("truncated integer"))?;
+    *pos += 1;
+    let mut value = first as usize & max;
+    if value < max {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*pos).ok_or_else(|| invalid("truncated integer"))?;
+        *pos += 1;
+        if shift > 28 {
+            return Err(invalid("integer too large"));
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn decode_string(block: &[u8], pos: &mut usize) -> std::io::Result<String> {
+    let huffman_coded = block.get(*pos).is_some_and(|byte| byte & 0x80 != 0);
+    let len = decode_integer(block, pos, 7)?;
+    let bytes = block.get(*pos..pos.saturating_add(len)).ok_or_else(|| invalid("truncated string"))?;
+    *pos += len;
+    let bytes = if huffman_coded { huffman_decode(bytes)? } else { bytes.to_vec() };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Walk the bits, matching a code as soon as the bits so far make one. What
+// is left at the end has to be fewer than 8 one bits, the start of the
+// end-of-string code.
+fn huffman_decode(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let huffman = huffman();
+    let mut decoded = Vec::new();
+    let (mut code, mut length, mut first, mut offset) = (0u32, 0, 0u32, 0);
+    for byte in bytes {
+        for bit in (0..8).rev() {
+            code = (code << 1) | u32::from(byte >> bit & 1);
+            length += 1;
+            if length > 30 {
+                return Err(invalid("bad Huffman code"));
+            }
+            let count = huffman.counts[length];
+            if code - first < count {
+                match huffman.symbols[offset + (code - first) as usize] {
+                    256 => return Err(invalid("end of string inside a string")),
+                    symbol => decoded.push(symbol as u8),
+                }
+                (code, length, first, offset) = (0, 0, 0, 0);
+            } else {
+                offset += count as usize;
+                first = (first + count) << 1;
+            }
+        }
+    }
+    if length >= 8 || code != (1 << length) - 1 {
+        return Err(invalid("bad Huffman padding"));
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The bytes of hex digits, spaces ignored, as RFC 7541 prints its examples
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    // The dynamic table, newest first, and its size
+    fn table(decoder: &Decoder) -> (Vec<(String, String)>, usize) {
+        (decoder.table.iter().cloned().collect(), decoder.size)
+    }
+
+    #[test]
+    fn integers_fill_the_prefix_then_seven_bits_at_a_time() {
+        // C.1.1 to C.1.3
+        for (value, prefix, encoded) in [(10, 5, "0a"), (1337, 5, "1f9a0a"), (42, 8, "2a")] {
+            let mut block = Vec::new();
+            encode_integer(&mut block, prefix, 0, value);
+            assert_eq!(block, hex(encoded));
+            assert_eq!(decode_integer(&block, &mut 0, prefix).unwrap(), value);
+        }
+        assert!(decode_integer(&hex("1f9a"), &mut 0, 5).is_err());
+        assert!(decode_integer(&hex("1fffffffffff7f"), &mut 0, 5).is_err());
+    }
+
+    #[test]
+    fn literal_fields() {
+        // C.2.1: added to the table
+        let mut decoder = Decoder::new();
+        let block = hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572");
+        assert_eq!(decoder.decode(&block).unwrap(), fields(&[("custom-key", "custom-header")]));
+        assert_eq!(table(&decoder), (fields(&[("custom-key", "custom-header")]), 55));
+
+        // C.2.2 and C.2.3: not added, and never to be
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&hex("040c 2f73 616d 706c 652f 7061 7468")).unwrap(), fields(&[(":path", "/sample/path")]));
+        assert_eq!(decoder.decode(&hex("1008 7061 7373 776f 7264 0673 6563 7265 74")).unwrap(), fields(&[("password", "secret")]));
+        assert_eq!(table(&decoder), (Vec::new(), 0));
+
+        // C.2.4: from the static table
+        assert_eq!(decoder.decode(&hex("82")).unwrap(), fields(&[(":method", "GET")]));
+    }
+
+    // C.3 and C.4: the same three requests, each sharing a table with the
+    // ones before
+    fn requests(blocks: [&str; 3]) {
+        let mut decoder = Decoder::new();
+        let authority = (":authority", "www.example.com");
+        assert_eq!(decoder.decode(&hex(blocks[0])).unwrap(), fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), authority]));
+        assert_eq!(table(&decoder), (fields(&[authority]), 57));
+
+        let cache = ("cache-control", "no-cache");
+        assert_eq!(decoder.decode(&hex(blocks[1])).unwrap(), fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), authority, cache]));
+        assert_eq!(table(&decoder), (fields(&[cache, authority]), 110));
+
+        let custom = ("custom-key", "custom-value");
+        let third = fields(&[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"), authority, custom]);
+        assert_eq!(decoder.decode(&hex(blocks[2])).unwrap(), third);
+        assert_eq!(table(&decoder), (fields(&[custom, cache, authority]), 164));
+    }
+
+    #[test]
+    fn requests_without_huffman_coding() {
+        requests([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ]);
+    }
+
+    #[test]
+    fn requests_with_huffman_coding() {
+        requests([
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+        ]);
+    }
+
+    // C.5 and C.6: three responses in a 256-byte table, so later entries
+    // push out the oldest
+    fn responses(blocks: [&str; 3]) {
+        let mut decoder = Decoder::new();
+        decoder.max_size = 256;
+        let private = ("cache-control", "private");
+        let date = ("date", "Mon, 21 Oct 2013 20:13:21 GMT");
+        let location = ("location", "https://www.example.com");
+        assert_eq!(decoder.decode(&hex(blocks[0])).unwrap(), fields(&[(":status", "302"), private, date, location]));
+        assert_eq!(table(&decoder), (fields(&[location, date, private, (":status", "302")]), 222));
+
+        assert_eq!(decoder.decode(&hex(blocks[1])).unwrap(), fields(&[(":status", "307"), private, date, location]));
+        assert_eq!(table(&decoder), (fields(&[(":status", "307"), location, date, private]), 222));
+
+        let later = ("date", "Mon, 21 Oct 2013 20:13:22 GMT");
+        let gzip = ("content-encoding", "gzip");
+        let cookie = ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1");
+        assert_eq!(decoder.decode(&hex(blocks[2])).unwrap(), fields(&[(":status", "200"), private, later, location, gzip, cookie]));
+        assert_eq!(table(&decoder), (fields(&[cookie, gzip, later]), 215));
+    }
+
+    #[test]
+    fn responses_without_huffman_coding() {
+        responses([
+            "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "4803 3330 37c1 c0bf",
+            "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d 54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049 5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e 3d31",
+        ]);
+    }
+
+    #[test]
+    fn responses_with_huffman_coding() {
+        responses([
+            "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6 2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            "4883 640e ffc1 c0bf",
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab 77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+        ]);
+    }
+
+    #[test]
+    fn size_updates_shrink_the_table() {
+        let mut decoder = Decoder::new();
+        decoder.decode(&hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572")).unwrap();
+        assert_eq!(decoder.decode(&hex("20")).unwrap(), Vec::new());
+        assert_eq!(table(&decoder), (Vec::new(), 0));
+        // Too big for the table now, so given but not kept
+        assert_eq!(decoder.decode(&hex("4002 6e6f 0132")).unwrap(), fields(&[("no", "2")]));
+        assert_eq!(table(&decoder), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn encoded_fields_are_lower_case_literals_left_out_of_the_table() {
+        let block = encode(&[(":method", "GET"), ("Custom-Key", "custom-header")]);
+        assert_eq!(block, hex("0007 3a6d 6574 686f 6403 4745 5400 0a63 7573 746f 6d2d 6b65 790d 6375 7374 6f6d 2d68 6561 6465 72"));
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&block).unwrap(), fields(&[(":method", "GET"), ("custom-key", "custom-header")]));
+        assert_eq!(table(&decoder), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn bad_blocks_are_refused() {
+        let error = |block: &str| Decoder::new().decode(&hex(block)).unwrap_err().to_string();
+        assert_eq!(error("80"), "Bad HPACK header block: index 0");
+        assert_eq!(error("be"), "Bad HPACK header block: index past the table");
+        assert_eq!(error("0003 6162"), "Bad HPACK header block: truncated string");
+        // A '0' and three zero bits, which aren't the end-of-string code
+        assert_eq!(error("0081 00"), "Bad HPACK header block: bad Huffman padding");
+        // A whole byte of padding is one too many
+        assert_eq!(error("0082 1fff"), "Bad HPACK header block: bad Huffman padding");
+        assert_eq!(error("0084 ffff fffc"), "Bad HPACK header block: end of string inside a string");
+    }
+}
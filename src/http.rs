@@ -0,0 +1,1844 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{IoSlice, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::cookies::CookieJar;
+use crate::dns;
+use crate::engine::CHUNK_SIZE;
+use crate::h2;
+use crate::har::HarLog;
+use crate::hosts::Hosts;
+use crate::integrity;
+use crate::middleware::{Request, Response, Stack};
+use crate::netrc::Netrc;
+use crate::oauth2::ClientCredentials;
+use crate::pac::Pac;
+use crate::proxy::{self, Proxy, ProxyAuthRequired};
+use crate::refresh::UrlRefresh;
+use crate::sigv4::Signer;
+use crate::sockopt;
+use crate::splice;
+use crate::timing::{Clock, Phases, TimingLog};
+use crate::trace::Trace;
+use crate::url::Url;
+
+// Bytes asked for in each read from a connection, unless Settings says otherwise
+pub const DEFAULT_READ_BUFFER: usize = 256 * 1024;
+
+// How long a read waits at most while the cancellation token has a deadline,
+// and so how far past it a stalled connection can run
+const DEADLINE_POLL: Duration = Duration::from_millis(250);
+
+// Bodies at least this long are held back behind Expect: 100-continue, so a
+// server about to refuse the request can say so before any of it is sent
+const EXPECT_CONTINUE_MIN: u64 = 1024 * 1024;
+
+// How long to wait for the 100 before sending the body anyway, as servers
+// that don't know Expect never answer it
+const EXPECT_CONTINUE_WAIT: Duration = Duration::from_secs(1);
+
+// How connections are made. The default has no timeouts.
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    // Limit on connecting, and on each read or write once connected
+    pub timeout: Option<Duration>,
+    // Send small writes at once rather than waiting to fill a segment
+    pub nodelay: bool,
+    // Resolve names to, and connect to, addresses of this family only
+    pub ip_family: Option<IpFamily>,
+    // Send each request in the SYN with TCP Fast Open, on Linux
+    pub fast_open: bool,
+    // Speak HTTP/2 from the first byte (h2c with prior knowledge) rather
+    // than HTTP/1.1
+    pub http2: bool,
+    // Probe idle connections this often, so middleboxes don't drop them
+    pub keepalive: Option<Duration>,
+    // Bytes to ask for in each read, DEFAULT_READ_BUFFER when None
+    pub read_buffer: Option<usize>,
+    // Kernel receive and send buffer sizes (SO_RCVBUF and SO_SNDBUF) in bytes
+    pub recv_buffer: Option<usize>,
+    pub send_buffer: Option<usize>,
+    // Give up on a connection that stays below this speed, so a server
+    // dribbling bytes is left for another attempt rather than waited on
+    pub low_speed: Option<SpeedLimit>,
+    // Checked between reads, to give up on a response part way through
+    pub cancel: Option<CancellationToken>,
+    // Print the requests and responses of every connection
+    pub trace: Option<Trace>,
+    // Record every exchange for a HAR file
+    pub har: Option<HarLog>,
+    // Sent with every request, after Host
+    pub headers: Vec<(String, String)>,
+    // Note how long each phase of every connection took
+    pub timing: Option<TimingLog>,
+    // Cookies to send, and to keep those the server sets
+    pub cookies: Option<CookieJar>,
+    // Sign every request with AWS Signature Version 4
+    pub sigv4: Option<Signer>,
+    // Mint a new URL when a range request is refused, as expired signed URLs are
+    pub refresh: Option<UrlRefresh>,
+    // Send a bearer token from an OAuth 2.0 token endpoint, renewed as it expires
+    pub oauth2: Option<ClientCredentials>,
+    // Send every request through this HTTP proxy
+    pub proxy: Option<Proxy>,
+    // Pick each request's proxy with a proxy auto-config script instead
+    pub pac: Option<Pac>,
+    // Other addresses to connect to for some hosts, the first match winning
+    pub connect_to: Vec<ConnectTo>,
+    // Addresses to use for some hosts instead of asking DNS, the first match winning
+    pub resolve: Vec<Resolve>,
+    // Looked in before DNS, after resolve
+    pub hosts: Option<Hosts>,
+    // DNS servers to ask, in turn, instead of the system's resolver
+    pub dns_servers: Vec<SocketAddr>,
+    // Or DNS servers to ask over TLS, which take their place
+    #[cfg(feature = "tls")]
+    pub dot_servers: Vec<dns::TlsServer>,
+    // Authorization values by host, such as those kept in the keyring
+    pub authorizations: Vec<(String, String)>,
+    // Basic credentials per host, for requests that carry no other Authorization
+    pub netrc: Option<Netrc>,
+    // Layers every request goes through on its way out, outermost first
+    pub middleware: Stack,
+}
+
+// Connect somewhere else for requests to a host and port, keeping the URL
+// and Host header, as curl's --connect-to does. An empty field matches any
+// host or port, or keeps the URL's own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConnectTo {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub to_host: Option<String>,
+    pub to_port: Option<u16>,
+}
+
+impl ConnectTo {
+    // From "HOST:PORT:CONNECT-TO-HOST:CONNECT-TO-PORT", IPv6 addresses in brackets
+    pub fn parse(value: &str) -> Result<ConnectTo, String> {
+        let invalid = || format!("Invalid --connect-to value: {} (expected HOST:PORT:OTHERHOST:OTHERPORT)", value);
+        let mut fields = Vec::new();
+        let mut rest = value;
+        for i in 0..4 {
+            let (field, after) = match rest.strip_prefix('[') {
+                Some(v6) => {
+                    let (host, after) = v6.split_once(']').ok_or_else(invalid)?;
+                    (host, after)
+                }
+                None => match rest.find(':') {
+                    Some(end) => (&rest[..end], &rest[end..]),
+                    None => (rest, ""),
+                },
+            };
+            fields.push(field);
+            rest = match (i, after.strip_prefix(':')) {
+                (3, None) if after.is_empty() => after,
+                (0..=2, Some(after)) => after,
+                _ => return Err(invalid()),
+            };
+        }
+        let host = |field: &str| Some(field.to_string()).filter(|host| !host.is_empty());
+        let port = |field: &str| match field {
+            "" => Ok(None),
+            port => port.parse().map(Some).map_err(|_| invalid()),
+        };
+        Ok(ConnectTo { host: host(fields[0]), port: port(fields[1])?, to_host: host(fields[2]), to_port: port(fields[3])? })
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        self.host.as_ref().is_none_or(|host| host.eq_ignore_ascii_case(&url.host)) && self.port.is_none_or(|port| port == url.port)
+    }
+}
+
+// Status and headers of an HTTP response
+#[derive(Clone, Debug)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    // Parse the head at the start of a response, up to the blank line
+    pub fn parse(response: &[u8]) -> ResponseHead {
+        let text = String::from_utf8_lossy(response);
+        let mut lines = text.split("\r\n");
+
+        // Status line, e.g. "HTTP/1.1 206 Partial Content"
+        let status = lines.next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse::<u16>().ok())
+            .unwrap_or(0);
+
+        let headers = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        ResponseHead { status, headers }
+    }
+
+    // Value of the first header with the given name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("Content-Length")?.parse().ok()
+    }
+
+    // Length of the body that follows the head. Responses to HEAD and 1xx,
+    // 204 and 304 responses never have one, whatever Content-Length says.
+    // None when it runs until the connection closes.
+    pub fn body_length(&self, method: &str) -> Option<u64> {
+        if method == "HEAD" || matches!(self.status, 100..=199 | 204 | 304) {
+            return Some(0);
+        }
+        self.content_length()
+    }
+
+    // Where the part in a 206 starts, from a "bytes 0-99/1234" Content-Range
+    pub fn content_range_start(&self) -> Option<u64> {
+        let range = self.header("Content-Range")?.strip_prefix("bytes ")?;
+        range.split_once('-')?.0.trim().parse().ok()
+    }
+
+    // Full size of the body from Content-Range, in either the "bytes 0-99/1234"
+    // or the "bytes */1234" form. None when the size is given as "*".
+    pub fn content_range_total(&self) -> Option<u64> {
+        let range = self.header("Content-Range")?.strip_prefix("bytes ")?;
+        range.rsplit_once('/')?.1.trim().parse().ok()
+    }
+}
+
+// The error a range request ends with when the server answers 416 Range Not
+// Satisfiable, meaning the range starts at or past the end of the body.
+// Carried inside an io::Error; use RangeNotSatisfiable::from_error to get at it.
+#[derive(Debug)]
+pub struct RangeNotSatisfiable {
+    pub range: String,
+    // The body's real size, when the server said
+    pub total_size: Option<u64>,
+}
+
+impl RangeNotSatisfiable {
+    pub fn from_error(error: &std::io::Error) -> Option<&RangeNotSatisfiable> {
+        error.get_ref()?.downcast_ref::<RangeNotSatisfiable>()
+    }
+
+    fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, self)
+    }
+}
+
+impl std::fmt::Display for RangeNotSatisfiable {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.total_size {
+            Some(total_size) => write!(f, "Range {} not satisfiable, the body has {} bytes", self.range, total_size),
+            None => write!(f, "Range {} not satisfiable", self.range),
+        }
+    }
+}
+
+impl std::error::Error for RangeNotSatisfiable {}
+
+// The error a range request past the start ends with when the server answers
+// 200 with the whole body, which doesn't do ranges, rather than the body
+// being taken for the bytes asked for. Carried inside an io::Error; use
+// RangeIgnored::from_error to get at it.
+#[derive(Debug)]
+pub struct RangeIgnored {
+    pub url: String,
+    pub start: u64,
+}
+
+impl RangeIgnored {
+    pub fn from_error(error: &std::io::Error) -> Option<&RangeIgnored> {
+        error.get_ref()?.downcast_ref::<RangeIgnored>()
+    }
+
+    fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Unsupported, self)
+    }
+}
+
+impl std::fmt::Display for RangeIgnored {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} answered the range from byte {} with the whole body; it doesn't serve ranges", self.url, self.start)
+    }
+}
+
+impl std::error::Error for RangeIgnored {}
+
+// The error a range request ends with when the server answers 403 Forbidden,
+// as it does once a signed URL has expired, and no fresh URL was had or it
+// was refused as well
+#[derive(Debug)]
+pub struct Forbidden {
+    pub url: String,
+}
+
+impl Forbidden {
+    pub fn from_error(error: &std::io::Error) -> Option<&Forbidden> {
+        error.get_ref()?.downcast_ref::<Forbidden>()
+    }
+
+    fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, self)
+    }
+}
+
+impl std::fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} answered 403 Forbidden; a signed URL may have expired", self.url)
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
+// The error a range request ends with when the server answers with any other
+// status outside 2xx, rather than the error page being taken for the body
+#[derive(Debug)]
+pub struct UnexpectedStatus {
+    pub url: String,
+    pub status: u16,
+}
+
+impl UnexpectedStatus {
+    pub fn from_error(error: &std::io::Error) -> Option<&UnexpectedStatus> {
+        error.get_ref()?.downcast_ref::<UnexpectedStatus>()
+    }
+
+    fn into_error(self) -> std::io::Error {
+        std::io::Error::other(self)
+    }
+}
+
+impl std::fmt::Display for UnexpectedStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} answered {} to a range request", self.url, self.status)
+    }
+}
+
+impl std::error::Error for UnexpectedStatus {}
+
+// The error connecting ends with when the host's name can't be looked up
+#[derive(Debug)]
+pub struct Unresolved {
+    pub host: String,
+    pub reason: String,
+}
+
+impl Unresolved {
+    pub fn from_error(error: &std::io::Error) -> Option<&Unresolved> {
+        error.get_ref()?.downcast_ref::<Unresolved>()
+    }
+}
+
+impl std::fmt::Display for Unresolved {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Could not resolve {}: {}", self.host, self.reason)
+    }
+}
+
+impl std::error::Error for Unresolved {}
+
+// Addresses for a host and port that DNS isn't asked about, as curl's
+// --resolve gives. The URL and Host header are left alone. A host of "*"
+// matches any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolve {
+    pub host: Option<String>,
+    pub port: u16,
+    pub addresses: Vec<IpAddr>,
+}
+
+impl Resolve {
+    // From "HOST:PORT:ADDRESS[,ADDRESS]...", IPv6 addresses in brackets
+    pub fn parse(value: &str) -> Result<Resolve, String> {
+        let invalid = || format!("Invalid --resolve value: {} (expected HOST:PORT:ADDRESS[,ADDRESS]...)", value);
+        let (host, rest) = match value.strip_prefix('[') {
+            Some(v6) => v6.split_once("]:").ok_or_else(invalid)?,
+            None => value.split_once(':').ok_or_else(invalid)?,
+        };
+        let (port, addresses) = rest.split_once(':').ok_or_else(invalid)?;
+        let port = port.parse().map_err(|_| invalid())?;
+        let addresses = addresses.split(',')
+            .map(|address| {
+                let address = address.trim();
+                let address = address.strip_prefix('[').and_then(|v6| v6.strip_suffix(']')).unwrap_or(address);
+                address.parse::<IpAddr>().map_err(|_| invalid())
+            })
+            .collect::<Result<Vec<IpAddr>, String>>()?;
+        let host = Some(host.to_string()).filter(|host| host != "*");
+        if host.as_ref().is_some_and(String::is_empty) {
+            return Err(invalid());
+        }
+        Ok(Resolve { host, port, addresses })
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        self.host.as_ref().is_none_or(|host| host.eq_ignore_ascii_case(&url.host)) && self.port == url.port
+    }
+}
+
+// IPv4 or IPv6, as curl's -4 and -6 pick
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn matches(self, addr: &SocketAddr) -> bool {
+        match self {
+            IpFamily::V4 => addr.is_ipv4(),
+            IpFamily::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+impl std::fmt::Display for IpFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            IpFamily::V4 => "IPv4",
+            IpFamily::V6 => "IPv6",
+        })
+    }
+}
+
+// The slowest a connection may go, on average over a window of time, before
+// it is taken to have stalled, as curl's --speed-limit and --speed-time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpeedLimit {
+    pub bytes_per_second: u64,
+    pub time: Duration,
+}
+
+// The error a read ends with once a connection has stayed below the speed
+// limit for a whole window, carried inside an io::Error of kind TimedOut
+#[derive(Debug)]
+pub struct TooSlow {
+    pub limit: SpeedLimit,
+}
+
+impl TooSlow {
+    pub fn from_error(error: &std::io::Error) -> Option<&TooSlow> {
+        error.get_ref()?.downcast_ref::<TooSlow>()
+    }
+
+    fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, self)
+    }
+}
+
+impl std::fmt::Display for TooSlow {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Transfer slower than {} bytes/s for {} s", self.limit.bytes_per_second, self.limit.time.as_secs_f64())
+    }
+}
+
+impl std::error::Error for TooSlow {}
+
+// Watches what one connection reads: against Settings::low_speed, a window
+// of the limit's length at a time, and for the cancellation token's deadline.
+// Connections are opened with a read timeout no longer than the window, and
+// short while there is a deadline, so a server sending nothing is caught too.
+pub(crate) struct ReadWatch {
+    limit: Option<SpeedLimit>,
+    timeout: Option<Duration>,
+    cancel: Option<CancellationToken>,
+    window_start: Instant,
+    bytes: u64,
+    // When anything last arrived, for telling Settings::timeout running out
+    // from a read that only timed out for the watch to look
+    last_read: Instant,
+}
+
+impl ReadWatch {
+    pub(crate) fn new(settings: &Settings) -> ReadWatch {
+        ReadWatch {
+            limit: settings.low_speed,
+            timeout: settings.timeout,
+            cancel: settings.cancel.clone(),
+            window_start: Instant::now(),
+            bytes: 0,
+            last_read: Instant::now(),
+        }
+    }
+
+    // Count bytes just read, failing with TooSlow once a window has gone by
+    // with fewer than the limit allows
+    pub(crate) fn received(&mut self, n: usize) -> std::io::Result<()> {
+        if n > 0 {
+            self.last_read = Instant::now();
+        }
+        let Some(limit) = self.limit else {
+            return Ok(());
+        };
+        self.bytes += n as u64;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < limit.time {
+            return Ok(());
+        }
+        if (self.bytes as f64) < limit.bytes_per_second as f64 * elapsed.as_secs_f64() {
+            return Err(TooSlow { limit }.into_error());
+        }
+        self.window_start = Instant::now();
+        self.bytes = 0;
+        Ok(())
+    }
+
+    // Look at a failed read: Ok if it was interrupted by a signal, as reads
+    // with a timeout are even under SA_RESTART, or only timed out for the
+    // watch on a connection fast enough to read from again. Else the error
+    // to end with.
+    pub(crate) fn failed(&mut self, error: std::io::Error) -> std::io::Result<()> {
+        if error.kind() == std::io::ErrorKind::Interrupted {
+            return Ok(());
+        }
+        let deadline = self.cancel.as_ref().and_then(CancellationToken::deadline);
+        if !matches!(error.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) || (self.limit.is_none() && deadline.is_none()) {
+            return Err(error);
+        }
+        if self.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        self.received(0)?;
+        if self.timeout.is_some_and(|timeout| self.last_read.elapsed() >= timeout) {
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Read from the connection, counting what arrives
+    pub(crate) fn read(&mut self, conn: &mut impl Read, buffer: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            match conn.read(buffer) {
+                Ok(n) => {
+                    self.received(n)?;
+                    return Ok(n);
+                }
+                Err(e) => self.failed(e)?,
+            }
+        }
+    }
+}
+
+// How long a read may wait: the timeout, cut to the low-speed window and to
+// DEADLINE_POLL while there is a deadline, so the watch gets to look
+fn read_timeout(settings: &Settings) -> Option<Duration> {
+    let deadline = settings.cancel.as_ref().and_then(CancellationToken::deadline).map(|_| DEADLINE_POLL);
+    [settings.timeout, settings.low_speed.map(|limit| limit.time), deadline].into_iter().flatten().min()
+}
+
+// How long connecting may take: the timeout, cut to what is left before the
+// deadline
+fn connect_timeout(settings: &Settings) -> Option<Duration> {
+    let left = settings.cancel.as_ref()
+        .and_then(CancellationToken::deadline)
+        .map(|deadline| deadline.saturating_duration_since(Instant::now()).max(Duration::from_millis(1)));
+    [settings.timeout, left].into_iter().flatten().min()
+}
+
+// The proxy for a request: the one the PAC script picks, else the one set
+fn proxy_for(url: &Url, settings: &Settings) -> std::io::Result<Option<Proxy>> {
+    match &settings.pac {
+        Some(pac) => pac.find_proxy(url),
+        None => Ok(settings.proxy.clone()),
+    }
+}
+
+// Open a connection to the URL's host, the proxy for it or the address
+// --connect-to gives for it, marking when the name was resolved and the
+// connection made
+fn connect(url: &Url, proxy: Option<&Proxy>, settings: &Settings, clock: &mut Clock) -> std::io::Result<TcpStream> {
+    let target = match proxy {
+        Some(proxy) => proxy.url.clone(),
+        None => match settings.connect_to.iter().find(|connect_to| connect_to.matches(url)) {
+            Some(connect_to) => Url {
+                host: connect_to.to_host.clone().unwrap_or(url.host.clone()),
+                port: connect_to.to_port.unwrap_or(url.port),
+                path: url.path.clone(),
+            },
+            None => url.clone(),
+        },
+    };
+    let mut conn = open(&target, settings, clock)?;
+    if let Some(trace) = &settings.trace {
+        trace.connected(url, conn.peer_addr().ok());
+    }
+    if let Some(proxy) = proxy.filter(|proxy| proxy.tunnel) {
+        proxy::tunnel(&mut conn, url, proxy, settings.trace.as_ref())?;
+    }
+    clock.connected();
+    Ok(conn)
+}
+
+// The addresses for the URL's host: those --resolve pins it to, else those
+// the hosts file lists for it, else what DNS says, from the servers given if
+// there are any, over TLS if those are
+fn lookup(url: &Url, settings: &Settings) -> std::io::Result<Vec<SocketAddr>> {
+    let pinned = match settings.resolve.iter().find(|resolve| resolve.matches(url)) {
+        Some(resolve) => resolve.addresses.clone(),
+        None => settings.hosts.as_ref().map(|hosts| hosts.addresses(&url.host)).unwrap_or_default(),
+    };
+    if !pinned.is_empty() {
+        return Ok(pinned.into_iter().map(|address| SocketAddr::new(address, url.port)).collect());
+    }
+    let unresolved = |e: std::io::Error| std::io::Error::new(e.kind(), Unresolved { host: url.host.clone(), reason: e.to_string() });
+    #[cfg(feature = "tls")]
+    if !settings.dot_servers.is_empty() {
+        return dns::lookup_tls(&settings.dot_servers, &url.host, settings.ip_family, settings.timeout)
+            .map(|addresses| addresses.into_iter().map(|address| SocketAddr::new(address, url.port)).collect())
+            .map_err(unresolved);
+    }
+    if !settings.dns_servers.is_empty() {
+        return dns::lookup(&settings.dns_servers, &url.host, settings.ip_family, settings.timeout)
+            .map(|addresses| addresses.into_iter().map(|address| SocketAddr::new(address, url.port)).collect())
+            .map_err(unresolved);
+    }
+    (url.host.as_str(), url.port)
+        .to_socket_addrs()
+        .map(Iterator::collect)
+        .map_err(unresolved)
+}
+
+fn open(url: &Url, settings: &Settings, clock: &mut Clock) -> std::io::Result<TcpStream> {
+    let mut addrs = lookup(url, settings)?;
+    addrs.retain(|addr| settings.ip_family.is_none_or(|family| family.matches(addr)));
+    clock.resolved();
+    if let (Some(family), true) = (settings.ip_family, addrs.is_empty()) {
+        let reason = format!("no {} address", family);
+        return Err(std::io::Error::new(std::io::ErrorKind::NotFound, Unresolved { host: url.host.clone(), reason }));
+    }
+
+    // Try each address in turn, connect_timeout takes a single one
+    let mut last_error = None;
+    for addr in addrs {
+        let attempt = match connect_timeout(settings) {
+            // The handshake waits for the request, so the write timeout covers it
+            _ if settings.fast_open && cfg!(target_os = "linux") => sockopt::connect_fast_open(&addr),
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match attempt {
+            Ok(conn) => {
+                conn.set_read_timeout(read_timeout(settings))?;
+                conn.set_write_timeout(settings.timeout)?;
+                if settings.nodelay {
+                    conn.set_nodelay(true)?;
+                }
+                if let Some(interval) = settings.keepalive {
+                    sockopt::set_keepalive(&conn, interval)?;
+                }
+                if settings.recv_buffer.is_some() || settings.send_buffer.is_some() {
+                    sockopt::set_buffer_sizes(&conn, settings.recv_buffer, settings.send_buffer)?;
+                }
+                return Ok(conn);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or(std::io::Error::other(format!("No addresses found for {}", url.host))))
+}
+
+// Pass the phases of a finished exchange on to the trace and the timing log
+fn finish(clock: &Clock, settings: &Settings) -> Phases {
+    let phases = clock.phases();
+    if let Some(trace) = &settings.trace {
+        trace.timing(&phases);
+    }
+    if let Some(timing) = &settings.timing {
+        timing.record(phases);
+    }
+    phases
+}
+
+// A request head kept in its parts: the caller's headers are borrowed and
+// only those made for this request are owned. It is written out as slices,
+// so nothing is joined into one string just to be sent.
+struct RequestHead<'a> {
+    method: &'a str,
+    target: String,
+    host: String,
+    given: Vec<&'a (String, String)>,
+    added: Vec<(String, String)>,
+    // Ask the server to close the connection after answering
+    close: bool,
+}
+
+impl RequestHead<'_> {
+    // The request line, headers and blank line, with the body after them
+    fn slices<'s>(&'s self, body: &'s [u8]) -> Vec<IoSlice<'s>> {
+        let mut slices = vec![
+            IoSlice::new(self.method.as_bytes()),
+            IoSlice::new(b" "),
+            IoSlice::new(self.target.as_bytes()),
+            IoSlice::new(b" HTTP/1.1\r\nHost: "),
+            IoSlice::new(self.host.as_bytes()),
+            IoSlice::new(b"\r\n"),
+        ];
+        for (name, value) in self.given.iter().copied().chain(&self.added) {
+            slices.extend([
+                IoSlice::new(name.as_bytes()),
+                IoSlice::new(b": "),
+                IoSlice::new(value.as_bytes()),
+                IoSlice::new(b"\r\n"),
+            ]);
+        }
+        slices.push(IoSlice::new(if self.close { b"Connection: close\r\n\r\n" } else { b"\r\n" }));
+        if !body.is_empty() {
+            slices.push(IoSlice::new(body));
+        }
+        slices
+    }
+}
+
+impl RequestHead<'_> {
+    // The headers as HTTP/2 fields, without those that only mean something
+    // to a single HTTP/1.1 connection
+    fn fields(&self) -> Vec<(&str, &str)> {
+        const HOP_BY_HOP: [&str; 5] = ["Connection", "Keep-Alive", "Proxy-Connection", "Transfer-Encoding", "Upgrade"];
+        self.given.iter().copied().chain(&self.added)
+            .filter(|(name, _)| !HOP_BY_HOP.iter().any(|hop| name.eq_ignore_ascii_case(hop)))
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect()
+    }
+}
+
+// The bytes of the slices in one piece, for the trace and the HAR log
+fn joined(slices: &[IoSlice]) -> Vec<u8> {
+    slices.iter().flat_map(|slice| slice.iter().copied()).collect()
+}
+
+// write_all for slices: a writev may take only part of them, so the rest
+// goes in further calls
+fn write_all_vectored(conn: &mut impl Write, mut slices: &mut [IoSlice]) -> std::io::Result<()> {
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match conn.write_vectored(slices) {
+            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "Connection stopped taking the request")),
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// The request line and headers: the configured headers, then those of this
+// request, then any bearer token or stored or .netrc credentials, cookies for
+// the URL and the signature. Host is the URL's unless a Host header was given. Fails when a token was needed and couldn't be had.
+fn request_head<'a>(
+    method: &'a str,
+    url: &Url,
+    headers: &'a [(String, String)],
+    proxy: Option<&Proxy>,
+    settings: &'a Settings,
+) -> std::io::Result<RequestHead<'a>> {
+    let authorized = settings.oauth2.is_some()
+        || settings.sigv4.is_some()
+        || settings.headers.iter().chain(headers).any(|(name, _)| name.eq_ignore_ascii_case("Authorization"));
+    let stored = settings.authorizations.iter()
+        .find(|(host, _)| host.eq_ignore_ascii_case(&url.host))
+        .map(|(_, authorization)| authorization.clone());
+    let login = stored
+        .or_else(|| settings.netrc.as_ref().and_then(|netrc| netrc.credentials(&url.host)).map(|(login, password)| {
+            let credentials = format!("{}:{}", login, password);
+            format!("Basic {}", integrity::encode_base64(credentials.as_bytes()))
+        }))
+        .filter(|_| !authorized)
+        .map(|authorization| ("Authorization".to_string(), authorization));
+    let bearer = match &settings.oauth2 {
+        Some(client) => Some(("Authorization".to_string(), client.authorization(settings)?)),
+        None => None,
+    };
+    let cookie = settings.cookies.as_ref()
+        .and_then(|jar| jar.header(url))
+        .map(|cookie| ("Cookie".to_string(), cookie));
+    // A Host header among the others takes the place of the URL's, so a
+    // virtual host can be reached by its address
+    let host = settings.headers.iter()
+        .chain(headers)
+        .rfind(|(name, _)| name.eq_ignore_ascii_case("Host"))
+        .map_or(url.authority(), |(_, host)| host.clone());
+    let signature = settings.sigv4.as_ref()
+        .map(|signer| signer.headers(method, url, &host, std::time::SystemTime::now()))
+        .unwrap_or_default();
+    // A proxy that isn't tunnelling gets the whole URL, and its credentials
+    let forwarding = proxy.filter(|proxy| !proxy.tunnel);
+    let proxy_login = forwarding.and_then(Proxy::authorization);
+    let not_host = |(name, _): &&(String, String)| !name.eq_ignore_ascii_case("Host");
+    let given = settings.headers.iter().chain(headers).filter(not_host).collect();
+    let added = bearer.into_iter()
+        .chain(login)
+        .chain(cookie)
+        .chain(signature)
+        .chain(proxy_login)
+        .filter(|header| not_host(&header))
+        .collect();
+    let target = if forwarding.is_some() { url.to_string() } else { url.path.clone() };
+    Ok(RequestHead { method, target, host, given, added, close: true })
+}
+
+// Fetch the response head for the whole content, which tells us its size
+pub fn probe(url: &Url, settings: &Settings) -> std::io::Result<ResponseHead> {
+    // Make a full request first to get the total size
+    fetch_head(url, "GET", &[], settings).map(|(head, _)| head)
+}
+
+// Like probe, with extra request headers such as If-None-Match, so the
+// answer may be a 304 instead
+pub fn probe_with_headers(url: &Url, headers: &[(String, String)], settings: &Settings) -> std::io::Result<ResponseHead> {
+    fetch_head(url, "GET", headers, settings).map(|(head, _)| head)
+}
+
+// Like probe_with_headers, also saying how long each phase of the exchange
+// took, which tells how far away the server is
+pub fn probe_timed(url: &Url, headers: &[(String, String)], settings: &Settings) -> std::io::Result<(ResponseHead, Phases)> {
+    fetch_head(url, "GET", headers, settings)
+}
+
+// Fetch the response head with a HEAD request, which has no body at all
+pub fn head(url: &Url, settings: &Settings) -> std::io::Result<ResponseHead> {
+    fetch_head(url, "HEAD", &[], settings).map(|(head, _)| head)
+}
+
+// Send a request through the middleware and read back only the response head.
+// An answer from a layer took no time on the network.
+fn fetch_head(url: &Url, method: &str, headers: &[(String, String)], settings: &Settings) -> std::io::Result<(ResponseHead, Phases)> {
+    if settings.middleware.is_empty() {
+        return send_head(url, method, headers, settings);
+    }
+    let phases = Cell::new(Phases::default());
+    let request = Request { method: method.to_string(), url: url.clone(), headers: headers.to_vec(), body: Vec::new(), head_only: true };
+    let response = settings.middleware.run(request, &|request| {
+        let (head, taken) = send_head(&request.url, &request.method, &request.headers, settings)?;
+        phases.set(taken);
+        Ok(Response { head, body: Vec::new() })
+    })?;
+    Ok((response.head, phases.get()))
+}
+
+// Send a request and read back only the response head
+fn send_head(url: &Url, method: &str, headers: &[(String, String)], settings: &Settings) -> std::io::Result<(ResponseHead, Phases)> {
+    let proxy = proxy_for(url, settings)?;
+    let outgoing = request_head(method, url, headers, proxy.as_ref(), settings)?;
+    let mut slices = outgoing.slices(&[]);
+    // Only kept whole when something records it
+    let request = (settings.trace.is_some() || settings.har.is_some()).then(|| joined(&slices));
+    
+    let mut clock = Clock::start();
+    let response = if settings.http2 {
+        exchange_h2(url, &outgoing, &[], true, proxy.as_ref(), settings, &mut clock)?
+    } else {
+        let mut conn = connect(url, proxy.as_ref(), settings, &mut clock)?;
+        write_all_vectored(&mut conn, &mut slices)?;
+        clock.sent();
+        if let Some(trace) = &settings.trace {
+            trace.sent(request.as_deref().unwrap_or_default());
+        }
+        
+        // We don't need to read all the data, just the headers
+        let mut response = Vec::new();
+        let mut buffer = vec![0; settings.read_buffer.unwrap_or(DEFAULT_READ_BUFFER)];
+        
+        // Read just enough to get the headers
+        let mut watch = ReadWatch::new(settings);
+        loop {
+            match watch.read(&mut conn, &mut buffer) {
+                Ok(0) => break,
+                Ok(n) => {
+                    clock.received();
+                    response.extend_from_slice(&buffer[0..n]);
+                    // If we have the final response's headers, we can stop
+                    if response[final_response_start(&response)..].windows(4).any(|w| w == b"\r\n\r\n") {
+                        break;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if let Some(trace) = &settings.trace {
+            trace.received(&response);
+        }
+        let response = response[final_response_start(&response)..].to_vec();
+        if let Some(har) = &settings.har {
+            har.record(url, request.as_deref().unwrap_or_default(), &response, &clock);
+        }
+        response
+    };
+    let phases = finish(&clock, settings);
+    
+    let head = ResponseHead::parse(&response);
+    check_proxy(&head, proxy.as_ref())?;
+    if let Some(jar) = &settings.cookies {
+        jar.receive(url, &head);
+    }
+    Ok((head, phases))
+}
+
+// Download a chunk of data starting at the specified position
+pub fn download_chunk(url: &Url, start_position: u64, settings: &Settings) -> std::io::Result<Vec<u8>> {
+    download_range(url, start_position, start_position.saturating_add(CHUNK_SIZE as u64 - 1), settings)
+}
+
+// Download the bytes from start to end (inclusive). The server may send
+// fewer bytes than asked for, but not others: a 206 for another range fails,
+// and so does a 200 with the whole body unless the range starts at 0.
+pub fn download_range(url: &Url, start_position: u64, end_position: u64, settings: &Settings) -> std::io::Result<Vec<u8>> {
+    let response = fetch_response(url, Some(format!("bytes={}-{}", start_position, end_position)), settings)?;
+    check_range(url, &response.head, start_position, end_position)?;
+    Ok(response.body)
+}
+
+// Whether a 2xx answer to the range from start to end is for those bytes
+pub(crate) fn check_range(url: &Url, head: &ResponseHead, start_position: u64, end_position: u64) -> std::io::Result<()> {
+    match head.status {
+        200 if start_position > 0 => Err(RangeIgnored { url: url.to_string(), start: start_position }.into_error()),
+        206 if head.content_range_start() != Some(start_position) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "The answer for bytes {}-{} was for another range ({})",
+                start_position, end_position, head.header("Content-Range").unwrap_or("no Content-Range")
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Download a whole response body, optionally for the given Range header
+// value. A 416 answer to a range fails with RangeNotSatisfiable, and a 403
+// with Forbidden once a refreshed URL hasn't helped, rather than handing back
+// the error page as data.
+pub fn fetch(url: &Url, range: Option<String>, settings: &Settings) -> std::io::Result<Vec<u8>> {
+    fetch_response(url, range, settings).map(|response| response.body)
+}
+
+// Like fetch, also handing back the response head
+fn fetch_response(url: &Url, range: Option<String>, settings: &Settings) -> std::io::Result<Response> {
+    let headers: Vec<(String, String)> = range.iter().map(|range| ("Range".to_string(), range.clone())).collect();
+    let target = settings.refresh.as_ref().map_or_else(|| url.clone(), |refresh| refresh.resolve(url));
+    let sent = Instant::now();
+    let mut response = exchange(&target, &headers, settings)?;
+    
+    // Check if we got a valid response
+    if response.head.status == 0 && response.body.is_empty() {
+        return Ok(response);
+    }
+    if let Some(range) = range {
+        // A bearer token that stopped working early, then with a new one
+        if response.head.status == 401
+            && let Some(client) = &settings.oauth2
+        {
+            client.refused(sent);
+            response = exchange(&target, &headers, settings)?;
+        }
+        if response.head.status == 403
+            && let Some(refresh) = &settings.refresh
+        {
+            let fresh = refresh.renew(url, &target)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("Could not refresh {}: {}", url, e)))?;
+            response = exchange(&fresh, &headers, settings)?;
+        }
+        let head = &response.head;
+        if head.status == 403 {
+            return Err(Forbidden { url: url.to_string() }.into_error());
+        }
+        if head.status == 416 {
+            return Err(RangeNotSatisfiable { range, total_size: head.content_range_total() }.into_error());
+        }
+        if !(200..300).contains(&head.status) {
+            return Err(UnexpectedStatus { url: url.to_string(), status: head.status }.into_error());
+        }
+    }
+    
+    Ok(response)
+}
+
+// Download several ranges, each from start to end (inclusive), in one
+// request. The answer is a multipart/byteranges body with a part per range,
+// though a server may merge neighbouring ranges, send fewer bytes, or answer
+// with a single part or the whole body; whatever came is handed back as
+// (offset, bytes) pieces for the caller to check against what it asked for.
+pub fn download_ranges(url: &Url, ranges: &[(u64, u64)], settings: &Settings) -> std::io::Result<Vec<(u64, Vec<u8>)>> {
+    let spec: Vec<String> = ranges.iter().map(|(start, end)| format!("{}-{}", start, end)).collect();
+    let response = fetch_response(url, Some(format!("bytes={}", spec.join(","))), settings)?;
+    if response.head.status == 200 {
+        return Ok(vec![(0, response.body)]);
+    }
+    let boundary = response.head.header("Content-Type").and_then(|content_type| {
+        let (essence, parameters) = content_type.split_once(';')?;
+        if !essence.trim().eq_ignore_ascii_case("multipart/byteranges") {
+            return None;
+        }
+        parameters.split(';')
+            .filter_map(|parameter| parameter.trim().split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+            .map(|(_, value)| value.trim().trim_matches('"').to_string())
+    });
+    match boundary {
+        Some(boundary) => byteranges(&response.body, &boundary)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Malformed multipart/byteranges response")),
+        None => {
+            let start = response.head.content_range_start()
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "206 response without a Content-Range"))?;
+            Ok(vec![(start, response.body)])
+        }
+    }
+}
+
+// The parts of a multipart/byteranges body, each found by its Content-Range
+// so a part's bytes are never searched for the boundary
+fn byteranges(body: &[u8], boundary: &str) -> Option<Vec<(u64, Vec<u8>)>> {
+    let find = |data: &[u8], needle: &[u8]| data.windows(needle.len()).position(|window| window == needle);
+    let delimiter = format!("--{}", boundary);
+    let mut pos = find(body, delimiter.as_bytes())?;
+    let mut parts = Vec::new();
+    loop {
+        let rest = &body[pos + delimiter.len()..];
+        if rest.starts_with(b"--") {
+            return Some(parts);
+        }
+        // The delimiter line stands in for a status line
+        let head_len = find(&body[pos..], b"\r\n\r\n")? + 4;
+        let head = ResponseHead::parse(&body[pos..pos + head_len]);
+        let range = head.header("Content-Range")?.strip_prefix("bytes ")?;
+        let (start, end) = range.split_once('/')?.0.split_once('-')?;
+        let (start, end): (u64, u64) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+        let len = usize::try_from(end.checked_sub(start)? + 1).ok()?;
+        let data = body.get(pos + head_len..)?.get(..len)?;
+        parts.push((start, data.to_vec()));
+        pos += head_len + len;
+        pos += find(&body[pos..], delimiter.as_bytes())?;
+    }
+}
+
+// Connect and send a GET for the bytes from start to end (inclusive),
+// leaving the response on the connection for the caller to read. Not with
+// HTTP/2, whose bodies come in frames.
+pub(crate) fn request_range(url: &Url, start_position: u64, end_position: u64, settings: &Settings) -> std::io::Result<TcpStream> {
+    if settings.http2 {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "reading a body off the connection needs HTTP/1.1"));
+    }
+    let headers = [("Range".to_string(), format!("bytes={}-{}", start_position, end_position))];
+    let target = settings.refresh.as_ref().map_or_else(|| url.clone(), |refresh| refresh.resolve(url));
+    let proxy = proxy_for(&target, settings)?;
+    let outgoing = request_head("GET", &target, &headers, proxy.as_ref(), settings)?;
+    
+    let mut clock = Clock::start();
+    let mut conn = connect(&target, proxy.as_ref(), settings, &mut clock)?;
+    write_all_vectored(&mut conn, &mut outgoing.slices(&[]))?;
+    Ok(conn)
+}
+
+// A kept-alive connection with several range requests out at once, the next
+// sent before the previous answer has arrived so the server is never left
+// waiting for one. Answers come back in the order the requests went out. A
+// server that closes the connection gets the unanswered requests again on a
+// new one, and one that answers for the wrong range, as servers that read
+// pipelined requests as one do, gets them one at a time from then on.
+pub struct Pipeline<'a> {
+    url: Url,
+    settings: &'a Settings,
+    depth: usize,
+    // The connection and the proxy it goes through, if any
+    conn: Option<(Link, Option<Proxy>)>,
+    // Answers read on the connection so far
+    answered: usize,
+    // Bytes read past the end of the last answer
+    buffered: Vec<u8>,
+    // Ranges asked for and not yet answered, oldest first, and how many of
+    // them have gone out on the connection
+    pending: VecDeque<(u64, u64)>,
+    sent: usize,
+    // The speed of the HTTP/1.1 connection; HTTP/2 keeps its own
+    watch: ReadWatch,
+}
+
+// What a pipeline's requests go out on: one connection answering in order,
+// or HTTP/2 with a stream for each request, the ones open oldest first
+enum Link {
+    Http1(TcpStream),
+    Http2(Box<h2::Connection>, VecDeque<u32>),
+}
+
+impl<'a> Pipeline<'a> {
+    // A pipeline of up to depth requests; nothing connects until the first
+    // answer is wanted. With HTTP/2 the requests are streams, as many open at
+    // once as the depth and the server allow.
+    pub fn new(url: &Url, depth: usize, settings: &'a Settings) -> Pipeline<'a> {
+        let url = settings.refresh.as_ref().map_or_else(|| url.clone(), |refresh| refresh.resolve(url));
+        Pipeline {
+            url,
+            settings,
+            depth: depth.max(1),
+            conn: None,
+            answered: 0,
+            buffered: Vec::new(),
+            pending: VecDeque::new(),
+            sent: 0,
+            watch: ReadWatch::new(settings),
+        }
+    }
+
+    // Whether another request would go past the depth
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= self.depth
+    }
+
+    // Ask for the bytes from start to end (inclusive) behind the requests
+    // already out. Past the depth it waits to be sent until an answer is in.
+    pub fn send(&mut self, start_position: u64, end_position: u64) -> std::io::Result<()> {
+        self.pending.push_back((start_position, end_position));
+        self.send_pending()
+    }
+
+    // The body of the answer to the oldest request, which the server may
+    // have cut short. Any failure drops that request and the connection; the
+    // requests behind it are sent again on the next connection.
+    pub fn receive(&mut self) -> std::io::Result<Vec<u8>> {
+        let Some(&(start_position, end_position)) = self.pending.front() else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "No request is waiting for an answer"));
+        };
+        let answer = self.answer();
+        self.pending.pop_front();
+        self.sent = self.sent.saturating_sub(1);
+        let (head, body) = match answer {
+            Ok(answer) => answer,
+            Err(e) => {
+                self.drop_connection();
+                return Err(e);
+            }
+        };
+        // With one request out at a time, or a stream for each, there is
+        // nothing to mix it up with
+        if self.depth > 1 && !self.settings.http2 && head.status == 206 && head.content_range_start() != Some(start_position) {
+            self.depth = 1;
+            self.drop_connection();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("The answer for bytes {}-{} was for another range; no longer pipelining", start_position, end_position),
+            ));
+        }
+        if head.status == 403 {
+            return Err(Forbidden { url: self.url.to_string() }.into_error());
+        }
+        if head.status == 416 {
+            let range = format!("bytes={}-{}", start_position, end_position);
+            return Err(RangeNotSatisfiable { range, total_size: head.content_range_total() }.into_error());
+        }
+        if !(200..300).contains(&head.status) {
+            return Err(UnexpectedStatus { url: self.url.to_string(), status: head.status }.into_error());
+        }
+        if let Err(e) = check_range(&self.url, &head, start_position, end_position) {
+            self.drop_connection();
+            return Err(e);
+        }
+        self.send_pending()?;
+        Ok(body)
+    }
+
+    // Forget the requests still out, and the connection they went out on
+    pub fn reset(&mut self) {
+        self.drop_connection();
+        self.pending.clear();
+    }
+
+    fn drop_connection(&mut self) {
+        self.conn = None;
+        self.sent = 0;
+    }
+
+    // Put pending requests on the connection up to the depth. If it breaks
+    // while sending, they go out again on a new one when an answer is wanted.
+    fn send_pending(&mut self) -> std::io::Result<()> {
+        let Some((link, proxy)) = &mut self.conn else {
+            return Ok(());
+        };
+        match link {
+            Link::Http1(conn) => {
+                let mut requests = Vec::new();
+                let waiting = self.pending.iter().take(self.depth).skip(self.sent);
+                for &(start_position, end_position) in waiting {
+                    let headers = [range_header(start_position, end_position)];
+                    let mut outgoing = request_head("GET", &self.url, &headers, proxy.as_ref(), self.settings)?;
+                    outgoing.close = false;
+                    let request = joined(&outgoing.slices(&[]));
+                    if let Some(trace) = &self.settings.trace {
+                        trace.sent(&request);
+                    }
+                    requests.extend(request);
+                    self.sent += 1;
+                }
+                if !requests.is_empty() && conn.write_all(&requests).is_err() {
+                    self.drop_connection();
+                }
+            }
+            // A stream that can't be opened, after a GOAWAY or with the
+            // connection broken, waits for the streams already open to be
+            // answered and then goes out on a new connection
+            Link::Http2(h2, streams) => {
+                let waiting = self.pending.iter().take(self.depth.min(h2.max_streams())).skip(self.sent);
+                for &(start_position, end_position) in waiting {
+                    let headers = [range_header(start_position, end_position)];
+                    let mut outgoing = request_head("GET", &self.url, &headers, proxy.as_ref(), self.settings)?;
+                    outgoing.close = false;
+                    let fields = outgoing.fields();
+                    let Ok(id) = h2.open(outgoing.method, &outgoing.host, &outgoing.target, &fields, &[]) else {
+                        break;
+                    };
+                    if let Some(trace) = &self.settings.trace {
+                        trace.sent(&h2::describe(outgoing.method, &outgoing.host, &outgoing.target, &fields));
+                    }
+                    streams.push_back(id);
+                    self.sent += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Read the answer to the oldest pending request, connecting first if
+    // there is no connection. A connection that has answered before and
+    // closes before this answer begins, as servers do with connections they
+    // won't reuse, is made again, as is one whose server refused the stream.
+    fn answer(&mut self) -> std::io::Result<(ResponseHead, Vec<u8>)> {
+        loop {
+            if self.conn.is_none() {
+                let proxy = proxy_for(&self.url, self.settings)?;
+                let mut clock = Clock::start();
+                let conn = connect(&self.url, proxy.as_ref(), self.settings, &mut clock)?;
+                let link = if self.settings.http2 {
+                    if proxy.as_ref().is_some_and(|proxy| !proxy.tunnel) {
+                        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "HTTP/2 only goes through a proxy that tunnels"));
+                    }
+                    Link::Http2(Box::new(h2::Connection::handshake(conn, self.settings)?), VecDeque::new())
+                } else {
+                    Link::Http1(conn)
+                };
+                self.conn = Some((link, proxy));
+                self.watch = ReadWatch::new(self.settings);
+                self.answered = 0;
+                self.buffered.clear();
+                self.send_pending()?;
+            }
+            match self.read_answer()? {
+                Some(answer) => {
+                    self.answered += 1;
+                    return Ok(answer);
+                }
+                None => self.drop_connection(),
+            }
+        }
+    }
+
+    // The next answer on the connection, or None if it closed or was reset
+    // between answers after at least one
+    fn read_answer(&mut self) -> std::io::Result<Option<(ResponseHead, Vec<u8>)>> {
+        let (conn, proxy) = match &mut self.conn {
+            None => return Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "Connection dropped while sending the requests")),
+            Some((Link::Http1(conn), proxy)) => (conn, proxy),
+            Some((Link::Http2(..), _)) => return self.read_stream(),
+        };
+        let mut buffer = vec![0; self.settings.read_buffer.unwrap_or(DEFAULT_READ_BUFFER)];
+        // Until the head is in, then until the body is or, for a body
+        // without a length, until the connection closes
+        let mut split = None;
+        let mut closed = false;
+        loop {
+            if split.is_none() {
+                split = split_response(&self.buffered);
+            }
+            if let Some((_, _, Some(end))) = split
+                && self.buffered.len() >= end
+            {
+                break;
+            }
+            if self.settings.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+            }
+            let between = self.buffered.is_empty() && self.answered > 0;
+            match self.watch.read(conn, &mut buffer) {
+                Ok(0) if between => return Ok(None),
+                Err(e) if between && e.kind() == std::io::ErrorKind::ConnectionReset => return Ok(None),
+                Err(e) => return Err(e),
+                Ok(0) if split.as_ref().is_some_and(|(_, _, end)| end.is_none()) => {
+                    closed = true;
+                    break;
+                }
+                Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed before the whole answer arrived")),
+                Ok(n) => self.buffered.extend_from_slice(&buffer[..n]),
+            }
+        }
+        let (head, body_start, end) = split.expect("the loop ends once the head is in");
+        let end = end.unwrap_or(self.buffered.len());
+        if let Some(trace) = &self.settings.trace {
+            trace.received(&self.buffered[..body_start]);
+        }
+        check_proxy(&head, proxy.as_ref())?;
+        if let Some(jar) = &self.settings.cookies {
+            jar.receive(&self.url, &head);
+        }
+        let body = self.buffered[body_start..end].to_vec();
+        self.buffered.drain(..end);
+        // Whatever is still out goes on a new connection if this one ends here
+        if closed || head.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close")) {
+            self.drop_connection();
+        }
+        Ok(Some((head, body)))
+    }
+
+    // The answer on the oldest open stream, or None if the server refused it
+    // or no stream could be opened, after at least one answer
+    fn read_stream(&mut self) -> std::io::Result<Option<(ResponseHead, Vec<u8>)>> {
+        let Some((Link::Http2(h2, streams), proxy)) = &mut self.conn else {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotConnected, "No HTTP/2 connection"));
+        };
+        let response = match streams.pop_front() {
+            Some(id) => h2.response(id)?,
+            None => None,
+        };
+        let Some(response) = response else {
+            if self.answered == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "The server refused the HTTP/2 stream"));
+            }
+            return Ok(None);
+        };
+        let (head, body_start, end) =
+            split_response(&response).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Incomplete HTTP/2 answer"))?;
+        if let Some(trace) = &self.settings.trace {
+            trace.received(&response[..body_start]);
+        }
+        check_proxy(&head, proxy.as_ref())?;
+        if let Some(jar) = &self.settings.cookies {
+            jar.receive(&self.url, &head);
+        }
+        let body = response[body_start..end.unwrap_or(response.len())].to_vec();
+        Ok(Some((head, body)))
+    }
+}
+
+// The header asking for the bytes from start to end (inclusive)
+fn range_header(start_position: u64, end_position: u64) -> (String, String) {
+    ("Range".to_string(), format!("bytes={}-{}", start_position, end_position))
+}
+
+// Ask for the bytes from start to end (inclusive) and, when they come back as
+// a 206 of no more than that, move the body into the file at offset without
+// reading it, returning how long it was. A 2xx for other bytes fails as it
+// does for download_range; any other answer gives None with nothing written,
+// so the range can be fetched the usual way.
+pub(crate) fn splice_range(
+    url: &Url,
+    start_position: u64,
+    end_position: u64,
+    file: &File,
+    offset: u64,
+    settings: &Settings,
+) -> std::io::Result<Option<u64>> {
+    let mut watch = ReadWatch::new(settings);
+    let mut conn = request_range(url, start_position, end_position, settings)?;
+    let response = read_head(&mut conn, settings)?;
+    let (head, _, _) = split_response(&response).expect("read_head stops at a complete head");
+    if let Some(jar) = &settings.cookies {
+        jar.receive(url, &head);
+    }
+    check_range(url, &head, start_position, end_position)?;
+    match head.body_length("GET") {
+        Some(length) if head.status == 206 && (1..=end_position - start_position + 1).contains(&length) => {
+            splice::copy(&conn, file, offset, length, settings.cancel.as_ref(), &mut watch)?;
+            Ok(Some(length))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Read up to the end of the final response head and no further, peeking
+// first so none of the body is taken off the connection
+fn read_head(conn: &mut TcpStream, settings: &Settings) -> std::io::Result<Vec<u8>> {
+    let mut response = Vec::new();
+    let mut buffer = vec![0; settings.read_buffer.unwrap_or(DEFAULT_READ_BUFFER)];
+    loop {
+        let peeked = conn.peek(&mut buffer)?;
+        if peeked == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed before the response head"));
+        }
+        let before = response.len();
+        response.extend_from_slice(&buffer[..peeked]);
+        let head_end = split_response(&response).map(|(_, body_start, _)| body_start);
+        response.truncate(head_end.unwrap_or(response.len()));
+        conn.read_exact(&mut buffer[..response.len() - before])?;
+        if head_end.is_some() {
+            return Ok(response);
+        }
+    }
+}
+
+// Send a GET with extra request headers, returning the response head and body
+pub fn get(url: &Url, headers: &[(String, String)], settings: &Settings) -> std::io::Result<(ResponseHead, Vec<u8>)> {
+    let response = exchange(url, headers, settings)?;
+    Ok((response.head, response.body))
+}
+
+// Send a POST with the body, returning the response head and body
+pub fn post(url: &Url, headers: &[(String, String)], body: &[u8], settings: &Settings) -> std::io::Result<(ResponseHead, Vec<u8>)> {
+    let mut headers = headers.to_vec();
+    headers.push(("Content-Length".to_string(), body.len().to_string()));
+    let response = request("POST", url, &headers, body, settings)?;
+    Ok((response.head, response.body))
+}
+
+// Send a GET and read the whole response
+fn exchange(url: &Url, headers: &[(String, String)], settings: &Settings) -> std::io::Result<Response> {
+    request("GET", url, headers, &[], settings)
+}
+
+// Send a request with the body through the middleware and read the whole response
+fn request(method: &str, url: &Url, headers: &[(String, String)], body: &[u8], settings: &Settings) -> std::io::Result<Response> {
+    let read = |response: Vec<u8>| Response { head: ResponseHead::parse(&response), body: extract_body(&response) };
+    if settings.middleware.is_empty() {
+        return send(method, url, headers, body, settings).map(read);
+    }
+    let request = Request { method: method.to_string(), url: url.clone(), headers: headers.to_vec(), body: body.to_vec(), head_only: false };
+    settings.middleware.run(request, &|request| {
+        send(&request.method, &request.url, &request.headers, &request.body, settings).map(read)
+    })
+}
+
+// Send a PUT or other request whose body is read from body as it goes out,
+// length bytes of it, so a file of any size can be uploaded without being
+// held in memory; returns the response head and body. A body of
+// EXPECT_CONTINUE_MIN or more waits behind Expect: 100-continue, so a server
+// refusing the request at once gets none of it. Uploads go over HTTP/1.1 and
+// don't go through the middleware, their bodies never being in memory.
+pub fn upload(
+    method: &str,
+    url: &Url,
+    headers: &[(String, String)],
+    body: &mut dyn Read,
+    length: u64,
+    settings: &Settings,
+) -> std::io::Result<(ResponseHead, Vec<u8>)> {
+    if settings.http2 {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Uploads go over HTTP/1.1 only"));
+    }
+    let mut headers = headers.to_vec();
+    headers.push(("Content-Length".to_string(), length.to_string()));
+    let response = send_body(method, url, &headers, Body::Stream(body, length), settings)?;
+    Ok((ResponseHead::parse(&response), extract_body(&response)))
+}
+
+// A request body: in memory, or read as it is sent
+enum Body<'a> {
+    Bytes(&'a [u8]),
+    Stream(&'a mut dyn Read, u64),
+}
+
+impl Body<'_> {
+    fn len(&self) -> u64 {
+        match self {
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::Stream(_, length) => *length,
+        }
+    }
+}
+
+// Send a request with the body and read the whole response, head included
+fn send(method: &str, url: &Url, headers: &[(String, String)], body: &[u8], settings: &Settings) -> std::io::Result<Vec<u8>> {
+    send_body(method, url, headers, Body::Bytes(body), settings)
+}
+
+fn send_body(method: &str, url: &Url, headers: &[(String, String)], body: Body, settings: &Settings) -> std::io::Result<Vec<u8>> {
+    let proxy = proxy_for(url, settings)?;
+    let mut outgoing = request_head(method, url, headers, proxy.as_ref(), settings)?;
+    let expect = !settings.http2
+        && body.len() >= EXPECT_CONTINUE_MIN
+        && !outgoing.given.iter().any(|(name, _)| name.eq_ignore_ascii_case("Expect"));
+    if expect {
+        outgoing.added.push(("Expect".to_string(), "100-continue".to_string()));
+    }
+    
+    let mut clock = Clock::start();
+    let response = match body {
+        Body::Bytes(body) if settings.http2 => exchange_h2(url, &outgoing, body, false, proxy.as_ref(), settings, &mut clock)?,
+        Body::Stream(..) if settings.http2 => {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "A streamed body goes over HTTP/1.1 only"));
+        }
+        body => {
+            let mut conn = connect(url, proxy.as_ref(), settings, &mut clock)?;
+            // What came back while the body was held back, and whether it was
+            // the final answer, so the body is never sent
+            let (response, refused) = if expect {
+                write_all_vectored(&mut conn, &mut outgoing.slices(&[]))?;
+                await_continue(&mut conn, settings)?
+            } else {
+                (Vec::new(), false)
+            };
+            // Only kept whole when something records it, and a streamed body
+            // not at all
+            let request = (settings.trace.is_some() || settings.har.is_some()).then(|| match &body {
+                Body::Bytes(bytes) if !refused => joined(&outgoing.slices(bytes)),
+                _ => joined(&outgoing.slices(&[])),
+            });
+            let sent = match body {
+                _ if refused => Ok(()),
+                Body::Bytes(bytes) => {
+                    let mut slices = outgoing.slices(bytes);
+                    // The head went ahead of a held-back body, which is last
+                    let written = if expect { slices.len() - 1 } else { 0 };
+                    write_all_vectored(&mut conn, &mut slices[written..])
+                }
+                Body::Stream(reader, length) => {
+                    let head = if expect { Ok(()) } else { write_all_vectored(&mut conn, &mut outgoing.slices(&[])) };
+                    head.and_then(|()| stream_body(&mut conn, reader, length, settings))
+                }
+            };
+            clock.sent();
+            if let Some(trace) = &settings.trace {
+                trace.sent(request.as_deref().unwrap_or_default());
+            }
+            
+            // A server that stopped reading the body may have said why
+            let mut response = match (sent, read_response(&mut conn, response, method, settings, &mut clock)) {
+                (Ok(()), response) => response?,
+                (Err(_), Ok(response)) if !response.is_empty() => response,
+                (Err(e), _) => return Err(e),
+            };
+            
+            if let Some(trace) = &settings.trace {
+                trace.received(&response);
+            }
+            response.drain(..final_response_start(&response));
+            if let Some(har) = &settings.har {
+                har.record(url, request.as_deref().unwrap_or_default(), &response, &clock);
+            }
+            response
+        }
+    };
+    finish(&clock, settings);
+    let head_len = response.windows(4).position(|w| w == b"\r\n\r\n").map_or(response.len(), |i| i + 4);
+    let head = ResponseHead::parse(&response[..head_len]);
+    check_proxy(&head, proxy.as_ref())?;
+    if let Some(jar) = &settings.cookies {
+        jar.receive(url, &head);
+    }
+    
+    Ok(response)
+}
+
+// Copy length bytes from the reader to the connection, a buffer at a time
+fn stream_body(conn: &mut TcpStream, reader: &mut dyn Read, length: u64, settings: &Settings) -> std::io::Result<()> {
+    let mut buffer = vec![0; settings.read_buffer.unwrap_or(DEFAULT_READ_BUFFER)];
+    let mut sent: u64 = 0;
+    while sent < length {
+        if settings.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        let wanted = usize::try_from(length - sent).map_or(buffer.len(), |left| left.min(buffer.len()));
+        let n = match reader.read(&mut buffer[..wanted]) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("The body ended after {} of {} bytes", sent, length),
+                ));
+            }
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        conn.write_all(&buffer[..n])?;
+        sent += n as u64;
+    }
+    Ok(())
+}
+
+// Read the whole response after what already arrived, up to where its head
+// says it ends or the connection closes
+fn read_response(conn: &mut TcpStream, mut response: Vec<u8>, method: &str, settings: &Settings, clock: &mut Clock) -> std::io::Result<Vec<u8>> {
+    let mut buffer = vec![0; settings.read_buffer.unwrap_or(DEFAULT_READ_BUFFER)];
+    // Set once the head is in: where the response ends, if it says
+    let mut end = response_end(&response, method);
+    
+    let mut watch = ReadWatch::new(settings);
+    loop {
+        if let Some(Some(end)) = end
+            && response.len() >= end
+        {
+            response.truncate(end);
+            return Ok(response);
+        }
+        if settings.cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        match watch.read(conn, &mut buffer)? {
+            0 => return Ok(response),
+            n => {
+                clock.received();
+                response.extend_from_slice(&buffer[0..n]);
+                if end.is_none() {
+                    end = response_end(&response, method);
+                }
+            }
+        }
+    }
+}
+
+// After sending a head with Expect: 100-continue, wait a little for the
+// server's answer: what arrived, and whether it was final, such as a 413 or
+// a 401, so the held-back body is not to be sent. Nothing coming, or only a
+// 100, means sending the body; the connection closing before a whole head
+// is in fails with UnexpectedEof.
+fn await_continue(conn: &mut TcpStream, settings: &Settings) -> std::io::Result<(Vec<u8>, bool)> {
+    let wait = [Some(EXPECT_CONTINUE_WAIT), settings.timeout].into_iter().flatten().min();
+    conn.set_read_timeout(wait)?;
+    let started = Instant::now();
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+    let refused = loop {
+        if started.elapsed() >= EXPECT_CONTINUE_WAIT {
+            break false;
+        }
+        match conn.read(&mut buffer) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "The server closed the connection before answering Expect: 100-continue",
+                ));
+            }
+            Ok(n) => response.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break false,
+            Err(e) => return Err(e),
+        }
+        if final_response_start(&response) > 0 {
+            // A 100, or another interim answer that lets the body go
+            break false;
+        }
+        if response.windows(4).any(|w| w == b"\r\n\r\n") {
+            break true;
+        }
+    };
+    conn.set_read_timeout(read_timeout(settings))?;
+    Ok((response, refused))
+}
+
+// One request on a connection of its own speaking HTTP/2, read to the end,
+// or only to the end of the head when head_only, as HTTP/1.1-style bytes.
+// Only a proxy that tunnels can be gone through this way.
+#[allow(clippy::too_many_arguments)]
+fn exchange_h2(
+    url: &Url,
+    outgoing: &RequestHead,
+    body: &[u8],
+    head_only: bool,
+    proxy: Option<&Proxy>,
+    settings: &Settings,
+    clock: &mut Clock,
+) -> std::io::Result<Vec<u8>> {
+    if proxy.is_some_and(|proxy| !proxy.tunnel) {
+        return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "HTTP/2 only goes through a proxy that tunnels"));
+    }
+    let conn = connect(url, proxy, settings, clock)?;
+    let mut h2 = h2::Connection::handshake(conn, settings)?;
+    let fields = outgoing.fields();
+    let id = h2.open(outgoing.method, &outgoing.host, &outgoing.target, &fields, body)?;
+    clock.sent();
+    let request = (settings.trace.is_some() || settings.har.is_some())
+        .then(|| h2::describe(outgoing.method, &outgoing.host, &outgoing.target, &fields));
+    if let Some(trace) = &settings.trace {
+        trace.sent(request.as_deref().unwrap_or_default());
+    }
+    let response = if head_only { h2.head(id)? } else { h2.response(id)? };
+    let response = response.ok_or_else(|| std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "The server refused the HTTP/2 stream"))?;
+    if head_only {
+        // Done with the stream; the connection goes too
+        let _ = h2.cancel(id);
+    }
+    clock.received();
+    if let Some(trace) = &settings.trace {
+        trace.received(&response);
+    }
+    if let Some(har) = &settings.har {
+        har.record(url, request.as_deref().unwrap_or_default(), &response, clock);
+    }
+    Ok(response)
+}
+
+// A 407 from the proxy ends the request with ProxyAuthRequired
+fn check_proxy(head: &ResponseHead, proxy: Option<&Proxy>) -> std::io::Result<()> {
+    match proxy {
+        Some(proxy) if head.status == 407 && !proxy.tunnel => Err(ProxyAuthRequired {
+            proxy: proxy.url.authority(),
+            challenge: head.header("Proxy-Authenticate").map(str::to_string),
+            rejected: proxy.auth.is_some(),
+        }.into_error()),
+        _ => Ok(()),
+    }
+}
+
+// Where a response ends, interim responses included, once the final head has
+// arrived: Some(None) when the body runs until the connection closes, None
+// while the head is still incomplete
+fn response_end(response: &[u8], method: &str) -> Option<Option<usize>> {
+    let start = final_response_start(response);
+    let head_len = response[start..].windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let head = ResponseHead::parse(&response[start..start + head_len]);
+    Some(head.body_length(method).map(|length| body_end(start + head_len, length)))
+}
+
+// The final head of a GET response read so far, once it is all in, with
+// where the body starts and, if the head says, where it ends
+pub(crate) fn split_response(response: &[u8]) -> Option<(ResponseHead, usize, Option<usize>)> {
+    let start = final_response_start(response);
+    let head_len = response[start..].windows(4).position(|w| w == b"\r\n\r\n")? + 4;
+    let head = ResponseHead::parse(&response[start..start + head_len]);
+    let end = head.body_length("GET").map(|length| body_end(start + head_len, length));
+    Some((head, start + head_len, end))
+}
+
+// Where a body of the given length starting at start ends in memory. One too
+// long to ever be held in memory ends past anything that can be read.
+fn body_end(start: usize, length: u64) -> usize {
+    usize::try_from(length).map_or(usize::MAX, |length| start.saturating_add(length))
+}
+
+// Where the final response starts, past any interim 1xx responses such as
+// 100 Continue or 103 Early Hints that came before it. 101 Switching
+// Protocols is final. While an interim head is still incomplete this is
+// where it starts.
+fn final_response_start(response: &[u8]) -> usize {
+    let mut start = 0;
+    loop {
+        let rest = &response[start..];
+        // Status line, e.g. "HTTP/1.1 103 Early Hints"
+        let code = rest.strip_prefix(b"HTTP/")
+            .and_then(|line| line.iter().position(|&b| b == b' ').and_then(|space| line.get(space + 1..space + 4)));
+        let interim = code.is_some_and(|code| code[0] == b'1' && code != b"101");
+        if !interim {
+            return start;
+        }
+        match rest.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(end) => start += end + 4,
+            None => return start,
+        }
+    }
+}
+
+// Extract the HTTP body from a complete HTTP response
+fn extract_body(response: &[u8]) -> Vec<u8> {
+    // Look for the double CRLF that separates headers from body
+    let mut i = 0;
+    while i + 3 < response.len() {
+        if &response[i..i+4] == b"\r\n\r\n" {
+            return response[i+4..].to_vec();
+        }
+        i += 1;
+    }
+    
+    // If we can't find the separator, return an empty vector
+    // This is safer than returning potentially incorrect data
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, response};
+
+    // A part of a multipart/byteranges body, with the CRLF before its delimiter
+    fn part(boundary: &str, start: u64, data: &[u8]) -> Vec<u8> {
+        let head = format!("\r\n--{}\r\nContent-Type: text/plain\r\nContent-Range: bytes {}-{}/1000\r\n\r\n", boundary, start, start + data.len() as u64 - 1);
+        [head.as_bytes(), data].concat()
+    }
+
+    #[test]
+    fn parts_are_found_by_their_content_range() {
+        // The second part holds what looks like a delimiter, which has to be
+        // taken as data
+        let body = [part("sep", 0, b"abc"), part("sep", 100, b"\r\n--sep\r\n"), b"\r\n--sep--\r\n".to_vec()].concat();
+        let parts = byteranges(&body, "sep").unwrap();
+        assert_eq!(parts, vec![(0, b"abc".to_vec()), (100, b"\r\n--sep\r\n".to_vec())]);
+
+        // A preamble before the first delimiter and no CRLF before it
+        let body = [b"preamble".to_vec(), part("sep", 5, b"x")[2..].to_vec(), b"\r\n--sep--".to_vec()].concat();
+        assert_eq!(byteranges(&body, "sep").unwrap(), vec![(5, b"x".to_vec())]);
+        assert_eq!(byteranges(b"--sep--", "sep").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn malformed_parts_are_refused() {
+        // No closing delimiter, a part shorter than its range, a backwards
+        // range, and a part without a Content-Range
+        let unclosed = part("sep", 0, b"abc");
+        let short = b"--sep\r\nContent-Range: bytes 0-99/1000\r\n\r\nabc\r\n--sep--".to_vec();
+        let backwards = b"--sep\r\nContent-Range: bytes 9-3/10\r\n\r\n\r\n--sep--".to_vec();
+        let unlabelled = b"--sep\r\nContent-Type: text/plain\r\n\r\nabc\r\n--sep--".to_vec();
+        for body in [unclosed, short, backwards, unlabelled, b"no delimiter".to_vec()] {
+            assert_eq!(byteranges(&body, "sep"), None, "{:?}", String::from_utf8_lossy(&body));
+        }
+    }
+
+    // Answers every request with the response, handing back the Range headers asked with
+    fn serve(response: Vec<u8>) -> (Url, std::sync::mpsc::Receiver<String>) {
+        let (sender, ranges) = std::sync::mpsc::channel();
+        let url = testing::serve(move |head| {
+            let range = head.lines().find_map(|line| line.strip_prefix("Range: ")).unwrap_or_default();
+            let _ = sender.send(range.to_string());
+            response.clone()
+        });
+        (Url::parse(&url).unwrap(), ranges)
+    }
+
+    #[test]
+    fn several_ranges_in_one_request() {
+        let body = [part("a b", 0, b"abc"), part("a b", 10, b"klm"), b"\r\n--a b--\r\n".to_vec()].concat();
+        let (url, ranges) = serve(response("206 Partial Content", "Content-Type: multipart/byteranges; Boundary=\"a b\"\r\n", &body));
+        let parts = download_ranges(&url, &[(0, 2), (10, 12)], &Settings::default()).unwrap();
+        assert_eq!(parts, vec![(0, b"abc".to_vec()), (10, b"klm".to_vec())]);
+        assert_eq!(ranges.recv().unwrap(), "bytes=0-2,10-12");
+    }
+
+    #[test]
+    fn servers_may_answer_with_one_part_or_the_whole_body() {
+        let (url, _) = serve(response("206 Partial Content", "Content-Range: bytes 0-12/1000\r\n", b"abcdefghijklm"));
+        let parts = download_ranges(&url, &[(0, 2), (10, 12)], &Settings::default()).unwrap();
+        assert_eq!(parts, vec![(0, b"abcdefghijklm".to_vec())]);
+
+        let (url, _) = serve(response("200 OK", "", b"everything"));
+        let parts = download_ranges(&url, &[(0, 2), (10, 12)], &Settings::default()).unwrap();
+        assert_eq!(parts, vec![(0, b"everything".to_vec())]);
+    }
+
+    #[test]
+    fn unusable_answers_fail() {
+        let (url, _) = serve(response("206 Partial Content", "", b"abc"));
+        let error = download_ranges(&url, &[(0, 2), (10, 12)], &Settings::default()).unwrap_err();
+        assert_eq!(error.to_string(), "206 response without a Content-Range");
+
+        let (url, _) = serve(response("206 Partial Content", "Content-Type: multipart/byteranges; boundary=sep\r\n", b"--sep\r\n\r\nabc"));
+        let error = download_ranges(&url, &[(0, 2), (10, 12)], &Settings::default()).unwrap_err();
+        assert_eq!((error.kind(), error.to_string()), (std::io::ErrorKind::InvalidData, "Malformed multipart/byteranges response".to_string()));
+    }
+
+    #[test]
+    fn spliced_answers_for_other_bytes_are_refused() {
+        let path = testing::temp_path("splice");
+        let file = File::create(&path).unwrap();
+        let (url, _) = serve(response("206 Partial Content", "Content-Range: bytes 0-2/1000\r\n", b"abc"));
+        let error = splice_range(&url, 10, 12, &file, 10, &Settings::default()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let (url, _) = serve(response("200 OK", "", b"everything"));
+        let error = splice_range(&url, 10, 12, &file, 10, &Settings::default()).unwrap_err();
+        assert!(RangeIgnored::from_error(&error).is_some());
+        assert_eq!(file.metadata().unwrap().len(), 0);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,699 @@
+// Streaming decoders for DEFLATE (RFC 1951) and gzip (RFC 1952) data, after
+// the structure of zlib's "puff" reference decoder. Input is read as it is
+// needed and output produced as it is read, keeping only the 32KB of history
+// back-references can reach, so a body of any size can go through.
+
+use std::io::{Cursor, ErrorKind, Read};
+
+const MAX_BITS: usize = 15;
+const WINDOW: usize = 32 * 1024;
+// Bytes of input read at a time
+const INPUT_BUFFER: usize = 32 * 1024;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// Order code length code lengths are sent in for dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn invalid(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, format!("Invalid deflate data: {}", message))
+}
+
+// Reads the input least significant bit first, as DEFLATE packs it
+struct BitReader<R> {
+    input: R,
+    buffer: Vec<u8>,
+    // Bytes of the buffer filled by the last read, and those used of them
+    filled: usize,
+    pos: usize,
+    // Bytes taken from the input so far
+    consumed: u64,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(input: R) -> BitReader<R> {
+        BitReader { input, buffer: Vec::new(), filled: 0, pos: 0, consumed: 0, bit_buf: 0, bit_count: 0 }
+    }
+
+    // The next whole byte of input, ignoring the bit buffer
+    fn byte(&mut self) -> std::io::Result<u8> {
+        if self.pos == self.filled {
+            self.fill()?;
+        }
+        let byte = self.buffer[self.pos];
+        self.pos += 1;
+        self.consumed += 1;
+        Ok(byte)
+    }
+
+    // Append the next count bytes of input to out
+    fn copy(&mut self, out: &mut Vec<u8>, mut count: usize) -> std::io::Result<()> {
+        while count > 0 {
+            if self.pos == self.filled {
+                self.fill()?;
+            }
+            let take = count.min(self.filled - self.pos);
+            out.extend_from_slice(&self.buffer[self.pos..self.pos + take]);
+            self.pos += take;
+            self.consumed += take as u64;
+            count -= take;
+        }
+        Ok(())
+    }
+
+    // Read more input into the buffer once it has all been used
+    fn fill(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            self.buffer = vec![0; INPUT_BUFFER];
+        }
+        self.filled = loop {
+            match self.input.read(&mut self.buffer) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        };
+        self.pos = 0;
+        if self.filled == 0 { Err(ErrorKind::UnexpectedEof.into()) } else { Ok(()) }
+    }
+
+    fn bits(&mut self, count: u32) -> std::io::Result<u32> {
+        while self.bit_count < count {
+            let byte = self.byte()?;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1u64 << count) - 1) as u32;
+        self.bit_buf = if count == 32 { 0 } else { self.bit_buf >> count };
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    // Drop the bits left in the current byte
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+}
+
+// A canonical Huffman code: the number of codes of each length, and the
+// symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> std::io::Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+
+        // Over-subscribed codes can't be decoded; incomplete ones are allowed
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(invalid("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, input: &mut BitReader<impl Read>) -> std::io::Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= input.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+enum Block {
+    // Between blocks, about to read a block header
+    Header,
+    Stored(usize),
+    Huffman(Huffman, Huffman),
+    Done,
+}
+
+// A DEFLATE decoder reading from a stream, or a slice of memory
+pub struct Inflater<R> {
+    input: BitReader<R>,
+    block: Block,
+    last_block: bool,
+    // Decoded output; everything from `unread` on hasn't been returned yet
+    history: Vec<u8>,
+    unread: usize,
+}
+
+impl<R: Read> Inflater<R> {
+    pub fn new(input: R) -> Inflater<R> {
+        Inflater {
+            input: BitReader::new(input),
+            block: Block::Header,
+            last_block: false,
+            history: Vec::new(),
+            unread: 0,
+        }
+    }
+
+    // Bytes of input used, valid once the whole stream has been read
+    pub fn consumed(&self) -> u64 {
+        self.input.consumed
+    }
+
+    // Decode some more output into the history
+    fn step(&mut self) -> std::io::Result<()> {
+        match &mut self.block {
+            Block::Done => {}
+            Block::Header if self.last_block => self.block = Block::Done,
+            Block::Header => {
+                self.last_block = self.input.bits(1)? == 1;
+                self.block = match self.input.bits(2)? {
+                    0 => {
+                        self.input.align();
+                        let mut header = [0u8; 4];
+                        for byte in &mut header {
+                            *byte = self.input.byte()?;
+                        }
+                        let len = u16::from_le_bytes([header[0], header[1]]);
+                        let nlen = u16::from_le_bytes([header[2], header[3]]);
+                        if len != !nlen {
+                            return Err(invalid("stored block length mismatch"));
+                        }
+                        Block::Stored(len as usize)
+                    }
+                    1 => fixed_codes()?,
+                    2 => dynamic_codes(&mut self.input)?,
+                    _ => return Err(invalid("reserved block type")),
+                };
+            }
+            Block::Stored(remaining) => {
+                let take = (*remaining).min(WINDOW);
+                self.input.copy(&mut self.history, take)?;
+                *remaining -= take;
+                if *remaining == 0 {
+                    self.block = Block::Header;
+                }
+            }
+            Block::Huffman(literals, distances) => {
+                let target = self.history.len() + WINDOW;
+                while self.history.len() < target {
+                    let symbol = literals.decode(&mut self.input)? as usize;
+                    if symbol < 256 {
+                        self.history.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        self.block = Block::Header;
+                        break;
+                    }
+
+                    let symbol = symbol - 257;
+                    if symbol >= LENGTH_BASE.len() {
+                        return Err(invalid("bad length symbol"));
+                    }
+                    let len = LENGTH_BASE[symbol] as usize + self.input.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+                    let symbol = distances.decode(&mut self.input)? as usize;
+                    if symbol >= DIST_BASE.len() {
+                        return Err(invalid("bad distance symbol"));
+                    }
+                    let dist = DIST_BASE[symbol] as usize + self.input.bits(DIST_EXTRA[symbol] as u32)? as usize;
+                    if dist > self.history.len() {
+                        return Err(invalid("distance too far back"));
+                    }
+
+                    // Byte by byte, since the copy may overlap what it produces
+                    let start = self.history.len() - dist;
+                    for i in 0..len {
+                        self.history.push(self.history[start + i]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.unread == self.history.len() {
+            if matches!(self.block, Block::Done) {
+                return Ok(0);
+            }
+            // Keep only the history back-references can still reach
+            if self.history.len() > 2 * WINDOW {
+                let drop = self.history.len() - WINDOW;
+                self.history.drain(..drop);
+                self.unread -= drop;
+            }
+            self.step()?;
+        }
+
+        let n = buf.len().min(self.history.len() - self.unread);
+        buf[..n].copy_from_slice(&self.history[self.unread..self.unread + n]);
+        self.unread += n;
+        Ok(n)
+    }
+}
+
+fn fixed_codes() -> std::io::Result<Block> {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok(Block::Huffman(Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+fn dynamic_codes(input: &mut BitReader<impl Read>) -> std::io::Result<Block> {
+    let literal_count = input.bits(5)? as usize + 257;
+    let distance_count = input.bits(5)? as usize + 1;
+    let code_length_count = input.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(invalid("too many codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[i] = input.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = code_length_code.decode(input)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 if i == 0 => return Err(invalid("repeat with no previous length")),
+            16 => (lengths[i - 1], 3 + input.bits(2)? as usize),
+            17 => (0, 3 + input.bits(3)? as usize),
+            _ => (0, 11 + input.bits(7)? as usize),
+        };
+        if i + repeat > lengths.len() {
+            return Err(invalid("too many code lengths"));
+        }
+        lengths[i..i + repeat].fill(value);
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(invalid("no end of block code"));
+    }
+
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok(Block::Huffman(literals, distances))
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+// Running CRC-32 as used by gzip and zip; start with 0
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |c, &b| CRC_TABLE[((c ^ b as u32) & 0xff) as usize] ^ (c >> 8))
+}
+
+// Decodes a gzip member, checking its CRC-32 and length at the end
+pub struct GzDecoder<R> {
+    inflater: Inflater<R>,
+    crc: u32,
+    size: u32,
+}
+
+impl<R: Read> GzDecoder<R> {
+    // Read the gzip header, leaving the compressed data to be read
+    pub fn new(input: R) -> std::io::Result<GzDecoder<R>> {
+        let mut inflater = Inflater::new(input);
+        read_gzip_header(&mut inflater.input)?;
+        Ok(GzDecoder { inflater, crc: 0, size: 0 })
+    }
+}
+
+// Whether data starts with the gzip magic bytes
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&[0x1f, 0x8b])
+}
+
+fn read_gzip_header(input: &mut BitReader<impl Read>) -> std::io::Result<()> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    let truncated = |e: std::io::Error| match e.kind() {
+        ErrorKind::UnexpectedEof => std::io::Error::new(ErrorKind::UnexpectedEof, "Truncated gzip header"),
+        _ => e,
+    };
+    // The magic bytes and the method, which has to be deflate
+    for expected in [0x1f, 0x8b, 8] {
+        match input.byte() {
+            Ok(byte) if byte == expected => {}
+            Ok(_) => return Err(std::io::Error::new(ErrorKind::InvalidData, "Not gzip data")),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "Not gzip data"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let mut fixed = [0u8; 7];
+    for byte in &mut fixed {
+        *byte = input.byte().map_err(truncated)?;
+    }
+    let flags = fixed[0];
+
+    if flags & FEXTRA != 0 {
+        let mut len = [0u8; 2];
+        for byte in &mut len {
+            *byte = input.byte().map_err(truncated)?;
+        }
+        for _ in 0..u16::from_le_bytes(len) {
+            input.byte().map_err(truncated)?;
+        }
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            while input.byte().map_err(truncated)? != 0 {}
+        }
+    }
+    if flags & FHCRC != 0 {
+        for _ in 0..2 {
+            input.byte().map_err(truncated)?;
+        }
+    }
+    Ok(())
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inflater.read(buf)?;
+        self.crc = crc32(self.crc, &buf[..n]);
+        self.size = self.size.wrapping_add(n as u32);
+
+        if n == 0 && !buf.is_empty() {
+            // The trailer starts at the next whole byte after the last block
+            let input = &mut self.inflater.input;
+            input.align();
+            let mut trailer = [0u8; 8];
+            for byte in &mut trailer {
+                *byte = input.byte()
+                    .map_err(|_| std::io::Error::new(ErrorKind::UnexpectedEof, "Truncated gzip trailer"))?;
+            }
+            let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+            let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+            if crc != self.crc || size != self.size {
+                return Err(std::io::Error::new(ErrorKind::InvalidData, "gzip CRC or length mismatch"));
+            }
+        }
+
+        Ok(n)
+    }
+}
+
+// Undo an HTTP Content-Encoding such as "gzip" or "deflate", a list of them
+// in the reverse of the order they were applied. deflate is meant to be
+// zlib-wrapped, but some servers send bare DEFLATE, which is taken too.
+pub fn decode_content(encoding: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    content_decoder(encoding, Box::new(data))?.read_to_end(&mut body)?;
+    Ok(body)
+}
+
+// As decode_content, for a body read as it is decoded
+pub fn content_decoder<'a>(encoding: &str, input: Box<dyn Read + 'a>) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut body = input;
+    for coding in encoding.rsplit(',').map(|coding| coding.trim().to_ascii_lowercase()) {
+        body = match coding.as_str() {
+            "" | "identity" => body,
+            "gzip" | "x-gzip" => Box::new(GzDecoder::new(body)?),
+            "deflate" => Box::new(Inflater::new(zlib_body(body)?)),
+            other => {
+                return Err(std::io::Error::new(ErrorKind::Unsupported, format!("Can't decode a {} Content-Encoding", other)));
+            }
+        };
+    }
+    Ok(body)
+}
+
+// The DEFLATE stream in zlib data, or the data itself when it has no zlib
+// header. The Adler-32 at the end is left unchecked.
+fn zlib_body<'a>(mut input: Box<dyn Read + 'a>) -> std::io::Result<Box<dyn Read + 'a>> {
+    let mut header = Vec::with_capacity(2);
+    (&mut input).take(2).read_to_end(&mut header)?;
+    match header[..] {
+        [method, flags] if method & 0x0f == 8 && (u16::from(method) << 8 | u16::from(flags)) % 31 == 0 => Ok(input),
+        _ => Ok(Box::new(Cursor::new(header).chain(input))),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    fn inflate(data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Inflater::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    // Hands out its data a byte per read, so every refill lands mid-stream
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else { return Ok(0) };
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    // Writes DEFLATE's bit order: values least significant bit first,
+    // Huffman codes most significant bit first
+    #[derive(Default)]
+    struct Bits {
+        out: Vec<u8>,
+        acc: u32,
+        count: u32,
+    }
+
+    impl Bits {
+        fn put(&mut self, value: u32, count: u32) {
+            for i in 0..count {
+                self.acc |= (value >> i & 1) << self.count;
+                self.count += 1;
+                if self.count == 8 {
+                    self.out.push(self.acc as u8);
+                    (self.acc, self.count) = (0, 0);
+                }
+            }
+        }
+
+        fn code(&mut self, code: u32, len: u32) {
+            self.put(code.reverse_bits() >> (32 - len), len);
+        }
+
+        // A symbol in the fixed literal/length code
+        fn fixed(&mut self, symbol: u32) {
+            match symbol {
+                0..=143 => self.code(0x30 + symbol, 8),
+                256..=279 => self.code(symbol - 256, 7),
+                _ => self.code(0xc0 + symbol - 280, 8),
+            }
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            if self.count > 0 {
+                self.out.push(self.acc as u8);
+            }
+            self.out
+        }
+    }
+
+    // Stored blocks of up to 65535 bytes each
+    fn stored(data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let blocks: Vec<&[u8]> = data.chunks(65535).collect();
+        for (i, block) in blocks.iter().enumerate() {
+            out.push(u8::from(i == blocks.len() - 1));
+            let len = block.len() as u16;
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(!len).to_le_bytes());
+            out.extend_from_slice(block);
+        }
+        out
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn fixed_huffman_block() {
+        assert_eq!(inflate(&hex("cb48cdc9c957c84022cbf38b7252b800")).unwrap(), b"hello hello hello world\n");
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        let data = hex(concat!(
+            "9dd55b16c1500c46e177a3c810e40f2d66e37268397a68d56df41633b09fb3f653be95e4b64b365dd9ad49761ddbedc936",
+            "7d7974b62f4f3b8ee7cb60e59efadf38afdf2fdb95c3247f1b078d4013a09981660e9a0a34356816a059929d2208448213",
+            "0a4e2c38c1e04483130e4e3c3801e144848808a1db40448888101121224244848808111122228288082222d0bb20228288",
+            "082222888820228288883f457c00",
+        ));
+        let expected: Vec<u8> = (0..40)
+            .flat_map(|i| format!("line {}: the quick brown fox jumps over the lazy dog\n", i).into_bytes())
+            .collect();
+        assert_eq!(inflate(&data).unwrap(), expected);
+        let mut trickled = Vec::new();
+        Inflater::new(Trickle(&data)).read_to_end(&mut trickled).unwrap();
+        assert_eq!(trickled, expected);
+    }
+
+    #[test]
+    fn stored_blocks_read_in_pieces() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let encoded = stored(&data);
+        assert_eq!(inflate(&encoded).unwrap(), data);
+        let mut trickled = Vec::new();
+        Inflater::new(Trickle(&encoded)).read_to_end(&mut trickled).unwrap();
+        assert_eq!(trickled, data);
+    }
+
+    #[test]
+    fn back_references_reach_the_whole_window() {
+        let mut bits = Bits::default();
+        bits.put(1, 1);
+        bits.put(1, 2);
+        let mut expected = b"abcd".to_vec();
+        for &b in b"abcd" {
+            bits.fixed(b as u32);
+        }
+        // 258 bytes from 4 back, over and over, well past the window size
+        for _ in 0..200 {
+            bits.fixed(285);
+            bits.code(3, 5);
+            for _ in 0..258 {
+                expected.push(expected[expected.len() - 4]);
+            }
+        }
+        // 3 bytes from exactly 32768 back: distance code 29 plus 8191
+        bits.fixed(257);
+        bits.code(29, 5);
+        bits.put(8191, 13);
+        for _ in 0..3 {
+            expected.push(expected[expected.len() - WINDOW]);
+        }
+        bits.fixed(256);
+        assert_eq!(inflate(&bits.finish()).unwrap(), expected);
+    }
+
+    #[test]
+    fn distance_before_the_start_is_refused() {
+        let mut bits = Bits::default();
+        bits.put(1, 1);
+        bits.put(1, 2);
+        bits.fixed(b'a' as u32);
+        bits.fixed(257);
+        bits.code(1, 5);
+        bits.fixed(256);
+        let error = inflate(&bits.finish()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(error.to_string().contains("distance too far back"));
+    }
+
+    #[test]
+    fn bad_block_headers_are_refused() {
+        assert!(inflate(&[0x07]).unwrap_err().to_string().contains("reserved block type"));
+        assert!(inflate(&[0x01, 0x05, 0x00, 0x00, 0x00]).unwrap_err().to_string().contains("stored block length mismatch"));
+        assert_eq!(inflate(&[0x01, 0x05, 0x00, 0xfa, 0xff, b'a']).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn gzip_header_fields_are_skipped() {
+        let data = hex("1f8b081e0000000000ff03006162636e616d652e74787400636f6d6d656e740057674bafca2c5048ca4fa9e402009cdb1d400a000000");
+        let mut body = Vec::new();
+        GzDecoder::new(&data[..]).unwrap().read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"gzip body\n");
+    }
+
+    #[test]
+    fn gzip_trailer_is_checked() {
+        let mut data = hex("1f8b081e0000000000ff03006162636e616d652e74787400636f6d6d656e740057674bafca2c5048ca4fa9e402009cdb1d400a000000");
+        let crc = data.len() - 8;
+        data[crc] ^= 1;
+        let error = GzDecoder::new(&data[..]).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(error.to_string().contains("CRC or length mismatch"));
+        let error = GzDecoder::new(&data[..data.len() - 3]).unwrap().read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Truncated gzip trailer");
+    }
+
+    #[test]
+    fn gzip_headers_are_checked() {
+        assert_eq!(GzDecoder::new(&b"PK\x03\x04"[..]).err().unwrap().to_string(), "Not gzip data");
+        assert_eq!(GzDecoder::new(&[0x1f, 0x8b, 0x08, 0x08, 0, 0][..]).err().unwrap().to_string(), "Truncated gzip header");
+    }
+
+    #[test]
+    fn content_encodings_are_undone_in_reverse() {
+        assert_eq!(decode_content("deflate", &hex("789cabcac94c52282f4a2c28484de1020023d304cf")).unwrap(), b"zlib wrapped\n");
+        assert_eq!(decode_content("deflate", &hex("cb48cdc9c957c84022cbf38b7252b800")).unwrap(), b"hello hello hello world\n");
+        let twice = hex("1f8b0800000000000203ab98a3ad79dec7ef291303c72626750005a82f170e000000");
+        assert_eq!(decode_content("deflate, GZIP", &twice).unwrap(), b"twice\n");
+        assert_eq!(decode_content("identity", b"as is").unwrap(), b"as is");
+        assert_eq!(decode_content("br", b"").unwrap_err().kind(), ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,349 @@
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+// Hash algorithms an expected digest can be given in, weakest first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Algorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl Algorithm {
+    // Name as used in Subresource Integrity strings
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha384 => "sha384",
+            Algorithm::Sha512 => "sha512",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Some(Algorithm::Sha256),
+            "sha384" => Some(Algorithm::Sha384),
+            "sha512" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    fn digest_len(self) -> usize {
+        match self {
+            Algorithm::Sha256 => 32,
+            Algorithm::Sha384 => 48,
+            Algorithm::Sha512 => 64,
+        }
+    }
+
+    // Hash the given data with this algorithm
+    pub fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Sha256 => Sha256::digest(data).to_vec(),
+            Algorithm::Sha384 => Sha384::digest(data).to_vec(),
+            Algorithm::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+}
+
+// Hashes data that comes a piece at a time, such as a file too big to read
+// into memory
+#[derive(Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha384 => Hasher::Sha384(Sha384::new()),
+            Algorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Hasher::Sha256(_) => Algorithm::Sha256,
+            Hasher::Sha384(_) => Algorithm::Sha384,
+            Hasher::Sha512(_) => Algorithm::Sha512,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha384(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha384(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+// Hashes a body in SHA-256 and the algorithm of every expected digest at
+// once, as it goes by
+#[derive(Clone)]
+pub struct Digests {
+    hashers: Vec<Hasher>,
+}
+
+impl Digests {
+    pub fn new(expected: &[ExpectedDigest]) -> Digests {
+        let mut hashers = vec![Hasher::new(Algorithm::Sha256)];
+        for digest in expected {
+            if !hashers.iter().any(|hasher| hasher.algorithm() == digest.algorithm) {
+                hashers.push(Hasher::new(digest.algorithm));
+            }
+        }
+        Digests { hashers }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for hasher in &mut self.hashers {
+            hasher.update(data);
+        }
+    }
+
+    // Hash everything input has left, a buffer at a time; returns its length
+    pub fn read_from(&mut self, mut input: impl std::io::Read) -> std::io::Result<u64> {
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut total = 0;
+        loop {
+            match input.read(&mut buffer) {
+                Ok(0) => return Ok(total),
+                Ok(n) => {
+                    self.update(&buffer[..n]);
+                    total += n as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // The digests, SHA-256 first
+    pub fn finish(self) -> Vec<(Algorithm, Vec<u8>)> {
+        self.hashers.into_iter().map(|hasher| (hasher.algorithm(), hasher.finish())).collect()
+    }
+}
+
+// Check finished digests against the expected ones, as verify does
+pub fn check_digests(expected: &[ExpectedDigest], digests: &[(Algorithm, Vec<u8>)]) -> std::io::Result<Vec<Algorithm>> {
+    check(expected, |algorithm| {
+        digests.iter()
+            .find(|(hashed, _)| *hashed == algorithm)
+            .map(|(_, digest)| digest.clone())
+            .expect("every expected algorithm is hashed")
+    })
+}
+
+// A digest the downloaded body is expected to match
+#[derive(Clone, Debug)]
+pub struct ExpectedDigest {
+    pub algorithm: Algorithm,
+    pub bytes: Vec<u8>,
+}
+
+// Parse a plain hex SHA-256 digest, as printed by sha256sum
+pub fn parse_hex_sha256(value: &str) -> Result<ExpectedDigest, String> {
+    let bytes = decode_hex(value.trim())
+        .filter(|bytes| bytes.len() == Algorithm::Sha256.digest_len())
+        .ok_or(format!("Invalid SHA-256 hex digest: {}", value))?;
+
+    Ok(ExpectedDigest { algorithm: Algorithm::Sha256, bytes })
+}
+
+// Parse a Subresource Integrity string such as "sha256-<base64>".
+// Several space separated entries may be given; "?options" suffixes are ignored.
+pub fn parse_sri(value: &str) -> Result<Vec<ExpectedDigest>, String> {
+    let mut digests = Vec::new();
+
+    for token in value.split_whitespace() {
+        let token = token.split('?').next().unwrap_or(token);
+        let (name, encoded) = token.split_once('-')
+            .ok_or(format!("Invalid integrity entry: {}", token))?;
+        let algorithm = Algorithm::from_name(name)
+            .ok_or(format!("Unsupported integrity algorithm: {}", name))?;
+        let bytes = decode_base64(encoded)
+            .filter(|bytes| bytes.len() == algorithm.digest_len())
+            .ok_or(format!("Invalid {} integrity digest: {}", name, encoded))?;
+
+        digests.push(ExpectedDigest { algorithm, bytes });
+    }
+
+    if digests.is_empty() {
+        return Err("Empty integrity string".to_string());
+    }
+
+    Ok(digests)
+}
+
+// The error a body that doesn't hash to what it was expected to ends with,
+// carried inside an io::Error of kind Other. Use Mismatch::from_error to get
+// at it.
+#[derive(Debug)]
+pub struct Mismatch {
+    // Name of the hash that differed, such as "sha256" or "md5"
+    pub algorithm: &'static str,
+    pub reason: String,
+}
+
+impl Mismatch {
+    pub fn from_error(error: &std::io::Error) -> Option<&Mismatch> {
+        error.get_ref()?.downcast_ref::<Mismatch>()
+    }
+
+    pub fn into_error(self) -> std::io::Error {
+        std::io::Error::other(self)
+    }
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+// Check data against the expected digests. As Subresource Integrity has it,
+// only those in the strongest algorithm given count, and one of them has to
+// match; returns the algorithm checked.
+pub fn verify(expected: &[ExpectedDigest], data: &[u8]) -> std::io::Result<Vec<Algorithm>> {
+    check(expected, |algorithm| algorithm.hash(data))
+}
+
+// As verify, with the digest in each algorithm worked out by actual, for
+// bodies hashed as they went by
+pub fn check(expected: &[ExpectedDigest], actual: impl Fn(Algorithm) -> Vec<u8>) -> std::io::Result<Vec<Algorithm>> {
+    let Some(strongest) = expected.iter().map(|digest| digest.algorithm).max() else {
+        return Ok(Vec::new());
+    };
+
+    let actual = actual(strongest);
+    if !expected.iter().any(|d| d.algorithm == strongest && d.bytes == actual) {
+        return Err(Mismatch {
+            algorithm: strongest.name(),
+            reason: format!("Integrity check failed: {} of the download is {}", strongest.name(), to_sri(strongest, &actual)),
+        }
+        .into_error());
+    }
+
+    Ok(vec![strongest])
+}
+
+// Format a digest in Subresource Integrity form
+pub fn to_sri(algorithm: Algorithm, bytes: &[u8]) -> String {
+    format!("{}-{}", algorithm.name(), encode_base64(bytes))
+}
+
+pub fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Decode standard base64, also accepting the URL-safe alphabet
+pub fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let value = value.trim_end_matches('=');
+    let mut out = Vec::with_capacity(value.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for c in value.bytes() {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+
+    Some(out)
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().fold(0u32, |acc, &b| (acc << 8) | b as u32) << (8 * (3 - chunk.len()));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_strongest_algorithm_counts() {
+        let data = b"hello";
+        let sha512 = to_sri(Algorithm::Sha512, &Algorithm::Sha512.hash(data));
+        let stale = to_sri(Algorithm::Sha256, &Algorithm::Sha256.hash(b"old"));
+        let expected = parse_sri(&format!("{} {}", stale, sha512)).unwrap();
+        assert_eq!(verify(&expected, data).unwrap(), vec![Algorithm::Sha512]);
+    }
+
+    #[test]
+    fn any_digest_of_the_strongest_matching_is_enough() {
+        let data = b"hello";
+        let wrong = to_sri(Algorithm::Sha384, &Algorithm::Sha384.hash(b"other"));
+        let right = to_sri(Algorithm::Sha384, &Algorithm::Sha384.hash(data));
+        let expected = parse_sri(&format!("{} {}", wrong, right)).unwrap();
+        assert!(verify(&expected, data).is_ok());
+    }
+
+    #[test]
+    fn a_mismatch_in_the_strongest_fails() {
+        let data = b"hello";
+        let sha256 = to_sri(Algorithm::Sha256, &Algorithm::Sha256.hash(data));
+        let stale = to_sri(Algorithm::Sha512, &Algorithm::Sha512.hash(b"old"));
+        let expected = parse_sri(&format!("{} {}", sha256, stale)).unwrap();
+        let error = verify(&expected, data).unwrap_err();
+        assert_eq!(Mismatch::from_error(&error).unwrap().algorithm, "sha512");
+    }
+
+    #[test]
+    fn digests_hashed_in_pieces_match_the_whole() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let expected = parse_sri(&to_sri(Algorithm::Sha384, &Algorithm::Sha384.hash(&data))).unwrap();
+        let mut digests = Digests::new(&expected);
+        assert_eq!(digests.read_from(&data[..]).unwrap(), data.len() as u64);
+        let digests = digests.finish();
+        assert_eq!(digests[0], (Algorithm::Sha256, Algorithm::Sha256.hash(&data)));
+        assert_eq!(check_digests(&expected, &digests).unwrap(), vec![Algorithm::Sha384]);
+    }
+}
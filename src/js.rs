@@ -0,0 +1,1298 @@
+// Just enough JavaScript for proxy auto-config scripts and the client's
+// --script callbacks: functions, var, if/else, loops, numbers, strings,
+// arrays and the string methods PAC files use on host names. There are no
+// objects, regular expressions, exceptions or closures over an enclosing
+// function's variables.
+//
+// A Program is parsed once and run afresh for every call, so it can be shared
+// between threads while the values of a run stay on the thread running it.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Nesting deeper than this is refused rather than risking the stack
+const MAX_DEPTH: usize = 64;
+const MAX_CALL_DEPTH: usize = 100;
+// Statements and calls a run may take, so a script stuck in a loop fails
+const MAX_STEPS: usize = 1_000_000;
+
+// Longest first, so "===" isn't read as "==" and "="
+const PUNCTUATION: &[&str] = &[
+    "===", "!==", "==", "!=", "<=", ">=", "&&", "||", "++", "--", "+=", "-=", "*=", "/=", "%=",
+    "{", "}", "(", ")", "[", "]", ";", ",", "<", ">", "+", "-", "*", "/", "%", "!", "?", ":", "=", ".",
+];
+
+// Operators of each binary precedence level, loosest first
+const BINARY_LEVELS: &[&[&str]] = &[
+    &["||"],
+    &["&&"],
+    &["==", "!=", "===", "!=="],
+    &["<", ">", "<=", ">="],
+    &["+", "-"],
+    &["*", "/", "%"],
+];
+
+const ASSIGNMENTS: &[&str] = &["=", "+=", "-=", "*=", "/=", "%="];
+
+// Functions every run has, besides those the host adds
+const GLOBAL_FUNCTIONS: &[&str] = &["parseInt", "parseFloat", "isNaN", "String", "Number"];
+
+// What the host runs for the natives it adds
+pub type Host<'a, 'h> = &'h dyn Fn(&str, &[Value<'a>]) -> Result<Value<'a>, String>;
+
+// A value during a run. Functions point into the program they came from.
+#[derive(Clone, Debug)]
+pub enum Value<'a> {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Rc<RefCell<Vec<Value<'a>>>>),
+    Function(&'a Function),
+    // One of GLOBAL_FUNCTIONS or the host's functions
+    Native(&'static str),
+}
+
+impl Value<'_> {
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Undefined | Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::String(s) => !s.is_empty(),
+            Value::Array(_) | Value::Function(_) | Value::Native(_) => true,
+        }
+    }
+
+    pub fn to_number(&self) -> f64 {
+        match self {
+            Value::Undefined => f64::NAN,
+            Value::Null => 0.0,
+            Value::Bool(b) => f64::from(u8::from(*b)),
+            Value::Number(n) => *n,
+            Value::String(s) => string_to_number(s),
+            Value::Array(_) => string_to_number(&self.to_string()),
+            Value::Function(_) | Value::Native(_) => f64::NAN,
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Undefined => "undefined",
+            Value::Null | Value::Array(_) => "object",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+        }
+    }
+}
+
+// As JavaScript's String() would convert it
+impl fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Undefined => write!(f, "undefined"),
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", number_to_string(*n)),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Array(items) => {
+                let items: Vec<String> = items.borrow().iter()
+                    .map(|item| match item {
+                        Value::Undefined | Value::Null => String::new(),
+                        item => item.to_string(),
+                    })
+                    .collect();
+                write!(f, "{}", items.join(","))
+            }
+            Value::Function(function) => write!(f, "function {}() {{ [code] }}", function.name),
+            Value::Native(name) => write!(f, "function {}() {{ [native code] }}", name),
+        }
+    }
+}
+
+fn number_to_string(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == n.trunc() && n.abs() < 1e21 {
+        format!("{}", n as i64)
+    } else {
+        format!("{}", n)
+    }
+}
+
+fn string_to_number(s: &str) -> f64 {
+    let s = s.trim();
+    if s.is_empty() {
+        return 0.0;
+    }
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16).map_or(f64::NAN, |n| n as f64);
+    }
+    match s {
+        "Infinity" | "+Infinity" => f64::INFINITY,
+        "-Infinity" => f64::NEG_INFINITY,
+        // Rust would also take "inf" and "nan"
+        s if s.chars().all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-')) => {
+            s.parse().unwrap_or(f64::NAN)
+        }
+        _ => f64::NAN,
+    }
+}
+
+#[derive(Debug)]
+pub struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Function(Function),
+    Var(Vec<(String, Option<Expr>)>),
+    Expr(Expr),
+    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    While(Expr, Box<Stmt>),
+    For(Option<Box<Stmt>>, Option<Expr>, Option<Expr>, Box<Stmt>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+    Block(Vec<Stmt>),
+    Empty,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(f64),
+    String(String),
+    Bool(bool),
+    Null,
+    Array(Vec<Expr>),
+    Name(String),
+    Function(Function),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Assign(&'static str, Box<Expr>, Box<Expr>),
+    // The operator, whether it came before the operand, and the operand
+    Update(&'static str, bool, Box<Expr>),
+    Member(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+}
+
+// A parsed script
+#[derive(Debug)]
+pub struct Program {
+    body: Vec<Stmt>,
+}
+
+impl Program {
+    pub fn parse(source: &str) -> Result<Program, String> {
+        let mut parser = Parser { tokens: tokenize(source)?, pos: 0, depth: 0 };
+        let mut body = Vec::new();
+        while parser.peek() != &Token::End {
+            body.push(parser.statement()?);
+        }
+        Ok(Program { body })
+    }
+
+    // Whether the script declares a function of this name at the top level
+    pub fn defines(&self, name: &str) -> bool {
+        self.body.iter().any(|stmt| matches!(stmt, Stmt::Function(function) if function.name == name))
+    }
+
+    // Run the script's top level, then call its function with the arguments.
+    // The host implements the extra natives the script may call.
+    pub fn call<'a>(
+        &'a self,
+        name: &str,
+        args: Vec<Value<'a>>,
+        natives: &[&'static str],
+        host: Host<'a, '_>,
+    ) -> Result<Value<'a>, String> {
+        let mut globals = HashMap::new();
+        globals.insert("undefined".to_string(), Value::Undefined);
+        globals.insert("NaN".to_string(), Value::Number(f64::NAN));
+        globals.insert("Infinity".to_string(), Value::Number(f64::INFINITY));
+        for native in GLOBAL_FUNCTIONS.iter().chain(natives) {
+            globals.insert(native.to_string(), Value::Native(native));
+        }
+        hoist(&self.body, &mut globals);
+
+        let mut run = Run { globals, frames: Vec::new(), host, steps: 0 };
+        run.block(&self.body)?;
+        match run.globals.get(name) {
+            Some(Value::Function(function)) => run.call_function(function, args),
+            _ => Err(format!("{} is not a function", name)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    String(String),
+    Name(String),
+    Punct(&'static str),
+    End,
+}
+
+// The tokens of the source, each with its line
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(format!("line {}: unterminated comment", line));
+            }
+            i += 2;
+        } else if c.is_ascii_digit() || (c == '.' && next.is_some_and(|c| c.is_ascii_digit())) {
+            let start = i;
+            if c == '0' && matches!(next, Some('x' | 'X')) {
+                i += 2;
+                while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+            } else {
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                if i < chars.len() && matches!(chars[i], 'e' | 'E') {
+                    i += 1;
+                    if i < chars.len() && matches!(chars[i], '+' | '-') {
+                        i += 1;
+                    }
+                    while i < chars.len() && chars[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            let number = string_to_number(&text);
+            if number.is_nan() {
+                return Err(format!("line {}: invalid number {}", line, text));
+            }
+            tokens.push((Token::Number(number), line));
+        } else if c == '"' || c == '\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                let Some(&d) = chars.get(i) else {
+                    return Err(format!("line {}: unterminated string", line));
+                };
+                i += 1;
+                match d {
+                    d if d == c => break,
+                    '\n' => return Err(format!("line {}: unterminated string", line)),
+                    '\\' => {
+                        let Some(&escape) = chars.get(i) else { continue };
+                        i += 1;
+                        match escape {
+                            'n' => text.push('\n'),
+                            't' => text.push('\t'),
+                            'r' => text.push('\r'),
+                            '0' => text.push('\0'),
+                            'x' | 'u' => {
+                                let len = if escape == 'x' { 2 } else { 4 };
+                                let hex: String = chars.iter().skip(i).take(len).collect();
+                                let code = u32::from_str_radix(&hex, 16)
+                                    .ok()
+                                    .and_then(char::from_u32)
+                                    .ok_or(format!("line {}: invalid escape \\{}{}", line, escape, hex))?;
+                                text.push(code);
+                                i += len;
+                            }
+                            // A line continuation
+                            '\n' => line += 1,
+                            escape => text.push(escape),
+                        }
+                    }
+                    d => text.push(d),
+                }
+            }
+            tokens.push((Token::String(text), line));
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$') {
+                i += 1;
+            }
+            tokens.push((Token::Name(chars[start..i].iter().collect()), line));
+        } else {
+            let punct = PUNCTUATION.iter()
+                .find(|punct| punct.chars().enumerate().all(|(j, p)| chars.get(i + j) == Some(&p)))
+                .ok_or(format!("line {}: unexpected character {}", line, c))?;
+            i += punct.len();
+            tokens.push((Token::Punct(punct), line));
+        }
+    }
+    tokens.push((Token::End, line));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.line(), message)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Token::Punct(p) if *p == punct)
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Token::Name(n) if n == name)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.is_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat_punct(punct) { Ok(()) } else { Err(self.error(&format!("expected {}", punct))) }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.peek().clone() {
+            Token::Name(name) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("expected a name")),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH { Err(self.error("nested too deeply")) } else { Ok(()) }
+    }
+
+    // Semicolons may be left out, as at the end of a line
+    fn end_statement(&mut self) {
+        self.eat_punct(";");
+    }
+
+    fn statement(&mut self) -> Result<Stmt, String> {
+        self.enter()?;
+        let stmt = self.statement_inner();
+        self.depth -= 1;
+        stmt
+    }
+
+    fn statement_inner(&mut self) -> Result<Stmt, String> {
+        if self.eat_punct("{") {
+            return Ok(Stmt::Block(self.block_rest()?));
+        }
+        if self.eat_punct(";") {
+            return Ok(Stmt::Empty);
+        }
+        let Token::Name(keyword) = self.peek().clone() else {
+            let expr = self.expression()?;
+            self.end_statement();
+            return Ok(Stmt::Expr(expr));
+        };
+        let line = self.line();
+        match keyword.as_str() {
+            "function" => {
+                self.pos += 1;
+                Ok(Stmt::Function(self.function(true)?))
+            }
+            "var" | "let" | "const" => {
+                self.pos += 1;
+                let decls = self.declarations()?;
+                self.end_statement();
+                Ok(Stmt::Var(decls))
+            }
+            "if" => {
+                self.pos += 1;
+                self.expect_punct("(")?;
+                let condition = self.expression()?;
+                self.expect_punct(")")?;
+                let then = Box::new(self.statement()?);
+                let otherwise = if self.is_name("else") {
+                    self.pos += 1;
+                    Some(Box::new(self.statement()?))
+                } else {
+                    None
+                };
+                Ok(Stmt::If(condition, then, otherwise))
+            }
+            "while" => {
+                self.pos += 1;
+                self.expect_punct("(")?;
+                let condition = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(Stmt::While(condition, Box::new(self.statement()?)))
+            }
+            "for" => {
+                self.pos += 1;
+                self.expect_punct("(")?;
+                let init = if self.is_punct(";") {
+                    None
+                } else if self.is_name("var") || self.is_name("let") || self.is_name("const") {
+                    self.pos += 1;
+                    Some(Box::new(Stmt::Var(self.declarations()?)))
+                } else {
+                    Some(Box::new(Stmt::Expr(self.expression()?)))
+                };
+                if self.is_name("in") || self.is_name("of") {
+                    return Err(self.error("for...in and for...of loops aren't supported"));
+                }
+                self.expect_punct(";")?;
+                let condition = if self.is_punct(";") { None } else { Some(self.expression()?) };
+                self.expect_punct(";")?;
+                let update = if self.is_punct(")") { None } else { Some(self.expression()?) };
+                self.expect_punct(")")?;
+                Ok(Stmt::For(init, condition, update, Box::new(self.statement()?)))
+            }
+            "return" => {
+                self.pos += 1;
+                // A value has to start on the same line
+                let value = if self.is_punct(";") || self.is_punct("}") || self.peek() == &Token::End || self.line() != line {
+                    None
+                } else {
+                    Some(self.expression()?)
+                };
+                self.end_statement();
+                Ok(Stmt::Return(value))
+            }
+            "break" | "continue" => {
+                self.pos += 1;
+                self.end_statement();
+                Ok(if keyword == "break" { Stmt::Break } else { Stmt::Continue })
+            }
+            "switch" | "do" | "try" | "throw" | "with" | "class" => {
+                Err(self.error(&format!("{} statements aren't supported", keyword)))
+            }
+            _ => {
+                let expr = self.expression()?;
+                self.end_statement();
+                Ok(Stmt::Expr(expr))
+            }
+        }
+    }
+
+    // The statements of a block whose { has been read
+    fn block_rest(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut body = Vec::new();
+        while !self.eat_punct("}") {
+            if self.peek() == &Token::End {
+                return Err(self.error("expected }"));
+            }
+            body.push(self.statement()?);
+        }
+        Ok(body)
+    }
+
+    fn declarations(&mut self) -> Result<Vec<(String, Option<Expr>)>, String> {
+        let mut decls = Vec::new();
+        loop {
+            let name = self.name()?;
+            let value = if self.eat_punct("=") { Some(self.assignment()?) } else { None };
+            decls.push((name, value));
+            if !self.eat_punct(",") {
+                return Ok(decls);
+            }
+        }
+    }
+
+    // A function whose keyword has been read; declarations must be named
+    fn function(&mut self, declaration: bool) -> Result<Function, String> {
+        let name = if declaration || matches!(self.peek(), Token::Name(_)) { self.name()? } else { String::new() };
+        self.expect_punct("(")?;
+        let mut params = Vec::new();
+        while !self.eat_punct(")") {
+            params.push(self.name()?);
+            if !self.is_punct(")") {
+                self.expect_punct(",")?;
+            }
+        }
+        self.expect_punct("{")?;
+        Ok(Function { name, params, body: self.block_rest()? })
+    }
+
+    fn expression(&mut self) -> Result<Expr, String> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, String> {
+        let target = self.conditional()?;
+        let Some(&op) = ASSIGNMENTS.iter().find(|op| self.is_punct(op)) else {
+            return Ok(target);
+        };
+        if !matches!(target, Expr::Name(_) | Expr::Index(..) | Expr::Member(..)) {
+            return Err(self.error("invalid assignment target"));
+        }
+        self.pos += 1;
+        Ok(Expr::Assign(op, Box::new(target), Box::new(self.assignment()?)))
+    }
+
+    fn conditional(&mut self) -> Result<Expr, String> {
+        let condition = self.binary(0)?;
+        if !self.eat_punct("?") {
+            return Ok(condition);
+        }
+        let then = self.assignment()?;
+        self.expect_punct(":")?;
+        let otherwise = self.assignment()?;
+        Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        let Some(ops) = BINARY_LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(&op) = ops.iter().find(|op| self.is_punct(op)) {
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        self.enter()?;
+        let expr = self.unary_inner();
+        self.depth -= 1;
+        expr
+    }
+
+    fn unary_inner(&mut self) -> Result<Expr, String> {
+        for op in ["!", "-", "+"] {
+            if self.eat_punct(op) {
+                return Ok(Expr::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        if self.is_name("typeof") {
+            self.pos += 1;
+            return Ok(Expr::Unary("typeof", Box::new(self.unary()?)));
+        }
+        for op in ["++", "--"] {
+            if self.eat_punct(op) {
+                return Ok(Expr::Update(op, true, Box::new(self.unary()?)));
+            }
+        }
+        let line = self.line();
+        let expr = self.call_member()?;
+        for op in ["++", "--"] {
+            if self.line() == line && self.eat_punct(op) {
+                return Ok(Expr::Update(op, false, Box::new(expr)));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn call_member(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat_punct(".") {
+                expr = Expr::Member(Box::new(expr), self.name()?);
+            } else if self.eat_punct("[") {
+                let index = self.expression()?;
+                self.expect_punct("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else if self.eat_punct("(") {
+                let args = self.list(")")?;
+                expr = Expr::Call(Box::new(expr), args);
+            } else {
+                return Ok(expr);
+            }
+        }
+    }
+
+    // Comma-separated expressions up to the closing punctuation
+    fn list(&mut self, close: &str) -> Result<Vec<Expr>, String> {
+        let mut items = Vec::new();
+        while !self.eat_punct(close) {
+            items.push(self.assignment()?);
+            if !self.is_punct(close) {
+                self.expect_punct(",")?;
+            }
+        }
+        Ok(items)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::String(s) => Ok(Expr::String(s)),
+            Token::Punct("(") => {
+                let expr = self.expression()?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Token::Punct("[") => Ok(Expr::Array(self.list("]")?)),
+            Token::Name(name) => match name.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "null" => Ok(Expr::Null),
+                "function" => Ok(Expr::Function(self.function(false)?)),
+                "new" | "this" => Err(self.error(&format!("{} isn't supported", name))),
+                _ => Ok(Expr::Name(name)),
+            },
+            Token::Punct("/") => Err(self.error("regular expressions aren't supported")),
+            Token::Punct(punct) => {
+                self.pos -= 1;
+                Err(self.error(&format!("unexpected {}", punct)))
+            }
+            Token::End => Err(self.error("unexpected end of script")),
+        }
+    }
+}
+
+// Functions and vars are declared before a body runs
+fn hoist<'a>(body: &'a [Stmt], scope: &mut HashMap<String, Value<'a>>) {
+    for stmt in body {
+        hoist_stmt(stmt, scope);
+    }
+}
+
+fn hoist_stmt<'a>(stmt: &'a Stmt, scope: &mut HashMap<String, Value<'a>>) {
+    match stmt {
+        Stmt::Function(function) => {
+            scope.insert(function.name.clone(), Value::Function(function));
+        }
+        Stmt::Var(decls) => {
+            for (name, _) in decls {
+                scope.entry(name.clone()).or_insert(Value::Undefined);
+            }
+        }
+        Stmt::If(_, then, otherwise) => {
+            hoist_stmt(then, scope);
+            if let Some(otherwise) = otherwise {
+                hoist_stmt(otherwise, scope);
+            }
+        }
+        Stmt::While(_, body) => hoist_stmt(body, scope),
+        Stmt::For(init, _, _, body) => {
+            if let Some(init) = init {
+                hoist_stmt(init, scope);
+            }
+            hoist_stmt(body, scope);
+        }
+        Stmt::Block(body) => hoist(body, scope),
+        _ => {}
+    }
+}
+
+// How a statement finished
+enum Flow<'a> {
+    Normal,
+    Return(Value<'a>),
+    Break,
+    Continue,
+}
+
+struct Run<'a, 'h> {
+    globals: HashMap<String, Value<'a>>,
+    // The variables of each function being called, innermost last
+    frames: Vec<HashMap<String, Value<'a>>>,
+    host: Host<'a, 'h>,
+    steps: usize,
+}
+
+impl<'a> Run<'a, '_> {
+    fn step(&mut self) -> Result<(), String> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS { Err("the script ran too long".to_string()) } else { Ok(()) }
+    }
+
+    fn lookup(&self, name: &str) -> Result<Value<'a>, String> {
+        self.frames.last()
+            .and_then(|frame| frame.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+            .ok_or(format!("{} is not defined", name))
+    }
+
+    // Assigning to a variable that was never declared makes it global
+    fn assign(&mut self, name: &str, value: Value<'a>) {
+        if let Some(slot) = self.frames.last_mut().and_then(|frame| frame.get_mut(name)) {
+            *slot = value;
+        } else {
+            self.globals.insert(name.to_string(), value);
+        }
+    }
+
+    fn declare(&mut self, name: &str, value: Value<'a>) {
+        self.frames.last_mut().unwrap_or(&mut self.globals).insert(name.to_string(), value);
+    }
+
+    fn block(&mut self, body: &'a [Stmt]) -> Result<Flow<'a>, String> {
+        for stmt in body {
+            match self.exec(stmt)? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn exec(&mut self, stmt: &'a Stmt) -> Result<Flow<'a>, String> {
+        self.step()?;
+        match stmt {
+            Stmt::Function(_) | Stmt::Empty => {}
+            Stmt::Var(decls) => {
+                for (name, value) in decls {
+                    if let Some(value) = value {
+                        let value = self.eval(value)?;
+                        self.declare(name, value);
+                    }
+                }
+            }
+            Stmt::Expr(expr) => {
+                self.eval(expr)?;
+            }
+            Stmt::If(condition, then, otherwise) => {
+                if self.eval(condition)?.truthy() {
+                    return self.exec(then);
+                } else if let Some(otherwise) = otherwise {
+                    return self.exec(otherwise);
+                }
+            }
+            Stmt::While(condition, body) => {
+                while self.eval(condition)?.truthy() {
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                }
+            }
+            Stmt::For(init, condition, update, body) => {
+                if let Some(init) = init {
+                    self.exec(init)?;
+                }
+                loop {
+                    if let Some(condition) = condition
+                        && !self.eval(condition)?.truthy()
+                    {
+                        break;
+                    }
+                    match self.exec(body)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    if let Some(update) = update {
+                        self.eval(update)?;
+                    }
+                }
+            }
+            Stmt::Return(value) => {
+                let value = match value {
+                    Some(value) => self.eval(value)?,
+                    None => Value::Undefined,
+                };
+                return Ok(Flow::Return(value));
+            }
+            Stmt::Break => return Ok(Flow::Break),
+            Stmt::Continue => return Ok(Flow::Continue),
+            Stmt::Block(body) => return self.block(body),
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn eval(&mut self, expr: &'a Expr) -> Result<Value<'a>, String> {
+        match expr {
+            Expr::Number(n) => Ok(Value::Number(*n)),
+            Expr::String(s) => Ok(Value::String(s.clone())),
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+            Expr::Null => Ok(Value::Null),
+            Expr::Array(items) => {
+                let items = items.iter().map(|item| self.eval(item)).collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Array(Rc::new(RefCell::new(items))))
+            }
+            Expr::Name(name) => self.lookup(name),
+            Expr::Function(function) => Ok(Value::Function(function)),
+            // typeof is the one place an undeclared name isn't an error
+            Expr::Unary("typeof", operand) => match &**operand {
+                Expr::Name(name) if self.lookup(name).is_err() => Ok(Value::String("undefined".to_string())),
+                operand => Ok(Value::String(self.eval(operand)?.type_name().to_string())),
+            },
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *op {
+                    "!" => Value::Bool(!value.truthy()),
+                    "-" => Value::Number(-value.to_number()),
+                    _ => Value::Number(value.to_number()),
+                })
+            }
+            Expr::Binary("&&", left, right) => {
+                let left = self.eval(left)?;
+                if left.truthy() { self.eval(right) } else { Ok(left) }
+            }
+            Expr::Binary("||", left, right) => {
+                let left = self.eval(left)?;
+                if left.truthy() { Ok(left) } else { self.eval(right) }
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                Ok(binary(op, &left, &right))
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.eval(condition)?.truthy() { self.eval(then) } else { self.eval(otherwise) }
+            }
+            Expr::Assign(op, target, value) => {
+                let mut value = self.eval(value)?;
+                if *op != "=" {
+                    let current = self.eval(target)?;
+                    value = binary(&op[..1], &current, &value);
+                }
+                self.store(target, value.clone())?;
+                Ok(value)
+            }
+            Expr::Update(op, prefix, target) => {
+                let old = self.eval(target)?.to_number();
+                let new = if *op == "++" { old + 1.0 } else { old - 1.0 };
+                self.store(target, Value::Number(new))?;
+                Ok(Value::Number(if *prefix { new } else { old }))
+            }
+            Expr::Member(object, name) => {
+                let object = self.eval(object)?;
+                Ok(match (&object, name.as_str()) {
+                    (Value::String(s), "length") => Value::Number(s.chars().count() as f64),
+                    (Value::Array(items), "length") => Value::Number(items.borrow().len() as f64),
+                    (Value::Undefined | Value::Null, _) => {
+                        return Err(format!("can't read {} of {}", name, object));
+                    }
+                    _ => Value::Undefined,
+                })
+            }
+            Expr::Index(object, index) => {
+                let object = self.eval(object)?;
+                let index = self.eval(index)?.to_number();
+                let valid = index >= 0.0 && index == index.trunc();
+                Ok(match &object {
+                    Value::String(s) if valid => {
+                        s.chars().nth(index as usize).map_or(Value::Undefined, |c| Value::String(c.to_string()))
+                    }
+                    Value::Array(items) if valid => items.borrow().get(index as usize).cloned().unwrap_or(Value::Undefined),
+                    Value::Undefined | Value::Null => return Err(format!("can't index {}", object)),
+                    _ => Value::Undefined,
+                })
+            }
+            Expr::Call(callee, args) => {
+                if let Expr::Member(object, name) = &**callee {
+                    let object = self.eval(object)?;
+                    let args = self.eval_all(args)?;
+                    return method(&object, name, &args);
+                }
+                let callee = self.eval(callee)?;
+                let args = self.eval_all(args)?;
+                match callee {
+                    Value::Function(function) => self.call_function(function, args),
+                    Value::Native(name) => self.native(name, &args),
+                    callee => Err(format!("{} is not a function", callee.type_name())),
+                }
+            }
+        }
+    }
+
+    fn eval_all(&mut self, exprs: &'a [Expr]) -> Result<Vec<Value<'a>>, String> {
+        exprs.iter().map(|expr| self.eval(expr)).collect()
+    }
+
+    fn store(&mut self, target: &'a Expr, value: Value<'a>) -> Result<(), String> {
+        match target {
+            Expr::Name(name) => {
+                self.assign(name, value);
+                Ok(())
+            }
+            Expr::Index(object, index) => {
+                let object = self.eval(object)?;
+                let index = self.eval(index)?.to_number();
+                match object {
+                    Value::Array(items) if index >= 0.0 && index == index.trunc() && index < 1e6 => {
+                        let mut items = items.borrow_mut();
+                        let index = index as usize;
+                        if index >= items.len() {
+                            items.resize(index + 1, Value::Undefined);
+                        }
+                        items[index] = value;
+                        Ok(())
+                    }
+                    _ => Err("only array elements can be assigned".to_string()),
+                }
+            }
+            _ => Err("objects aren't supported".to_string()),
+        }
+    }
+
+    fn call_function(&mut self, function: &'a Function, args: Vec<Value<'a>>) -> Result<Value<'a>, String> {
+        self.step()?;
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err("too much recursion".to_string());
+        }
+        let mut frame = HashMap::new();
+        let mut args = args.into_iter();
+        for param in &function.params {
+            frame.insert(param.clone(), args.next().unwrap_or(Value::Undefined));
+        }
+        hoist(&function.body, &mut frame);
+        self.frames.push(frame);
+        let flow = self.block(&function.body);
+        self.frames.pop();
+        match flow? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(Value::Undefined),
+        }
+    }
+
+    fn native(&mut self, name: &str, args: &[Value<'a>]) -> Result<Value<'a>, String> {
+        let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Undefined);
+        match name {
+            "parseInt" => Ok(Value::Number(parse_int(&arg(0).to_string(), arg(1).to_number()))),
+            "parseFloat" => Ok(Value::Number(parse_float(&arg(0).to_string()))),
+            "isNaN" => Ok(Value::Bool(arg(0).to_number().is_nan())),
+            "String" => Ok(Value::String(if args.is_empty() { String::new() } else { arg(0).to_string() })),
+            "Number" => Ok(Value::Number(if args.is_empty() { 0.0 } else { arg(0).to_number() })),
+            name => (self.host)(name, args),
+        }
+    }
+}
+
+fn binary<'a>(op: &str, left: &Value<'a>, right: &Value<'a>) -> Value<'a> {
+    let strings = matches!(left, Value::String(_) | Value::Array(_)) || matches!(right, Value::String(_) | Value::Array(_));
+    match op {
+        "+" if strings => Value::String(format!("{}{}", left, right)),
+        "+" => Value::Number(left.to_number() + right.to_number()),
+        "-" => Value::Number(left.to_number() - right.to_number()),
+        "*" => Value::Number(left.to_number() * right.to_number()),
+        "/" => Value::Number(left.to_number() / right.to_number()),
+        "%" => Value::Number(left.to_number() % right.to_number()),
+        "==" => Value::Bool(loose_equals(left, right)),
+        "!=" => Value::Bool(!loose_equals(left, right)),
+        "===" => Value::Bool(strict_equals(left, right)),
+        "!==" => Value::Bool(!strict_equals(left, right)),
+        _ => {
+            let ordering = match (left, right) {
+                (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+                _ => left.to_number().partial_cmp(&right.to_number()),
+            };
+            Value::Bool(ordering.is_some_and(|ordering| match op {
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                _ => ordering.is_ge(),
+            }))
+        }
+    }
+}
+
+fn strict_equals<'a>(left: &Value<'a>, right: &Value<'a>) -> bool {
+    match (left, right) {
+        (Value::Undefined, Value::Undefined) | (Value::Null, Value::Null) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Array(a), Value::Array(b)) => Rc::ptr_eq(a, b),
+        (Value::Function(a), Value::Function(b)) => std::ptr::eq(*a, *b),
+        (Value::Native(a), Value::Native(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn loose_equals<'a>(left: &Value<'a>, right: &Value<'a>) -> bool {
+    match (left, right) {
+        (Value::Undefined | Value::Null, Value::Undefined | Value::Null) => true,
+        (Value::Undefined | Value::Null, _) | (_, Value::Undefined | Value::Null) => false,
+        (Value::Bool(_), _) | (_, Value::Bool(_)) if std::mem::discriminant(left) != std::mem::discriminant(right) => {
+            left.to_number() == right.to_number()
+        }
+        (Value::Number(_), Value::String(_)) | (Value::String(_), Value::Number(_)) => left.to_number() == right.to_number(),
+        (Value::Array(_), Value::String(_) | Value::Number(_)) | (Value::String(_) | Value::Number(_), Value::Array(_)) => {
+            loose_equals(&Value::String(left.to_string()), &Value::String(right.to_string()))
+        }
+        _ => strict_equals(left, right),
+    }
+}
+
+// A string or array method
+fn method<'a>(object: &Value<'a>, name: &str, args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let arg = |i: usize| args.get(i).cloned().unwrap_or(Value::Undefined);
+    let string_arg = |i: usize| arg(i).to_string();
+    if let Value::Array(items) = object {
+        return match name {
+            "join" => {
+                let separator = match arg(0) {
+                    Value::Undefined => ",".to_string(),
+                    separator => separator.to_string(),
+                };
+                let items: Vec<String> = items.borrow().iter()
+                    .map(|item| match item {
+                        Value::Undefined | Value::Null => String::new(),
+                        item => item.to_string(),
+                    })
+                    .collect();
+                Ok(Value::String(items.join(&separator)))
+            }
+            "indexOf" => Ok(Value::Number(
+                items.borrow().iter().position(|item| strict_equals(item, &arg(0))).map_or(-1.0, |i| i as f64),
+            )),
+            "includes" => Ok(Value::Bool(items.borrow().iter().any(|item| strict_equals(item, &arg(0))))),
+            "push" => {
+                items.borrow_mut().extend(args.iter().cloned());
+                Ok(Value::Number(items.borrow().len() as f64))
+            }
+            "toString" => Ok(Value::String(object.to_string())),
+            _ => Err(format!("arrays have no method {}", name)),
+        };
+    }
+    let Value::String(s) = object else {
+        return Err(format!("can't call {} on {}", name, object.type_name()));
+    };
+
+    let chars: Vec<char> = s.chars().collect();
+    let len = chars.len() as f64;
+    // A position argument, NaN as 0, clamped to the string
+    let position = |value: Value, default: f64| -> usize {
+        let n = match value {
+            Value::Undefined => default,
+            value => value.to_number(),
+        };
+        let n = if n.is_nan() { 0.0 } else { n.trunc() };
+        n.clamp(0.0, len) as usize
+    };
+    // A slice() argument, which counts from the end when negative
+    let relative = |value: Value, default: f64| -> usize {
+        let n = match value {
+            Value::Undefined => default,
+            value => value.to_number(),
+        };
+        let n = if n.is_nan() { 0.0 } else { n.trunc() };
+        (if n < 0.0 { len + n } else { n }).clamp(0.0, len) as usize
+    };
+    let substring = |start: usize, end: usize| Value::String(chars[start..end.max(start)].iter().collect());
+    let needle: Vec<char> = string_arg(0).chars().collect();
+    let find_from = |from: usize| (from..=chars.len()).find(|&i| chars[i..].starts_with(&needle));
+
+    Ok(match name {
+        "toLowerCase" => Value::String(s.to_lowercase()),
+        "toUpperCase" => Value::String(s.to_uppercase()),
+        "trim" => Value::String(s.trim().to_string()),
+        "toString" => Value::String(s.clone()),
+        "indexOf" => Value::Number(find_from(position(arg(1), 0.0)).map_or(-1.0, |i| i as f64)),
+        "lastIndexOf" => Value::Number(
+            (0..=chars.len()).rev().find(|&i| chars[i..].starts_with(&needle)).map_or(-1.0, |i| i as f64),
+        ),
+        "includes" => Value::Bool(find_from(0).is_some()),
+        "startsWith" => Value::Bool(chars[position(arg(1), 0.0)..].starts_with(&needle)),
+        "endsWith" => Value::Bool(chars[..position(arg(1), len)].ends_with(&needle)),
+        "charAt" => {
+            let i = position(arg(0), 0.0);
+            Value::String(chars.get(i).map(char::to_string).unwrap_or_default())
+        }
+        "charCodeAt" => {
+            let i = position(arg(0), 0.0);
+            Value::Number(chars.get(i).map_or(f64::NAN, |&c| f64::from(u32::from(c))))
+        }
+        "substring" => {
+            let (start, end) = (position(arg(0), 0.0), position(arg(1), len));
+            substring(start.min(end), start.max(end))
+        }
+        "substr" => {
+            let start = relative(arg(0), 0.0);
+            let count = match arg(1) {
+                Value::Undefined => len,
+                count => count.to_number(),
+            };
+            let count = if count.is_nan() { 0.0 } else { count.trunc().max(0.0) };
+            substring(start, (start as f64 + count).min(len) as usize)
+        }
+        "slice" => substring(relative(arg(0), 0.0), relative(arg(1), len)),
+        "split" => {
+            let parts: Vec<Value> = match arg(0) {
+                Value::Undefined => vec![Value::String(s.clone())],
+                _ if needle.is_empty() => chars.iter().map(|c| Value::String(c.to_string())).collect(),
+                separator => s.split(&separator.to_string()).map(|part| Value::String(part.to_string())).collect(),
+            };
+            let limit = match arg(1) {
+                Value::Undefined => parts.len(),
+                limit => limit.to_number().max(0.0) as usize,
+            };
+            Value::Array(Rc::new(RefCell::new(parts.into_iter().take(limit).collect())))
+        }
+        "replace" => Value::String(s.replacen(&string_arg(0), &string_arg(1), 1)),
+        "concat" => Value::String(args.iter().fold(s.clone(), |mut s, arg| {
+            s.push_str(&arg.to_string());
+            s
+        })),
+        _ => return Err(format!("strings have no method {}", name)),
+    })
+}
+
+fn parse_int(text: &str, radix: f64) -> f64 {
+    let text = text.trim_start();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let given = !(radix.is_nan() || radix == 0.0);
+    let mut radix = if given { radix as u32 } else { 10 };
+    let mut text = text;
+    if (!given || radix == 16)
+        && let Some(rest) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X"))
+    {
+        radix = 16;
+        text = rest;
+    }
+    if !(2..=36).contains(&radix) {
+        return f64::NAN;
+    }
+    let digits: Vec<u32> = text.chars().map_while(|c| c.to_digit(radix)).collect();
+    if digits.is_empty() {
+        return f64::NAN;
+    }
+    let value = digits.iter().fold(0.0, |value, &digit| value * f64::from(radix) + f64::from(digit));
+    if negative { -value } else { value }
+}
+
+// The longest number at the start of the text
+fn parse_float(text: &str) -> f64 {
+    let text = text.trim_start();
+    if text.strip_prefix(['+', '-']).unwrap_or(text).starts_with("Infinity") {
+        return if text.starts_with('-') { f64::NEG_INFINITY } else { f64::INFINITY };
+    }
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let digits = |mut end: usize| {
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+        end
+    };
+    end = digits(end);
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exponent = end + 1;
+        if matches!(bytes.get(exponent), Some(b'+' | b'-')) {
+            exponent += 1;
+        }
+        let exponent_end = digits(exponent);
+        if exponent_end > exponent {
+            end = exponent_end;
+        }
+    }
+    text[..end].parse().unwrap_or(f64::NAN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the script's f() returns, as a string
+    fn run(source: &str) -> Result<String, String> {
+        let program = Program::parse(source)?;
+        program.call("f", Vec::new(), &[], &|name, _| Err(format!("{} is not defined", name))).map(|value| value.to_string())
+    }
+
+    #[test]
+    fn runs_loops_and_string_methods() {
+        assert_eq!(run("function f() { var s = 0; for (var i = 0; i < 5; i++) { s += i; } return s; }").unwrap(), "10");
+        assert_eq!(run("function f() { return 'a.b.c'.split('.').join('-') + parseInt('42px'); }").unwrap(), "a-b-c42");
+        assert_eq!(run("function f() { return 'WWW.Example.com'.toLowerCase().substring(4); }").unwrap(), "example.com");
+        assert_eq!(run("function f() { return 0x10 + 1.5e1 == 31 && '1' == 1 && !('1' === 1); }").unwrap(), "true");
+    }
+
+    #[test]
+    fn top_level_code_runs_before_the_call() {
+        assert_eq!(run("var proxy = 'p'; function f() { return proxy + g(); } function g() { return 1; }").unwrap(), "p1");
+    }
+
+    #[test]
+    fn tokenizer_errors_name_the_line() {
+        assert_eq!(run("function f() {\n return 'open;\n}").unwrap_err(), "line 2: unterminated string");
+        assert_eq!(run("function f() {}\n/* never closed").unwrap_err(), "line 2: unterminated comment");
+        assert_eq!(run("function f() { return 1.2.3; }").unwrap_err(), "line 1: invalid number 1.2.3");
+        assert_eq!(run("function f() { return '\\xZZ'; }").unwrap_err(), "line 1: invalid escape \\xZZ");
+    }
+
+    #[test]
+    fn parser_errors_name_the_line() {
+        assert_eq!(run("function f() {\n return 1;").unwrap_err(), "line 2: expected }");
+        assert_eq!(run("function f() { 1 = 2; }").unwrap_err(), "line 1: invalid assignment target");
+        assert!(run("function f() { for (var x in y) {} }").unwrap_err().contains("for...in and for...of loops aren't supported"));
+    }
+
+    #[test]
+    fn runtime_errors_stop_the_call() {
+        assert_eq!(run("function f() { return missing(); }").unwrap_err(), "missing is not defined");
+        assert_eq!(run("function f() { var x; return x.length; }").unwrap_err(), "can't read length of undefined");
+        assert_eq!(run("function g() {}").unwrap_err(), "f is not a function");
+    }
+
+    #[test]
+    fn deep_nesting_is_refused() {
+        let nested = format!("function f() {{ return {}1{}; }}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert!(run(&nested).unwrap_err().ends_with("nested too deeply"));
+        let shallow = format!("function f() {{ return {}1{}; }}", "(".repeat(MAX_DEPTH / 2), ")".repeat(MAX_DEPTH / 2));
+        assert_eq!(run(&shallow).unwrap(), "1");
+    }
+
+    #[test]
+    fn endless_loops_and_recursion_are_stopped() {
+        assert_eq!(run("function f() { while (true) {} }").unwrap_err(), "the script ran too long");
+        assert_eq!(run("function f() { return f(); }").unwrap_err(), "too much recursion");
+    }
+}
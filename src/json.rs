@@ -0,0 +1,341 @@
+// Just enough JSON for the formats this crate reads and writes, like HAR.
+
+// A parsed JSON value. Objects keep their members in document order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    // Member of an object; None for other values or a missing key
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+// Parse a complete JSON document
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { text: text.as_bytes(), pos: 0 };
+    let value = parser.nested(0)?;
+    parser.skip_whitespace();
+    if parser.pos < parser.text.len() {
+        return Err(parser.error("Trailing data"));
+    }
+    Ok(value)
+}
+
+// A string as a JSON string literal, quotes included
+pub fn quote(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Nesting deeper than this is refused rather than risking the stack
+const MAX_DEPTH: usize = 128;
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {} of the JSON", message, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.text.get(self.pos).is_some_and(|b| b" \t\r\n".contains(b)) {
+            self.pos += 1;
+        }
+    }
+
+    fn nested(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.error("Nested too deeply"));
+        }
+        self.skip_whitespace();
+        match self.text.get(self.pos) {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b't') => self.literal("true", Value::Bool(true)),
+            Some(b'f') => self.literal("false", Value::Bool(false)),
+            Some(b'n') => self.literal("null", Value::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("Unknown literal"))
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while self.text.get(self.pos).is_some_and(|b| b"+-.eE0123456789".contains(b)) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.text[start..self.pos])
+            .ok()
+            .and_then(|number| number.parse().ok())
+            .map(Value::Number)
+            .ok_or_else(|| self.error("Invalid number"))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        // Skip the opening quote
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&byte) = self.text.get(self.pos) else {
+                return Err(self.error("Unterminated string"));
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.text.get(self.pos) else {
+                        return Err(self.error("Unterminated string"));
+                    };
+                    self.pos += 1;
+                    let c = match escape {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return Err(self.error("Invalid escape")),
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8 in string"))
+    }
+
+    // The code point of a \u escape, combining a surrogate pair if there is one.
+    // Lone surrogates become U+FFFD.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return Ok(char::from_u32(high).unwrap_or('\u{fffd}'));
+        }
+        if !self.text[self.pos..].starts_with(b"\\u") {
+            return Ok('\u{fffd}');
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Ok('\u{fffd}');
+        }
+        Ok(char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)).unwrap_or('\u{fffd}'))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        // from_str_radix would take a sign as well
+        let digits = self.text.get(self.pos..self.pos + 4)
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("Invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.nested(depth + 1)?);
+            self.skip_whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                _ => return Err(self.error("Expected , or ]")),
+            }
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value, String> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.text.get(self.pos) == Some(&b'}') {
+            self.pos += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.text.get(self.pos) != Some(&b'"') {
+                return Err(self.error("Expected a member name"));
+            }
+            let name = self.string()?;
+            self.skip_whitespace();
+            if self.text.get(self.pos) != Some(&b':') {
+                return Err(self.error("Expected :"));
+            }
+            self.pos += 1;
+            members.push((name, self.nested(depth + 1)?));
+            self.skip_whitespace();
+            match self.text.get(self.pos) {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Value::Object(members));
+                }
+                _ => return Err(self.error("Expected , or }")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(text: &str) -> Value {
+        Value::String(text.to_string())
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(parse(r#""a\"\\\/\b\f\n\r\tz""#).unwrap(), string("a\"\\/\u{8}\u{c}\n\r\tz"));
+        assert_eq!(parse(r#""\u0041\u00e9\u20AC""#).unwrap(), string("Aé€"));
+        // Text that isn't escaped comes through as it is
+        assert_eq!(parse("\"é €\"").unwrap(), string("é €"));
+        for text in ["a\"b\\c\n\r\t", "\u{1}\u{1f}", "é €😀"] {
+            assert_eq!(parse(&quote(text)).unwrap(), string(text), "{:?}", text);
+        }
+        assert_eq!(quote("\u{1}"), r#""\u0001""#);
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(parse(r#""\ud83d\ude00""#).unwrap(), string("😀"));
+        assert_eq!(parse(r#""\uD834\uDD1E!""#).unwrap(), string("𝄞!"));
+        // Lone halves become U+FFFD, and what follows a lone high half is kept
+        assert_eq!(parse(r#""\ud83d""#).unwrap(), string("\u{fffd}"));
+        assert_eq!(parse(r#""\ud83dx""#).unwrap(), string("\u{fffd}x"));
+        assert_eq!(parse(r#""\ude00""#).unwrap(), string("\u{fffd}"));
+        assert_eq!(parse(r#""\ud83d\u0041""#).unwrap(), string("\u{fffd}"));
+    }
+
+    #[test]
+    fn hex_escapes_need_four_hex_digits() {
+        for text in [r#""\u00""#, r#""\u00g1""#, r#""\u+041""#, r#""\u-041""#, r#""\ud83d\u00""#, r#""\u"#] {
+            assert!(parse(text).unwrap_err().starts_with("Invalid \\u escape"), "{}", text);
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        let value = parse(r#" {"a": [1, -2.5e1, true, null, {"b": "c"}], "d": {}, "e": [], "a": 0} "#).unwrap();
+        let items = value.get("a").and_then(Value::as_array).unwrap();
+        assert_eq!(items.len(), 5);
+        assert_eq!(items[1].as_f64(), Some(-25.0));
+        assert_eq!((&items[2], &items[3]), (&Value::Bool(true), &Value::Null));
+        assert_eq!(items[4].get("b").and_then(Value::as_str), Some("c"));
+        assert_eq!(value.get("d"), Some(&Value::Object(Vec::new())));
+        assert_eq!(value.get("e").and_then(Value::as_array), Some(&[][..]));
+        // Members stay in order, repeats included, and get finds the first
+        let Value::Object(members) = &value else { panic!("not an object") };
+        assert_eq!(members.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["a", "d", "e", "a"]);
+        assert_eq!(value.get("missing"), None);
+        assert_eq!(items[0].get("a"), None);
+
+        let deep = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&deep(MAX_DEPTH + 1)).is_ok());
+        assert_eq!(parse(&deep(MAX_DEPTH + 2)).unwrap_err(), format!("Nested too deeply at byte {} of the JSON", MAX_DEPTH + 1));
+        // Far deeper than the stack would take is still only an error
+        assert!(parse(&deep(100_000)).is_err());
+    }
+
+    #[test]
+    fn malformed_input_is_an_error() {
+        let cases = [
+            ("", "Unexpected end at byte 0"),
+            ("   ", "Unexpected end at byte 3"),
+            ("[1,", "Unexpected end at byte 3"),
+            ("[1 2]", "Expected , or ] at byte 3"),
+            ("{\"a\" 1}", "Expected : at byte 5"),
+            ("{\"a\": 1,}", "Expected a member name at byte 8"),
+            ("{1: 2}", "Expected a member name at byte 1"),
+            ("{\"a\": 1 \"b\"}", "Expected , or } at byte 8"),
+            ("\"abc", "Unterminated string at byte 4"),
+            ("\"abc\\", "Unterminated string at byte 5"),
+            ("\"\\x\"", "Invalid escape at byte 3"),
+            ("tru", "Unknown literal at byte 0"),
+            ("nul", "Unknown literal at byte 0"),
+            ("-", "Invalid number at byte 1"),
+            ("1e", "Invalid number at byte 2"),
+            ("1.2.3", "Invalid number at byte 5"),
+            ("'a'", "Unexpected character at byte 0"),
+            ("[] []", "Trailing data at byte 3"),
+            ("{}x", "Trailing data at byte 2"),
+        ];
+        for (text, error) in cases {
+            assert_eq!(parse(text).unwrap_err(), format!("{} of the JSON", error), "{:?}", text);
+        }
+    }
+}
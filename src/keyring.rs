@@ -0,0 +1,239 @@
+// Per-host credentials kept in the platform keyring rather than in a file:
+// the Secret Service through secret-tool on Linux and other Unixes, the
+// login keychain through security on macOS, and the Credential Manager on
+// Windows. Each is stored as the value of the Authorization header it stands
+// for, under the service "http_client" and the host name.
+
+use crate::integrity;
+
+#[cfg(windows)]
+pub use credential_manager::{lookup, remove, store};
+#[cfg(not(windows))]
+pub use tools::{lookup, remove, store};
+
+const SERVICE: &str = "http_client";
+
+// The Authorization value for what auth add was given: "Bearer TOKEN" as it is,
+// anything else as "user:password" for Basic auth
+pub fn authorization(secret: &str) -> Result<String, String> {
+    if secret.starts_with("Bearer ") {
+        return Ok(secret.to_string());
+    }
+    if !secret.contains(':') {
+        return Err("Expected user:password or Bearer TOKEN".to_string());
+    }
+    Ok(format!("Basic {}", integrity::encode_base64(secret.as_bytes())))
+}
+
+// The command line tools, which get secrets on stdin so that they never show
+// in the list of processes
+#[cfg(not(windows))]
+mod tools {
+    use std::io::Write;
+    use std::process::{Command, Output, Stdio};
+
+    use super::SERVICE;
+
+    // What security exits with when there is no such item (errSecItemNotFound)
+    const SECURITY_NOT_FOUND: i32 = 44;
+
+    pub fn store(host: &str, authorization: &str) -> std::io::Result<()> {
+        if cfg!(target_os = "macos") {
+            // add-generic-password takes the password as an argument, so the
+            // whole command goes in on stdin to security -i instead
+            if authorization.contains(['"', '\\', '\r', '\n']) || host.contains(['"', '\\', '\r', '\n']) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "The keychain can't be given a host or credentials with quotes, backslashes or line breaks in them",
+                ));
+            }
+            let mut command = Command::new("security");
+            command.arg("-i");
+            let line = format!("add-generic-password -U -s \"{}\" -a \"{}\" -w \"{}\"\n", SERVICE, host, authorization);
+            run(command, Some(&line)).map(|_| ())
+        } else {
+            let mut command = Command::new("secret-tool");
+            command.args(["store", "--label", &format!("{} {}", SERVICE, host), "service", SERVICE, "host", host]);
+            run(command, Some(authorization)).map(|_| ())
+        }
+    }
+
+    // The stored Authorization value for the host, if there is one
+    pub fn lookup(host: &str) -> std::io::Result<Option<String>> {
+        let mut command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("security");
+            command.args(["find-generic-password", "-s", SERVICE, "-a", host, "-w"]);
+            command
+        } else {
+            let mut command = Command::new("secret-tool");
+            command.args(["lookup", "service", SERVICE, "host", host]);
+            command
+        };
+        let output = spawn(&mut command, None)?;
+        // Only a missing item means there are no credentials: security says so
+        // with its exit code, secret-tool by failing without a message. A
+        // locked keyring or no Secret Service at all is an error.
+        let missing = if cfg!(target_os = "macos") {
+            output.status.code() == Some(SECURITY_NOT_FOUND)
+        } else {
+            output.status.code() == Some(1) && output.stderr.is_empty()
+        };
+        if missing {
+            return Ok(None);
+        }
+        let secret = succeeded(&command, output)?;
+        Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string()).filter(|secret| !secret.is_empty()))
+    }
+
+    pub fn remove(host: &str) -> std::io::Result<()> {
+        let command = if cfg!(target_os = "macos") {
+            let mut command = Command::new("security");
+            command.args(["delete-generic-password", "-s", SERVICE, "-a", host]);
+            command
+        } else {
+            let mut command = Command::new("secret-tool");
+            command.args(["clear", "service", SERVICE, "host", host]);
+            command
+        };
+        run(command, None).map(|_| ())
+    }
+
+    // Run a keyring tool, with the input on its stdin if given, returning its output
+    fn run(mut command: Command, input: Option<&str>) -> std::io::Result<String> {
+        let output = spawn(&mut command, input)?;
+        succeeded(&command, output)
+    }
+
+    fn spawn(command: &mut Command, input: Option<&str>) -> std::io::Result<Output> {
+        let mut child = command
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", program(command), e)))?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input.as_bytes())?;
+        }
+        child.wait_with_output()
+    }
+
+    // The tool's stdout, or an error with what it said on stderr
+    fn succeeded(command: &Command, output: Output) -> std::io::Result<String> {
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(std::io::Error::other(match message.trim() {
+                "" => format!("{} failed: {}", program(command), output.status),
+                message => format!("{} failed: {}: {}", program(command), output.status, message),
+            }));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn program(command: &Command) -> String {
+        command.get_program().to_string_lossy().into_owned()
+    }
+}
+
+// The Credential Manager, through the Cred* functions of advapi32: a
+// generic credential named "http_client:HOST" for each host
+#[cfg(windows)]
+mod credential_manager {
+    use std::ffi::c_void;
+
+    use super::SERVICE;
+
+    const CRED_TYPE_GENERIC: u32 = 1;
+    const CRED_PERSIST_LOCAL_MACHINE: u32 = 2;
+    const ERROR_NOT_FOUND: i32 = 1168;
+
+    #[repr(C)]
+    struct Credential {
+        flags: u32,
+        kind: u32,
+        target_name: *mut u16,
+        comment: *mut u16,
+        last_written: [u32; 2],
+        blob_size: u32,
+        blob: *mut u8,
+        persist: u32,
+        attribute_count: u32,
+        attributes: *mut c_void,
+        target_alias: *mut u16,
+        user_name: *mut u16,
+    }
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn CredWriteW(credential: *const Credential, flags: u32) -> i32;
+        fn CredReadW(target_name: *const u16, kind: u32, flags: u32, credential: *mut *mut Credential) -> i32;
+        fn CredDeleteW(target_name: *const u16, kind: u32, flags: u32) -> i32;
+        fn CredFree(buffer: *mut c_void);
+    }
+
+    // A string as the nul-terminated UTF-16 the functions take
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain([0]).collect()
+    }
+
+    fn target(host: &str) -> Vec<u16> {
+        wide(&format!("{}:{}", SERVICE, host))
+    }
+
+    pub fn store(host: &str, authorization: &str) -> std::io::Result<()> {
+        let mut target = target(host);
+        let mut user_name = wide(host);
+        let mut blob = authorization.as_bytes().to_vec();
+        let credential = Credential {
+            flags: 0,
+            kind: CRED_TYPE_GENERIC,
+            target_name: target.as_mut_ptr(),
+            comment: std::ptr::null_mut(),
+            last_written: [0; 2],
+            blob_size: blob.len() as u32,
+            blob: blob.as_mut_ptr(),
+            persist: CRED_PERSIST_LOCAL_MACHINE,
+            attribute_count: 0,
+            attributes: std::ptr::null_mut(),
+            target_alias: std::ptr::null_mut(),
+            user_name: user_name.as_mut_ptr(),
+        };
+        // SAFETY: every pointer in the credential is to a buffer that lives
+        // until the call returns, and the strings are nul-terminated
+        if unsafe { CredWriteW(&credential, 0) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // The stored Authorization value for the host, if there is one
+    pub fn lookup(host: &str) -> std::io::Result<Option<String>> {
+        let target = target(host);
+        let mut credential = std::ptr::null_mut();
+        // SAFETY: the target is nul-terminated, and a credential read is freed
+        // with CredFree once its blob is copied out
+        unsafe {
+            if CredReadW(target.as_ptr(), CRED_TYPE_GENERIC, 0, &mut credential) == 0 {
+                let e = std::io::Error::last_os_error();
+                return if e.raw_os_error() == Some(ERROR_NOT_FOUND) { Ok(None) } else { Err(e) };
+            }
+            let blob = if (*credential).blob.is_null() {
+                Vec::new()
+            } else {
+                std::slice::from_raw_parts((*credential).blob, (*credential).blob_size as usize).to_vec()
+            };
+            CredFree(credential.cast());
+            let secret = String::from_utf8(blob)
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("The credentials stored for {} aren't text", host)))?;
+            Ok(Some(secret).filter(|secret| !secret.is_empty()))
+        }
+    }
+
+    pub fn remove(host: &str) -> std::io::Result<()> {
+        let target = target(host);
+        // SAFETY: the target is nul-terminated
+        if unsafe { CredDeleteW(target.as_ptr(), CRED_TYPE_GENERIC, 0) } == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
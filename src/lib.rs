@@ -0,0 +1,61 @@
+// The parts of http_client that are useful on their own: the HTTP client,
+// URL parsing, digests and decompression, and a Download API for embedding
+// transfers in other programs.
+
+pub mod blocking;
+pub mod cancel;
+mod control;
+pub mod cookies;
+pub mod dns;
+pub mod download;
+pub mod engine;
+pub mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod future;
+mod h2;
+pub mod handle;
+pub mod har;
+mod hpack;
+pub mod hosts;
+pub mod http;
+pub mod inflate;
+pub mod integrity;
+pub mod js;
+pub mod json;
+pub mod lock;
+pub mod md4;
+pub mod md5;
+pub mod middleware;
+pub mod netrc;
+pub mod network;
+pub mod oauth2;
+pub mod pac;
+pub mod progress;
+pub mod proxy;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod refresh;
+pub mod retry;
+pub mod sha1;
+pub mod sigv4;
+pub mod sockopt;
+mod splice;
+#[cfg(test)]
+mod testing;
+pub mod time;
+pub mod timing;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod trace;
+pub mod url;
+mod uring;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+pub use cancel::{CancellationToken, Cancelled, TimeLimitExceeded};
+pub use download::{Download, DownloadBuilder, Summary};
+pub use events::DownloadEvent;
+pub use future::{DownloadFuture, download};
+pub use handle::{DownloadHandle, Stats};
+pub use progress::{ProgressObserver, SegmentMap, StatusReport};
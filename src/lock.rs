@@ -0,0 +1,100 @@
+// Advisory locks that keep two downloads from writing the same output at
+// once. The lock is taken on <output>.lock before the .part file is touched
+// and held until the output is in place; the file names the process holding
+// it and is removed when the lock is let go. Only downloads that take the
+// lock are kept out.
+
+use std::fs::{File, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// The lock on one output path, let go when dropped
+pub struct OutputLock {
+    path: PathBuf,
+    // Held open for the lock on it
+    _file: File,
+}
+
+// The error when another process holds the lock on the output, carried
+// inside an io::Error of kind ResourceBusy. Use Locked::from_error to get at
+// it.
+#[derive(Debug)]
+pub struct Locked {
+    pub output: PathBuf,
+    // The process holding the lock, if it could be read
+    pub holder: Option<u32>,
+}
+
+impl Locked {
+    pub fn from_error(error: &std::io::Error) -> Option<&Locked> {
+        error.get_ref()?.downcast_ref::<Locked>()
+    }
+
+    fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::ResourceBusy, self)
+    }
+}
+
+impl std::fmt::Display for Locked {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.holder {
+            Some(pid) => write!(f, "{} is being downloaded by another process (pid {})", self.output.display(), pid),
+            None => write!(f, "{} is being downloaded by another process", self.output.display()),
+        }
+    }
+}
+
+impl std::error::Error for Locked {}
+
+impl OutputLock {
+    // Lock the output path, waiting for another download holding it to
+    // finish if wait is set, and failing with Locked if not
+    pub fn acquire(output: &Path, wait: bool) -> std::io::Result<OutputLock> {
+        let mut name = output.as_os_str().to_owned();
+        name.push(".lock");
+        let path = PathBuf::from(name);
+        loop {
+            let mut file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) if wait => file.lock()?,
+                Err(TryLockError::WouldBlock) => {
+                    let holder = std::fs::read_to_string(&path).ok().and_then(|pid| pid.trim().parse().ok());
+                    return Err(Locked { output: output.to_path_buf(), holder }.into_error());
+                }
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+            // The holder before removes the file as it lets go, so a lock
+            // waited for may be on a file no one else will open
+            if is_current(&path, &file) {
+                file.set_len(0)?;
+                write!(file, "{}", std::process::id())?;
+                return Ok(OutputLock { path, _file: file });
+            }
+        }
+    }
+}
+
+impl Drop for OutputLock {
+    // Removed while still locked, so no one takes the lock on it in between
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Whether the open file is still the one at path
+#[cfg(unix)]
+fn is_current(path: &Path, file: &File) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(path), file.metadata()) {
+        (Ok(named), Ok(open)) => named.dev() == open.dev() && named.ino() == open.ino(),
+        _ => false,
+    }
+}
+
+// Elsewhere a file that is open can't be removed, so it always is
+#[cfg(not(unix))]
+fn is_current(_path: &Path, _file: &File) -> bool {
+    true
+}
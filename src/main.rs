@@ -1,138 +1,1262 @@
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::net::TcpStream;
-use sha2::{Sha256, Digest};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use http_client::{engine, http, inflate, integrity, json, md4, md5, retry, sha1, time, url};
+use http_client::{CancellationToken, Download, ProgressObserver, SegmentMap, StatusReport, TimeLimitExceeded};
+use http_client::cookies::CookieJar;
+use http_client::har::HarLog;
+use http_client::hosts::Hosts;
+use http_client::lock::{Locked, OutputLock};
+use http_client::netrc::Netrc;
+use http_client::network::{self, NetworkWatch};
+use http_client::pac::Pac;
+use http_client::refresh::UrlRefresh;
+use http_client::timing::TimingLog;
+use http_client::trace::Trace;
+use url::Url;
+
+mod archive;
+mod cache;
+mod cli;
+mod commands;
+mod completions;
+mod compress;
+mod curl;
+mod delta;
+mod exec;
+mod exitcode;
+mod hooks;
+mod keyring;
+mod mime;
+mod mirrors;
+mod notify;
+mod output;
+mod plan;
+mod replay;
+mod report;
+mod s3;
+mod script;
+mod signals;
+mod sink;
+mod speedtest;
+mod split;
+mod store;
+#[cfg(test)]
+mod testing;
+mod tui;
+mod verify;
+mod webhook;
+mod writeout;
+mod xattr;
+
+// Set when the download itself goes to stdout, so status messages move to stderr
+static STATUS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+// Set by --quiet, which leaves only errors
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+// The current download as SIGUSR1 reports it
+static STATUS: StatusReport = StatusReport::new();
+
+// Print a status message without mixing it into downloaded data on stdout
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if QUIET.load(Ordering::Relaxed) {
+            // Nothing but errors
+        } else if tui::active() {
+            tui::DASHBOARD.log(format!($($arg)*));
+        } else if STATUS_TO_STDERR.load(Ordering::Relaxed) {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
 
 fn main() -> std::io::Result<()> {
-    let host = "127.0.0.1:8080";
-    let output_file = "downloaded_data.bin";
-    
-    // First find out the expected total size
-    let total_size = get_total_size(host)?;
-    println!("Detected total size: {} bytes", total_size);
-    
-    let mut all_data = Vec::with_capacity(total_size);
-    let mut file = File::create(output_file)?;
-    let mut position = 0;
+    let mut options = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(exitcode::for_usage(&message));
+        }
+    };
+    if options.help {
+        print!("{}", cli::usage());
+        return Ok(());
+    }
     
-    // Download until we've reached the total size
-    while position < total_size {
-        let chunk = download_chunk(host, position)?;
-        
-        if chunk.is_empty() {
-            println!("Warning: Received empty chunk, retrying");
-            continue;
+    // The dump file is only created once the command line is known to be good
+    if let Some(path) = &options.trace_ascii {
+        let trace = Trace::with_ascii_dump(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not create {}: {}", path.display(), e)))?;
+        options.http.trace = Some(trace);
+    } else if options.trace {
+        options.http.trace = Some(Trace::new());
+    }
+    if options.har.is_some() {
+        options.http.har = Some(HarLog::new());
+    }
+    if options.json || options.write_out.is_some() {
+        options.http.timing = Some(TimingLog::new());
+    }
+    // --max-time runs from here, over every download together
+    if let Some(limit) = options.max_time {
+        let cancel = CancellationToken::new();
+        cancel.cancel_at(Instant::now() + limit);
+        options.http.cancel = Some(cancel);
+    }
+    // The PAC file is fetched directly, with none of the downloads' credentials
+    if let Some(location) = &options.proxy_pac {
+        let settings = http::Settings {
+            timeout: options.http.timeout,
+            trace: options.http.trace.clone(),
+            ..http::Settings::default()
+        };
+        let mut pac = Pac::load(location, &settings)?;
+        pac.auth = options.proxy_user.clone();
+        pac.tunnel = options.proxytunnel;
+        options.http.pac = Some(pac);
+    }
+    // ~/.netrc is read when it exists, a file that was asked for has to
+    match &options.netrc_file {
+        Some(path) => {
+            let netrc = Netrc::load(path)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read {}: {}", path.display(), e)))?;
+            options.http.netrc = Some(netrc);
+        }
+        None => match Netrc::default_path().map(|path| (Netrc::load(&path), path)) {
+            Some((Ok(netrc), _)) => options.http.netrc = Some(netrc),
+            Some((Err(e), path)) if e.kind() != std::io::ErrorKind::NotFound => {
+                eprintln!("Warning: Could not read {}: {}", path.display(), e);
+            }
+            _ => {}
+        },
+    }
+    if let Some(path) = &options.hosts_file {
+        let hosts = Hosts::load(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read {}: {}", path.display(), e)))?;
+        options.http.hosts = Some(hosts);
+    }
+    if let Some(path) = &options.script_file {
+        options.script = Some(script::Script::load(path, |line| status!("Script: {}", line))?);
+    }
+    if options.keyring {
+        let urls = options.urls.iter().chain(&options.mirrors).chain(options.command.url());
+        for url in urls {
+            if options.http.authorizations.iter().any(|(host, _)| *host == url.host) {
+                continue;
+            }
+            let stored = keyring::lookup(&url.host)
+                .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read the keyring for {}: {}", url.host, e)))?;
+            if let Some(authorization) = stored {
+                options.http.authorizations.push((url.host.clone(), authorization));
+            }
+        }
+    }
+    if let Some(command) = options.refresh_cmd.clone() {
+        options.http.refresh = Some(UrlRefresh::new(move |url| {
+            status!("Access to {} was refused, running {} for a fresh URL", url, command);
+            hooks::refresh(&command, url)
+        }));
+    }
+    if let Some(path) = &options.cookies {
+        // The jar a previous run would have saved may not exist yet
+        let jar = match CookieJar::load(path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && options.cookie_jar.as_ref() == Some(path) => CookieJar::new(),
+            jar => jar.map_err(|e| std::io::Error::new(e.kind(), format!("Could not read {}: {}", path.display(), e)))?,
+        };
+        options.http.cookies = Some(jar);
+    } else if options.cookie_jar.is_some() {
+        options.http.cookies = Some(CookieJar::new());
+    }
+
+    let result = run(&options);
+    // The HAR is written whatever happened, failures are what it's most wanted for
+    if let (Some(path), Some(har)) = (&options.har, &options.http.har)
+        && let Err(e) = har.write(path)
+    {
+        eprintln!("Warning: Could not write {}: {}", path.display(), e);
+    }
+    if let (Some(path), Some(jar)) = (&options.cookie_jar, &options.http.cookies)
+        && let Err(e) = jar.save(path)
+    {
+        eprintln!("Warning: Could not write {}: {}", path.display(), e);
+    }
+    match result {
+        Ok(0) => Ok(()),
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(exitcode::for_error(&e));
+        }
+    }
+}
+
+// Carry out the command, returning the exit status
+fn run(options: &cli::Options) -> std::io::Result<i32> {
+    match &options.command {
+        cli::Command::Download => {}
+        cli::Command::Head { url } => {
+            let ok = commands::head(url, &options.http)?;
+            return Ok(if ok { 0 } else { 1 });
+        }
+        cli::Command::Upload { file, url } => {
+            let ok = commands::upload(file, url, &options.http)?;
+            return Ok(if ok { 0 } else { 1 });
+        }
+        cli::Command::Verify { file, url } => {
+            let passed = verify::verify(file, url, &options.expected, &options.http)?;
+            return Ok(if passed { 0 } else { 1 });
+        }
+        cli::Command::Hash { files } => {
+            let all_read = commands::hash(files, options.algorithm);
+            return Ok(if all_read { 0 } else { 1 });
+        }
+        cli::Command::Replay { har } => {
+            let matched = replay::replay(har, options.in_order, options.original_headers, &options.http)?;
+            return Ok(if matched { 0 } else { 1 });
+        }
+        cli::Command::Bench { url, limit } => {
+            speedtest::bench(url, *limit, &options.http)?;
+            return Ok(0);
+        }
+        cli::Command::Auth { action, host } => {
+            commands::auth(*action, host)?;
+            return Ok(0);
+        }
+        cli::Command::Completions { shell } => {
+            print!("{}", shell.script());
+            return Ok(0);
         }
-        
-        file.write_all(&chunk)?;
-        all_data.extend_from_slice(&chunk);
-        position += chunk.len();
-        
-        println!("Downloaded: {}/{} bytes", position, total_size);
     }
     
-    // Verify we got the expected amount of data
-    if all_data.len() != total_size {
-        println!("Warning: Downloaded size ({}) doesn't match expected size ({})",
-                all_data.len(), total_size);
+    if let Some(limit) = options.speed_test {
+        for url in &options.urls {
+            speedtest::run(url, limit, &options.http)?;
+        }
+        return Ok(0);
     }
     
-    // Calculate SHA-256 hash
-    let mut hasher = Sha256::new();
-    hasher.update(&all_data);
-    let hash = format!("{:x}", hasher.finalize());
+    STATUS_TO_STDERR.store(options.tee || options.exec.is_some() || options.json, Ordering::Relaxed);
+    QUIET.store(options.quiet, Ordering::Relaxed);
+    // Asked for, so printed even with --quiet
+    signals::on_status_request(|| eprint!("{}", STATUS.render()));
     
-    println!("Download complete. SHA-256 hash: {}", hash);
-    println!("Verify this hash matches what the server displayed");
+    // Download every URL, carrying on past failures
+    let mut failed = 0;
+    let mut exit_code = exitcode::FAILED;
+    // A dry run only describes the downloads, so there is nothing for hooks to see
+    let pre_hook = options.pre_hook.as_ref().filter(|_| !options.dry_run);
+    let post_hook = options.post_hook.as_ref().filter(|_| !options.dry_run);
+    // While the dashboard is up, what would go to stderr waits for it to close
+    let screen = if options.tui { Some(tui::Screen::open(&options.urls)?) } else { None };
+    let mut deferred = String::new();
+    let mut warn = |text: String| match &screen {
+        Some(_) => deferred.push_str(&text),
+        None => eprint!("{}", text),
+    };
+    for (index, url) in options.urls.iter().enumerate() {
+        let mut transfer = writeout::Transfer { url: url.to_string(), ..writeout::Transfer::default() };
+        let started = Instant::now();
+        let begun = if tui::active() { tui::DASHBOARD.begin(index) } else { Ok(()) };
+        let result = begun.and_then(|()| match pre_hook {
+            Some(command) => hooks::pre(command, url).and_then(|()| download(url, options, &mut transfer)),
+            None => download(url, options, &mut transfer),
+        });
+        let result = result.map_err(|e| timed_out(e, options, &transfer));
+        if tui::active() {
+            tui::DASHBOARD.end(index, match &result {
+                Ok(Outcome::Skipped) => tui::Ending::Skipped,
+                Ok(_) => tui::Ending::Done,
+                Err(e) => tui::Ending::Failed(e),
+            });
+        }
+        if options.write_out.is_some() || options.json || options.notify_webhook.is_some() {
+            transfer.elapsed = started.elapsed();
+            transfer.connections = options.http.timing.as_ref().map(TimingLog::take).unwrap_or_default();
+            match &result {
+                Ok(Outcome::Saved { path, sha256 }) => {
+                    transfer.path = Some(path.clone());
+                    transfer.sha256 = Some(sha256.clone());
+                }
+                Ok(Outcome::Piped { sha256 }) => transfer.sha256 = Some(sha256.clone()),
+                Ok(Outcome::Skipped) => {}
+                Err(e) => transfer.error = Some(report::Failure::new(transfer.phase, e)),
+            }
+            let mut stdout = std::io::stdout().lock();
+            if options.json {
+                writeln!(stdout, "{}", transfer.to_json())?;
+            }
+            if let Some(template) = &options.write_out {
+                stdout.write_all(template.render(&transfer).as_bytes())?;
+            }
+            stdout.flush()?;
+        }
+        if let Some(command) = post_hook
+            && let Err(e) = hooks::post(command, url, &result)
+        {
+            warn(format!("Warning: post-hook for {} failed: {}\n", url, e));
+        }
+        if options.notify
+            && !options.dry_run
+            && let Err(e) = notify::download(url, &result, !options.expected.is_empty())
+        {
+            warn(format!("Warning: Could not raise a notification: {}\n", e));
+        }
+        if let Some(webhook) = &options.notify_webhook
+            && !options.dry_run
+        {
+            let status = match &result {
+                Ok(Outcome::Saved { .. }) => "saved",
+                Ok(Outcome::Piped { .. }) => "piped",
+                Ok(Outcome::Skipped) => "skipped",
+                Err(_) => "failed",
+            };
+            if let Err(e) = webhook::post(webhook, &transfer, status, &options.http) {
+                warn(format!("Warning: Could not report {} to the webhook: {}\n", url, e));
+            }
+        }
+
+        if let Err(e) = result {
+            // The --json line has it all already
+            if !options.json {
+                warn(report::Report { url, phase: transfer.phase, error: &e }.to_string());
+            }
+            failed += 1;
+            exit_code = exitcode::for_error(&e);
+        }
+    }
+    drop(screen);
+    eprint!("{}", deferred);
+
+    if failed > 0 {
+        eprintln!("{} of {} downloads failed", failed, options.urls.len());
+        return Ok(exit_code);
+    }
     
-    Ok(())
+    Ok(0)
+}
+
+// Cells in the --verbose segment map
+const MAP_WIDTH: usize = 64;
+
+// Whether --max-time is up, so every download is to stop rather than try again
+fn stopping(options: &cli::Options) -> bool {
+    options.http.cancel.as_ref().is_some_and(CancellationToken::is_cancelled)
+}
+
+// How often a wait between retries checks whether --max-time is up
+const BACKOFF_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Wait as the retry policy says after this many failed tries, cut short if
+// the run is being stopped meanwhile
+fn back_off(options: &cli::Options, failed: usize) {
+    let until = Instant::now() + options.retry.delay(failed);
+    while !stopping(options) {
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        std::thread::sleep(left.min(BACKOFF_CHECK_INTERVAL));
+    }
+}
+
+// Whatever a download failed with once --max-time is up, it stopped for that
+fn timed_out(error: std::io::Error, options: &cli::Options, transfer: &writeout::Transfer) -> std::io::Error {
+    match (options.max_time, &options.http.cancel) {
+        (Some(limit), Some(cancel)) if cancel.is_expired() && TimeLimitExceeded::from_error(&error).is_none() => {
+            TimeLimitExceeded { limit, downloaded: transfer.size_download, total_size: None }.into_error()
+        }
+        _ => error,
+    }
+}
+
+// The command line's progress display: a status line per chunk, with the
+// segment map on it in verbose mode. STATUS is kept up to date as well.
+struct ProgressPrinter {
+    map: Option<SegmentMap>,
+}
+
+impl ProgressObserver for ProgressPrinter {
+    fn on_started(&self, url: &Url, total_size: u64) {
+        STATUS.on_started(url, total_size);
+        if tui::active() {
+            tui::DASHBOARD.on_started(url, total_size);
+        }
+        if let Some(map) = &self.map {
+            map.on_started(url, total_size);
+        }
+    }
+
+    fn on_request(&self, offset: u64, len: u64) {
+        STATUS.on_request(offset, len);
+        if tui::active() {
+            tui::DASHBOARD.on_request(offset, len);
+        }
+        if let Some(map) = &self.map {
+            map.on_request(offset, len);
+        }
+    }
+
+    fn on_chunk(&self, offset: u64, len: usize, downloaded: u64, total_size: u64) {
+        STATUS.on_chunk(offset, len, downloaded, total_size);
+        // The dashboard shows the bytes itself
+        if tui::active() {
+            tui::DASHBOARD.on_chunk(offset, len, downloaded, total_size);
+            return;
+        }
+        match &self.map {
+            Some(map) => {
+                map.on_chunk(offset, len, downloaded, total_size);
+                status!("[{}] {}/{} bytes", map.render(), downloaded, total_size);
+            }
+            None => status!("Downloaded: {}/{} bytes", downloaded, total_size),
+        }
+    }
+
+    fn on_retry(&self, offset: u64, attempt: usize, reason: &str) {
+        STATUS.on_retry(offset, attempt, reason);
+        if tui::active() {
+            tui::DASHBOARD.on_retry(offset, attempt, reason);
+        }
+        if let Some(map) = &self.map {
+            map.on_retry(offset, attempt, reason);
+        }
+        status!("Warning: {}, retrying", reason);
+    }
 }
 
-// Get the total size of the content
-fn get_total_size(host: &str) -> std::io::Result<usize> {
-    // Make a full request first to get the total size
-    let request = "GET / HTTP/1.1\r\nHost: 127.0.0.1:8080\r\nConnection: close\r\n\r\n";
+// What became of a download that didn't fail
+enum Outcome {
+    Saved { path: PathBuf, sha256: String },
+    Piped { sha256: String },
+    Skipped,
+}
+
+// Download a single URL to its output file, noting what happened in transfer
+// for --write-out
+fn download(url: &Url, options: &cli::Options, transfer: &mut writeout::Transfer) -> std::io::Result<Outcome> {
+    // A script can send the download elsewhere, and add headers to its requests
+    let rewritten = match &options.script {
+        Some(script) => script.rewrite_url(url)?,
+        None => None,
+    };
+    if let Some(rewritten) = &rewritten {
+        status!("The script sends {} to {}", url, rewritten);
+    }
+    let url = rewritten.as_ref().unwrap_or(url);
+    let headers = match &options.script {
+        Some(script) => script.request_headers(url)?,
+        None => Vec::new(),
+    };
+    let scripted;
+    let settings = if headers.is_empty() {
+        &options.http
+    } else {
+        scripted = http::Settings { headers: [options.http.headers.clone(), headers].concat(), ..options.http.clone() };
+        &scripted
+    };
     
-    let mut conn = TcpStream::connect(host)?;
-    conn.write_all(request.as_bytes())?;
+    // A body with the expected digest already in the store needs no network
+    let stored = match &options.store {
+        Some(dir) => store::find(dir, &options.expected).unwrap_or_else(|e| {
+            status!("Warning: Could not read the store: {}", e);
+            None
+        }),
+        None => None,
+    };
+    let from_store = stored.is_some();
+    let entry = match &options.cache_dir {
+        Some(dir) if !from_store => cache::lookup(dir, url, &settings.headers).unwrap_or_else(|e| {
+            status!("Warning: Could not read the cache: {}", e);
+            None
+        }),
+        _ => None,
+    };
     
-    // We don't need to read all the data, just the headers
-    let mut response = Vec::new();
-    let mut buffer = [0; 1024];
+    // First find out the expected total size. A fresh copy in the cache saves
+    // asking the server at all, and a stale one is only downloaded again if
+    // the server says it has changed. How long the server took to answer
+    // sizes the chunks.
+    let (cached, head, round_trip) = match entry {
+        _ if from_store => {
+            status!("Using the stored copy of {}", url);
+            (None, store::head(stored.as_ref().expect("there is a stored copy")), Duration::ZERO)
+        }
+        None if options.offline => return Err(cache::Offline::Missing(url.to_string()).into_error()),
+        Some(entry) if entry.is_fresh(SystemTime::now()) => {
+            status!("Using the cached copy of {}", url);
+            let head = entry.head.clone();
+            (Some(entry), head, Duration::ZERO)
+        }
+        Some(_) if options.offline => return Err(cache::Offline::Stale(url.to_string()).into_error()),
+        Some(entry) if !entry.validators().is_empty() => {
+            transfer.phase = Some(report::Phase::Probe);
+            let (answer, phases) = http::probe_timed(url, &entry.validators(), settings)?;
+            if answer.status == 304 {
+                status!("The cached copy of {} is still current", url);
+                let head = entry.revalidated(&answer);
+                if let Err(e) = entry.update(url, &head, SystemTime::now()) {
+                    status!("Warning: Could not update the cache: {}", e);
+                }
+                (Some(entry), head, phases.round_trip())
+            } else {
+                (None, answer, phases.round_trip())
+            }
+        }
+        _ => {
+            transfer.phase = Some(report::Phase::Probe);
+            let (head, phases) = http::probe_timed(url, &[], settings)?;
+            (None, head, phases.round_trip())
+        }
+    };
+    if let Some(script) = &options.script {
+        script.on_response(head.status, &head.headers)?;
+    }
+    let received = SystemTime::now();
+    transfer.status = Some(head.status);
+    transfer.content_type = head.header("Content-Type").map(str::to_string);
+    let mut total_size = head.content_length()
+        .ok_or_else(|| report::MissingHeader { name: "Content-Length" }.into_error())?;
+    transfer.phase = None;
+    status!("Detected total size: {} bytes", total_size);
+    let remote_modified = head.header("Last-Modified").and_then(time::parse_http_date);
     
-    // Read just enough to get the headers
-    loop {
-        match conn.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => {
-                response.extend_from_slice(&buffer[0..n]);
-                // If we have the headers, we can stop
-                if response.windows(4).any(|w| w == b"\r\n\r\n") {
-                    break;
+    // The body is written to a .part file and only renamed to the output
+    // path once it has been verified. The output path may depend on the
+    // hash, in which case a temporary name is used until it is known.
+    let mut naming = output::Naming {
+        output: options.output.as_deref(),
+        template: options.output_template.as_ref(),
+        prefix: options.directory_prefix.as_deref(),
+        content: transfer.content_type.as_deref().filter(|_| options.adjust_extension).and_then(mime::for_content_type),
+        extension: options.compress.map(compress::Format::extension),
+    };
+    let resolved = if options.exec.is_some() { None } else { output::resolve_path(url, &naming, None) };
+    // Another run writing the same output is waited for or refused before
+    // anything is written, and before the existing-file policy looks at it
+    let _lock = match &resolved {
+        Some(path) if !options.dry_run => {
+            create_parent_dirs(path)?;
+            Some(lock_output(path, options)?)
+        }
+        _ => None,
+    };
+    let final_path = match resolved {
+        Some(path) => match output::place(path, options.existing, total_size, remote_modified, &options.expected)? {
+            Some(path) => Some(path),
+            None => {
+                status!("Skipping {}, output file already exists", url);
+                return Ok(Outcome::Skipped);
+            }
+        },
+        None => None,
+    };
+    let write_path = match &final_path {
+        Some(path) => output::part_path(path),
+        None => output::temp_path(url, &naming),
+    };
+    if options.dry_run {
+        let plan = plan::Plan { url, head: &head, total_size, output: final_path.as_deref(), part: &write_path };
+        plan::print(&plan, options);
+        return Ok(Outcome::Skipped);
+    }
+    create_parent_dirs(&write_path)?;
+    
+    // A stored body is linked into place rather than written out again, when
+    // nothing is done to the file on the way
+    let unchanged = options.compress.is_none() && !options.tee && options.exec.is_none() && options.split.is_none()
+        && options.mode.is_none() && !options.executable && !options.remote_time && !options.xattr;
+    if let Some(stored) = stored.as_ref().filter(|_| unchanged)
+        && let Some(path) = &final_path
+    {
+        let _ = std::fs::remove_file(&write_path);
+        match store::link(stored, &write_path).and_then(|link| std::fs::rename(&write_path, path).map(|()| link)) {
+            Ok(link) => {
+                let hash = to_hex(&stored.sha256);
+                status!("Placed {} from the store as a {}", path.display(), link);
+                return saved(path.clone(), hash, path, options);
+            }
+            Err(e) => status!("Could not link {} from the store ({}), writing it out", path.display(), e),
+        }
+    }
+    
+    // A stored or cached body is used as it is. Otherwise, with a zsync
+    // control file, reuse whatever a stale local copy already has.
+    let seed = final_path.as_ref().filter(|path| path.exists());
+    let reused = match (&options.zsync, seed) {
+        _ if from_store => stored.map(|stored| File::open(stored.path).map(Reused::File)).transpose()?,
+        _ if cached.is_some() => cached.as_ref().map(|entry| entry.open_body().map(Reused::File)).transpose()?,
+        (Some(control), Some(seed)) => {
+            let control = delta::ControlFile::load(control, settings)?;
+            if control.length != total_size {
+                return Err(std::io::Error::other(format!(
+                    "zsync control file is for {} bytes, but the server has {}",
+                    control.length, total_size
+                )));
+            }
+            // Rebuilt in the .part file, block by block
+            let part = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&write_path)?;
+            let stats = match delta::reconstruct(url, &control, seed, &part, settings) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    let _ = std::fs::remove_file(&write_path);
+                    return Err(e);
+                }
+            };
+            status!("Delta: reused {} of {} blocks from {}, fetched {} bytes in {} ranges over {} requests",
+                    stats.reused_blocks, stats.total_blocks, seed.display(), stats.fetched_bytes, stats.ranges, stats.requests);
+            transfer.size_download += stats.fetched_bytes;
+            Some(Reused::Part)
+        }
+        // A multipart S3 object comes part by part instead of in ranges, each
+        // written where it belongs in the .part file
+        _ if options.s3 => match s3::fetch_parts(url, &head, total_size, &File::create(&write_path)?, settings) {
+            Ok(Some(offsets)) => {
+                status!("Fetched {} in {} parts, multipart ETag matches", url, offsets.len());
+                transfer.size_download += total_size;
+                Some(Reused::Part)
+            }
+            Ok(None) => None,
+            Err(e) => {
+                let _ = std::fs::remove_file(&write_path);
+                return Err(e);
+            }
+        },
+        _ => None,
+    };
+    
+    // With --connections the body comes over several connections at once,
+    // unless it is here already
+    let parallel = options.connections.filter(|&connections| connections > 1 && reused.is_none());
+    
+    // Resuming picks up where an earlier run left its .part file
+    let resumed = match (options.resume && cached.is_none() && !from_store, &final_path) {
+        (false, _) => None,
+        (true, None) => {
+            return Err(std::io::Error::other("Can't resume when the output name depends on the hash"));
+        }
+        // With --connections the library picks up the chunks listed in the
+        // .part file's control file
+        (true, Some(_)) if parallel.is_some() => None,
+        (true, Some(_)) => match File::open(&write_path) {
+            Ok(part) => {
+                let have = part.metadata()?.len();
+                if have > total_size {
+                    return Err(std::io::Error::other(format!(
+                        "{} has {} bytes but the server has only {}, not resuming",
+                        write_path.display(), have, total_size
+                    )));
                 }
+                Some(part)
             }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
             Err(e) => return Err(e),
+        },
+    };
+    
+    // The body is hashed as it goes by, for the checks once it is all in,
+    // starting with what an earlier run left in the .part file
+    let mut hashed = BodyHash::new(options);
+    let mut position: u64 = 0;
+    let filled = parallel.is_some() || matches!(reused, Some(Reused::Part));
+    let mut file = match (parallel, resumed) {
+        (Some(connections), _) => {
+            transfer.size_download += fetch_parallel(url, &head, round_trip, &write_path, connections, settings, options)?;
+            hash_part(&write_path, &mut hashed, &mut position)?
+        }
+        (None, _) if filled => hash_part(&write_path, &mut hashed, &mut position)?,
+        (None, Some(part)) => {
+            position = read_pieces(part, |piece| {
+                hashed.update(piece);
+                Ok(())
+            })?;
+            status!("Resuming {} at byte {}", write_path.display(), position);
+            std::fs::OpenOptions::new().append(true).open(&write_path)?
+        }
+        (None, None) => File::create(&write_path)?,
+    };
+    
+    // Everything received goes to the file, and to stdout as well in tee mode
+    let mut compressor = match options.compress.map(|format| compress::Compressor::spawn(format, &file)) {
+        Some(Ok(compressor)) => Some(compressor),
+        Some(Err(e)) => {
+            drop(file);
+            let _ = std::fs::remove_file(&write_path);
+            return Err(e);
+        }
+        None => None,
+    };
+    
+    // Unless it is in the .part file already, the body is fetched here, one
+    // range after another
+    if !filled {
+        let mut writer = sink::MultiWriter::new();
+        match &mut compressor {
+            Some(compressor) => writer.add(compressor),
+            None => writer.add(&mut file),
+        }
+        if options.tee {
+            writer.add(std::io::stdout());
+        }
+        
+        // A thread does the writing, never more than a few chunks behind, so a slow
+        // disk or reader of stdout slows the download down instead of filling memory
+        std::thread::scope(|scope| -> std::io::Result<()> {
+            let mut writer = sink::Bounded::spawn(scope, writer, sink::QUEUE_DEPTH);
+            
+            match reused {
+                Some(Reused::File(reused)) => {
+                    position = read_pieces(reused, |piece| {
+                        hashed.update(piece);
+                        writer.write_all(piece)
+                    })?;
+                }
+                Some(Reused::Part) | None => {}
+            }
+            
+            // With mirrors, the chunks are spread over every source that has the body,
+            // the faster ones getting more
+            let mut sources = None;
+            if !options.mirrors.is_empty() && cached.is_none() && !from_store {
+                let (mirrors, dropped) = mirrors::Mirrors::probe(url, &options.mirrors, total_size, settings);
+                for reason in dropped {
+                    status!("Warning: not using {}", reason);
+                }
+                if mirrors.is_empty() {
+                    return Err(std::io::Error::other("None of the mirrors has the file"));
+                }
+                for line in mirrors.ranking() {
+                    status!("Mirror {}", line);
+                }
+                sources = Some(mirrors);
+            }
+            
+            // Download until we've reached the total size
+            let progress = ProgressPrinter { map: options.verbose.then(|| SegmentMap::new(MAP_WIDTH)) };
+            progress.on_started(url, total_size);
+            // Resumed or reused bytes are in already. A length beyond usize only
+            // happens on 32-bit platforms, where the map just shows it all done.
+            let already = usize::try_from(position).unwrap_or(usize::MAX);
+            if let Some(map) = &progress.map {
+                map.on_chunk(0, already, position, total_size);
+            }
+            if tui::active() {
+                tui::DASHBOARD.on_chunk(0, already, position, total_size);
+            }
+            let mut attempt = 1;
+            let started = Instant::now();
+            let mut fetched = 0;
+            let mut sizer = engine::ChunkSizer::new(round_trip);
+            // With --pipeline and a single source, the ranges asked for ahead of
+            // position that haven't been answered yet, oldest first. HTTP/2 keeps
+            // its one connection this way even without --pipeline.
+            let mut pipeline = options.pipeline
+                .or(settings.http2.then_some(1))
+                .filter(|_| sources.is_none())
+                .map(|depth| (http::Pipeline::new(url, depth, settings), VecDeque::new()));
+            let mut answered = Instant::now();
+            let connections = sources.as_ref().map_or(1, |mirrors| mirrors.len());
+            let network = NetworkWatch::new(connections, options.retry.max_attempts, options.network_wait.unwrap_or(network::DEFAULT_NETWORK_WAIT));
+            while position < total_size {
+                tui::DASHBOARD.checkpoint(position, total_size)?;
+                // The script can pick the mirror for each range itself
+                let chosen = match (&options.script, &sources) {
+                    (Some(script), Some(mirrors)) => {
+                        let urls = mirrors.urls();
+                        script.choose_mirror(&urls, position)?.map(|index| urls[index].clone())
+                    }
+                    _ => None,
+                };
+                let source = match (&mut sources, chosen) {
+                    (_, Some(chosen)) => chosen,
+                    (Some(mirrors), None) => mirrors.next().clone(),
+                    (None, None) => url.clone(),
+                };
+                // A pipelined answer has been on its way since the one before it
+                let requested = if pipeline.is_some() { answered } else { Instant::now() };
+                let answer = match &mut pipeline {
+                    Some((pipeline, asked)) => {
+                        let mut next = asked.back().map_or(position, |&(_, end)| end + 1);
+                        while !pipeline.is_full() && next < total_size {
+                            let end = next.saturating_add(sizer.size()).min(total_size) - 1;
+                            progress.on_request(next, end - next + 1);
+                            pipeline.send(next, end)?;
+                            asked.push_back((next, end));
+                            next = end + 1;
+                        }
+                        let answer = pipeline.receive();
+                        // Anything but the whole range leaves the requests behind it
+                        // asking for the wrong bytes, so they are asked for again
+                        let (start, end) = asked.pop_front().expect("a range is always asked for");
+                        transfer.phase = Some(report::Phase::Range { start, end });
+                        if !answer.as_ref().is_ok_and(|chunk| chunk.len() as u64 == end - start + 1) {
+                            pipeline.reset();
+                            asked.clear();
+                        }
+                        answer
+                    }
+                    None => {
+                        progress.on_request(position, sizer.size());
+                        let end = position.saturating_add(sizer.size()).min(total_size) - 1;
+                        transfer.phase = Some(report::Phase::Range { start: position, end });
+                        http::download_range(&source, position, end, settings)
+                    }
+                };
+                answered = Instant::now();
+                // Every source failing since the last answer is more likely the
+                // network changing under the download; once it is back, the range
+                // goes again with the tries forgiven
+                let answer = match answer {
+                    Err(e) if !stopping(options) => match network.on_failure(&e) {
+                        Some(generation) => {
+                            status!("Warning: {} failed ({}), checking the network", source, e);
+                            let probe = || http::probe(&source, settings).map(drop);
+                            if !network.recover(generation, probe, || stopping(options))? {
+                                Err(e)
+                            } else {
+                                progress.on_retry(position, attempt, &format!("The network came back after {}", e));
+                                if let Some((pipeline, asked)) = &mut pipeline {
+                                    pipeline.reset();
+                                    asked.clear();
+                                }
+                                attempt = 1;
+                                continue;
+                            }
+                        }
+                        None => Err(e),
+                    },
+                    answer => answer,
+                };
+                let chunk = match answer {
+                    Ok(chunk) => {
+                        network.on_success();
+                        sizer.observe(chunk.len() as u64, answered - requested);
+                        chunk
+                    }
+                    // Carry on with the other mirrors while there are any, unless
+                    // the whole run is being stopped
+                    Err(e) if sources.as_ref().is_some_and(|mirrors| mirrors.len() > 1) && !stopping(options) => {
+                        status!("Warning: {} failed ({}), dropping it", source, e);
+                        if let Some(mirrors) = &mut sources {
+                            mirrors.remove(&source);
+                        }
+                        continue;
+                    }
+                    // The body turned out to end right where we are, so it is complete
+                    Err(e) if http::RangeNotSatisfiable::from_error(&e).and_then(|e| e.total_size) == Some(position) => {
+                        status!("Server has only {} bytes, all of which are here", position);
+                        total_size = position;
+                        break;
+                    }
+                    // A failure on the way is worth asking again, any other ends the download
+                    Err(e) if retry::is_retryable(&e) && options.retry.allows(attempt) => {
+                        progress.on_retry(position, attempt, &e.to_string());
+                        back_off(options, attempt);
+                        attempt += 1;
+                        continue;
+                    }
+                    Err(e) => return Err(e),
+                };
+            
+                if chunk.is_empty() {
+                    if !options.retry.allows(attempt) {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            format!("No data for range starting at {} after {} tries", position, attempt),
+                        ));
+                    }
+                    progress.on_retry(position, attempt, "Received empty chunk");
+                    back_off(options, attempt);
+                    attempt += 1;
+                    continue;
+                }
+            
+                writer.write_all(&chunk)?;
+                hashed.update(&chunk);
+                progress.on_chunk(position, chunk.len(), position + chunk.len() as u64, total_size);
+                if let Some(script) = &options.script {
+                    script.on_chunk(position, chunk.len())?;
+                }
+                position += chunk.len() as u64;
+                attempt = 1;
+            
+                fetched += chunk.len() as u64;
+                transfer.size_download += chunk.len() as u64;
+                if let Some(rate) = options.limit_rate {
+                    throttle(started, fetched, rate);
+                }
+            }
+            writer.finish()
+        })?;
+    }
+    transfer.phase = Some(report::Phase::Finish);
+    if let Some(compressor) = compressor {
+        compressor.finish()?;
+    }
+    
+    // With --compressed a body that came encoded is saved, checked and stored
+    // decoded, streamed from the file as it came into the .part file. The
+    // cache keeps it as it came, to go with its headers.
+    let mut received_path = write_path.clone();
+    let received_hash = hashed;
+    let mut hashed = received_hash.clone();
+    if let Some(encoding) = head.header("Content-Encoding").filter(|_| options.compressed)
+        && position == total_size
+    {
+        drop(file);
+        received_path = output::encoded_path(&write_path);
+        std::fs::rename(&write_path, &received_path)?;
+        hashed = BodyHash::new(options);
+        let decoded = File::create(&write_path).and_then(|mut decoded| {
+            let encoded = Box::new(File::open(&received_path)?);
+            let size = read_pieces(inflate::content_decoder(encoding, encoded)?, |piece| {
+                hashed.update(piece);
+                decoded.write_all(piece)
+            })?;
+            Ok((decoded, size))
+        });
+        let size;
+        (file, size) = match decoded {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                let _ = std::fs::remove_file(&received_path);
+                let _ = std::fs::remove_file(&write_path);
+                return Err(std::io::Error::new(e.kind(), format!("Could not decode the body: {}", e)));
+            }
+        };
+        status!("Decoded {} bytes of {} to {} bytes", position, encoding, size);
+    }
+    // Whatever happens next, the encoded copy is only needed for the cache
+    let discard = || {
+        let _ = std::fs::remove_file(&write_path);
+        if received_path != write_path {
+            let _ = std::fs::remove_file(&received_path);
+        }
+    };
+    
+    // Carry the remote modification time over to the file
+    if options.remote_time {
+        match remote_modified {
+            Some(modified) => file.set_modified(modified)?,
+            None => status!("Warning: No usable Last-Modified header, keeping the local time"),
         }
     }
+    file.sync_all()?;
+    drop(file);
     
-    // Parse the headers to find Content-Length
-    let headers = String::from_utf8_lossy(&response);
-    let content_length = headers.lines()
-        .find(|line| line.to_lowercase().starts_with("content-length:"))
-        .and_then(|line| line.split(':').nth(1))
-        .and_then(|len| len.trim().parse::<usize>().ok())
-        .ok_or(std::io::Error::new(std::io::ErrorKind::Other, "No Content-Length header"))?;
+    // Verify we got the expected amount of data
+    if position != total_size {
+        discard();
+        return Err(std::io::Error::other(format!(
+            "Downloaded size ({}) doesn't match expected size ({})",
+            position, total_size
+        )));
+    }
     
-    Ok(content_length)
-}
-
-// Download a chunk of data starting at the specified position
-fn download_chunk(host: &str, start_position: usize) -> std::io::Result<Vec<u8>> {
-    let chunk_size = 64 * 1024; // 64KB chunks
-    let end_position = start_position + chunk_size - 1;
+    if let Some(md5) = received_hash.md5 {
+        match s3::check_md5_etag(&head, &md5.finish()) {
+            Ok(true) => status!("ETag check passed (MD5)"),
+            Ok(false) => {}
+            Err(e) => {
+                discard();
+                return Err(e);
+            }
+        }
+    }
+    
+    let digests = hashed.digests.finish();
+    let hash = to_hex(&digests[0].1);
+    
+    status!("Download complete. SHA-256 hash: {}", hash);
+    
+    // Check against the expected digests, if any were given
+    if options.expected.is_empty() {
+        status!("Verify this hash matches what the server displayed");
+    } else {
+        let checked = match integrity::check_digests(&options.expected, &digests) {
+            Ok(checked) => checked,
+            Err(e) => {
+                discard();
+                return Err(e);
+            }
+        };
+        let names: Vec<&str> = checked.iter().map(|a| a.name()).collect();
+        status!("Integrity check passed ({})", names.join(", "));
+    }
     
-    let range = format!("bytes={}-{}", start_position, end_position);
-    let request = format!(
-        "GET / HTTP/1.1\r\nHost: 127.0.0.1:8080\r\nRange: {}\r\nConnection: close\r\n\r\n", 
-        range
-    );
+    if let Some(dir) = &options.cache_dir
+        && cached.is_none()
+        && !from_store
+        && let Err(e) = read_body(&received_path, options)
+            .and_then(|mut body| cache::store(dir, url, &settings.headers, &head, received, &mut body))
+    {
+        status!("Warning: Could not cache {}: {}", url, e);
+    }
+    if received_path != write_path {
+        std::fs::remove_file(&received_path)?;
+    }
     
-    let mut conn = TcpStream::connect(host)?;
-    conn.write_all(request.as_bytes())?;
+    if let Some(dir) = &options.store
+        && !from_store
+        && let Err(e) = read_body(&write_path, options).and_then(|mut body| store::add(dir, &mut body, &digests))
+    {
+        status!("Warning: Could not add {} to the store: {}", url, e);
+    }
     
-    let mut response = Vec::new();
-    let mut buffer = [0; 4096];
+    if options.mode.is_some() || options.executable {
+        output::set_permissions(&write_path, options.mode, options.executable)?;
+    }
     
-    // Read the entire response
+    // Extended attributes survive the rename, so they can be set on the .part file
+    if options.xattr
+        && let Err(e) = xattr::record_provenance(&write_path, url, head.header("ETag"), &hash)
+    {
+        status!("Warning: Could not record extended attributes: {}", e);
+    }
+    
+    // With --exec the verified body goes to the command instead of a file
+    if let Some(command) = &options.exec {
+        status!("Piping {} bytes to {}", total_size, command);
+        let piped = File::open(&write_path).and_then(|mut body| exec::pipe(command, &mut body));
+        std::fs::remove_file(&write_path)?;
+        piped?;
+        return Ok(Outcome::Piped { sha256: hash });
+    }
+    
+    // A body whose Content-Type didn't say what it is is named for what its
+    // first bytes show, if its name has no extension of its own
+    let final_path = match final_path {
+        Some(path) if options.adjust_extension && options.output.is_none() && naming.content.is_none() => {
+            let unnamed = url.file_name().is_none() || path.extension().is_none();
+            match mime::sniff(&body_prefix(&write_path, options)?).filter(|_| unnamed) {
+                Some(content) => {
+                    naming.content = Some(content);
+                    None
+                }
+                None => Some(path),
+            }
+        }
+        path => path,
+    };
+    let path = match final_path {
+        Some(path) => path,
+        None => {
+            let naming = output::Naming { output: None, ..naming };
+            let path = output::resolve_path(url, &naming, Some(&hash))
+                .expect("output path is known once the hash is");
+            match output::place(path, options.existing, total_size, remote_modified, &options.expected) {
+                Ok(Some(path)) => {
+                    create_parent_dirs(&path)?;
+                    path
+                }
+                Ok(None) => {
+                    std::fs::remove_file(&write_path)?;
+                    status!("Skipping {}, output file already exists", url);
+                    return Ok(Outcome::Skipped);
+                }
+                Err(e) => {
+                    let _ = std::fs::remove_file(&write_path);
+                    return Err(e);
+                }
+            }
+        }
+    };
+    
+    // Move the verified file into place in one step, or cut it into parts,
+    // once anything else has read it whole
+    if let Some(part_size) = options.split {
+        let outcome = saved(path.clone(), hash, &write_path, options)?;
+        let parts = split::split(&write_path, &path, part_size)?;
+        status!("Saved {} parts as {}.part*, hashes in {}",
+                parts.len(), path.display(), split::manifest_path(&path).display());
+        return Ok(outcome);
+    }
+    output::move_into_place(&write_path, &path)?;
+    status!("Saved to {}", path.display());
+    
+    saved(path.clone(), hash, &path, options)
+}
+
+// Fetch the body into the .part file with the library's Download, its
+// ranges spread over up to connections connections and written where they
+// belong as they arrive. If that fails, which chunks are in is left in the
+// .part file's control file. Returns how many bytes came over the network.
+fn fetch_parallel(url: &Url, head: &http::ResponseHead, round_trip: Duration, part: &Path, connections: usize, settings: &http::Settings, options: &cli::Options) -> std::io::Result<u64> {
+    // The control file is always kept for a `resume`, but only a `resume`
+    // uses what an earlier run left
+    if !options.resume {
+        let _ = std::fs::remove_file(output::control_path(part));
+    }
+    let progress = ProgressPrinter { map: options.verbose.then(|| SegmentMap::new(MAP_WIDTH)) };
+    let download = Download::builder(&url.to_string())
+        .connections(connections)
+        .min_split_size(options.min_split_size.unwrap_or(engine::DEFAULT_MIN_SPLIT_SIZE))
+        .pipeline(options.pipeline.unwrap_or(1))
+        .io_uring(options.io_uring)
+        .retry_policy(options.retry)
+        .resumable(true)
+        .network_wait(options.network_wait.unwrap_or(network::DEFAULT_NETWORK_WAIT))
+        .settings(settings.clone())
+        .observer(progress)
+        .build()?;
+    download.fetch_part(part, head, round_trip)
+}
+
+// Hash the body in the .part file so far, setting position to its end, and
+// open the file for the rest
+fn hash_part(path: &Path, hashed: &mut BodyHash, position: &mut u64) -> std::io::Result<File> {
+    *position = read_pieces(File::open(path)?, |piece| {
+        hashed.update(piece);
+        Ok(())
+    })?;
+    std::fs::OpenOptions::new().append(true).open(path)
+}
+
+// Bytes of the body already here before the download starts
+enum Reused {
+    // The stored or cached copy
+    File(File),
+    // Written into the .part file already, from S3 parts or rebuilt from a
+    // zsync seed
+    Part,
+}
+
+// The hashes checked once a body is all in, worked out as it goes by: its
+// digests, and its MD5 for an ETag with --s3
+#[derive(Clone)]
+struct BodyHash {
+    digests: integrity::Digests,
+    md5: Option<md5::Md5>,
+}
+
+impl BodyHash {
+    fn new(options: &cli::Options) -> BodyHash {
+        BodyHash { digests: integrity::Digests::new(&options.expected), md5: options.s3.then(md5::Md5::new) }
+    }
+    
+    fn update(&mut self, data: &[u8]) {
+        self.digests.update(data);
+        if let Some(md5) = &mut self.md5 {
+            md5.update(data);
+        }
+    }
+}
+
+// Bytes read at a time when a body is read from a file
+const READ_SIZE: usize = 64 * 1024;
+
+// Bytes of a saved body looked at to tell what it holds
+const SNIFF_SIZE: u64 = 64 * 1024;
+
+// Pass everything input has left to each, a buffer at a time; returns how
+// much there was
+fn read_pieces(mut input: impl Read, mut each: impl FnMut(&[u8]) -> std::io::Result<()>) -> std::io::Result<u64> {
+    let mut buffer = vec![0u8; READ_SIZE];
+    let mut total = 0;
     loop {
-        match conn.read(&mut buffer) {
-            Ok(0) => break,
-            Ok(n) => response.extend_from_slice(&buffer[0..n]),
+        match input.read(&mut buffer) {
+            Ok(0) => return Ok(total),
+            Ok(n) => {
+                each(&buffer[..n])?;
+                total += n as u64;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
             Err(e) => return Err(e),
         }
     }
+}
+
+// Read a saved body back as it was downloaded, undoing --compress-output
+fn read_body(path: &Path, options: &cli::Options) -> std::io::Result<Box<dyn Read>> {
+    let file = File::open(path)?;
+    match options.compress {
+        Some(format) => Ok(Box::new(compress::Decompressor::spawn(format, file)?)),
+        None => Ok(Box::new(file)),
+    }
+}
+
+// The first bytes of a saved body, enough to tell what it holds
+fn body_prefix(path: &Path, options: &cli::Options) -> std::io::Result<Vec<u8>> {
+    let mut prefix = Vec::new();
+    read_body(path, options)?.take(SNIFF_SIZE).read_to_end(&mut prefix)?;
+    Ok(prefix)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Finish with a file that is in place, with its body in the file at body:
+// list it in the checksum file and unpack it, if asked to
+fn saved(path: PathBuf, hash: String, body: &Path, options: &cli::Options) -> std::io::Result<Outcome> {
+    if let Some(sums) = &options.write_checksums {
+        output::append_checksum(sums, &path, &hash)?;
+    }
     
-    // Check if we got a valid response
-    if response.is_empty() {
-        return Ok(Vec::new());
+    if options.extract {
+        let kind = archive::detect(&body_prefix(body, options)?)
+            .ok_or_else(|| std::io::Error::other(format!("{} is not a tar, tar.gz or zip archive", path.display())))?;
+        let dest = match &options.extract_dir {
+            Some(dir) => dir.clone(),
+            None => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        let stats = match options.compress {
+            None => archive::extract(File::open(body)?, kind, &dest)?,
+            // A zip is read from the end, so a compressed output is
+            // decompressed into a file beside it first
+            Some(_) => {
+                let plain = output::decompressed_path(body);
+                let unpacked = read_body(body, options)
+                    .and_then(|mut input| std::io::copy(&mut input, &mut File::create(&plain)?))
+                    .and_then(|_| archive::extract(File::open(&plain)?, kind, &dest));
+                let _ = std::fs::remove_file(&plain);
+                unpacked?
+            }
+        };
+        let shown = if dest.as_os_str().is_empty() { Path::new(".") } else { &dest };
+        status!(
+            "Extracted {} files, {} directories and {} links into {}",
+            stats.files, stats.directories, stats.links, shown.display()
+        );
+        if stats.skipped > 0 {
+            status!("Skipped {} special entries", stats.skipped);
+        }
     }
     
-    // Extract just the body
-    Ok(extract_body(&response))
+    Ok(Outcome::Saved { path, sha256: hash })
 }
 
-// Extract the HTTP body from a complete HTTP response
-fn extract_body(response: &[u8]) -> Vec<u8> {
-    // Look for the double CRLF that separates headers from body
-    let mut i = 0;
-    while i + 3 < response.len() {
-        if &response[i..i+4] == b"\r\n\r\n" {
-            return response[i+4..].to_vec();
+// Lock the output path for the rest of the download, waiting for whoever
+// holds it with --wait-for-lock
+fn lock_output(path: &Path, options: &cli::Options) -> std::io::Result<OutputLock> {
+    match OutputLock::acquire(path, false) {
+        Err(e) if options.wait_for_lock && Locked::from_error(&e).is_some() => {
+            status!("Waiting: {}", e);
+            OutputLock::acquire(path, true)
         }
-        i += 1;
+        result => result,
     }
-    
-    // If we can't find the separator, return an empty vector
-    // This is safer than returning potentially incorrect data
-    Vec::new()
-}
\ No newline at end of file
+}
+
+// Sleep long enough to bring the average speed since started down to the
+// rate, in bytes per second
+fn throttle(started: Instant, fetched: u64, rate: u64) {
+    let due = Duration::from_secs_f64(fetched as f64 / rate as f64);
+    if let Some(ahead) = due.checked_sub(started.elapsed()) {
+        std::thread::sleep(ahead);
+    }
+}
+
+// Make sure the directory a file is about to be created in exists
+fn create_parent_dirs(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::create_dir_all(parent),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,79 @@
+// MD4 as specified in RFC 1320. zsync control files use truncated MD4 sums
+// as their strong per-block checksum, so this is only used for delta downloads.
+
+const ROUND_SHIFTS: [[u32; 4]; 3] = [[3, 7, 11, 19], [3, 5, 9, 13], [3, 9, 11, 15]];
+const ROUND_ORDER: [[usize; 16]; 3] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [0, 4, 8, 12, 1, 5, 9, 13, 2, 6, 10, 14, 3, 7, 11, 15],
+    [0, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15],
+];
+const ROUND_CONSTANTS: [u32; 3] = [0, 0x5a827999, 0x6ed9eba1];
+
+pub fn digest(data: &[u8]) -> [u8; 16] {
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    // Same padding as MD5: a 1 bit, zeros, and the length in bits
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for block in message.chunks_exact(64) {
+        let words: Vec<u32> = block.chunks_exact(4)
+            .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+            .collect();
+        // Registers in the order the next step works on: target first
+        let mut r = state;
+
+        for round in 0..3 {
+            for step in 0..16 {
+                let (b, c, d) = (r[1], r[2], r[3]);
+                let f = match round {
+                    0 => (b & c) | (!b & d),
+                    1 => (b & c) | (b & d) | (c & d),
+                    _ => b ^ c ^ d,
+                };
+                r[0] = r[0]
+                    .wrapping_add(f)
+                    .wrapping_add(words[ROUND_ORDER[round][step]])
+                    .wrapping_add(ROUND_CONSTANTS[round])
+                    .rotate_left(ROUND_SHIFTS[round][step % 4]);
+                r.rotate_right(1);
+            }
+        }
+
+        for (s, r) in state.iter_mut().zip(r) {
+            *s = s.wrapping_add(r);
+        }
+    }
+
+    let mut out = [0u8; 16];
+    for (i, word) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc_1320_test_suite() {
+        let cases = [
+            ("", "31d6cfe0d16ae931b73c59d7e0c089c0"),
+            ("a", "bde52cb31de33e46245e05fbdbd6fb24"),
+            ("abc", "a448017aaf21d8525fc10ae87aa6729d"),
+            ("message digest", "d9130a8164549fe818874806e1c7014b"),
+            ("abcdefghijklmnopqrstuvwxyz", "d79e1c308aa5bbcdeea8ed63df412da9"),
+            ("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789", "043f8582f241db351ce627e153e7f0e4"),
+            ("12345678901234567890123456789012345678901234567890123456789012345678901234567890", "e33b4ddc9c38f2199c3e7b164fcc0536"),
+        ];
+        for (input, expected) in cases {
+            let hex: String = digest(input.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+            assert_eq!(hex, expected, "{:?}", input);
+        }
+    }
+}
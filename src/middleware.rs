@@ -0,0 +1,161 @@
+// Layers around the requests the client sends, for library users to add
+// their own: each layer gets the request on its way out and calls next to
+// pass it on, or answers it itself, and gets the response on its way back.
+// Layers run in the order they were added, the first outermost.
+//
+// Every probe, range request, GET and POST goes through the layers. Ranges
+// read straight off the connection, pipelined or with io_uring, don't, as
+// their bodies never sit in a Response, and nor do uploads, whose bodies
+// never sit in a Request; a Download with layers doesn't splice for the same
+// reason.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http::ResponseHead;
+use crate::url::Url;
+
+// A request as the layers see it. The headers are the request's own, such
+// as Range; those every request gets, from Settings::headers, cookies and
+// credentials, are added once it leaves the last layer.
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub url: Url,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    // Only the head of the answer is wanted, as for a probe; the body is
+    // left unread and the Response's is empty
+    pub head_only: bool,
+}
+
+impl Request {
+    // Value of the first header with the given name, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Response {
+    pub head: ResponseHead,
+    pub body: Vec<u8>,
+}
+
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: Request, next: Next) -> std::io::Result<Response>;
+}
+
+// The rest of the layers, and the connection after them
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    send: &'a dyn Fn(Request) -> std::io::Result<Response>,
+}
+
+impl Next<'_> {
+    pub fn run(self, request: Request) -> std::io::Result<Response> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(request, Next { layers, send: self.send }),
+            None => (self.send)(request),
+        }
+    }
+}
+
+// The layers of a Settings. Clones share the layers.
+#[derive(Clone, Default)]
+pub struct Stack {
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl std::fmt::Debug for Stack {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Stack").field("layers", &self.layers.len()).finish()
+    }
+}
+
+impl Stack {
+    // Add a layer inside those already there
+    pub fn push(&mut self, layer: impl Middleware + 'static) {
+        self.layers.push(Arc::new(layer));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    // Add the layers of another stack inside those already there
+    pub(crate) fn append(&mut self, inner: Stack) {
+        self.layers.extend(inner.layers);
+    }
+
+    // Pass the request through every layer, then to send
+    pub(crate) fn run(&self, request: Request, send: &dyn Fn(Request) -> std::io::Result<Response>) -> std::io::Result<Response> {
+        Next { layers: &self.layers, send }.run(request)
+    }
+}
+
+// Sets a header on every request that doesn't have it already, such as an
+// Authorization for an API that wants one
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+impl Middleware for Header {
+    fn handle(&self, mut request: Request, next: Next) -> std::io::Result<Response> {
+        if request.header(&self.name).is_none() {
+            request.headers.push((self.name.clone(), self.value.clone()));
+        }
+        next.run(request)
+    }
+}
+
+// A line on stderr for every request and what came of it
+pub struct Log;
+
+impl Middleware for Log {
+    fn handle(&self, request: Request, next: Next) -> std::io::Result<Response> {
+        let line = match request.header("Range") {
+            Some(range) => format!("{} {} ({})", request.method, request.url, range),
+            None => format!("{} {}", request.method, request.url),
+        };
+        let started = Instant::now();
+        let response = next.run(request);
+        match &response {
+            Ok(response) => eprintln!(
+                "{}: {}, {} bytes in {} ms", line, response.head.status, response.body.len(), started.elapsed().as_millis()
+            ),
+            Err(e) => eprintln!("{}: {}", line, e),
+        }
+        response
+    }
+}
+
+// Spaces requests out to at most so many a second, however many threads
+// send them. The rate has to be more than zero.
+pub struct RateLimit {
+    interval: Duration,
+    // When the next request may go
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    pub fn per_second(requests: f64) -> RateLimit {
+        RateLimit { interval: Duration::from_secs_f64(1.0 / requests), next: Mutex::new(Instant::now()) }
+    }
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, request: Request, next: Next) -> std::io::Result<Response> {
+        let due = {
+            let mut slot = self.next.lock().expect("no thread panics holding the rate limit");
+            let due = (*slot).max(Instant::now());
+            *slot = due + self.interval;
+            due
+        };
+        std::thread::sleep(due.saturating_duration_since(Instant::now()));
+        next.run(request)
+    }
+}
@@ -0,0 +1,89 @@
+// What kind of file a body is, for --adjust-extension: the extension its
+// Content-Type calls for or, when that says nothing useful, the one its
+// first bytes give away.
+
+use http_client::inflate;
+
+use crate::archive::{self, Kind};
+
+// Content types and the extension each gets
+const TYPES: &[(&str, &str)] = &[
+    ("text/html", "html"),
+    ("application/xhtml+xml", "html"),
+    ("text/css", "css"),
+    ("text/csv", "csv"),
+    ("text/plain", "txt"),
+    ("text/xml", "xml"),
+    ("application/xml", "xml"),
+    ("text/javascript", "js"),
+    ("application/javascript", "js"),
+    ("application/json", "json"),
+    ("application/pdf", "pdf"),
+    ("application/gzip", "gz"),
+    ("application/x-gzip", "gz"),
+    ("application/x-bzip2", "bz2"),
+    ("application/x-xz", "xz"),
+    ("application/zstd", "zst"),
+    ("application/zip", "zip"),
+    ("application/x-tar", "tar"),
+    ("application/x-7z-compressed", "7z"),
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/svg+xml", "svg"),
+    ("audio/mpeg", "mp3"),
+    ("video/mp4", "mp4"),
+];
+
+// Other extensions a name can already have for the same kind of file
+const ALIASES: &[(&str, &str)] = &[("html", "htm"), ("jpg", "jpeg"), ("tar.gz", "tgz"), ("gz", "tgz")];
+
+// Signatures at the start of a body, checked after the archives
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"%PDF-", "pdf"),
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"BZh", "bz2"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"\x28\xb5\x2f\xfd", "zst"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+];
+
+// The extension for a Content-Type, None for application/octet-stream and
+// anything else that doesn't say what the file is
+pub fn for_content_type(content_type: &str) -> Option<&'static str> {
+    let essence = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    TYPES.iter().find(|(name, _)| *name == essence).map(|(_, extension)| *extension)
+}
+
+// The extension the body's first bytes point to
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    match archive::detect(data) {
+        Some(Kind::TarGz) => return Some("tar.gz"),
+        Some(Kind::Tar) => return Some("tar"),
+        Some(Kind::Zip) => return Some("zip"),
+        None if inflate::is_gzip(data) => return Some("gz"),
+        None => {}
+    }
+    if let Some((_, extension)) = MAGIC.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return Some(extension);
+    }
+    let start = String::from_utf8_lossy(&data[..data.len().min(256)]).trim_start().to_ascii_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return Some("html");
+    }
+    if start.starts_with("<?xml") {
+        return Some("xml");
+    }
+    None
+}
+
+// Whether the file name already ends in the extension, or another name for it
+pub fn has_extension(name: &str, extension: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let ends_in = |extension: &str| name.strip_suffix(extension).is_some_and(|stem| stem.ends_with('.'));
+    ends_in(extension) || ALIASES.iter().any(|&(of, alias)| of == extension && ends_in(alias))
+}
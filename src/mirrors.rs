@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use crate::http;
+use crate::url::Url;
+
+// Bytes fetched from each source to see how fast it is
+const PROBE_SIZE: u64 = 16 * 1024;
+
+// Places the same body can be fetched from, each given a share of the chunks
+// in proportion to how fast it answered its probe
+pub struct Mirrors {
+    sources: Vec<Source>,
+}
+
+struct Source {
+    url: Url,
+    latency: Duration,
+    // Bytes per second over the probe
+    weight: f64,
+    // Smooth weighted round-robin state: the source with the highest credit
+    // gets the next chunk
+    credit: f64,
+}
+
+impl Mirrors {
+    // Probe the URL and its mirrors with a small range request each and keep
+    // those that answer with the right size. Also returns why the others
+    // were left out.
+    pub fn probe(url: &Url, mirrors: &[Url], total_size: u64, settings: &http::Settings) -> (Mirrors, Vec<String>) {
+        let mut sources = Vec::new();
+        let mut dropped = Vec::new();
+        let range = vec![("Range".to_string(), format!("bytes=0-{}", PROBE_SIZE.min(total_size).max(1) - 1))];
+
+        for candidate in std::iter::once(url).chain(mirrors) {
+            let start = Instant::now();
+            let (head, body) = match http::get(candidate, &range, settings) {
+                Ok(response) => response,
+                Err(e) => {
+                    dropped.push(format!("{}: {}", candidate, e));
+                    continue;
+                }
+            };
+            let elapsed = start.elapsed();
+            let size = head.content_range_total().or(head.content_length().filter(|_| head.status == 200));
+            if size != Some(total_size) {
+                dropped.push(format!("{}: has {} bytes, not {}", candidate,
+                    size.map_or("an unknown number of".to_string(), |size| size.to_string()), total_size));
+                continue;
+            }
+            let speed = body.len() as f64 / elapsed.as_secs_f64().max(1e-6);
+            sources.push(Source { url: candidate.clone(), latency: elapsed, weight: speed, credit: 0.0 });
+        }
+
+        // Fastest first, which is also who gets the first chunk
+        sources.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+        (Mirrors { sources }, dropped)
+    }
+
+    // A line per source, fastest first
+    pub fn ranking(&self) -> Vec<String> {
+        self.sources.iter()
+            .map(|source| format!(
+                "{}: {} ms, {:.0} KB/s",
+                source.url, source.latency.as_millis(), source.weight / 1024.0
+            ))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    // Every source, fastest first
+    pub fn urls(&self) -> Vec<Url> {
+        self.sources.iter().map(|source| source.url.clone()).collect()
+    }
+
+    // The source to fetch the next chunk from. Over many chunks each source
+    // gets a share matching its weight, spread out rather than in runs.
+    pub fn next(&mut self) -> &Url {
+        let total: f64 = self.sources.iter().map(|source| source.weight).sum();
+        for source in &mut self.sources {
+            source.credit += source.weight;
+        }
+        // On a tie the faster source, which comes first, wins
+        let best = self.sources.iter_mut()
+            .reduce(|best, source| if source.credit > best.credit { source } else { best })
+            .expect("there is at least one source");
+        best.credit -= total;
+        &best.url
+    }
+
+    // Stop using a source that failed
+    pub fn remove(&mut self, url: &Url) {
+        self.sources.retain(|source| source.url != *url);
+    }
+}
@@ -0,0 +1,117 @@
+// Credentials from a .netrc file, as curl, wget and ftp read them: "machine
+// NAME login USER password PASS" entries, a "default" entry for every other
+// host, and "macdef" macros, which are skipped.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, Default)]
+pub struct Netrc {
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    // None for the default entry
+    machine: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+// Passwords stay out of debug output
+impl std::fmt::Debug for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Entry").field("machine", &self.machine).field("login", &self.login).finish_non_exhaustive()
+    }
+}
+
+impl Netrc {
+    pub fn parse(text: &str) -> Netrc {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            let mut tokens = tokens(line).into_iter();
+            while let Some(token) = tokens.next() {
+                match token.as_str() {
+                    "machine" => entries.push(Entry { machine: tokens.next(), login: None, password: None }),
+                    "default" => entries.push(Entry { machine: None, login: None, password: None }),
+                    "login" | "password" | "account" => {
+                        let value = tokens.next();
+                        let Some(entry) = entries.last_mut() else { continue };
+                        match token.as_str() {
+                            "login" => entry.login = value,
+                            "password" => entry.password = value,
+                            _ => {}
+                        }
+                    }
+                    // A macro runs to the next empty line
+                    "macdef" => {
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Netrc { entries }
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Netrc> {
+        Ok(Netrc::parse(&std::fs::read_to_string(path)?))
+    }
+
+    // The file to read when none is given: $NETRC, or .netrc in the home directory
+    pub fn default_path() -> Option<PathBuf> {
+        match std::env::var_os("NETRC") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".netrc")),
+        }
+    }
+
+    // Login and password for the host: the first entry for it, else the
+    // default entry
+    pub fn credentials(&self, host: &str) -> Option<(&str, &str)> {
+        let entry = self.entries.iter()
+            .find(|entry| entry.machine.as_deref().is_some_and(|machine| machine.eq_ignore_ascii_case(host)))
+            .or_else(|| self.entries.iter().find(|entry| entry.machine.is_none()))?;
+        Some((entry.login.as_deref().unwrap_or(""), entry.password.as_deref()?))
+    }
+}
+
+// The words of a line, where a double-quoted word may hold spaces and
+// backslash escapes
+fn tokens(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        // A comment runs to the end of the line
+        if c == '#' {
+            break;
+        }
+        let mut token = String::new();
+        if c == '"' {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => token.extend(chars.next()),
+                    c => token.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                token.push(c);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    tokens
+}
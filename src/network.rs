@@ -0,0 +1,164 @@
+// Telling a network that went away from a server having trouble. One
+// connection failing is the server's doing, or that connection's; every
+// connection failing one after another with none getting through is more
+// likely the machine's own network changing under them, as it does when a
+// laptop moves to another Wi-Fi network or onto a VPN. The download then
+// waits for the server to be reachable again instead of using up its
+// retries, and every connection starts afresh, the host name resolved again,
+// from the chunks already in.
+
+use std::io::ErrorKind;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::http::{TooSlow, UnexpectedStatus, Unresolved};
+
+// How long a download waits for the network by default
+pub const DEFAULT_NETWORK_WAIT: Duration = Duration::from_secs(120);
+
+// Failures in a row that count as the network being gone, when there are
+// fewer connections than this
+const MIN_FAILURES: usize = 2;
+
+// The wait between checks for the server starts at FIRST_CHECK and doubles
+// up to LONGEST_CHECK
+const FIRST_CHECK: Duration = Duration::from_secs(1);
+const LONGEST_CHECK: Duration = Duration::from_secs(15);
+
+// How often a wait between checks looks whether the download was stopped
+const STOP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Shared by every connection of a download
+pub struct NetworkWatch {
+    // Failures in a row across all connections that count as the network
+    // being gone
+    threshold: usize,
+    // How long to wait for it to come back; zero never waits
+    wait: Duration,
+    // Failures since the last request that got through
+    failures: AtomicUsize,
+    // Counts the times the network was checked, so a connection that failed
+    // before a check knows it is over
+    generation: AtomicU64,
+    // Held by the one connection checking; the others wait for its finding
+    recovery: Mutex<Recovery>,
+}
+
+#[derive(Default)]
+struct Recovery {
+    // Whether the last check found the network gone before it came back
+    was_down: bool,
+    // Why the network was given up on, if it was
+    lost: Option<(ErrorKind, String)>,
+}
+
+impl NetworkWatch {
+    // For a download over this many connections that tries each range up to
+    // attempts times. The network is checked before a range runs out of
+    // tries, even if every failure was its own.
+    pub fn new(connections: usize, attempts: usize, wait: Duration) -> NetworkWatch {
+        NetworkWatch {
+            threshold: connections.max(MIN_FAILURES).min(attempts.max(1)),
+            wait,
+            failures: AtomicUsize::new(0),
+            generation: AtomicU64::new(0),
+            recovery: Mutex::new(Recovery::default()),
+        }
+    }
+
+    // A request got through, so the network is there
+    pub fn on_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    // Count a failed request. If the network looks gone after it, what to
+    // pass to recover: a host name that resolved before and no longer does
+    // says so at once, failures on the way there or back once every
+    // connection has had them. Answers from the server never do.
+    pub fn on_failure(&self, error: &std::io::Error) -> Option<u64> {
+        if self.wait.is_zero() || !is_network_failure(error) {
+            return None;
+        }
+        let generation = self.generation.load(Ordering::Relaxed);
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        (failures >= self.threshold || Unresolved::from_error(error).is_some()).then_some(generation)
+    }
+
+    // Check until the server can be reached, with probe, or the download is
+    // stopped. Ok(true) if it couldn't be at first, so the request that
+    // failed is to go again on a new connection without counting the try;
+    // Ok(false) if the network was there all along and the failure is the
+    // server's. Connections finding the network gone while it is being
+    // checked take the same answer. An error once the wait is over.
+    pub fn recover(&self, generation: u64, mut probe: impl FnMut() -> std::io::Result<()>, stopped: impl Fn() -> bool) -> std::io::Result<bool> {
+        let mut recovery = self.recovery.lock().expect("no connection panics checking the network");
+        if let Some((kind, reason)) = &recovery.lost {
+            return Err(std::io::Error::new(*kind, reason.clone()));
+        }
+        if self.generation.load(Ordering::Relaxed) != generation {
+            return Ok(recovery.was_down);
+        }
+
+        let started = Instant::now();
+        let mut pause = FIRST_CHECK;
+        let mut was_down = false;
+        loop {
+            let error = match probe() {
+                Ok(()) => break,
+                Err(_) if stopped() => break,
+                Err(e) => e,
+            };
+            was_down = true;
+            if started.elapsed() >= self.wait {
+                let reason = format!("The network did not come back within {} s: {}", self.wait.as_secs(), error);
+                recovery.lost = Some((ErrorKind::NetworkDown, reason.clone()));
+                return Err(std::io::Error::new(ErrorKind::NetworkDown, reason));
+            }
+            let until = Instant::now() + pause.min(self.wait.saturating_sub(started.elapsed()));
+            while !stopped() && Instant::now() < until {
+                std::thread::sleep(until.saturating_duration_since(Instant::now()).min(STOP_CHECK_INTERVAL));
+            }
+            if stopped() {
+                break;
+            }
+            pause = (pause * 2).min(LONGEST_CHECK);
+        }
+
+        recovery.was_down = was_down && !stopped();
+        self.failures.store(0, Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        Ok(recovery.was_down)
+    }
+
+    // Changes each time the network has been checked, so connections kept
+    // open can tell they are to be made again
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+}
+
+// Whether a request failed on the way rather than being answered
+fn is_network_failure(error: &std::io::Error) -> bool {
+    if Unresolved::from_error(error).is_some() || TooSlow::from_error(error).is_some() {
+        return true;
+    }
+    if UnexpectedStatus::from_error(error).is_some() {
+        return false;
+    }
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::HostUnreachable
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::NetworkDown
+            | ErrorKind::AddrNotAvailable
+    )
+}
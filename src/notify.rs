@@ -0,0 +1,83 @@
+// Desktop notifications for --notify, raised with what the system has for
+// them: notify-send on Linux and the BSDs, osascript on macOS and a toast
+// from PowerShell on Windows. The osascript and PowerShell scripts get the
+// text from environment variables, so nothing in a file name can be taken
+// for script.
+
+use std::process::{Command, Stdio};
+
+use crate::exitcode::Cause;
+use crate::url::Url;
+use crate::Outcome;
+
+// Tell the desktop how a download went. Expected digests count as checked
+// once the download is saved, since a mismatch fails it.
+pub fn download(url: &Url, result: &std::io::Result<Outcome>, checked: bool) -> std::io::Result<()> {
+    let (title, body, failed) = match result {
+        Ok(Outcome::Skipped) => return Ok(()),
+        Ok(Outcome::Saved { path, sha256 }) => {
+            let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+            ("Download complete", format!("{}\n{}", name, verification(sha256, checked)), false)
+        }
+        Ok(Outcome::Piped { sha256 }) => ("Download complete", format!("{}\n{}", url, verification(sha256, checked)), false),
+        Err(e) if Cause::of(e) == Cause::HashMismatch => ("Hash verification failed", format!("{}\n{}", url, e), true),
+        Err(e) => ("Download failed", format!("{}\n{}", url, e), true),
+    };
+    show(title, &body, failed)
+}
+
+fn verification(sha256: &str, checked: bool) -> String {
+    if checked {
+        format!("Hash verified (SHA-256 {})", sha256)
+    } else {
+        format!("SHA-256 {}, not checked against an expected hash", sha256)
+    }
+}
+
+fn show(title: &str, body: &str, urgent: bool) -> std::io::Result<()> {
+    let mut command = notifier(title, body, urgent);
+    let status = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| std::io::Error::new(e.kind(), format!("Could not run {}: {}", command.get_program().to_string_lossy(), e)))?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("{} failed with {}", command.get_program().to_string_lossy(), status)));
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn notifier(title: &str, body: &str, _urgent: bool) -> Command {
+    let mut command = Command::new("osascript");
+    command
+        .args(["-e", r#"display notification (system attribute "HTTP_CLIENT_BODY") with title (system attribute "HTTP_CLIENT_TITLE")"#])
+        .env("HTTP_CLIENT_TITLE", title)
+        .env("HTTP_CLIENT_BODY", body);
+    command
+}
+
+#[cfg(windows)]
+fn notifier(title: &str, body: &str, _urgent: bool) -> Command {
+    const TOAST: &str = "\
+        [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] | Out-Null; \
+        $xml = [Windows.UI.Notifications.ToastNotificationManager]::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02); \
+        $text = $xml.GetElementsByTagName('text'); \
+        $text.Item(0).AppendChild($xml.CreateTextNode($env:HTTP_CLIENT_TITLE)) | Out-Null; \
+        $text.Item(1).AppendChild($xml.CreateTextNode($env:HTTP_CLIENT_BODY)) | Out-Null; \
+        [Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier('http_client').Show([Windows.UI.Notifications.ToastNotification]::new($xml))";
+    let mut command = Command::new("powershell");
+    command
+        .args(["-NoProfile", "-NonInteractive", "-Command", TOAST])
+        .env("HTTP_CLIENT_TITLE", title)
+        .env("HTTP_CLIENT_BODY", body);
+    command
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn notifier(title: &str, body: &str, urgent: bool) -> Command {
+    let mut command = Command::new("notify-send");
+    command.args(["--app-name=http_client", if urgent { "--urgency=critical" } else { "--urgency=normal" }, title, body]);
+    command
+}
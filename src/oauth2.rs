@@ -0,0 +1,140 @@
+// OAuth 2.0 client credentials grant (RFC 6749 4.4): bearer tokens are asked
+// for at the token endpoint with the client's id and secret, reused until
+// shortly before they expire, and asked for again when a server refuses one
+// with 401 part way through a transfer.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http::{self, Settings};
+use crate::integrity;
+use crate::json;
+use crate::url::{Url, percent_encode};
+
+// A token this close to expiring is replaced before it is sent
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+// Clones share the token
+#[derive(Clone)]
+pub struct ClientCredentials {
+    token_url: Url,
+    client_id: String,
+    client_secret: String,
+    scope: Option<String>,
+    token: Arc<Mutex<Option<Token>>>,
+}
+
+struct Token {
+    access_token: String,
+    obtained: Instant,
+    // None when the server didn't say
+    expires: Option<Instant>,
+}
+
+// The secret stays out of debug output
+impl std::fmt::Debug for ClientCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientCredentials {
+    pub fn new(token_url: Url, client_id: &str, client_secret: &str, scope: Option<&str>) -> ClientCredentials {
+        ClientCredentials {
+            token_url,
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            scope: scope.map(str::to_string),
+            token: Arc::default(),
+        }
+    }
+
+    // The client from OAUTH2_CLIENT_ID and OAUTH2_CLIENT_SECRET, with
+    // OAUTH2_SCOPE if it is set
+    pub fn from_env(token_url: Url) -> Result<ClientCredentials, String> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let (Some(client_id), Some(client_secret)) = (var("OAUTH2_CLIENT_ID"), var("OAUTH2_CLIENT_SECRET")) else {
+            return Err("--oauth2-token-url needs OAUTH2_CLIENT_ID and OAUTH2_CLIENT_SECRET set".to_string());
+        };
+        Ok(ClientCredentials::new(token_url, &client_id, &client_secret, var("OAUTH2_SCOPE").as_deref()))
+    }
+
+    // Value for the Authorization header, asking for a new token if there is
+    // none yet or it is about to expire
+    pub(crate) fn authorization(&self, settings: &Settings) -> std::io::Result<String> {
+        let mut token = self.token.lock().expect("no thread panics holding the token");
+        let usable = token.as_ref()
+            .is_some_and(|token| token.expires.is_none_or(|expires| Instant::now() + EXPIRY_MARGIN < expires));
+        if !usable {
+            *token = Some(self.request_token(settings)?);
+        }
+        Ok(format!("Bearer {}", token.as_ref().expect("a token was just obtained").access_token))
+    }
+
+    // A request made at the given time was refused: stop using the token it
+    // carried, unless another connection has replaced it since
+    pub(crate) fn refused(&self, sent: Instant) {
+        let mut token = self.token.lock().expect("no thread panics holding the token");
+        if token.as_ref().is_some_and(|token| token.obtained <= sent) {
+            *token = None;
+        }
+    }
+
+    fn request_token(&self, settings: &Settings) -> std::io::Result<Token> {
+        // The token endpoint gets none of the headers or credentials meant for
+        // the download itself
+        let settings = Settings {
+            headers: Vec::new(),
+            cookies: None,
+            sigv4: None,
+            refresh: None,
+            oauth2: None,
+            authorizations: Vec::new(),
+            netrc: None,
+            ..settings.clone()
+        };
+        let credentials = format!(
+            "{}:{}",
+            percent_encode(self.client_id.as_bytes()), percent_encode(self.client_secret.as_bytes())
+        );
+        let mut form = "grant_type=client_credentials".to_string();
+        if let Some(scope) = &self.scope {
+            form.push_str(&format!("&scope={}", percent_encode(scope.as_bytes())));
+        }
+        let headers = [
+            ("Authorization".to_string(), format!("Basic {}", integrity::encode_base64(credentials.as_bytes()))),
+            ("Content-Type".to_string(), "application/x-www-form-urlencoded".to_string()),
+            ("Accept".to_string(), "application/json".to_string()),
+        ];
+
+        let obtained = Instant::now();
+        let (head, body) = http::post(&self.token_url, &headers, form.as_bytes(), &settings)?;
+        let text = String::from_utf8_lossy(&body);
+        let response = json::parse(&text);
+        if head.status != 200 {
+            let error = response.as_ref().ok()
+                .and_then(|response| response.get("error"))
+                .and_then(json::Value::as_str)
+                .unwrap_or("no error code");
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                format!("Token endpoint {} refused the client: HTTP {} ({})", self.token_url, head.status, error),
+            ));
+        }
+        let response = response.map_err(|e| std::io::Error::other(format!(
+            "Token endpoint {} sent invalid JSON: {}", self.token_url, e
+        )))?;
+        let access_token = response.get("access_token")
+            .and_then(json::Value::as_str)
+            .ok_or(std::io::Error::other(format!("Token endpoint {} sent no access_token", self.token_url)))?;
+        let expires = response.get("expires_in")
+            .and_then(json::Value::as_f64)
+            .filter(|secs| *secs >= 0.0 && secs.is_finite())
+            .map(|secs| obtained + Duration::from_secs_f64(secs));
+        Ok(Token { access_token: access_token.to_string(), obtained, expires })
+    }
+}
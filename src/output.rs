@@ -0,0 +1,393 @@
+use std::io::{ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::integrity::{self, ExpectedDigest};
+use crate::mime;
+use crate::time;
+use crate::url::Url;
+
+// File name used when the URL path doesn't end in one
+pub const DEFAULT_FILE_NAME: &str = "downloaded_data.bin";
+
+// An output filename template such as "{host}/{path}/{filename}".
+//
+// Variables:
+//   {host}       host name from the URL
+//   {port}       port from the URL
+//   {path}       directory part of the URL path (all segments but the last)
+//   {path:N}     N-th segment of the URL path, counting from 1
+//   {filename}   last URL path segment, or downloaded_data.bin
+//   {date}       today's date as YYYY-MM-DD (UTC)
+//   {sha256}     hex SHA-256 of the body, {sha256:N} for its first N characters
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Literal(String),
+    Host,
+    Port,
+    Path,
+    PathSegment(usize),
+    Filename,
+    Date,
+    Sha256(Option<usize>),
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}')
+                .ok_or(format!("Unclosed '{{' in output template: {}", template))?;
+            let variable = &rest[start + 1..start + end];
+            let (name, arg) = match variable.split_once(':') {
+                Some((name, arg)) => {
+                    let arg = arg.parse::<usize>()
+                        .map_err(|_| format!("Invalid {{{}}} in output template", variable))?;
+                    (name, Some(arg))
+                }
+                None => (variable, None),
+            };
+
+            parts.push(match (name, arg) {
+                ("host", None) => Part::Host,
+                ("port", None) => Part::Port,
+                ("path", None) => Part::Path,
+                ("path", Some(n)) if n > 0 => Part::PathSegment(n),
+                ("filename", None) => Part::Filename,
+                ("date", None) => Part::Date,
+                ("sha256", n) => Part::Sha256(n),
+                _ => return Err(format!("Unknown variable {{{}}} in output template", variable)),
+            });
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+
+        Ok(Template { parts })
+    }
+
+    // Whether the expansion depends on the downloaded content
+    pub fn needs_hash(&self) -> bool {
+        self.parts.iter().any(|p| matches!(p, Part::Sha256(_)))
+    }
+
+    // Expand the template for a URL. sha256 has to be given if needs_hash() is true.
+    pub fn expand(&self, url: &Url, sha256: Option<&str>) -> PathBuf {
+        sanitize(&expand_parts(&self.parts, url, sha256))
+    }
+
+    // The directories of the expansion that come before anything that
+    // depends on the hash, which can be known before the download is
+    pub fn fixed_dir(&self, url: &Url) -> PathBuf {
+        let fixed = self.parts.iter().position(|p| matches!(p, Part::Sha256(_))).unwrap_or(self.parts.len());
+        let expanded = expand_parts(&self.parts[..fixed], url, None);
+        match expanded.rfind('/') {
+            Some(end) => clean(&expanded[..end]),
+            None => PathBuf::new(),
+        }
+    }
+}
+
+// The template's parts for a URL, before sanitizing
+fn expand_parts(parts: &[Part], url: &Url, sha256: Option<&str>) -> String {
+    let segments = url.segments();
+    let directories = match url.file_name() {
+        Some(_) => &segments[..segments.len() - 1],
+        None => &segments[..],
+    };
+    let mut expanded = String::new();
+
+    for part in parts {
+        match part {
+            Part::Literal(text) => expanded.push_str(text),
+            Part::Host => expanded.push_str(&url.host),
+            Part::Port => expanded.push_str(&url.port.to_string()),
+            Part::Path => expanded.push_str(&directories.join("/")),
+            Part::PathSegment(n) => expanded.push_str(segments.get(n - 1).copied().unwrap_or("")),
+            Part::Filename => expanded.push_str(url.file_name().unwrap_or(DEFAULT_FILE_NAME)),
+            Part::Date => expanded.push_str(&time::today()),
+            Part::Sha256(len) => {
+                let hash = sha256.unwrap_or("");
+                expanded.push_str(&hash[..len.unwrap_or(hash.len()).min(hash.len())]);
+            }
+        }
+    }
+
+    expanded
+}
+
+// How output paths are chosen
+pub struct Naming<'a> {
+    // Explicit output path, used as is
+    pub output: Option<&'a str>,
+    pub template: Option<&'a Template>,
+    // Directory derived names are placed under
+    pub prefix: Option<&'a Path>,
+    // Extension the content calls for, appended to derived names that
+    // don't already have it
+    pub content: Option<&'a str>,
+    // Extension appended to derived names, e.g. for compressed output
+    pub extension: Option<&'a str>,
+}
+
+// Work out where the body of a URL should be written. Returns None when the
+// path depends on the content hash and it isn't known yet.
+pub fn resolve_path(url: &Url, naming: &Naming, sha256: Option<&str>) -> Option<PathBuf> {
+    if let Some(output) = naming.output {
+        return Some(PathBuf::from(output));
+    }
+
+    let mut derived = match naming.template {
+        Some(template) if template.needs_hash() && sha256.is_none() => return None,
+        Some(template) => template.expand(url, sha256),
+        None => match (url.file_name(), naming.content) {
+            (None, Some(content)) => Path::new(DEFAULT_FILE_NAME).with_extension(content),
+            (name, _) => sanitize(name.unwrap_or(DEFAULT_FILE_NAME)),
+        },
+    };
+    if let Some(content) = naming.content
+        && !mime::has_extension(&derived.to_string_lossy(), content)
+    {
+        derived = append_extension(&derived, content);
+    }
+    if let Some(extension) = naming.extension {
+        derived = append_extension(&derived, extension);
+    }
+
+    Some(match naming.prefix {
+        Some(prefix) => prefix.join(derived),
+        None => derived,
+    })
+}
+
+// What to do when the output file already exists
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Existing {
+    // Overwrite it
+    #[default]
+    Clobber,
+    // Fail the download
+    Fail,
+    // Leave it alone and don't download, if it passes the check
+    Skip(SkipCheck),
+    // Save as file.1, file.2, ... instead, like wget
+    Numbered,
+}
+
+// How an existing file is checked before a download is skipped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipCheck {
+    // Skip whenever the file exists
+    None,
+    // Skip if the file's size matches the remote size
+    Size,
+    // Skip if the file matches the expected digests
+    Hash,
+    // Skip if the file has the remote size and isn't older than Last-Modified
+    Time,
+}
+
+impl SkipCheck {
+    pub fn parse(value: &str) -> Result<SkipCheck, String> {
+        match value {
+            "" => Ok(SkipCheck::None),
+            "size" => Ok(SkipCheck::Size),
+            "hash" => Ok(SkipCheck::Hash),
+            "time" => Ok(SkipCheck::Time),
+            _ => Err(format!("Unknown --skip-existing check: {} (expected size, hash or time)", value)),
+        }
+    }
+}
+
+// Apply the existing-file policy to an output path. Returns the path to
+// write to, or None when the download should be skipped.
+pub fn place(
+    path: PathBuf,
+    existing: Existing,
+    remote_size: u64,
+    remote_modified: Option<SystemTime>,
+    expected: &[ExpectedDigest],
+) -> std::io::Result<Option<PathBuf>> {
+    let metadata = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Some(path)),
+        Err(e) => return Err(e),
+    };
+
+    match existing {
+        Existing::Clobber => Ok(Some(path)),
+        Existing::Fail => Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        )),
+        Existing::Skip(SkipCheck::None) => Ok(None),
+        Existing::Skip(SkipCheck::Size) => {
+            if metadata.len() == remote_size { Ok(None) } else { Ok(Some(path)) }
+        }
+        Existing::Skip(SkipCheck::Hash) => {
+            let mut digests = integrity::Digests::new(expected);
+            digests.read_from(std::fs::File::open(&path)?)?;
+            if integrity::check_digests(expected, &digests.finish()).is_ok() { Ok(None) } else { Ok(Some(path)) }
+        }
+        // Without a Last-Modified there is no telling, so download again
+        Existing::Skip(SkipCheck::Time) => {
+            let current = metadata.len() == remote_size
+                && remote_modified.is_some_and(|remote| metadata.modified().is_ok_and(|local| local >= remote));
+            if current { Ok(None) } else { Ok(Some(path)) }
+        }
+        Existing::Numbered => {
+            let mut n = 1;
+            loop {
+                let mut candidate = path.clone().into_os_string();
+                candidate.push(format!(".{}", n));
+                let candidate = PathBuf::from(candidate);
+                if !candidate.exists() {
+                    return Ok(Some(candidate));
+                }
+                n += 1;
+            }
+        }
+    }
+}
+
+// File the body is written to until it has been verified: the output path
+// with ".part" appended
+pub fn part_path(path: &Path) -> PathBuf {
+    append_extension(path, "part")
+}
+
+// Where a body is kept as it came while --compressed decodes it into the
+// .part file
+pub fn encoded_path(part: &Path) -> PathBuf {
+    append_extension(part, "encoded")
+}
+
+// Where the library records which chunks of a --connections .part file
+// are in, for --resume
+pub fn control_path(part: &Path) -> PathBuf {
+    append_extension(part, "ctrl")
+}
+
+// Where a compressed output is decompressed to for --extract to read
+pub fn decompressed_path(path: &Path) -> PathBuf {
+    append_extension(path, "decompressed")
+}
+
+// Add an extension after any existing one, e.g. data.tar -> data.tar.gz
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut extended = path.as_os_str().to_owned();
+    extended.push(".");
+    extended.push(extension);
+    PathBuf::from(extended)
+}
+
+// Parse a file mode given in octal, such as 0644
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or(format!("Invalid file mode: {} (expected octal, e.g. 0644)", value))
+}
+
+// Apply the requested permissions to a downloaded file. With executable set,
+// an execute bit is added for everyone who can read the file.
+#[cfg(unix)]
+pub fn set_permissions(path: &Path, mode: Option<u32>, executable: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut mode = match mode {
+        Some(mode) => mode,
+        None => std::fs::metadata(path)?.permissions().mode() & 0o7777,
+    };
+    if executable {
+        mode |= (mode & 0o444) >> 2;
+    }
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+pub fn set_permissions(_path: &Path, _mode: Option<u32>, _executable: bool) -> std::io::Result<()> {
+    Err(std::io::Error::new(ErrorKind::Unsupported, "file modes are not supported on this platform"))
+}
+
+// Append a "hash  filename" line in sha256sum format to a checksum file. The
+// file name is written relative to the checksum file's directory when it is
+// inside it, so `sha256sum -c` can be run from there.
+pub fn append_checksum(sums: &Path, file: &Path, sha256: &str) -> std::io::Result<()> {
+    let sums_dir = std::path::absolute(sums)?.parent().map(Path::to_path_buf).unwrap_or_default();
+    let absolute = std::path::absolute(file)?;
+    let name = absolute.strip_prefix(&sums_dir).unwrap_or(file).to_string_lossy();
+
+    // sha256sum escapes backslashes and newlines, flagged by a leading backslash
+    let line = if name.contains(['\\', '\n']) {
+        format!("\\{}  {}\n", sha256, name.replace('\\', "\\\\").replace('\n', "\\n"))
+    } else {
+        format!("{}  {}\n", sha256, name)
+    };
+
+    let mut sums = std::fs::OpenOptions::new().create(true).append(true).open(sums)?;
+    sums.write_all(line.as_bytes())
+}
+
+// Temporary file used while the final name isn't known yet, kept in as much
+// of the output's directory as is known without the hash, so it is usually
+// on the same file system and can be renamed into place
+pub fn temp_path(url: &Url, naming: &Naming) -> PathBuf {
+    let name = format!(".http_client-{}.tmp", std::process::id());
+    let dir = naming.template.map(|template| template.fixed_dir(url)).unwrap_or_default();
+    match naming.prefix {
+        Some(prefix) => prefix.join(dir).join(name),
+        None => dir.join(name),
+    }
+}
+
+// Rename a finished file to its output path. Across file systems, where
+// rename can't work, it is copied to the output's .part path with its
+// permissions and modification time and renamed from there, so the output
+// still only ever appears complete. Extended attributes stay behind.
+pub fn move_into_place(from: &Path, to: &Path) -> std::io::Result<()> {
+    match std::fs::rename(from, to) {
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            let temp = part_path(to);
+            let copied = std::fs::copy(from, &temp).and_then(|_| {
+                let file = std::fs::OpenOptions::new().write(true).open(&temp)?;
+                file.set_modified(std::fs::metadata(from)?.modified()?)?;
+                file.sync_all()?;
+                std::fs::rename(&temp, to)
+            });
+            if let Err(e) = copied {
+                let _ = std::fs::remove_file(&temp);
+                return Err(e);
+            }
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+// Turn text derived from a URL into a relative path that can't escape the
+// current directory: empty, "." and ".." components are dropped.
+fn sanitize(path: &str) -> PathBuf {
+    let sanitized = clean(path);
+    if sanitized.as_os_str().is_empty() { PathBuf::from(DEFAULT_FILE_NAME) } else { sanitized }
+}
+
+// As sanitize, but possibly empty
+fn clean(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect()
+}
@@ -0,0 +1,327 @@
+// Proxy auto-config: a script's FindProxyForURL(url, host) picks the proxy for
+// each request, as browsers do on networks that only publish their proxy
+// settings as a PAC file. It has the usual helpers, isInNet, shExpMatch,
+// dnsResolve and the rest; the date and time ones go by UTC whether or not
+// the script says "GMT".
+//
+// Of the proxies the script lists, the first this client can use is taken:
+// PROXY (or HTTP) host:port, or DIRECT. SOCKS and HTTPS proxies are skipped.
+// Answers are kept per URL, so the script runs once for all of a download's
+// range requests.
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::{self, Settings};
+use crate::js::{Program, Value};
+use crate::proxy::{Proxy, ProxyAuth};
+use crate::time;
+use crate::url::Url;
+
+// The functions PAC scripts can call, besides JavaScript's own
+const BUILTINS: &[&str] = &[
+    "isPlainHostName", "dnsDomainIs", "localHostOrDomainIs", "isResolvable", "isInNet", "dnsResolve",
+    "myIpAddress", "dnsDomainLevels", "shExpMatch", "weekdayRange", "dateRange", "timeRange", "alert",
+];
+
+const WEEKDAYS: &[&str] = &["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+const MONTHS: &[&str] = &["JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC"];
+
+// Clones share the script and its answers
+#[derive(Clone)]
+pub struct Pac {
+    program: Arc<Program>,
+    // Sent to whichever proxy the script picks
+    pub auth: Option<ProxyAuth>,
+    // Have that proxy CONNECT rather than forward the requests
+    pub tunnel: bool,
+    // The proxy chosen for each URL, None for DIRECT
+    answers: Arc<Mutex<HashMap<String, Option<Url>>>>,
+}
+
+impl std::fmt::Debug for Pac {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Pac").field("auth", &self.auth).field("tunnel", &self.tunnel).finish_non_exhaustive()
+    }
+}
+
+impl Pac {
+    pub fn parse(source: &str) -> Result<Pac, String> {
+        let program = Program::parse(source).map_err(|e| format!("Invalid PAC script: {}", e))?;
+        if !program.defines("FindProxyForURL") {
+            return Err("The PAC script doesn't define FindProxyForURL".to_string());
+        }
+        Ok(Pac { program: Arc::new(program), auth: None, tunnel: false, answers: Arc::default() })
+    }
+
+    // The script at an http:// URL, fetched with the settings, or in a file
+    pub fn load(location: &str, settings: &Settings) -> std::io::Result<Pac> {
+        let read = |path: &str| std::fs::read_to_string(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read PAC file {}: {}", path, e)));
+        let source = match location.strip_prefix("file://") {
+            Some(path) => read(path)?,
+            None if !location.contains("://") => read(location)?,
+            None => {
+                let url = Url::parse(location).map_err(std::io::Error::other)?;
+                let (head, body) = http::get(&url, &[], settings)?;
+                if head.status != 200 {
+                    return Err(std::io::Error::other(format!("Could not fetch PAC file {}: HTTP {}", url, head.status)));
+                }
+                String::from_utf8_lossy(&body).into_owned()
+            }
+        };
+        Pac::parse(&source).map_err(std::io::Error::other)
+    }
+
+    // The proxy for the URL, or None to connect directly
+    pub(crate) fn find_proxy(&self, url: &Url) -> std::io::Result<Option<Proxy>> {
+        let key = url.to_string();
+        let cached = self.answers.lock().expect("no thread panics holding the answers").get(&key).cloned();
+        let chosen = match cached {
+            Some(chosen) => chosen,
+            None => {
+                let args = vec![Value::String(key.clone()), Value::String(url.host.clone())];
+                let answer = self.program.call("FindProxyForURL", args, BUILTINS, &|name, args| builtin(name, args))
+                    .map_err(|e| std::io::Error::other(format!("PAC script failed for {}: {}", url, e)))?;
+                let chosen = choose(&answer.to_string())
+                    .map_err(|e| std::io::Error::other(format!("PAC script answer for {}: {}", url, e)))?;
+                self.answers.lock().expect("no thread panics holding the answers").insert(key, chosen.clone());
+                chosen
+            }
+        };
+        Ok(chosen.map(|url| Proxy { url, auth: self.auth.clone(), tunnel: self.tunnel }))
+    }
+}
+
+// The first usable entry of an answer like "PROXY a:3128; SOCKS b:1080; DIRECT"
+fn choose(answer: &str) -> Result<Option<Url>, String> {
+    for entry in answer.split(';').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (kind, address) = entry.split_once(char::is_whitespace).unwrap_or((entry, ""));
+        match kind.to_ascii_uppercase().as_str() {
+            "DIRECT" => return Ok(None),
+            "PROXY" | "HTTP" => return Url::parse(&format!("http://{}/", address.trim())).map(Some),
+            _ => {}
+        }
+    }
+    Err(format!("no proxy this client can use in \"{}\"", answer))
+}
+
+fn builtin<'a>(name: &str, args: &[Value<'a>]) -> Result<Value<'a>, String> {
+    let arg = |i: usize| args.get(i).map(Value::to_string).unwrap_or_default();
+    Ok(match name {
+        "isPlainHostName" => Value::Bool(!arg(0).contains('.')),
+        "dnsDomainIs" => Value::Bool(arg(0).to_ascii_lowercase().ends_with(&arg(1).to_ascii_lowercase())),
+        "localHostOrDomainIs" => {
+            let (host, domain) = (arg(0).to_ascii_lowercase(), arg(1).to_ascii_lowercase());
+            Value::Bool(host == domain || (!host.contains('.') && domain.starts_with(&format!("{}.", host))))
+        }
+        "isResolvable" => Value::Bool(resolve(&arg(0)).is_some()),
+        "isInNet" => {
+            let matches = match (resolve(&arg(0)), arg(1).parse::<Ipv4Addr>(), arg(2).parse::<Ipv4Addr>()) {
+                (Some(address), Ok(pattern), Ok(mask)) => {
+                    let mask = u32::from(mask);
+                    u32::from(address) & mask == u32::from(pattern) & mask
+                }
+                _ => false,
+            };
+            Value::Bool(matches)
+        }
+        "dnsResolve" => resolve(&arg(0)).map_or(Value::Null, |address| Value::String(address.to_string())),
+        "myIpAddress" => Value::String(my_ip_address().to_string()),
+        "dnsDomainLevels" => Value::Number(arg(0).matches('.').count() as f64),
+        "shExpMatch" => {
+            let (text, pattern): (Vec<char>, Vec<char>) = (arg(0).chars().collect(), arg(1).chars().collect());
+            Value::Bool(glob_match(&text, &pattern))
+        }
+        "weekdayRange" | "dateRange" | "timeRange" => Value::Bool(time_matches(name, args)?),
+        "alert" => {
+            eprintln!("PAC: {}", arg(0));
+            Value::Undefined
+        }
+        _ => return Err(format!("{} is not defined", name)),
+    })
+}
+
+// An IPv4 address for the host, as dnsResolve gives
+fn resolve(host: &str) -> Option<Ipv4Addr> {
+    if let Ok(address) = host.parse() {
+        return Some(address);
+    }
+    (host, 0).to_socket_addrs().ok()?.find_map(|address| match address.ip() {
+        std::net::IpAddr::V4(address) => Some(address),
+        std::net::IpAddr::V6(_) => None,
+    })
+}
+
+// The address packets to the internet leave from. Connecting a UDP socket
+// sends nothing, only picks the route.
+fn my_ip_address() -> Ipv4Addr {
+    UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| {
+            socket.connect("192.0.2.1:80")?;
+            socket.local_addr()
+        })
+        .ok()
+        .and_then(|address| match address.ip() {
+            std::net::IpAddr::V4(address) if !address.is_unspecified() => Some(address),
+            _ => None,
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST)
+}
+
+// A shell expression: * for any run of characters, ? for any one
+fn glob_match(text: &[char], pattern: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|skip| glob_match(&text[skip..], rest)),
+        Some((&p, rest)) => text.split_first().is_some_and(|(&c, text)| (p == '?' || p == c) && glob_match(text, rest)),
+    }
+}
+
+// weekdayRange, dateRange or timeRange for now. One value must match; two
+// sets of values are the first and last of a range, which may wrap around.
+fn time_matches(name: &str, args: &[Value]) -> Result<bool, String> {
+    let mut args: Vec<String> = args.iter().map(Value::to_string).collect();
+    if args.last().is_some_and(|arg| arg.eq_ignore_ascii_case("GMT")) {
+        args.pop();
+    }
+
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let days = secs.div_euclid(86400);
+    let (year, month, day) = time::civil_from_days(days);
+    let now_weekday = (days + 4).rem_euclid(7);
+    let seconds = secs.rem_euclid(86400);
+    let now_time = [seconds / 3600, seconds / 60 % 60, seconds % 60];
+
+    // Each value as its field, ranked from the most significant
+    let fields: Vec<(usize, i64)> = match name {
+        "weekdayRange" => args.iter()
+            .map(|arg| Ok((0, position(WEEKDAYS, arg).ok_or(format!("invalid weekday {}", arg))?)))
+            .collect::<Result<_, String>>()?,
+        "dateRange" => args.iter()
+            .map(|arg| match position(MONTHS, arg) {
+                Some(month) => Ok((1, month + 1)),
+                None => match arg.parse::<i64>() {
+                    Ok(day @ 1..=31) => Ok((2, day)),
+                    Ok(year) if year > 31 => Ok((0, year)),
+                    _ => Err(format!("invalid date {}", arg)),
+                },
+            })
+            .collect::<Result<_, String>>()?,
+        _ => args.iter()
+            .map(|arg| arg.parse::<i64>().map(|n| (0, n)).map_err(|_| format!("invalid time {}", arg)))
+            .collect::<Result<_, String>>()?,
+    };
+    let now = |rank: usize| match name {
+        "weekdayRange" => now_weekday,
+        "dateRange" => [year, i64::from(month), i64::from(day)][rank],
+        _ => now_time[rank],
+    };
+
+    match fields.len() {
+        0 => Err(format!("{} needs arguments", name)),
+        1 => Ok(now(fields[0].0) == fields[0].1),
+        n if n % 2 == 0 => {
+            let (first, last) = fields.split_at(n / 2);
+            // timeRange counts its values in order within each half
+            let ranked = |half: &[(usize, i64)]| -> Vec<(usize, i64)> {
+                let mut half: Vec<(usize, i64)> = half.iter()
+                    .enumerate()
+                    .map(|(i, &(rank, value))| (if name == "timeRange" { i } else { rank }, value))
+                    .collect();
+                half.sort();
+                half
+            };
+            let (first, last) = (ranked(first), ranked(last));
+            if first.iter().map(|(rank, _)| rank).ne(last.iter().map(|(rank, _)| rank)) {
+                return Err(format!("{} needs the same fields at both ends", name));
+            }
+            let start: Vec<i64> = first.iter().map(|(_, value)| *value).collect();
+            let end: Vec<i64> = last.iter().map(|(_, value)| *value).collect();
+            let now: Vec<i64> = first.iter().map(|(rank, _)| now(*rank)).collect();
+            Ok(if start <= end { start <= now && now <= end } else { now >= start || now <= end })
+        }
+        _ => Err(format!("{} takes one value or two sets of them", name)),
+    }
+}
+
+fn position(names: &[&str], name: &str) -> Option<i64> {
+    names.iter().position(|candidate| candidate.eq_ignore_ascii_case(name)).map(|i| i as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What an expression comes to inside FindProxyForURL, as a string
+    fn eval(expression: &str) -> String {
+        let pac = Pac::parse(&format!("function FindProxyForURL(url, host) {{ return {}; }}", expression)).unwrap();
+        let args = vec![Value::String("http://example.com/".to_string()), Value::String("example.com".to_string())];
+        pac.program.call("FindProxyForURL", args, BUILTINS, &|name, args| builtin(name, args)).unwrap().to_string()
+    }
+
+    // The proxy the script picks for the URL, or "DIRECT"
+    fn answer(script: &str, url: &str) -> std::io::Result<String> {
+        let proxy = Pac::parse(script).unwrap().find_proxy(&Url::parse(url).unwrap())?;
+        Ok(proxy.map_or("DIRECT".to_string(), |proxy| proxy.url.to_string()))
+    }
+
+    #[test]
+    fn plain_host_names_have_no_dots() {
+        assert_eq!(eval("isPlainHostName('intranet')"), "true");
+        assert_eq!(eval("isPlainHostName('www.example.com')"), "false");
+        assert_eq!(eval("dnsDomainLevels('www.example.com')"), "2");
+    }
+
+    #[test]
+    fn dns_domain_is_matches_the_end_of_the_host() {
+        assert_eq!(eval("dnsDomainIs('www.Example.com', '.example.com')"), "true");
+        assert_eq!(eval("dnsDomainIs('www.example.org', '.example.com')"), "false");
+        assert_eq!(eval("localHostOrDomainIs('www', 'www.example.com')"), "true");
+        assert_eq!(eval("localHostOrDomainIs('www.example.org', 'www.example.com')"), "false");
+    }
+
+    #[test]
+    fn sh_exp_match_has_star_and_question_mark() {
+        assert_eq!(eval("shExpMatch('http://www.example.com/a/b', 'http://*.example.com/*')"), "true");
+        assert_eq!(eval("shExpMatch('abc', 'a?c')"), "true");
+        assert_eq!(eval("shExpMatch('abbc', 'a?c')"), "false");
+        assert_eq!(eval("shExpMatch('', '*')"), "true");
+        assert_eq!(eval("shExpMatch('example.com', '*.example.com')"), "false");
+    }
+
+    #[test]
+    fn is_in_net_compares_under_the_mask() {
+        assert_eq!(eval("isInNet('10.1.2.3', '10.0.0.0', '255.0.0.0')"), "true");
+        assert_eq!(eval("isInNet('10.1.2.3', '10.2.0.0', '255.255.0.0')"), "false");
+        assert_eq!(eval("isInNet('192.168.7.9', '192.168.7.9', '255.255.255.255')"), "true");
+        assert_eq!(eval("isInNet('10.1.2.3', '10.0.0.0', 'not a mask')"), "false");
+    }
+
+    #[test]
+    fn dns_resolve_gives_an_ipv4_address() {
+        assert_eq!(eval("dnsResolve('192.0.2.7')"), "192.0.2.7");
+        assert_eq!(eval("dnsResolve('localhost')"), "127.0.0.1");
+        assert_eq!(eval("isResolvable('127.0.0.1')"), "true");
+    }
+
+    #[test]
+    fn the_first_usable_proxy_is_taken() {
+        let script = "function FindProxyForURL(url, host) {
+            if (shExpMatch(host, '*.internal')) return 'DIRECT';
+            return 'SOCKS s:1080; HTTPS h:443; PROXY p:3128; DIRECT';
+        }";
+        assert_eq!(answer(script, "http://a.example.com/").unwrap(), "http://p:3128/");
+        assert_eq!(answer(script, "http://db.internal/").unwrap(), "DIRECT");
+        let unusable = "function FindProxyForURL(url, host) { return 'SOCKS s:1080'; }";
+        assert!(answer(unusable, "http://a/").unwrap_err().to_string().contains("no proxy this client can use"));
+    }
+
+    #[test]
+    fn scripts_without_find_proxy_for_url_are_refused() {
+        assert_eq!(Pac::parse("function other() {}").unwrap_err(), "The PAC script doesn't define FindProxyForURL");
+        assert!(Pac::parse("function FindProxyForURL( {").unwrap_err().starts_with("Invalid PAC script: line 1"));
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use crate::cli::Options;
+use crate::engine::{self, CHUNK_SIZE, DEFAULT_MIN_SPLIT_SIZE, MAX_CHUNK_SIZE};
+use crate::http::ResponseHead;
+use crate::output;
+use crate::url::Url;
+
+// Where a download would end up, as the dry run describes it
+pub struct Plan<'a> {
+    pub url: &'a Url,
+    pub head: &'a ResponseHead,
+    pub total_size: u64,
+    // None when the name depends on the hash or the body is piped
+    pub output: Option<&'a Path>,
+    pub part: &'a Path,
+}
+
+// Print what a download would do, for --dry-run
+pub fn print(plan: &Plan, options: &Options) {
+    // Resuming fetches only what the partial file is missing. With
+    // --connections that is whatever its control file doesn't list, which
+    // is only known once the download starts.
+    let parallel = options.connections.is_some_and(|connections| connections > 1);
+    let resume_from = match (options.resume && !parallel, plan.output) {
+        (true, Some(_)) => std::fs::metadata(plan.part).ok().map(|metadata| metadata.len()),
+        _ => None,
+    };
+    let remaining = plan.total_size.saturating_sub(resume_from.unwrap_or(0));
+    let ranges = match plan.head.header("Accept-Ranges") {
+        Some(units) if units.eq_ignore_ascii_case("bytes") => "supported",
+        Some(_) | None => "not advertised",
+    };
+
+    println!("Plan for {}:", plan.url);
+    println!("  Size:          {} bytes", plan.total_size);
+    // Chunk sizes depend on how the transfer goes, so only the bounds are known
+    println!("  Chunks:        {} bytes to fetch, in ranges of {} to {} bytes sized to the connection", remaining, CHUNK_SIZE, MAX_CHUNK_SIZE);
+    let min_split_size = options.min_split_size.unwrap_or(DEFAULT_MIN_SPLIT_SIZE);
+    println!("  Connections:   {}", engine::connections_for(remaining, options.connections.unwrap_or(1), min_split_size));
+    println!("  Range support: {}", ranges);
+
+    let output = match (&options.exec, plan.output) {
+        (Some(command), _) => format!("piped to {}", command),
+        (None, Some(path)) => path.display().to_string(),
+        (None, None) => "named after the SHA-256, once it is known".to_string(),
+    };
+    println!("  Output:        {}", output);
+    println!("  Written to:    {}", plan.part.display());
+
+    if options.resume {
+        match (resume_from, plan.output) {
+            (_, None) => println!("  Resume from:   not possible, the output name depends on the hash"),
+            (_, Some(_)) if parallel => {
+                let control = output::control_path(plan.part);
+                if control.exists() {
+                    println!("  Resume from:   the chunks listed in {}", control.display());
+                } else {
+                    println!("  Resume from:   the start, there is no control file");
+                }
+            }
+            (Some(offset), Some(_)) => println!("  Resume from:   byte {}", offset),
+            (None, Some(_)) => println!("  Resume from:   the start, there is no partial file"),
+        }
+    }
+    if let (Some(control), Some(output)) = (&options.zsync, plan.output)
+        && output.exists()
+    {
+        println!("  Delta:         reuse blocks of {} listed in {}", output.display(), control);
+    }
+
+    let verification = if options.expected.is_empty() {
+        "size only; the SHA-256 is printed to compare by hand".to_string()
+    } else {
+        let names: Vec<&str> = options.expected.iter().map(|digest| digest.algorithm.name()).collect();
+        format!("size and {} from the command line", names.join(", "))
+    };
+    println!("  Verification:  {}", verification);
+}
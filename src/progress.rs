@@ -0,0 +1,301 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::download::Summary;
+use crate::url::Url;
+
+// Told about a transfer as it happens, to drive a progress display. Every
+// method does nothing by default, so implement only the ones you need.
+// Downloads with several connections call these from several threads.
+pub trait ProgressObserver: Send + Sync {
+    // The size is known and the body is about to be fetched
+    fn on_started(&self, _url: &Url, _total_size: u64) {}
+
+    // A request for the len bytes at offset is going out
+    fn on_request(&self, _offset: u64, _len: u64) {}
+
+    // len bytes at offset arrived, making downloaded bytes so far
+    fn on_chunk(&self, _offset: u64, _len: usize, _downloaded: u64, _total_size: u64) {}
+
+    // The request for the range at offset failed or came back empty, and is
+    // being tried again
+    fn on_retry(&self, _offset: u64, _attempt: usize, _reason: &str) {}
+
+    // The source the body was coming from failed for good, and the rest of
+    // it comes from the next mirror
+    fn on_mirror_switched(&self, _from: &Url, _to: &Url) {}
+
+    // The transfer is over, successfully or not
+    fn on_finished(&self, _result: Result<&Summary, &std::io::Error>) {}
+}
+
+// So an observer can be kept to look at while the download has a clone
+impl<T: ProgressObserver + ?Sized> ProgressObserver for Arc<T> {
+    fn on_started(&self, url: &Url, total_size: u64) {
+        (**self).on_started(url, total_size);
+    }
+
+    fn on_request(&self, offset: u64, len: u64) {
+        (**self).on_request(offset, len);
+    }
+
+    fn on_chunk(&self, offset: u64, len: usize, downloaded: u64, total_size: u64) {
+        (**self).on_chunk(offset, len, downloaded, total_size);
+    }
+
+    fn on_retry(&self, offset: u64, attempt: usize, reason: &str) {
+        (**self).on_retry(offset, attempt, reason);
+    }
+
+    fn on_mirror_switched(&self, from: &Url, to: &Url) {
+        (**self).on_mirror_switched(from, to);
+    }
+
+    fn on_finished(&self, result: Result<&Summary, &std::io::Error>) {
+        (**self).on_finished(result);
+    }
+}
+
+// A picture of which parts of the body are in, like aria2's and axel's: the
+// body is split into cells, each drawn as '#' once it is in, '>' while a
+// request for it is out, '+' when it is partly in and '.' before then. A
+// stuck connection or mirror is a '>' that stays put while the rest fill in.
+// Add it as an observer, keeping an Arc to render from.
+pub struct SegmentMap {
+    cells: usize,
+    state: Mutex<MapState>,
+}
+
+#[derive(Default)]
+struct MapState {
+    total_size: u64,
+    // Bytes in per cell
+    filled: Vec<u64>,
+    // Start and end of the requests that are out
+    requests: Vec<(u64, u64)>,
+}
+
+impl SegmentMap {
+    // A map of at most cells cells; small bodies get one per byte
+    pub fn new(cells: usize) -> SegmentMap {
+        SegmentMap { cells: cells.max(1), state: Mutex::default() }
+    }
+
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("no thread panics holding the map");
+        (0..state.filled.len())
+            .map(|cell| {
+                let (start, end) = state.bounds(cell);
+                if state.requests.iter().any(|&(from, to)| from < end && start < to) {
+                    '>'
+                } else if state.filled[cell] >= end - start {
+                    '#'
+                } else if state.filled[cell] > 0 {
+                    '+'
+                } else {
+                    '.'
+                }
+            })
+            .collect()
+    }
+}
+
+impl MapState {
+    // The bytes a cell covers
+    fn bounds(&self, cell: usize) -> (u64, u64) {
+        let cells = self.filled.len() as u128;
+        let edge = |cell: usize| (cell as u128 * self.total_size as u128 / cells) as u64;
+        (edge(cell), edge(cell + 1))
+    }
+
+    // The cell a byte is in: roughly where it falls, then past any edges
+    // that rounding down left at or before it
+    fn cell(&self, offset: u64) -> usize {
+        let last = self.filled.len() - 1;
+        let mut cell = ((offset as u128 * self.filled.len() as u128 / self.total_size.max(1) as u128) as usize).min(last);
+        while cell < last && self.bounds(cell).1 <= offset {
+            cell += 1;
+        }
+        cell
+    }
+}
+
+impl ProgressObserver for SegmentMap {
+    fn on_started(&self, _url: &Url, total_size: u64) {
+        let cells = usize::try_from(total_size).map_or(self.cells, |size| self.cells.min(size.max(1)));
+        *self.state.lock().expect("no thread panics holding the map") =
+            MapState { total_size, filled: vec![0; cells], requests: Vec::new() };
+    }
+
+    fn on_request(&self, offset: u64, len: u64) {
+        let mut state = self.state.lock().expect("no thread panics holding the map");
+        state.requests.retain(|&(start, _)| start != offset);
+        state.requests.push((offset, offset.saturating_add(len)));
+    }
+
+    fn on_chunk(&self, offset: u64, len: usize, _downloaded: u64, _total_size: u64) {
+        let mut state = self.state.lock().expect("no thread panics holding the map");
+        if state.filled.is_empty() {
+            return;
+        }
+        state.requests.retain(|&(start, _)| start != offset);
+        // Spread the bytes over the cells they fall in
+        let end = offset.saturating_add(len as u64).min(state.total_size);
+        let mut at = offset;
+        while at < end {
+            let cell = state.cell(at);
+            let cell_end = state.bounds(cell).1.min(end);
+            state.filled[cell] += cell_end - at;
+            at = cell_end;
+        }
+    }
+
+    fn on_retry(&self, offset: u64, _attempt: usize, _reason: &str) {
+        self.state.lock().expect("no thread panics holding the map").requests.retain(|&(start, _)| start != offset);
+    }
+
+    fn on_finished(&self, _result: Result<&Summary, &std::io::Error>) {
+        self.state.lock().expect("no thread panics holding the map").requests.clear();
+    }
+}
+
+// How far back the recent speed of a StatusReport looks
+const RECENT: Duration = Duration::from_secs(5);
+
+// A report on a transfer to print whenever someone asks how it is doing, as
+// the command line does on SIGUSR1: bytes in, the average and recent speeds,
+// each request that is out with how long it has been waiting and how much of
+// it is in, and the retries so far. Each request out is a connection's
+// current range, or one of several on a pipelined connection. Add it as an
+// observer, keeping it to render from.
+pub struct StatusReport {
+    state: Mutex<ReportState>,
+}
+
+struct ReportState {
+    url: Option<String>,
+    started: Option<Instant>,
+    total_size: u64,
+    downloaded: u64,
+    // Bytes in by when, over the last RECENT
+    samples: VecDeque<(Instant, u64)>,
+    requests: Vec<Request>,
+    retries: usize,
+    last_retry: Option<String>,
+}
+
+struct Request {
+    offset: u64,
+    len: u64,
+    received: u64,
+    sent: Instant,
+}
+
+impl ReportState {
+    const fn empty() -> ReportState {
+        ReportState {
+            url: None,
+            started: None,
+            total_size: 0,
+            downloaded: 0,
+            samples: VecDeque::new(),
+            requests: Vec::new(),
+            retries: 0,
+            last_retry: None,
+        }
+    }
+}
+
+impl Default for StatusReport {
+    fn default() -> StatusReport {
+        StatusReport::new()
+    }
+}
+
+impl StatusReport {
+    pub const fn new() -> StatusReport {
+        StatusReport { state: Mutex::new(ReportState::empty()) }
+    }
+
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("no thread panics holding the report");
+        let (Some(url), Some(started)) = (&state.url, state.started) else {
+            return "No download in progress\n".to_string();
+        };
+        let now = Instant::now();
+        let elapsed = now.duration_since(started).as_secs_f64().max(1e-6);
+        let percent = if state.total_size > 0 { state.downloaded as f64 * 100.0 / state.total_size as f64 } else { 0.0 };
+        let recent = match state.samples.front() {
+            Some(&(since, at)) if now.duration_since(since) > Duration::ZERO => {
+                (state.downloaded - at) as f64 / now.duration_since(since).as_secs_f64()
+            }
+            _ => 0.0,
+        };
+        let mut report = format!(
+            "{}: {}/{} bytes ({:.1}%) in {:.1} s, {:.2} MB/s on average, {:.2} MB/s lately\n",
+            url, state.downloaded, state.total_size, percent, elapsed,
+            state.downloaded as f64 / elapsed / 1_000_000.0, recent / 1_000_000.0
+        );
+        report.push_str(&format!("{} request{} out\n", state.requests.len(), if state.requests.len() == 1 { "" } else { "s" }));
+        for request in &state.requests {
+            report.push_str(&format!(
+                "  bytes {}-{}: {} of {} in, sent {:.1} s ago\n",
+                request.offset, request.offset + request.len.saturating_sub(1), request.received, request.len,
+                now.duration_since(request.sent).as_secs_f64()
+            ));
+        }
+        match &state.last_retry {
+            Some(reason) => report.push_str(&format!("{} retries, the last because: {}\n", state.retries, reason)),
+            None => report.push_str("No retries\n"),
+        }
+        report
+    }
+}
+
+impl ProgressObserver for StatusReport {
+    fn on_started(&self, url: &Url, total_size: u64) {
+        let mut state = self.state.lock().expect("no thread panics holding the report");
+        *state = ReportState {
+            url: Some(url.to_string()),
+            started: Some(Instant::now()),
+            total_size,
+            ..ReportState::empty()
+        };
+    }
+
+    fn on_request(&self, offset: u64, len: u64) {
+        let mut state = self.state.lock().expect("no thread panics holding the report");
+        // Asked for past the end, the answer stops at it
+        let len = if state.total_size > 0 { len.min(state.total_size.saturating_sub(offset)) } else { len };
+        state.requests.retain(|request| request.offset != offset);
+        state.requests.push(Request { offset, len, received: 0, sent: Instant::now() });
+    }
+
+    fn on_chunk(&self, offset: u64, len: usize, downloaded: u64, _total_size: u64) {
+        let mut state = self.state.lock().expect("no thread panics holding the report");
+        let now = Instant::now();
+        state.downloaded = downloaded;
+        state.samples.push_back((now, downloaded));
+        while state.samples.front().is_some_and(|&(at, _)| now.duration_since(at) > RECENT) {
+            state.samples.pop_front();
+        }
+        // Bodies may arrive in pieces, each at the offset after the last
+        let within = |request: &Request| (request.offset..request.offset + request.len).contains(&offset);
+        if let Some(request) = state.requests.iter_mut().find(|request| within(request)) {
+            request.received += len as u64;
+        }
+        state.requests.retain(|request| request.received < request.len);
+    }
+
+    fn on_retry(&self, offset: u64, _attempt: usize, reason: &str) {
+        let mut state = self.state.lock().expect("no thread panics holding the report");
+        state.requests.retain(|request| request.offset != offset);
+        state.retries += 1;
+        state.last_retry = Some(reason.to_string());
+    }
+
+    fn on_finished(&self, _result: Result<&Summary, &std::io::Error>) {
+        self.state.lock().expect("no thread panics holding the report").requests.clear();
+    }
+}
@@ -0,0 +1,141 @@
+// Requests through an HTTP proxy. Normally each request goes to the proxy
+// with the full URL as its target; with tunnel set the proxy is asked to
+// CONNECT to the origin first and the request goes through the tunnel as it
+// would directly. Either way the proxy may want credentials, which are sent
+// as Proxy-Authorization.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use crate::integrity;
+use crate::trace::Trace;
+use crate::url::Url;
+
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    pub url: Url,
+    pub auth: Option<ProxyAuth>,
+    pub tunnel: bool,
+}
+
+// How to authenticate to the proxy. Only Basic so far.
+#[derive(Clone)]
+pub enum ProxyAuth {
+    Basic { user: String, password: String },
+}
+
+// The password stays out of debug output
+impl std::fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProxyAuth::Basic { user, .. } => f.debug_struct("Basic").field("user", user).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl ProxyAuth {
+    // Basic credentials from "user:password"
+    pub fn parse_basic(value: &str) -> Result<ProxyAuth, String> {
+        let (user, password) = value.split_once(':')
+            .ok_or(format!("Invalid proxy credentials: {} (expected USER:PASSWORD)", value))?;
+        Ok(ProxyAuth::Basic { user: user.to_string(), password: password.to_string() })
+    }
+
+    // Value for the Proxy-Authorization header
+    pub(crate) fn header(&self) -> String {
+        match self {
+            ProxyAuth::Basic { user, password } => {
+                let credentials = format!("{}:{}", user, password);
+                format!("Basic {}", integrity::encode_base64(credentials.as_bytes()))
+            }
+        }
+    }
+}
+
+impl Proxy {
+    // From the proxy's URL, e.g. http://proxy.example:3128
+    pub fn parse(value: &str) -> Result<Proxy, String> {
+        let url = Url::parse(value)?;
+        if url.path != "/" {
+            return Err(format!("Invalid proxy: {} (expected http://HOST:PORT)", value));
+        }
+        Ok(Proxy { url, auth: None, tunnel: false })
+    }
+
+    // The Proxy-Authorization header, when there are credentials
+    pub(crate) fn authorization(&self) -> Option<(String, String)> {
+        self.auth.as_ref().map(|auth| ("Proxy-Authorization".to_string(), auth.header()))
+    }
+}
+
+// The error a request ends with when the proxy answers 407 Proxy
+// Authentication Required, because no credentials were given or it didn't
+// take them. Carried inside an io::Error; use from_error to get at it.
+#[derive(Debug)]
+pub struct ProxyAuthRequired {
+    pub proxy: String,
+    // What the proxy asked for in Proxy-Authenticate, e.g. Basic realm="office"
+    pub challenge: Option<String>,
+    // Whether credentials were sent
+    pub rejected: bool,
+}
+
+impl ProxyAuthRequired {
+    pub fn from_error(error: &std::io::Error) -> Option<&ProxyAuthRequired> {
+        error.get_ref()?.downcast_ref::<ProxyAuthRequired>()
+    }
+
+    pub(crate) fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::PermissionDenied, self)
+    }
+}
+
+impl std::fmt::Display for ProxyAuthRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let challenge = self.challenge.as_deref().map(|challenge| format!(" ({})", challenge)).unwrap_or_default();
+        if self.rejected {
+            write!(f, "Proxy {} rejected the credentials{}", self.proxy, challenge)
+        } else {
+            write!(f, "Proxy {} requires authentication{}; give --proxy-user", self.proxy, challenge)
+        }
+    }
+}
+
+impl std::error::Error for ProxyAuthRequired {}
+
+// Ask the proxy for a tunnel to the URL's host on a fresh connection to it
+pub(crate) fn tunnel(conn: &mut TcpStream, url: &Url, proxy: &Proxy, trace: Option<&Trace>) -> std::io::Result<()> {
+    let extra: String = proxy.authorization().iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+    let request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n{1}\r\n", url.authority_with_port(), extra);
+    conn.write_all(request.as_bytes())?;
+    if let Some(trace) = trace {
+        trace.sent(request.as_bytes());
+    }
+
+    // Nothing comes after the head until the request is sent, so reading in
+    // blocks can't take any of the origin's response
+    let mut response = Vec::new();
+    let mut buffer = [0; 1024];
+    while !response.windows(4).any(|w| w == b"\r\n\r\n") {
+        match conn.read(&mut buffer)? {
+            0 => return Err(std::io::Error::other(format!("Proxy {} closed the connection", proxy.url.authority()))),
+            n => response.extend_from_slice(&buffer[..n]),
+        }
+    }
+    if let Some(trace) = trace {
+        trace.received(&response);
+    }
+
+    let head = crate::http::ResponseHead::parse(&response);
+    match head.status {
+        200..=299 => Ok(()),
+        407 => Err(ProxyAuthRequired {
+            proxy: proxy.url.authority(),
+            challenge: head.header("Proxy-Authenticate").map(str::to_string),
+            rejected: proxy.auth.is_some(),
+        }.into_error()),
+        status => Err(std::io::Error::other(format!(
+            "Proxy {} refused to connect to {}: HTTP {}", proxy.url.authority(), url.authority_with_port(), status
+        ))),
+    }
+}
@@ -0,0 +1,85 @@
+// Python bindings, enabled with the python feature. Build the extension
+// module with
+//
+//     cargo rustc --lib --release --features python --crate-type cdylib
+//
+// and copy target/release/libhttp_client.so to http_client.so (.pyd on
+// Windows) somewhere on the Python path. Then:
+//
+//     import http_client
+//     result = http_client.download(url, "file.iso", sha256="9f86d0...", connections=4,
+//                                   progress=lambda done, total: print(done, total))
+//     result["sha256"], result["size"], result["path"]
+
+use std::sync::{Arc, Mutex};
+
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::cancel::{CancellationToken, Cancelled};
+use crate::download::Download;
+use crate::progress::ProgressObserver;
+
+// Calls the Python progress callable. If it raises, the download is
+// cancelled and the exception is raised from download() instead.
+struct CallableObserver {
+    callable: Py<PyAny>,
+    cancel: CancellationToken,
+    raised: Arc<Mutex<Option<PyErr>>>,
+}
+
+impl ProgressObserver for CallableObserver {
+    fn on_chunk(&self, _offset: u64, _len: usize, downloaded: u64, total_size: u64) {
+        Python::attach(|py| {
+            if let Err(e) = self.callable.call1(py, (downloaded, total_size)) {
+                self.raised.lock().expect("no thread panics holding the exception").get_or_insert(e);
+                self.cancel.cancel();
+            }
+        });
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (url, path, sha256=None, connections=4, progress=None))]
+fn download<'py>(
+    py: Python<'py>,
+    url: &str,
+    path: &str,
+    sha256: Option<&str>,
+    connections: usize,
+    progress: Option<Py<PyAny>>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let cancel = CancellationToken::new();
+    let raised = Arc::new(Mutex::new(None));
+
+    let mut builder = Download::builder(url).output(path).connections(connections).cancellation(cancel.clone());
+    if let Some(hex) = sha256 {
+        builder = builder.expected_sha256(hex);
+    }
+    if let Some(callable) = progress {
+        builder = builder.observer(CallableObserver { callable, cancel, raised: raised.clone() });
+    }
+
+    // Let other Python threads run while the transfer does
+    let result = py.detach(|| builder.build()?.run());
+
+    match result {
+        Ok(summary) => {
+            let dict = PyDict::new(py);
+            dict.set_item("path", summary.path.to_string_lossy())?;
+            dict.set_item("size", summary.size)?;
+            dict.set_item("sha256", summary.sha256)?;
+            Ok(dict)
+        }
+        Err(e) => match raised.lock().expect("no thread panics holding the exception").take() {
+            Some(exception) if Cancelled::from_error(&e).is_some() => Err(exception),
+            _ => Err(PyOSError::new_err(e.to_string())),
+        },
+    }
+}
+
+#[pymodule]
+fn http_client(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(download, module)?)
+}
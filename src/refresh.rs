@@ -0,0 +1,59 @@
+// Fresh URLs for downloads from signed URLs that expire part way through.
+// When a range request is refused with 403 Forbidden, the callback is asked
+// for a new URL for the same body, and that request and every later one for
+// the original URL go to the new one instead, from the same offset.
+
+use std::sync::{Arc, Mutex};
+
+use crate::url::Url;
+
+type Mint = dyn Fn(&Url) -> std::io::Result<Url> + Send + Sync;
+
+// Clones share the callback and the URLs it handed out
+#[derive(Clone)]
+pub struct UrlRefresh {
+    mint: Arc<Mint>,
+    // Each original URL with the one that replaces it
+    current: Arc<Mutex<Vec<(Url, Url)>>>,
+}
+
+impl std::fmt::Debug for UrlRefresh {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("UrlRefresh").finish_non_exhaustive()
+    }
+}
+
+impl UrlRefresh {
+    // The callback gets the original URL and returns a new one for the same body
+    pub fn new(mint: impl Fn(&Url) -> std::io::Result<Url> + Send + Sync + 'static) -> UrlRefresh {
+        UrlRefresh { mint: Arc::new(mint), current: Arc::default() }
+    }
+
+    // Where requests for the URL go now
+    pub(crate) fn resolve(&self, url: &Url) -> Url {
+        let current = self.current.lock().expect("no thread panics holding the refreshed URLs");
+        current.iter()
+            .find(|(original, _)| original == url)
+            .map_or_else(|| url.clone(), |(_, replacement)| replacement.clone())
+    }
+
+    // A new URL in place of one that was refused. When several connections
+    // are refused at once only the first asks the callback; the others get
+    // what it returned.
+    pub(crate) fn renew(&self, url: &Url, refused: &Url) -> std::io::Result<Url> {
+        let mut current = self.current.lock().expect("no thread panics holding the refreshed URLs");
+        let index = current.iter().position(|(original, _)| original == url);
+        if let Some(index) = index
+            && current[index].1 != *refused
+        {
+            return Ok(current[index].1.clone());
+        }
+
+        let fresh = (self.mint)(url)?;
+        match index {
+            Some(index) => current[index].1 = fresh.clone(),
+            None => current.push((url.clone(), fresh.clone())),
+        }
+        Ok(fresh)
+    }
+}
@@ -0,0 +1,118 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use crate::http;
+use crate::json;
+use crate::url::Url;
+
+// Requests replayed at the same time unless they have to go in order
+const PARALLEL: usize = 8;
+
+// Headers the client sets itself, or that only make sense on the original
+// connection (HTTP/2 pseudo-headers start with ':')
+const SKIPPED_HEADERS: &[&str] = &["host", "connection", "content-length", "transfer-encoding", "keep-alive"];
+
+// A GET request from the HAR file, with what the server answered back then
+struct Recorded {
+    url: String,
+    headers: Vec<(String, String)>,
+    status: Option<u16>,
+    size: Option<usize>,
+}
+
+// Re-issue the GET requests recorded in a HAR file and compare the answers
+// with the recorded ones. Returns whether every request succeeded with the
+// recorded status.
+pub fn replay(har: &Path, in_order: bool, original_headers: bool, settings: &http::Settings) -> std::io::Result<bool> {
+    let text = std::fs::read_to_string(har)?;
+    let log = json::parse(&text)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}: {}", har.display(), e)))?;
+    let entries = log.get("log").and_then(|log| log.get("entries")).and_then(json::Value::as_array)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} has no log.entries", har.display())))?;
+
+    let mut requests = Vec::new();
+    let mut skipped = 0;
+    for entry in entries {
+        match recorded(entry, original_headers) {
+            Some(request) => requests.push(request),
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        println!("Skipping {} entries that aren't GET requests", skipped);
+    }
+
+    let workers = if in_order { 1 } else { PARALLEL.min(requests.len()) };
+    let next = AtomicUsize::new(0);
+    let differed = AtomicUsize::new(0);
+    // Keeps each report in one piece when requests run in parallel
+    let output = Mutex::new(());
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(request) = requests.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let (ok, report) = send(request, settings);
+                    if !ok {
+                        differed.fetch_add(1, Ordering::Relaxed);
+                    }
+                    let _guard = output.lock().expect("no thread panics holding the output");
+                    println!("{}", report);
+                }
+            });
+        }
+    });
+
+    let differed = differed.into_inner();
+    println!("Replayed {} requests, {} failed or got a different status", requests.len(), differed);
+    Ok(differed == 0)
+}
+
+fn recorded(entry: &json::Value, original_headers: bool) -> Option<Recorded> {
+    let request = entry.get("request")?;
+    if !request.get("method")?.as_str()?.eq_ignore_ascii_case("GET") {
+        return None;
+    }
+    let url = request.get("url")?.as_str()?.to_string();
+
+    let headers = match request.get("headers").and_then(json::Value::as_array) {
+        Some(headers) if original_headers => headers.iter()
+            .filter_map(|header| Some((header.get("name")?.as_str()?, header.get("value")?.as_str()?)))
+            .filter(|(name, _)| !name.starts_with(':') && !SKIPPED_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let response = entry.get("response");
+    let status = response.and_then(|response| response.get("status")?.as_f64()).map(|status| status as u16);
+    let size = response
+        .and_then(|response| response.get("bodySize")?.as_f64())
+        .filter(|size| *size >= 0.0)
+        .map(|size| size as usize);
+    Some(Recorded { url, headers, status, size })
+}
+
+// Send one request, returning whether it matched and a line describing it
+fn send(request: &Recorded, settings: &http::Settings) -> (bool, String) {
+    let url = match Url::parse(&request.url) {
+        Ok(url) => url,
+        Err(e) => return (false, format!("GET {}: {}", request.url, e)),
+    };
+
+    let start = Instant::now();
+    let (head, body) = match http::get(&url, &request.headers, settings) {
+        Ok(response) => response,
+        Err(e) => return (false, format!("GET {}: {}", request.url, e)),
+    };
+    let elapsed = start.elapsed().as_millis();
+
+    let recorded_status = request.status.map(|status| format!(" (recorded {})", status)).unwrap_or_default();
+    let recorded_size = request.size.map(|size| format!(" (recorded {})", size)).unwrap_or_default();
+    let ok = request.status.is_none_or(|status| status == head.status) && head.status != 0;
+    (ok, format!(
+        "GET {} -> {}{}, {} bytes{}, {} ms",
+        request.url, head.status, recorded_status, body.len(), recorded_size, elapsed
+    ))
+}
@@ -0,0 +1,205 @@
+// Telling the user what went wrong with a download in enough detail to act
+// on: the error, what the download was doing at the time, the status or
+// header to blame and, where there is one, a way round it. The first line is
+// the message as it always was, so scripts matching on it still do.
+
+use http_client::http::{Forbidden, RangeIgnored, RangeNotSatisfiable, TooSlow, UnexpectedStatus, Unresolved};
+use http_client::lock::Locked;
+use http_client::proxy::ProxyAuthRequired;
+use http_client::{json, TimeLimitExceeded};
+
+use crate::exitcode::Cause;
+use crate::url::Url;
+
+// What a download was doing when it failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    // The first request, which finds out how big the body is
+    Probe,
+    // Fetching the bytes from start to end (inclusive)
+    Range { start: u64, end: u64 },
+    // Checking what arrived and putting it in place
+    Finish,
+}
+
+// A header the download can't do without, missing from the server's answer
+#[derive(Debug)]
+pub struct MissingHeader {
+    pub name: &'static str,
+}
+
+impl MissingHeader {
+    pub fn from_error(error: &std::io::Error) -> Option<&MissingHeader> {
+        error.get_ref()?.downcast_ref::<MissingHeader>()
+    }
+
+    pub fn into_error(self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, self)
+    }
+}
+
+impl std::fmt::Display for MissingHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "No {} header", self.name)
+    }
+}
+
+impl std::error::Error for MissingHeader {}
+
+// A failed download as --json records it
+#[derive(Clone, Debug)]
+pub struct Failure {
+    pub cause: Cause,
+    // The first byte of the range being fetched, where a resumed download
+    // would carry on from; None if no range was
+    pub offset: Option<u64>,
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(phase: Option<Phase>, error: &std::io::Error) -> Failure {
+        Failure {
+            cause: Cause::of(error),
+            offset: match phase {
+                Some(Phase::Range { start, .. }) => Some(start),
+                _ => None,
+            },
+            status: status(error),
+            message: error.to_string(),
+        }
+    }
+
+    pub fn to_json(&self, url: &str) -> String {
+        let optional = |number: Option<u64>| number.map_or("null".to_string(), |number| number.to_string());
+        format!(
+            "{{\"kind\":{},\"url\":{},\"offset\":{},\"status\":{},\"message\":{}}}",
+            json::quote(self.cause.name()),
+            json::quote(url),
+            optional(self.offset),
+            optional(self.status.map(u64::from)),
+            json::quote(&self.message),
+        )
+    }
+}
+
+// The lines printed for a failed download
+pub struct Report<'a> {
+    pub url: &'a Url,
+    pub phase: Option<Phase>,
+    pub error: &'a std::io::Error,
+}
+
+impl std::fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Error downloading {}: {}", self.url, self.error)?;
+        let step = step(self.url, self.error);
+        // A download stopped on purpose stops between requests
+        let phase = self.phase.filter(|_| self.error.kind() != std::io::ErrorKind::Interrupted);
+        match (phase, step) {
+            (Some(phase), Some(step)) => writeln!(f, "  during: {}, {}", describe(phase), step)?,
+            (Some(phase), None) => writeln!(f, "  during: {}", describe(phase))?,
+            (None, Some(step)) => writeln!(f, "  during: {}", step)?,
+            (None, None) => {}
+        }
+        if let Some(status) = status(self.error) {
+            writeln!(f, "  status: {}", status)?;
+        }
+        if let Some(missing) = MissingHeader::from_error(self.error) {
+            writeln!(f, "  header: {} is missing from the answer", missing.name)?;
+        }
+        if let Some(hint) = hint(self.url, self.error) {
+            writeln!(f, "  help: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+fn describe(phase: Phase) -> String {
+    match phase {
+        Phase::Probe => "the first request, for the size of the body".to_string(),
+        Phase::Range { start, end } => format!("the range request for bytes {}-{}", start, end),
+        Phase::Finish => "checking and saving the download".to_string(),
+    }
+}
+
+// The part of a request that failed, when the error tells
+fn step(url: &Url, error: &std::io::Error) -> Option<String> {
+    if let Some(unresolved) = Unresolved::from_error(error) {
+        return Some(format!("resolving {}", unresolved.host));
+    }
+    if is_connect_failure(error) {
+        return Some(format!("connecting to {}:{}", url.host, url.port));
+    }
+    None
+}
+
+fn is_connect_failure(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(error.kind(), ErrorKind::ConnectionRefused | ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable | ErrorKind::AddrNotAvailable)
+}
+
+// The status the server answered with, for the failures that come from one
+fn status(error: &std::io::Error) -> Option<u16> {
+    if let Some(answer) = UnexpectedStatus::from_error(error) {
+        return Some(answer.status);
+    }
+    if Forbidden::from_error(error).is_some() {
+        return Some(403);
+    }
+    if ProxyAuthRequired::from_error(error).is_some() {
+        return Some(407);
+    }
+    if RangeIgnored::from_error(error).is_some() {
+        return Some(200);
+    }
+    RangeNotSatisfiable::from_error(error).map(|_| 416)
+}
+
+// What might get the download through next time
+fn hint(url: &Url, error: &std::io::Error) -> Option<String> {
+    if let Some(missing) = MissingHeader::from_error(error) {
+        return Some(format!(
+            "the download is fetched in ranges and needs {} to plan them; `http_client head {}` shows what the server sends",
+            missing.name, url
+        ));
+    }
+    if Unresolved::from_error(error).is_some() {
+        return Some("check the host name; --resolve or --hosts-file can give its address, and --dns-server asks another resolver".to_string());
+    }
+    if is_connect_failure(error) {
+        return Some("check the port, and whether this network needs --proxy".to_string());
+    }
+    if TooSlow::from_error(error).is_some() {
+        return Some("the connection stayed under --speed-limit for --speed-time; lower the one or raise the other".to_string());
+    }
+    if TimeLimitExceeded::from_error(error).is_some() {
+        return Some(format!("`http_client resume {}` carries on from the .part file, or raise --max-time", url));
+    }
+    if Locked::from_error(error).is_some() {
+        return Some("another download is writing the same file; --wait-for-lock waits for it to finish".to_string());
+    }
+    if Forbidden::from_error(error).is_some() {
+        return Some("a signed URL that has expired can be renewed as it goes with --refresh-cmd".to_string());
+    }
+    if RangeNotSatisfiable::from_error(error).is_some() {
+        return Some("the body on the server changed size; start over without --continue".to_string());
+    }
+    if RangeIgnored::from_error(error).is_some() {
+        return Some("the server sends the whole body whatever range is asked for, so a .part file can't be carried on; start over without --continue".to_string());
+    }
+    if let Some(proxy) = ProxyAuthRequired::from_error(error) {
+        return Some(if proxy.rejected {
+            "the proxy turned the credentials down; check --proxy-user".to_string()
+        } else {
+            "give the proxy credentials with --proxy-user USER:PASSWORD".to_string()
+        });
+    }
+    match UnexpectedStatus::from_error(error)?.status {
+        401 => Some("the server wants credentials: --header \"Authorization: ...\", --netrc-file or --keyring".to_string()),
+        404 | 410 => Some("check the URL".to_string()),
+        429 => Some("the server wants fewer requests; --limit-rate or a longer --retry-delay sends them slower".to_string()),
+        500..=599 => Some("the server is failing; try again later, or raise --retry and --retry-delay".to_string()),
+        _ => None,
+    }
+}
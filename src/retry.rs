@@ -0,0 +1,137 @@
+// Which failures are worth another attempt. A request that failed on the way
+// there or back (a refused or reset connection, a timeout, a stall, a body
+// cut short, a 5xx, 408 or 429 answer) may well work the next time; one that
+// was turned down for good (any other 4xx, a host name that doesn't resolve,
+// a body shorter than the range asked for or a whole one for a range past
+// the start, a proxy wanting credentials) or stopped on purpose fails the
+// same way however often it is sent. This client speaks plain HTTP, so there
+// are no TLS errors to tell apart; https URLs are refused before anything is
+// sent.
+//
+// How often and how soon a range is tried again is a RetryPolicy: each wait
+// multiplies the one before, up to a ceiling, with some randomness so that
+// many clients failing at once don't all come back at once.
+
+use std::hash::{BuildHasher, RandomState};
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use crate::cancel::{Cancelled, TimeLimitExceeded};
+use crate::engine::MAX_ATTEMPTS;
+use crate::http::{Forbidden, RangeIgnored, RangeNotSatisfiable, TooSlow, UnexpectedStatus, Unresolved};
+use crate::lock::Locked;
+use crate::proxy::ProxyAuthRequired;
+
+// When to try a failed range again. The default makes MAX_ATTEMPTS tries in
+// all, waiting about a quarter of a second before the second and doubling
+// from there, never more than ten seconds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    // Tries in all, the first one included
+    pub max_attempts: usize,
+    // The wait before the second try
+    pub initial_delay: Duration,
+    // What each wait is multiplied by for the next
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    pub jitter: Jitter,
+}
+
+// How much of a wait is left to chance
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Jitter {
+    // The wait exactly
+    None,
+    // Anything from nothing up to the wait
+    Full,
+    // Half the wait, and anything up to the other half
+    Equal,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: MAX_ATTEMPTS,
+            initial_delay: Duration::from_millis(250),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(10),
+            jitter: Jitter::Full,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Whether another try is allowed after this many have failed
+    pub fn allows(&self, failed: usize) -> bool {
+        failed < self.max_attempts
+    }
+
+    // How long to wait before the next try, once this many have failed
+    pub fn delay(&self, failed: usize) -> Duration {
+        let exponent = i32::try_from(failed.saturating_sub(1)).unwrap_or(i32::MAX);
+        let wait = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let wait = wait.min(self.max_delay.as_secs_f64());
+        let wait = match self.jitter {
+            Jitter::None => wait,
+            Jitter::Full => wait * random_fraction(),
+            Jitter::Equal => wait / 2.0 + wait / 2.0 * random_fraction(),
+        };
+        Duration::try_from_secs_f64(wait).unwrap_or(self.max_delay)
+    }
+}
+
+impl Jitter {
+    pub fn parse(value: &str) -> Result<Jitter, String> {
+        match value {
+            "none" => Ok(Jitter::None),
+            "full" => Ok(Jitter::Full),
+            "equal" => Ok(Jitter::Equal),
+            _ => Err(format!("Unknown jitter: {} (expected none, full or equal)", value)),
+        }
+    }
+}
+
+// A number from 0 up to 1 that differs from call to call, from the random
+// keys the standard library seeds each RandomState with
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().hash_one(0u8);
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// Whether sending the same request again could succeed
+pub fn is_retryable(error: &std::io::Error) -> bool {
+    if let Some(answer) = UnexpectedStatus::from_error(error) {
+        return matches!(answer.status, 408 | 429 | 500..=599);
+    }
+    if TooSlow::from_error(error).is_some() {
+        return true;
+    }
+    let fatal = RangeNotSatisfiable::from_error(error).is_some()
+        || RangeIgnored::from_error(error).is_some()
+        || Forbidden::from_error(error).is_some()
+        || Unresolved::from_error(error).is_some()
+        || ProxyAuthRequired::from_error(error).is_some()
+        || Locked::from_error(error).is_some()
+        || Cancelled::from_error(error).is_some()
+        || TimeLimitExceeded::from_error(error).is_some();
+    if fatal {
+        return false;
+    }
+    matches!(
+        error.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::TimedOut
+            | ErrorKind::WouldBlock
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::HostUnreachable
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::NetworkDown
+            | ErrorKind::AddrNotAvailable
+            // A garbled or mixed-up answer, which a fresh connection sorts out
+            | ErrorKind::InvalidData
+    )
+}
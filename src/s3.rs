@@ -0,0 +1,148 @@
+// Downloads that follow S3's own semantics. An object uploaded in parts has
+// an ETag of the form "<md5>-<parts>", where <md5> is the MD5 of the parts'
+// MD5s: such objects are fetched part by part with ?partNumber=N, in
+// parallel, and each part's digest goes into checking the ETag. Objects
+// uploaded whole have the MD5 of the body as their ETag instead, unless they
+// are encrypted with KMS or a customer key.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::http::{self, ResponseHead};
+use crate::integrity;
+use crate::md5;
+use crate::url::Url;
+
+// Parts fetched at the same time
+const PART_CONNECTIONS: usize = 8;
+
+// Where a fetched part went in the file, and its MD5
+#[derive(Clone)]
+struct Part {
+    offset: u64,
+    len: u64,
+    md5: [u8; 16],
+}
+
+// The MD5 and part count of a multipart ETag
+fn multipart_etag(head: &ResponseHead) -> Option<([u8; 16], usize)> {
+    let (digest, parts) = etag(head)?.split_once('-')?;
+    let parts = parts.parse().ok().filter(|parts| *parts > 0)?;
+    Some((integrity::decode_hex(digest)?.try_into().ok()?, parts))
+}
+
+// The MD5 of the body, if the ETag is one
+fn md5_etag(head: &ResponseHead) -> Option<[u8; 16]> {
+    let encrypted = head.header("x-amz-server-side-encryption").is_some_and(|sse| sse.eq_ignore_ascii_case("aws:kms"))
+        || head.header("x-amz-server-side-encryption-customer-algorithm").is_some();
+    if encrypted {
+        return None;
+    }
+    integrity::decode_hex(etag(head)?)?.try_into().ok()
+}
+
+fn etag(head: &ResponseHead) -> Option<&str> {
+    let etag = head.header("ETag")?.trim();
+    Some(etag.strip_prefix("W/").unwrap_or(etag).trim_matches('"'))
+}
+
+// The URL of one part, numbered from 1
+fn part_url(url: &Url, part: usize) -> Url {
+    let separator = if url.path.contains('?') { '&' } else { '?' };
+    Url { path: format!("{}{}partNumber={}", url.path, separator, part), ..url.clone() }
+}
+
+// Fetch a multipart object one part per request, several at once, writing
+// each at its offset in the file as it comes, and check the parts against
+// the ETag. Only the parts being fetched are held in memory. Returns where
+// each part starts, or None if the ETag isn't a multipart one, in which case
+// the object is fetched in ranges as usual.
+pub fn fetch_parts(url: &Url, head: &ResponseHead, total_size: u64, file: &File, settings: &http::Settings) -> std::io::Result<Option<Vec<u64>>> {
+    let Some((expected, count)) = multipart_etag(head) else {
+        return Ok(None);
+    };
+
+    let next = AtomicUsize::new(1);
+    let file = Mutex::new(file);
+    let parts: Mutex<Vec<Option<Part>>> = Mutex::new(vec![None; count]);
+    let worker = || -> std::io::Result<()> {
+        loop {
+            let part = next.fetch_add(1, Ordering::Relaxed);
+            if part > count {
+                return Ok(());
+            }
+            let (head, body) = http::get(&part_url(url, part), &[], settings)?;
+            if head.status != 206 && !(head.status == 200 && count == 1) {
+                return Err(std::io::Error::other(format!("Part {} of {}: HTTP {}", part, url, head.status)));
+            }
+            if let Some(stated) = head.header("x-amz-mp-parts-count").and_then(|stated| stated.trim().parse::<usize>().ok())
+                && stated != count
+            {
+                return Err(std::io::Error::other(format!(
+                    "{} has {} parts, but its ETag says {}", url, stated, count
+                )));
+            }
+            // A part says where it goes with its Content-Range
+            let offset = match head.status {
+                200 => 0,
+                _ if head.content_range_total() != Some(total_size) => {
+                    return Err(std::io::Error::other(format!("Part {} of {} isn't of a {}-byte object", part, url, total_size)));
+                }
+                _ => head.content_range_start().ok_or(std::io::Error::other(format!("Part {} of {} has no Content-Range", part, url)))?,
+            };
+            let len = body.len() as u64;
+            if offset.checked_add(len).is_none_or(|end| end > total_size) {
+                return Err(std::io::Error::other(format!("Part {} of {} goes past the end of the object", part, url)));
+            }
+            let mut file = file.lock().expect("no thread panics holding the file");
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&body)?;
+            drop(file);
+            parts.lock().expect("no thread panics holding the parts")[part - 1] = Some(Part { offset, len, md5: md5::digest(&body) });
+        }
+    };
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..PART_CONNECTIONS.min(count)).map(|_| scope.spawn(worker)).collect();
+        handles.into_iter().try_for_each(|handle| handle.join().expect("part worker panicked"))
+    })?;
+
+    let parts: Vec<Part> = parts.into_inner()
+        .expect("no thread panics holding the parts")
+        .into_iter()
+        .map(|part| part.expect("every part was fetched"))
+        .collect();
+    let digests: Vec<u8> = parts.iter().flat_map(|part| part.md5).collect();
+    if md5::digest(&digests) != expected {
+        return Err(std::io::Error::other(format!("The parts of {} don't match its multipart ETag", url)));
+    }
+    // Each part has to carry on where the one before it ended
+    let mut size = 0;
+    for (number, part) in (1..).zip(&parts) {
+        if part.offset != size {
+            return Err(std::io::Error::other(format!(
+                "Part {} of {} starts at byte {}, not {}", number, url, part.offset, size
+            )));
+        }
+        size += part.len;
+    }
+    if size != total_size {
+        return Err(std::io::Error::other(format!(
+            "The parts of {} add up to {} bytes, not {}", url, size, total_size
+        )));
+    }
+    Ok(Some(parts.iter().map(|part| part.offset).collect()))
+}
+
+// Check the MD5 of a body fetched whole or in ranges against an ETag that
+// is its MD5. Returns whether there was one to check.
+pub fn check_md5_etag(head: &ResponseHead, md5: &[u8; 16]) -> std::io::Result<bool> {
+    match md5_etag(head) {
+        Some(expected) if *md5 != expected => {
+            Err(integrity::Mismatch { algorithm: "md5", reason: "The body doesn't match the MD5 in its ETag".to_string() }.into_error())
+        }
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
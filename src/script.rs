@@ -0,0 +1,152 @@
+// Scripts for --script: JavaScript functions, in the same small dialect PAC
+// files are run with, that a download calls along the way. A script defines
+// whichever of these it needs:
+//
+//   rewrite_url(url)             the URL to download instead, or nothing to
+//                                keep it
+//   request_headers(url)         an array of "Name: value" lines to send with
+//                                every request for the download
+//   on_response(status, headers) the first answer, its headers as "Name:
+//                                value" lines; false or a message refuses it
+//   on_chunk(offset, len)        each chunk as it arrives; false stops the
+//                                download
+//   choose_mirror(urls, offset)  with --mirror, the URL or index in urls
+//                                to fetch the range at offset from, or
+//                                nothing to go by the ranking
+//
+// Scripts can call log(message) for a status line and env(name) to read an
+// environment variable. The top level runs again before every call, so
+// nothing a call sets is there for the next.
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use http_client::js::{Program, Value};
+
+use crate::url::Url;
+
+const CALLBACKS: &[&str] = &["rewrite_url", "request_headers", "on_response", "on_chunk", "choose_mirror"];
+
+// The functions scripts can call, besides JavaScript's own
+const NATIVES: &[&str] = &["log", "env"];
+
+pub struct Script {
+    program: Program,
+    // Where log() lines go
+    log: fn(&str),
+}
+
+impl Script {
+    pub fn load(path: &Path, log: fn(&str)) -> std::io::Result<Script> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| std::io::Error::new(e.kind(), format!("Could not read {}: {}", path.display(), e)))?;
+        let program = Program::parse(&source)
+            .map_err(|e| std::io::Error::other(format!("Invalid script {}: {}", path.display(), e)))?;
+        if !CALLBACKS.iter().any(|name| program.defines(name)) {
+            return Err(std::io::Error::other(format!(
+                "{} defines none of {}", path.display(), CALLBACKS.join(", ")
+            )));
+        }
+        Ok(Script { program, log })
+    }
+
+    pub fn rewrite_url(&self, url: &Url) -> std::io::Result<Option<Url>> {
+        let answer = self.call("rewrite_url", vec![Value::String(url.to_string())], |answer| match answer {
+            Value::Undefined | Value::Null => Ok(None),
+            Value::String(text) => Url::parse(&text).map(Some),
+            other => Err(format!("returned {}, not a URL", other)),
+        })?;
+        Ok(answer.flatten())
+    }
+
+    pub fn request_headers(&self, url: &Url) -> std::io::Result<Vec<(String, String)>> {
+        let answer = self.call("request_headers", vec![Value::String(url.to_string())], |answer| match answer {
+            Value::Undefined | Value::Null => Ok(Vec::new()),
+            Value::Array(lines) => lines.borrow().iter().map(|line| header(&line.to_string())).collect(),
+            other => Err(format!("returned {}, not an array of headers", other)),
+        })?;
+        Ok(answer.unwrap_or_default())
+    }
+
+    pub fn on_response(&self, status: u16, headers: &[(String, String)]) -> std::io::Result<()> {
+        let lines = headers.iter().map(|(name, value)| Value::String(format!("{}: {}", name, value))).collect();
+        let args = vec![Value::Number(f64::from(status)), Value::Array(Rc::new(RefCell::new(lines)))];
+        let refusal = self.call("on_response", args, |answer| Ok(match answer {
+            Value::Bool(false) => Some(format!("HTTP {}", status)),
+            Value::String(reason) => Some(reason),
+            _ => None,
+        }))?;
+        match refusal.flatten() {
+            Some(reason) => Err(std::io::Error::other(format!("The script refused the response: {}", reason))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn on_chunk(&self, offset: u64, len: usize) -> std::io::Result<()> {
+        let args = vec![Value::Number(offset as f64), Value::Number(len as f64)];
+        let stop = self.call("on_chunk", args, |answer| Ok(matches!(answer, Value::Bool(false))))?;
+        if stop == Some(true) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Interrupted,
+                format!("Stopped by the script at byte {}", offset + len as u64),
+            ));
+        }
+        Ok(())
+    }
+
+    // Which of the URLs to fetch the range at offset from, None to leave it
+    // to the mirrors' shares
+    pub fn choose_mirror(&self, urls: &[Url], offset: u64) -> std::io::Result<Option<usize>> {
+        let list = urls.iter().map(|url| Value::String(url.to_string())).collect();
+        let args = vec![Value::Array(Rc::new(RefCell::new(list))), Value::Number(offset as f64)];
+        let answer = self.call("choose_mirror", args, |answer| match answer {
+            Value::Undefined | Value::Null => Ok(None),
+            Value::Number(index) if index >= 0.0 && index == index.trunc() && (index as usize) < urls.len() => {
+                Ok(Some(index as usize))
+            }
+            Value::String(text) => match urls.iter().position(|url| url.to_string() == text) {
+                Some(index) => Ok(Some(index)),
+                None => Err(format!("chose {}, which isn't one of the mirrors", text)),
+            },
+            other => Err(format!("returned {}, not one of the mirrors", other)),
+        })?;
+        Ok(answer.flatten())
+    }
+
+    // Call the script's function, if it has one, and read what it returned
+    fn call<'a, T>(
+        &'a self,
+        name: &str,
+        args: Vec<Value<'a>>,
+        read: impl FnOnce(Value<'a>) -> Result<T, String>,
+    ) -> std::io::Result<Option<T>> {
+        if !self.program.defines(name) {
+            return Ok(None);
+        }
+        self.program.call(name, args, NATIVES, &|native, args| self.native(native, args))
+            .and_then(read)
+            .map(Some)
+            .map_err(|e| std::io::Error::other(format!("Script {} failed: {}", name, e)))
+    }
+
+    fn native<'a>(&self, name: &str, args: &[Value<'a>]) -> Result<Value<'a>, String> {
+        let arg = |i: usize| args.get(i).map(Value::to_string).unwrap_or_default();
+        Ok(match name {
+            "log" => {
+                (self.log)(&arg(0));
+                Value::Undefined
+            }
+            "env" => std::env::var(arg(0)).map_or(Value::Undefined, Value::String),
+            _ => return Err(format!("{} is not a function", name)),
+        })
+    }
+}
+
+// A "Name: value" line
+fn header(line: &str) -> Result<(String, String), String> {
+    match line.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("returned \"{}\", not a \"Name: value\" header", line)),
+    }
+}
@@ -0,0 +1,75 @@
+// Asking a running download how it is doing without stopping it: SIGUSR1 on
+// Unix, as dd takes it, and Ctrl+Break on Windows. The handler only sets a
+// flag, since little is safe inside one; a thread looking at the flag does
+// the printing while the transfer carries on.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// How often the watching thread looks for a request
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+// Call report on a thread of its own each time a status dump is asked for,
+// for as long as the program runs
+pub fn on_status_request(report: impl Fn() + Send + 'static) {
+    if !install() {
+        return;
+    }
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if REQUESTED.swap(false, Ordering::Relaxed) {
+                report();
+            }
+        }
+    });
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly"))]
+fn install() -> bool {
+    use std::ffi::c_int;
+
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const SIGUSR1: c_int = 10;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const SIGUSR1: c_int = 30;
+    // What signal returns when it fails
+    const SIG_ERR: usize = usize::MAX;
+
+    extern "C" fn handle(_signum: c_int) {
+        REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe { signal(SIGUSR1, handle) != SIG_ERR }
+}
+
+#[cfg(windows)]
+fn install() -> bool {
+    unsafe extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+    const CTRL_BREAK_EVENT: u32 = 1;
+
+    // Ctrl+Break is taken here; Ctrl+C and the rest go on to end the program
+    extern "system" fn handle(event: u32) -> i32 {
+        if event != CTRL_BREAK_EVENT {
+            return 0;
+        }
+        REQUESTED.store(true, Ordering::Relaxed);
+        1
+    }
+
+    // SAFETY: the handler only stores to an atomic
+    unsafe { SetConsoleCtrlHandler(handle, 1) != 0 }
+}
+
+#[cfg(not(any(windows, target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", target_os = "openbsd", target_os = "dragonfly")))]
+fn install() -> bool {
+    false
+}
@@ -0,0 +1,110 @@
+use std::io::Write;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::thread::{Scope, ScopedJoinHandle};
+
+// Buffers a Bounded writer has, and so how far behind its thread can fall
+pub const QUEUE_DEPTH: usize = 8;
+
+// Most a Bounded writer's buffer takes at once, so one large write doesn't
+// grow a buffer to its size
+const BUFFER_SIZE: usize = 64 * 1024;
+
+// A writer that passes everything written to it on to several sinks, such as
+// the output file and stdout in tee mode
+#[derive(Default)]
+pub struct MultiWriter<'a> {
+    sinks: Vec<Box<dyn Write + Send + 'a>>,
+}
+
+impl<'a> MultiWriter<'a> {
+    pub fn new() -> MultiWriter<'a> {
+        MultiWriter { sinks: Vec::new() }
+    }
+
+    pub fn add<W: Write + Send + 'a>(&mut self, sink: W) {
+        self.sinks.push(Box::new(sink));
+    }
+}
+
+impl Write for MultiWriter<'_> {
+    // Every sink gets the whole buffer, so a short write is never reported
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for sink in &mut self.sinks {
+            sink.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// A writer that hands what is written to it to a thread, which writes it to
+// the sink. It has depth buffers; once they are all waiting for the thread,
+// write blocks until the sink catches up, so a slow sink holds back whoever
+// is writing rather than letting data pile up in memory. Buffers go back to
+// the pool once written, so there are never more than depth of them.
+pub struct Bounded<'scope> {
+    queue: Option<SyncSender<Vec<u8>>>,
+    pool: Receiver<Vec<u8>>,
+    thread: Option<ScopedJoinHandle<'scope, std::io::Result<()>>>,
+}
+
+impl<'scope> Bounded<'scope> {
+    pub fn spawn<'env, W: Write + Send + 'scope>(scope: &'scope Scope<'scope, 'env>, mut sink: W, depth: usize) -> Bounded<'scope> {
+        let (queue, queued) = std::sync::mpsc::sync_channel::<Vec<u8>>(depth);
+        let (returns, pool) = std::sync::mpsc::sync_channel(depth);
+        for _ in 0..depth {
+            returns.send(Vec::new()).expect("the pool has room for every buffer");
+        }
+        let thread = scope.spawn(move || {
+            for buffer in queued {
+                sink.write_all(&buffer)?;
+                // Only fails once the writer is gone and doesn't need it back
+                let _ = returns.send(buffer);
+            }
+            sink.flush()
+        });
+        Bounded { queue: Some(queue), pool, thread: Some(thread) }
+    }
+
+    // Wait for the thread to write everything, failing if any of it failed
+    pub fn finish(mut self) -> std::io::Result<()> {
+        drop(self.queue.take());
+        self.join()
+    }
+
+    // Why the thread stopped: the sink's error, once it has failed
+    fn join(&mut self) -> std::io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("the writer thread doesn't panic"),
+            None => Err(std::io::Error::other("The output failed earlier")),
+        }
+    }
+}
+
+impl Write for Bounded<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let buf = &buf[..buf.len().min(BUFFER_SIZE)];
+        // Either channel only breaks when the thread has stopped on an error
+        let Ok(mut buffer) = self.pool.recv() else {
+            return self.join().and(Err(std::io::ErrorKind::BrokenPipe.into()));
+        };
+        buffer.clear();
+        buffer.extend_from_slice(buf);
+        let queue = self.queue.as_ref().expect("the queue is open until finish");
+        if queue.send(buffer).is_err() {
+            return self.join().and(Err(std::io::ErrorKind::BrokenPipe.into()));
+        }
+        Ok(buf.len())
+    }
+
+    // The thread flushes the sink when it is done; finish waits for that
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
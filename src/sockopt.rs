@@ -0,0 +1,183 @@
+// Socket options the standard library has no setters for, set with
+// setsockopt on Linux and macOS, and TCP Fast Open connections on Linux.
+
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    unsafe extern "C" {
+        pub fn setsockopt(socket: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe extern "C" {
+        pub fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+        pub fn connect(socket: c_int, address: *const c_void, len: u32) -> c_int;
+    }
+
+    pub const IPPROTO_TCP: c_int = 6;
+
+    #[cfg(target_os = "linux")]
+    pub const AF_INET: c_int = 2;
+    #[cfg(target_os = "linux")]
+    pub const AF_INET6: c_int = 10;
+    #[cfg(target_os = "linux")]
+    pub const SOCK_STREAM: c_int = 1;
+    #[cfg(target_os = "linux")]
+    pub const SOCK_CLOEXEC: c_int = 0o2000000;
+    #[cfg(target_os = "linux")]
+    pub const EINPROGRESS: i32 = 115;
+    // connect() returns at once and the SYN goes out with the first write
+    #[cfg(target_os = "linux")]
+    pub const TCP_FASTOPEN_CONNECT: c_int = 30;
+
+    // struct sockaddr_in and sockaddr_in6, with the port and address in
+    // network byte order
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    pub struct SockaddrIn {
+        pub family: u16,
+        pub port: [u8; 2],
+        pub addr: [u8; 4],
+        pub zero: [u8; 8],
+    }
+
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    pub struct SockaddrIn6 {
+        pub family: u16,
+        pub port: [u8; 2],
+        pub flowinfo: u32,
+        pub addr: [u8; 16],
+        pub scope_id: u32,
+    }
+
+    #[cfg(target_os = "linux")]
+    pub const SOL_SOCKET: c_int = 1;
+    #[cfg(target_os = "linux")]
+    pub const SO_KEEPALIVE: c_int = 9;
+    #[cfg(target_os = "linux")]
+    pub const SO_SNDBUF: c_int = 7;
+    #[cfg(target_os = "linux")]
+    pub const SO_RCVBUF: c_int = 8;
+    // How long a connection is idle before the first probe, and between probes
+    #[cfg(target_os = "linux")]
+    pub const TCP_KEEPIDLE: c_int = 4;
+    #[cfg(target_os = "linux")]
+    pub const TCP_KEEPINTVL: c_int = 5;
+
+    #[cfg(target_os = "macos")]
+    pub const SOL_SOCKET: c_int = 0xffff;
+    #[cfg(target_os = "macos")]
+    pub const SO_KEEPALIVE: c_int = 0x8;
+    #[cfg(target_os = "macos")]
+    pub const SO_SNDBUF: c_int = 0x1001;
+    #[cfg(target_os = "macos")]
+    pub const SO_RCVBUF: c_int = 0x1002;
+    #[cfg(target_os = "macos")]
+    pub const TCP_KEEPIDLE: c_int = 0x10;
+    #[cfg(target_os = "macos")]
+    pub const TCP_KEEPINTVL: c_int = 0x101;
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set(conn: &TcpStream, level: std::ffi::c_int, name: std::ffi::c_int, value: std::ffi::c_int) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: the descriptor is open for as long as conn is borrowed, and
+    // value is a c_int of the size given
+    let result = unsafe {
+        sys::setsockopt(conn.as_raw_fd(), level, name, (&raw const value).cast(), size_of_val(&value) as u32)
+    };
+    if result == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+// Send keepalive probes once the connection has been idle for the interval,
+// and again every interval until the peer answers or the system gives up
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set_keepalive(conn: &TcpStream, interval: Duration) -> std::io::Result<()> {
+    let secs = interval.as_secs().clamp(1, i32::MAX as u64) as std::ffi::c_int;
+    set(conn, sys::SOL_SOCKET, sys::SO_KEEPALIVE, 1)?;
+    set(conn, sys::IPPROTO_TCP, sys::TCP_KEEPIDLE, secs)?;
+    set(conn, sys::IPPROTO_TCP, sys::TCP_KEEPINTVL, secs)
+}
+
+// Ask for kernel buffers of these sizes; the system may round or cap them
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set_buffer_sizes(conn: &TcpStream, recv: Option<usize>, send: Option<usize>) -> std::io::Result<()> {
+    let bytes = |size: usize| size.min(i32::MAX as usize) as std::ffi::c_int;
+    if let Some(size) = recv {
+        set(conn, sys::SOL_SOCKET, sys::SO_RCVBUF, bytes(size))?;
+    }
+    if let Some(size) = send {
+        set(conn, sys::SOL_SOCKET, sys::SO_SNDBUF, bytes(size))?;
+    }
+    Ok(())
+}
+
+// A connection that sends the first bytes written in its SYN when the server
+// has handed out a Fast Open cookie before, and makes a normal handshake
+// otherwise. Neither happens until the first write.
+#[cfg(target_os = "linux")]
+pub fn connect_fast_open(addr: &SocketAddr) -> std::io::Result<TcpStream> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let domain = if addr.is_ipv4() { sys::AF_INET } else { sys::AF_INET6 };
+    // SAFETY: socket takes no pointers; the descriptor it returns is owned
+    // by conn from here on
+    let fd = unsafe { sys::socket(domain, sys::SOCK_STREAM | sys::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let conn = TcpStream::from(unsafe { OwnedFd::from_raw_fd(fd) });
+    set(&conn, sys::IPPROTO_TCP, sys::TCP_FASTOPEN_CONNECT, 1)?;
+
+    // SAFETY: each address struct has the layout the kernel expects for its
+    // family and outlives the call
+    let result = match addr {
+        SocketAddr::V4(addr) => {
+            let raw = sys::SockaddrIn {
+                family: sys::AF_INET as u16,
+                port: addr.port().to_be_bytes(),
+                addr: addr.ip().octets(),
+                zero: [0; 8],
+            };
+            unsafe { sys::connect(conn.as_raw_fd(), (&raw const raw).cast(), size_of_val(&raw) as u32) }
+        }
+        SocketAddr::V6(addr) => {
+            let raw = sys::SockaddrIn6 {
+                family: sys::AF_INET6 as u16,
+                port: addr.port().to_be_bytes(),
+                flowinfo: addr.flowinfo().to_be(),
+                addr: addr.ip().octets(),
+                scope_id: addr.scope_id(),
+            };
+            unsafe { sys::connect(conn.as_raw_fd(), (&raw const raw).cast(), size_of_val(&raw) as u32) }
+        }
+    };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(sys::EINPROGRESS) {
+            return Err(error);
+        }
+    }
+    Ok(conn)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn connect_fast_open(_addr: &SocketAddr) -> std::io::Result<TcpStream> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TCP Fast Open is only supported on Linux"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn set_buffer_sizes(_conn: &TcpStream, _recv: Option<usize>, _send: Option<usize>) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "socket buffer sizes can't be set on this platform"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn set_keepalive(_conn: &TcpStream, _interval: Duration) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "TCP keepalive is not supported on this platform"))
+}
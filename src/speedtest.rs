@@ -0,0 +1,158 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::engine::CHUNK_SIZE;
+use crate::http;
+use crate::split;
+use crate::url::Url;
+
+// Numbers of parallel connections each speed test is run with
+const CONNECTION_COUNTS: &[usize] = &[1, 2, 4, 8];
+
+// Range sizes bench tries with each of those
+const BENCH_CHUNK_SIZES: &[usize] = &[16 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024];
+
+// How long each run of a speed test goes on for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    Duration(Duration),
+    Bytes(u64),
+}
+
+impl Default for Limit {
+    fn default() -> Limit {
+        Limit::Duration(Duration::from_secs(10))
+    }
+}
+
+impl Limit {
+    // Seconds such as "10s", or a byte count such as "100M"; empty for the
+    // default of ten seconds
+    pub fn parse(value: &str) -> Result<Limit, String> {
+        if value.is_empty() {
+            return Ok(Limit::default());
+        }
+        Limit::parse_given(value)
+    }
+
+    // Like parse, without a default
+    pub fn parse_given(value: &str) -> Result<Limit, String> {
+        match value.strip_suffix('s') {
+            Some(seconds) => seconds.parse::<f64>()
+                .ok()
+                .filter(|seconds| *seconds > 0.0 && seconds.is_finite())
+                .map(|seconds| Limit::Duration(Duration::from_secs_f64(seconds)))
+                .ok_or(format!("Invalid duration: {} (expected e.g. 10s)", value)),
+            None => split::parse_size(value).map(Limit::Bytes),
+        }
+    }
+
+    fn reached(&self, started: Instant, received: u64) -> bool {
+        match *self {
+            Limit::Duration(duration) => started.elapsed() >= duration,
+            Limit::Bytes(bytes) => received >= bytes,
+        }
+    }
+}
+
+// Download from the URL with 1, 2, 4 and 8 connections in turn until the
+// limit is reached each time, throwing the data away, and print the speed
+// each got. Ranges wrap around to the start, so small files do as well.
+pub fn run(url: &Url, limit: Limit, settings: &http::Settings) -> std::io::Result<()> {
+    let total_size = body_size(url, settings)?;
+    println!("Speed test of {} ({} bytes)", url, total_size);
+
+    for &connections in CONNECTION_COUNTS {
+        let (received, elapsed) = measure(url, total_size, CHUNK_SIZE, connections, limit, settings)?;
+        let seconds = elapsed.as_secs_f64().max(1e-6);
+        println!(
+            "{} connection{}: {} bytes in {:.2} s, {:.2} MB/s",
+            connections, if connections == 1 { "" } else { "s" }, received, seconds,
+            received as f64 / seconds / 1_000_000.0
+        );
+    }
+    Ok(())
+}
+
+// Try every combination of range size and connection count for the limit
+// each, print a table of the throughputs and suggest the fastest
+pub fn bench(url: &Url, limit: Limit, settings: &http::Settings) -> std::io::Result<()> {
+    let total_size = body_size(url, settings)?;
+    println!("Benchmark of {} ({} bytes), MB/s", url, total_size);
+    let columns: Vec<String> = CONNECTION_COUNTS.iter().map(|connections| format!("{:>8}", connections)).collect();
+    println!("{:>8}{}", "chunk", columns.join(""));
+
+    let mut best = None;
+    for &chunk_size in BENCH_CHUNK_SIZES {
+        let mut row = format!("{:>8}", size_label(chunk_size));
+        for &connections in CONNECTION_COUNTS {
+            let (received, elapsed) = measure(url, total_size, chunk_size, connections, limit, settings)?;
+            let speed = received as f64 / elapsed.as_secs_f64().max(1e-6) / 1_000_000.0;
+            row.push_str(&format!("{:>8.2}", speed));
+            if best.is_none_or(|(fastest, _, _)| speed > fastest) {
+                best = Some((speed, chunk_size, connections));
+            }
+        }
+        println!("{}", row);
+    }
+
+    if let Some((speed, chunk_size, connections)) = best {
+        println!(
+            "Fastest: {} connection{} with {} chunks, {:.2} MB/s",
+            connections, if connections == 1 { "" } else { "s" }, size_label(chunk_size), speed
+        );
+        println!("Suggested settings:\n  connections = {}\n  chunk_size = {}", connections, size_label(chunk_size));
+    }
+    Ok(())
+}
+
+// A size in the K/M form parse_size reads
+fn size_label(size: usize) -> String {
+    match size {
+        size if size % (1024 * 1024) == 0 => format!("{}M", size / (1024 * 1024)),
+        size if size % 1024 == 0 => format!("{}K", size / 1024),
+        size => size.to_string(),
+    }
+}
+
+fn body_size(url: &Url, settings: &http::Settings) -> std::io::Result<u64> {
+    let head = http::probe(url, settings)?;
+    head.content_length()
+        .filter(|size| *size > 0)
+        .ok_or(std::io::Error::other("No Content-Length header, or an empty body"))
+}
+
+// One run: returns the bytes received and how long it took
+fn measure(
+    url: &Url,
+    total_size: u64,
+    chunk_size: usize,
+    connections: usize,
+    limit: Limit,
+    settings: &http::Settings,
+) -> std::io::Result<(u64, Duration)> {
+    let chunk_size = chunk_size as u64;
+    let chunks = total_size.div_ceil(chunk_size);
+    let next = AtomicU64::new(0);
+    let received = AtomicU64::new(0);
+    let started = Instant::now();
+
+    let worker = || -> std::io::Result<()> {
+        while !limit.reached(started, received.load(Ordering::Relaxed)) {
+            let start = next.fetch_add(1, Ordering::Relaxed) % chunks * chunk_size;
+            let end = (start + chunk_size).min(total_size) - 1;
+            let chunk = http::download_range(url, start, end, settings)?;
+            if chunk.is_empty() {
+                return Err(std::io::Error::other(format!("No data for range starting at {}", start)));
+            }
+            received.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        Ok(())
+    };
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..connections).map(|_| scope.spawn(worker)).collect();
+        handles.into_iter().try_for_each(|handle| handle.join().expect("speed test worker panicked"))
+    })?;
+
+    Ok((received.into_inner(), started.elapsed()))
+}
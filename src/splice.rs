@@ -0,0 +1,92 @@
+// Moving a response body from a connection to a file without it passing
+// through this process, with splice on Linux: the socket's bytes go into a
+// pipe and from the pipe to the file, both inside the kernel. The file
+// position is left alone, so writers at other offsets need no lock.
+// Elsewhere copy fails with Unsupported and callers read and write as usual.
+
+use std::fs::File;
+use std::net::TcpStream;
+
+use crate::cancel::CancellationToken;
+use crate::http::ReadWatch;
+
+// Whether copy can work on this platform at all
+pub(crate) const SUPPORTED: bool = cfg!(target_os = "linux");
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_int, c_uint};
+
+    unsafe extern "C" {
+        pub fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+        pub fn splice(fd_in: c_int, off_in: *mut i64, fd_out: c_int, off_out: *mut i64, len: usize, flags: c_uint) -> isize;
+    }
+
+    pub const O_CLOEXEC: c_int = 0o2000000;
+    // Move pages rather than copy them where the kernel can, and say more
+    // is coming so the socket side batches
+    pub const SPLICE_F_MOVE: c_uint = 1;
+    pub const SPLICE_F_MORE: c_uint = 4;
+}
+
+// Move len bytes from the connection into the file at offset
+#[cfg(target_os = "linux")]
+pub(crate) fn copy(conn: &TcpStream, file: &File, offset: u64, len: u64, cancel: Option<&CancellationToken>, watch: &mut ReadWatch) -> std::io::Result<()> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { sys::pipe2(fds.as_mut_ptr(), sys::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just made and nothing else owns them
+    let (pipe_out, pipe_in) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    // Retried when a signal interrupts it
+    let splice = |from: &dyn AsRawFd, to: &dyn AsRawFd, offset: *mut i64, len: usize, flags| loop {
+        // SAFETY: the descriptors are open while borrowed, and offset is
+        // null or points at a live i64
+        let moved = unsafe { sys::splice(from.as_raw_fd(), std::ptr::null_mut(), to.as_raw_fd(), offset, len, flags) };
+        if moved >= 0 {
+            return Ok(moved as usize);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    };
+
+    let mut position = i64::try_from(offset).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Offset too large"))?;
+    let mut left = len;
+    while left > 0 {
+        if cancel.is_some_and(CancellationToken::is_cancelled) {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled"));
+        }
+        let wanted = usize::try_from(left).unwrap_or(usize::MAX);
+        // The socket's read timeout holds for splice as for a read
+        let mut in_pipe = match splice(conn, &pipe_in, std::ptr::null_mut(), wanted, sys::SPLICE_F_MOVE | sys::SPLICE_F_MORE) {
+            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed before the whole body arrived")),
+            Ok(moved) => {
+                watch.received(moved)?;
+                moved
+            }
+            Err(e) => {
+                watch.failed(e)?;
+                continue;
+            }
+        };
+        left -= in_pipe as u64;
+        while in_pipe > 0 {
+            match splice(&pipe_out, file, &raw mut position, in_pipe, sys::SPLICE_F_MOVE)? {
+                0 => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "Could not write to the file")),
+                written => in_pipe -= written,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn copy(_conn: &TcpStream, _file: &File, _offset: u64, _len: u64, _cancel: Option<&CancellationToken>, _watch: &mut ReadWatch) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "splice is only available on Linux"))
+}
@@ -0,0 +1,85 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::output;
+
+// Parse a part size such as 1G, 500M or 4096. Suffixes are binary multiples.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size: {} (expected e.g. 1G, 500M or 4096)", value);
+    let upper = value.trim().to_ascii_uppercase();
+    let number = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (digits, shift) = match number.as_bytes().last() {
+        Some(b'K') => (&number[..number.len() - 1], 10),
+        Some(b'M') => (&number[..number.len() - 1], 20),
+        Some(b'G') => (&number[..number.len() - 1], 30),
+        Some(b'T') => (&number[..number.len() - 1], 40),
+        _ => (number, 0),
+    };
+
+    digits.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .filter(|&size| size > 0)
+        .ok_or_else(invalid)
+}
+
+// Name of the numbered part of a split file: file.part001, file.part002, ...
+fn part_name(path: &Path, number: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".part{:03}", number));
+    PathBuf::from(name)
+}
+
+// Manifest listing the SHA-256 of every part, in sha256sum format
+pub fn manifest_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".parts.sha256");
+    PathBuf::from(name)
+}
+
+// Cut a finished download into parts of at most part_size bytes named after
+// path, and write their manifest. The source file is removed afterwards; the
+// parts get its permissions and modification time.
+pub fn split(source: &Path, path: &Path, part_size: u64) -> std::io::Result<Vec<PathBuf>> {
+    let metadata = std::fs::metadata(source)?;
+    let manifest = manifest_path(path);
+    match std::fs::remove_file(&manifest) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+
+    let mut input = File::open(source)?;
+    let mut parts = Vec::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    // An empty download still gets one (empty) part
+    while parts.is_empty() || (parts.len() as u64) * part_size < metadata.len() {
+        let part = part_name(path, parts.len() + 1);
+        let mut file = File::create(&part)?;
+        let mut hasher = Sha256::new();
+        let mut remaining = part_size;
+
+        while remaining > 0 {
+            let wanted = remaining.min(buffer.len() as u64) as usize;
+            let read = input.read(&mut buffer[..wanted])?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            file.write_all(&buffer[..read])?;
+            remaining -= read as u64;
+        }
+
+        file.set_permissions(metadata.permissions())?;
+        file.set_modified(metadata.modified()?)?;
+        file.sync_all()?;
+        output::append_checksum(&manifest, &part, &format!("{:x}", hasher.finalize()))?;
+        parts.push(part);
+    }
+
+    std::fs::remove_file(source)?;
+    Ok(parts)
+}
@@ -0,0 +1,145 @@
+// A content-addressed store on disk: verified bodies kept under their own
+// digests, so a download whose expected digest is already there is made from
+// the stored copy without touching the network, whatever URL it came from.
+//
+// Each body is a file <algorithm>/<hex digest>, under its SHA-256 and under
+// any other digest it was checked against, the extra names hard links where
+// the file system allows. Outputs are placed from the store the same way,
+// as reflinks or hard links, so copies of one body share its disk blocks.
+
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use http_client::http::ResponseHead;
+use http_client::integrity::{self, Algorithm, Digests, ExpectedDigest};
+
+// A body found in the store: the file it is kept in, its length and
+// SHA-256
+pub struct Stored {
+    pub path: PathBuf,
+    pub len: u64,
+    pub sha256: Vec<u8>,
+}
+
+// How a stored body was put at an output path
+pub enum Link {
+    // A copy-on-write clone, which has blocks of its own only once changed
+    Reflink,
+    // Another name for the stored file itself
+    HardLink,
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Link::Reflink => write!(f, "reflink"),
+            Link::HardLink => write!(f, "hard link"),
+        }
+    }
+}
+
+// The stored body matching the expected digests, if there is one. A stored
+// file that no longer matches is removed and counts as missing.
+pub fn find(dir: &Path, expected: &[ExpectedDigest]) -> std::io::Result<Option<Stored>> {
+    for digest in expected {
+        let path = path(dir, digest.algorithm, &digest.bytes);
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let mut digests = Digests::new(expected);
+        let len = digests.read_from(file)?;
+        let mut digests = digests.finish();
+        if integrity::check_digests(expected, &digests).is_ok() {
+            let (_, sha256) = digests.swap_remove(0);
+            return Ok(Some(Stored { path, len, sha256 }));
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+    Ok(None)
+}
+
+// The head a stored body stands in for a response with
+pub fn head(stored: &Stored) -> ResponseHead {
+    ResponseHead { status: 200, headers: vec![("Content-Length".to_string(), stored.len.to_string())] }
+}
+
+// Keep a verified body, read from body, under the digests it was hashed to:
+// its SHA-256 and those in the algorithms of the expected digests. Names
+// already in the store are left as they are.
+pub fn add(dir: &Path, body: &mut dyn Read, digests: &[(Algorithm, Vec<u8>)]) -> std::io::Result<()> {
+    let mut first: Option<PathBuf> = None;
+    for (algorithm, digest) in digests {
+        let path = path(dir, *algorithm, digest);
+        if !path.exists() {
+            std::fs::create_dir_all(path.parent().expect("store paths are inside a directory"))?;
+            match &first {
+                Some(first) if std::fs::hard_link(first, &path).is_ok() => {}
+                Some(first) => write_new(&path, &mut File::open(first)?)?,
+                None => write_new(&path, body)?,
+            }
+        }
+        first.get_or_insert(path);
+    }
+    Ok(())
+}
+
+// Give the stored body a new name at path without copying it: a reflink
+// where the file system can clone files, else a hard link. A hard link is
+// the stored file, so it is only right for outputs left as they are. Fails
+// if neither works, such as across file systems, or path already exists.
+pub fn link(stored: &Stored, path: &Path) -> std::io::Result<Link> {
+    match reflink(&stored.path, path) {
+        Ok(()) => Ok(Link::Reflink),
+        Err(_) => std::fs::hard_link(&stored.path, path).map(|()| Link::HardLink),
+    }
+}
+
+// Clone the file's blocks into a new file with the FICLONE ioctl, which
+// Btrfs, XFS and bcachefs support
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> std::io::Result<()> {
+    use std::ffi::{c_int, c_ulong};
+    use std::os::fd::AsRawFd;
+
+    unsafe extern "C" {
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+    const FICLONE: c_ulong = 0x4004_9409;
+
+    let source = File::open(from)?;
+    let target = File::create_new(to)?;
+    // SAFETY: both descriptors are open for the length of the call
+    if unsafe { ioctl(target.as_raw_fd(), FICLONE, source.as_raw_fd()) } < 0 {
+        let error = std::io::Error::last_os_error();
+        drop(target);
+        let _ = std::fs::remove_file(to);
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_from: &Path, _to: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "reflinks are only made on Linux"))
+}
+
+fn path(dir: &Path, algorithm: Algorithm, digest: &[u8]) -> PathBuf {
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    dir.join(algorithm.name()).join(hex)
+}
+
+// Write under a temporary name first, so a reader never finds a file that is
+// there in part
+fn write_new(path: &Path, data: &mut dyn Read) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    if let Err(e) = std::io::copy(data, &mut File::create(&temp)?) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, path)
+}
@@ -0,0 +1,38 @@
+// Fixtures the in-file tests share: a local HTTP server that answers each
+// connection once, and names in the temporary directory that runs of the
+// tests in other processes don't share.
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+
+// Serves http://127.0.0.1:PORT/f on a free port, one request a connection:
+// what answer gives for each request's head is written back before hanging
+// up. Clients that go before the answer is out are no error.
+pub fn serve(answer: impl Fn(&str) -> Vec<u8> + Send + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/f", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for conn in listener.incoming() {
+            let mut conn = conn.unwrap();
+            let mut head = Vec::new();
+            let mut byte = [0];
+            while !head.ends_with(b"\r\n\r\n") && conn.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            let _ = conn.write_all(&answer(&String::from_utf8_lossy(&head)));
+        }
+    });
+    url
+}
+
+// A response with the headers given, each ending in CRLF, and the body
+pub fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+    let head = format!("HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n", status, headers, body.len());
+    [head.as_bytes(), body].concat()
+}
+
+// http_client_NAME_PID in the temporary directory
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("http_client_{}_{}", name, std::process::id()))
+}
@@ -0,0 +1,147 @@
+// Per-phase timing of connections: name resolution, connecting, the TLS
+// handshake, waiting for the first byte and the transfer itself.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+// How long each phase of one exchange took. The phases follow each other, so
+// they add up to the time the exchange took.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Phases {
+    pub dns: Duration,
+    pub connect: Duration,
+    // Connections are plain HTTP, so there is no handshake and this stays zero
+    pub tls: Duration,
+    // Writing the request
+    pub send: Duration,
+    // From the request being sent to the first byte of the response
+    pub wait: Duration,
+    // From the first byte of the response to the last
+    pub transfer: Duration,
+}
+
+impl Phases {
+    // Time to first byte, from the start of the exchange
+    pub fn first_byte(&self) -> Duration {
+        self.dns + self.connect + self.tls + self.send + self.wait
+    }
+
+    pub fn total(&self) -> Duration {
+        self.first_byte() + self.transfer
+    }
+
+    // About how long a packet takes to the server and back: a TCP handshake
+    // is one round trip, and waiting for the first byte at least one when
+    // there was no handshake to time
+    pub fn round_trip(&self) -> Duration {
+        if self.connect.is_zero() { self.wait } else { self.connect }
+    }
+
+    // The phases as a JSON object, in milliseconds
+    pub fn to_json(&self) -> String {
+        let ms = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        format!(
+            "{{\"dns\":{},\"connect\":{},\"tls\":{},\"first_byte\":{},\"transfer\":{},\"total\":{}}}",
+            ms(self.dns), ms(self.connect), ms(self.tls), ms(self.first_byte()), ms(self.transfer), ms(self.total())
+        )
+    }
+}
+
+impl std::ops::Add for Phases {
+    type Output = Phases;
+
+    fn add(self, other: Phases) -> Phases {
+        Phases {
+            dns: self.dns + other.dns,
+            connect: self.connect + other.connect,
+            tls: self.tls + other.tls,
+            send: self.send + other.send,
+            wait: self.wait + other.wait,
+            transfer: self.transfer + other.transfer,
+        }
+    }
+}
+
+// The timings of the connections made so far. Clones share them, so one log
+// can be given to every connection of a session.
+#[derive(Clone, Debug, Default)]
+pub struct TimingLog {
+    connections: Arc<Mutex<Vec<Phases>>>,
+}
+
+impl TimingLog {
+    pub fn new() -> TimingLog {
+        TimingLog::default()
+    }
+
+    pub(crate) fn record(&self, phases: Phases) {
+        self.connections.lock().expect("no thread panics holding the timing log").push(phases);
+    }
+
+    // The connections recorded since the last call, oldest first
+    pub fn take(&self) -> Vec<Phases> {
+        std::mem::take(&mut *self.connections.lock().expect("no thread panics holding the timing log"))
+    }
+}
+
+// Every phase summed over a number of connections
+pub fn aggregate(connections: &[Phases]) -> Phases {
+    connections.iter().fold(Phases::default(), |sum, phases| sum + *phases)
+}
+
+// Marks the phases of one exchange as it happens
+pub(crate) struct Clock {
+    pub(crate) started: SystemTime,
+    start: Instant,
+    resolved: Option<Instant>,
+    connected: Option<Instant>,
+    sent: Option<Instant>,
+    first_byte: Option<Instant>,
+}
+
+impl Clock {
+    pub(crate) fn start() -> Clock {
+        Clock {
+            started: SystemTime::now(),
+            start: Instant::now(),
+            resolved: None,
+            connected: None,
+            sent: None,
+            first_byte: None,
+        }
+    }
+
+    pub(crate) fn resolved(&mut self) {
+        self.resolved = Some(Instant::now());
+    }
+
+    pub(crate) fn connected(&mut self) {
+        self.connected = Some(Instant::now());
+    }
+
+    pub(crate) fn sent(&mut self) {
+        self.sent = Some(Instant::now());
+    }
+
+    // Only the first call counts
+    pub(crate) fn received(&mut self) {
+        self.first_byte.get_or_insert_with(Instant::now);
+    }
+
+    // Split the time since start into phases; unmarked phases took no time
+    pub(crate) fn phases(&self) -> Phases {
+        let end = Instant::now();
+        let resolved = self.resolved.unwrap_or(self.start);
+        let connected = self.connected.unwrap_or(resolved);
+        let sent = self.sent.unwrap_or(connected);
+        let first_byte = self.first_byte.unwrap_or(end);
+        Phases {
+            dns: resolved - self.start,
+            connect: connected - resolved,
+            tls: Duration::ZERO,
+            send: sent - connected,
+            wait: first_byte.saturating_duration_since(sent),
+            transfer: end - first_byte.max(sent),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// TLS client connections, with rustls, for the parts of the crate that need
+// them: so far only DNS over TLS. Certificates are checked against the
+// system's roots unless other roots are given.
+
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::{Arc, OnceLock};
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+// A client configuration trusting the system's roots, loaded once
+pub fn system_config() -> std::io::Result<Arc<ClientConfig>> {
+    static CONFIG: OnceLock<Result<Arc<ClientConfig>, String>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let found = rustls_native_certs::load_native_certs();
+            let mut roots = RootCertStore::empty();
+            let (added, _) = roots.add_parsable_certificates(found.certs);
+            match (added, found.errors.first()) {
+                (0, Some(e)) => Err(format!("Couldn't load the system's root certificates: {}", e)),
+                (0, None) => Err("No root certificates found on the system".to_string()),
+                _ => Ok(config(roots)),
+            }
+        })
+        .clone()
+        .map_err(|e| std::io::Error::new(ErrorKind::NotFound, e))
+}
+
+// A client configuration trusting only the certificates in the PEM text given,
+// such as a private CA's
+pub fn config_with_roots(pem: &[u8]) -> std::io::Result<Arc<ClientConfig>> {
+    let invalid = |e: rustls::pki_types::pem::Error| std::io::Error::new(ErrorKind::InvalidData, format!("Invalid root certificate: {}", e));
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(pem) {
+        roots.add(cert.map_err(invalid)?).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("Invalid root certificate: {}", e)))?;
+    }
+    if roots.is_empty() {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "No root certificates in the PEM given"));
+    }
+    Ok(config(roots))
+}
+
+fn config(roots: RootCertStore) -> Arc<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+// Start TLS over a connection, the certificate having to be for name: a
+// host name, or an IP address in text. The handshake is done before
+// returning, so certificate errors come from here.
+pub fn connect(conn: TcpStream, name: &str, config: Arc<ClientConfig>) -> std::io::Result<TlsStream> {
+    let server_name = ServerName::try_from(name.to_string())
+        .map_err(|_| std::io::Error::new(ErrorKind::InvalidInput, format!("Not a name a certificate can be for: {}", name)))?;
+    let client = ClientConnection::new(config, server_name).map_err(std::io::Error::other)?;
+    let mut stream = StreamOwned::new(client, conn);
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    Ok(stream)
+}
@@ -0,0 +1,120 @@
+// A wire trace like curl -v: request and response heads of every connection
+// go to stderr, and with an ASCII dump everything sent and received, bodies
+// included, is written to a file as well.
+
+use std::fs::File;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::timing::Phases;
+use crate::url::Url;
+
+// Bytes per line of the ASCII dump
+const DUMP_WIDTH: usize = 64;
+
+#[derive(Clone, Debug, Default)]
+pub struct Trace {
+    // Shared by all connections; each block is written under the lock
+    ascii: Option<Arc<Mutex<File>>>,
+}
+
+impl Trace {
+    // Trace heads to stderr
+    pub fn new() -> Trace {
+        Trace::default()
+    }
+
+    // Trace heads to stderr and dump all traffic to a file, replacing it
+    pub fn with_ascii_dump(path: &Path) -> std::io::Result<Trace> {
+        let file = File::create(path)?;
+        Ok(Trace { ascii: Some(Arc::new(Mutex::new(file))) })
+    }
+
+    pub(crate) fn connected(&self, url: &Url, addr: Option<SocketAddr>) {
+        let line = match addr {
+            Some(addr) => format!("* Connected to {} ({}) port {}", url.host, addr.ip(), addr.port()),
+            None => format!("* Connected to {} port {}", url.host, url.port),
+        };
+        eprintln!("{}", line);
+        self.dump_text(&line);
+    }
+
+    // A request as sent, head and any body
+    pub(crate) fn sent(&self, request: &[u8]) {
+        let (head, body) = split_head(request);
+        print_head(">", head);
+        self.dump("=> Send header", head);
+        if !body.is_empty() {
+            self.dump("=> Send data", body);
+        }
+    }
+
+    // A response as received, or as much of it as was read
+    pub(crate) fn received(&self, response: &[u8]) {
+        let (head, body) = split_head(response);
+        print_head("<", head);
+        self.dump("<= Recv header", head);
+        if !body.is_empty() {
+            self.dump("<= Recv data", body);
+        }
+    }
+
+    // How long the phases of the exchange took, once it is over
+    pub(crate) fn timing(&self, phases: &Phases) {
+        let ms = |duration: std::time::Duration| format!("{:.3} ms", duration.as_secs_f64() * 1000.0);
+        let line = format!(
+            "* Timing: dns {}, connect {}, tls {}, first byte {}, transfer {}, total {}",
+            ms(phases.dns), ms(phases.connect), ms(phases.tls), ms(phases.first_byte()), ms(phases.transfer), ms(phases.total())
+        );
+        eprintln!("{}", line);
+        self.dump_text(&line);
+    }
+
+    fn dump_text(&self, line: &str) {
+        if let Some(file) = &self.ascii {
+            let mut file = file.lock().expect("no thread panics holding the trace file");
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+
+    // Write a block in curl's --trace-ascii layout: a title with the size,
+    // then offset-prefixed lines with unprintable bytes shown as dots
+    fn dump(&self, title: &str, data: &[u8]) {
+        let Some(file) = &self.ascii else {
+            return;
+        };
+        let mut text = format!("{}, {} bytes (0x{:x})\n", title, data.len(), data.len());
+        for (n, line) in data.chunks(DUMP_WIDTH).enumerate() {
+            let shown: String = line.iter()
+                .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                .collect();
+            text.push_str(&format!("{:04x}: {}\n", n * DUMP_WIDTH, shown));
+        }
+
+        let mut file = file.lock().expect("no thread panics holding the trace file");
+        let _ = file.write_all(text.as_bytes());
+    }
+}
+
+// Split a message after the blank line ending its head; all head if there is none
+fn split_head(message: &[u8]) -> (&[u8], &[u8]) {
+    match message.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => message.split_at(end + 4),
+        None => (message, &[]),
+    }
+}
+
+// Print a head to stderr, one prefixed line per header, in one write so the
+// heads of different connections don't interleave
+fn print_head(prefix: &str, head: &[u8]) {
+    let text = String::from_utf8_lossy(head);
+    let mut lines = String::new();
+    for line in text.split("\r\n").filter(|line| !line.is_empty()) {
+        lines.push_str(&format!("{} {}\n", prefix, line));
+    }
+    lines.push_str(prefix);
+    lines.push('\n');
+    let _ = std::io::stderr().lock().write_all(lines.as_bytes());
+}
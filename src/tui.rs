@@ -0,0 +1,475 @@
+// The --tui dashboard: the whole terminal given over to the batch, one line
+// per download with its state, bytes, speed and retries, and under them the
+// requests out for the one running, its segment map and the latest messages.
+// It is drawn with plain ANSI escapes on the alternate screen, and keys are
+// read from the terminal put in raw mode with stty, as `auth` turns echo off:
+//
+//   Up/Down or k/j  pick a download
+//   p               pause or resume it; a queued one waits when its turn comes
+//   c               cancel it; a queued one is skipped
+//   q or Ctrl+C     cancel everything left and quit
+//
+// A pause or cancel takes effect between ranges, so within the couple of
+// seconds a range is sized to take. A cancelled download keeps its .part file
+// for `resume`.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use http_client::{Cancelled, ProgressObserver, SegmentMap, StatusReport, Summary};
+
+use crate::url::Url;
+
+// The one dashboard, fed by the command line's progress display
+pub static DASHBOARD: Dashboard = Dashboard::new();
+
+// How often the screen is drawn again
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+// Messages kept for the bottom of the screen
+const MESSAGES: usize = 5;
+
+// The size assumed when stty can't tell
+const DEFAULT_SIZE: (usize, usize) = (24, 80);
+
+// Lines the screen has besides the downloads, and the fewest downloads shown
+const FIXED_LINES: usize = 12 + MESSAGES;
+const MIN_ROWS: usize = 3;
+
+// Switch to the alternate screen and hide the cursor, and back
+const ENTER: &str = "\x1b[?1049h\x1b[?25l";
+const LEAVE: &str = "\x1b[?25h\x1b[?1049l";
+
+pub struct Dashboard {
+    active: AtomicBool,
+    state: Mutex<State>,
+    // Woken when a download is resumed or cancelled
+    changed: Condvar,
+    // The download running, for its requests out and retries
+    report: StatusReport,
+    map: OnceLock<SegmentMap>,
+    // Where the screen is drawn; taken when it is closed
+    out: Mutex<Option<File>>,
+}
+
+struct State {
+    downloads: Vec<Entry>,
+    current: Option<usize>,
+    selected: usize,
+    messages: VecDeque<String>,
+    rows: usize,
+    columns: usize,
+}
+
+struct Entry {
+    url: String,
+    state: EntryState,
+    paused: bool,
+    cancelled: bool,
+    downloaded: u64,
+    total_size: Option<u64>,
+    started: Option<Instant>,
+    elapsed: Duration,
+    retries: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum EntryState {
+    Queued,
+    Running,
+    Done,
+    Skipped,
+    Failed(String),
+    Cancelled,
+}
+
+// What became of a download, for its line
+pub enum Ending<'a> {
+    Done,
+    Skipped,
+    Failed(&'a std::io::Error),
+}
+
+// The dashboard being on screen; closed again when dropped, the terminal put
+// back as it was
+pub struct Screen {
+    tty: File,
+    saved: String,
+}
+
+// Whether the dashboard is on screen
+pub fn active() -> bool {
+    DASHBOARD.active.load(Ordering::Relaxed)
+}
+
+impl Screen {
+    // Take over the terminal for a batch of these downloads
+    pub fn open(urls: &[Url]) -> std::io::Result<Screen> {
+        let tty = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/tty")
+            .map_err(|e| std::io::Error::new(e.kind(), format!("--tui needs a terminal: {}", e)))?;
+        let saved = stty(&tty, &["-g"])?.trim().to_string();
+        let (rows, columns) = stty(&tty, &["size"]).ok()
+            .and_then(|size| {
+                let (rows, columns) = size.trim().split_once(' ')?;
+                Some((rows.parse().ok()?, columns.parse().ok()?))
+            })
+            .filter(|&(rows, columns)| rows > 0 && columns > 0)
+            .unwrap_or(DEFAULT_SIZE);
+        // Keys arrive one at a time and unechoed, Ctrl+C among them
+        stty(&tty, &["-icanon", "-echo", "-isig", "min", "1", "time", "0"])?;
+
+        {
+            let mut state = DASHBOARD.state.lock().expect("no thread panics holding the dashboard");
+            state.downloads = urls.iter().map(|url| Entry::new(url.to_string())).collect();
+            state.rows = rows;
+            state.columns = columns;
+        }
+        let _ = DASHBOARD.map.set(SegmentMap::new(columns.saturating_sub(4)));
+        let mut out = tty.try_clone()?;
+        out.write_all(ENTER.as_bytes())?;
+        *DASHBOARD.out.lock().expect("no thread panics drawing the dashboard") = Some(out);
+        DASHBOARD.active.store(true, Ordering::Relaxed);
+
+        let keys = tty.try_clone()?;
+        std::thread::spawn(move || DASHBOARD.read_keys(keys));
+        std::thread::spawn(|| {
+            while DASHBOARD.draw() {
+                std::thread::sleep(REDRAW_INTERVAL);
+            }
+        });
+        Ok(Screen { tty, saved })
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        DASHBOARD.active.store(false, Ordering::Relaxed);
+        if let Some(mut out) = DASHBOARD.out.lock().expect("no thread panics drawing the dashboard").take() {
+            let _ = out.write_all(LEAVE.as_bytes());
+        }
+        let _ = stty(&self.tty, &[&self.saved]);
+    }
+}
+
+// Run stty on the terminal, returning what it printed
+fn stty(tty: &File, args: &[&str]) -> std::io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::from(tty.try_clone()?)).stderr(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other("--tui needs a terminal stty can set up"));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Entry {
+    fn new(url: String) -> Entry {
+        Entry {
+            url,
+            state: EntryState::Queued,
+            paused: false,
+            cancelled: false,
+            downloaded: 0,
+            total_size: None,
+            started: None,
+            elapsed: Duration::ZERO,
+            retries: 0,
+        }
+    }
+
+    fn speed(&self) -> f64 {
+        let elapsed = match (&self.state, self.started) {
+            (EntryState::Running, Some(started)) if !self.paused => started.elapsed(),
+            _ => self.elapsed,
+        };
+        self.downloaded as f64 / elapsed.as_secs_f64().max(1e-6)
+    }
+
+    fn label(&self) -> &str {
+        match &self.state {
+            _ if self.cancelled && self.state == EntryState::Queued => "cancelled",
+            _ if self.paused && matches!(self.state, EntryState::Queued | EntryState::Running) => "paused",
+            EntryState::Queued => "queued",
+            EntryState::Running => "running",
+            EntryState::Done => "done",
+            EntryState::Skipped => "skipped",
+            EntryState::Failed(_) => "failed",
+            EntryState::Cancelled => "cancelled",
+        }
+    }
+}
+
+impl Dashboard {
+    const fn new() -> Dashboard {
+        Dashboard {
+            active: AtomicBool::new(false),
+            state: Mutex::new(State {
+                downloads: Vec::new(),
+                current: None,
+                selected: 0,
+                messages: VecDeque::new(),
+                rows: DEFAULT_SIZE.0,
+                columns: DEFAULT_SIZE.1,
+            }),
+            changed: Condvar::new(),
+            report: StatusReport::new(),
+            map: OnceLock::new(),
+            out: Mutex::new(None),
+        }
+    }
+
+    // A status message, shown at the bottom instead of printed
+    pub fn log(&self, message: String) {
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        state.messages.push_back(message);
+        while state.messages.len() > MESSAGES {
+            state.messages.pop_front();
+        }
+    }
+
+    // The download at index is next. Waits while it is paused; an error if
+    // it was cancelled before it could start.
+    pub fn begin(&self, index: usize) -> std::io::Result<()> {
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        state.current = Some(index);
+        let mut state = self.changed
+            .wait_while(state, |state| state.downloads[index].paused && !state.downloads[index].cancelled)
+            .expect("no thread panics holding the dashboard");
+        let entry = &mut state.downloads[index];
+        if entry.cancelled {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "Cancelled before it started"));
+        }
+        entry.state = EntryState::Running;
+        entry.started = Some(Instant::now());
+        Ok(())
+    }
+
+    // Between ranges of the download running: waits while it is paused, and
+    // an error once it is cancelled
+    pub fn checkpoint(&self, downloaded: u64, total_size: u64) -> std::io::Result<()> {
+        if !active() {
+            return Ok(());
+        }
+        let state = self.state.lock().expect("no thread panics holding the dashboard");
+        let Some(index) = state.current else {
+            return Ok(());
+        };
+        let state = self.changed
+            .wait_while(state, |state| state.downloads[index].paused && !state.downloads[index].cancelled)
+            .expect("no thread panics holding the dashboard");
+        if state.downloads[index].cancelled {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, Cancelled { downloaded, total_size }));
+        }
+        Ok(())
+    }
+
+    // The download at index is over
+    pub fn end(&self, index: usize, ending: Ending) {
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        state.current = None;
+        let entry = &mut state.downloads[index];
+        entry.elapsed = entry.started.map_or(Duration::ZERO, |started| started.elapsed());
+        entry.state = match ending {
+            Ending::Done => EntryState::Done,
+            Ending::Skipped => EntryState::Skipped,
+            Ending::Failed(e) if e.kind() == std::io::ErrorKind::Interrupted => EntryState::Cancelled,
+            Ending::Failed(e) => EntryState::Failed(e.to_string()),
+        };
+        // The selection follows the batch along unless it was moved
+        if state.selected == index && index + 1 < state.downloads.len() {
+            state.selected = index + 1;
+        }
+    }
+
+    fn read_keys(&self, mut tty: File) {
+        let mut escape = Vec::new();
+        let mut byte = [0];
+        while active() && tty.read(&mut byte).is_ok_and(|len| len == 1) {
+            // Arrow keys come as ESC [ A and ESC [ B
+            match (escape.as_slice(), byte[0]) {
+                ([], 0x1b) | ([0x1b], b'[') => {
+                    escape.push(byte[0]);
+                    continue;
+                }
+                ([0x1b, b'['], b'A') => self.select(-1),
+                ([0x1b, b'['], b'B') => self.select(1),
+                (_, b'k') => self.select(-1),
+                (_, b'j') => self.select(1),
+                (_, b'p') => self.toggle_pause(),
+                (_, b'c') => self.cancel(false),
+                (_, b'q' | 0x03) => self.cancel(true),
+                _ => {}
+            }
+            escape.clear();
+        }
+    }
+
+    fn select(&self, step: isize) {
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        let last = state.downloads.len().saturating_sub(1);
+        state.selected = state.selected.saturating_add_signed(step).min(last);
+    }
+
+    fn toggle_pause(&self) {
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        let selected = state.selected;
+        if let Some(entry) = state.downloads.get_mut(selected)
+            && matches!(entry.state, EntryState::Queued | EntryState::Running)
+        {
+            entry.paused = !entry.paused;
+            // Time paused doesn't count against the speed
+            if let Some(started) = &mut entry.started {
+                entry.elapsed = started.elapsed();
+                if !entry.paused {
+                    *started = Instant::now() - entry.elapsed;
+                }
+            }
+        }
+        self.changed.notify_all();
+    }
+
+    // Cancel the selected download, or with all every one not over yet
+    fn cancel(&self, all: bool) {
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        let selected = state.selected;
+        for (index, entry) in state.downloads.iter_mut().enumerate() {
+            if (all || index == selected) && matches!(entry.state, EntryState::Queued | EntryState::Running) {
+                entry.cancelled = true;
+            }
+        }
+        self.changed.notify_all();
+    }
+
+    // Draw the screen; false once it has been closed
+    fn draw(&self) -> bool {
+        let mut out = self.out.lock().expect("no thread panics drawing the dashboard");
+        let Some(out) = out.as_mut() else {
+            return false;
+        };
+        let frame = self.render();
+        out.write_all(frame.as_bytes()).and_then(|()| out.flush()).is_ok()
+    }
+
+    fn render(&self) -> String {
+        let state = self.state.lock().expect("no thread panics holding the dashboard");
+        let mut lines = Vec::new();
+
+        let count = |label: &str| state.downloads.iter().filter(|entry| entry.label() == label).count();
+        lines.push(format!(
+            "http_client: {} downloads, {} done, {} running, {} paused, {} queued, {} failed, {} cancelled",
+            state.downloads.len(), count("done"), count("running"), count("paused"), count("queued"), count("failed"), count("cancelled")
+        ));
+        lines.push(String::new());
+        lines.push(format!("  {:>3} {:<9} {:>17} {:>6} {:>10} {:>7}  URL", "#", "State", "Bytes", "Done", "Speed", "Retries"));
+
+        // As many downloads as fit, scrolled to keep the selected one in view
+        let shown = state.rows.saturating_sub(FIXED_LINES).max(MIN_ROWS);
+        let first = (state.selected + 1).saturating_sub(shown);
+        for (index, entry) in state.downloads.iter().enumerate().skip(first).take(shown) {
+            let total = entry.total_size.map_or("?".to_string(), |total| total.to_string());
+            let percent = match entry.total_size {
+                Some(total) if total > 0 => format!("{:.1}%", entry.downloaded as f64 * 100.0 / total as f64),
+                _ => "-".to_string(),
+            };
+            let line = format!(
+                "{} {:>3} {:<9} {:>17} {:>6} {:>5.2} MB/s {:>7}  {}",
+                if index == state.selected { ">" } else { " " },
+                index + 1, entry.label(), format!("{}/{}", entry.downloaded, total), percent,
+                entry.speed() / 1_000_000.0, entry.retries, entry.url
+            );
+            lines.push(if index == state.selected { format!("\x1b[7m{}\x1b[0m", fit(&line, state.columns)) } else { line });
+        }
+        if let Some(EntryState::Failed(reason)) = state.downloads.get(state.selected).map(|entry| &entry.state) {
+            lines.push(format!("    failed: {}", reason));
+        }
+        lines.push(String::new());
+
+        match state.current {
+            Some(index) => {
+                lines.push(format!("Download {}:", index + 1));
+                // Everything but the summary line, which the table has
+                lines.extend(self.report.render().lines().skip(1).map(|line| format!("  {}", line)));
+                if let Some(map) = self.map.get() {
+                    lines.push(format!("  [{}]", map.render()));
+                }
+            }
+            None => lines.push("Nothing downloading".to_string()),
+        }
+        lines.push(String::new());
+        lines.extend(state.messages.iter().map(|message| format!("  {}", message)));
+        lines.push(String::new());
+        lines.push("Up/Down select   p pause/resume   c cancel   q cancel all and quit".to_string());
+
+        let mut frame = String::from("\x1b[H");
+        for line in lines.iter().take(state.rows) {
+            frame.push_str(&fit(line, state.columns));
+            frame.push_str("\x1b[K\r\n");
+        }
+        frame.push_str("\x1b[J");
+        frame
+    }
+}
+
+// A line cut to the width of the screen. Highlighted lines are cut before
+// the highlight goes on, so escapes are never cut.
+fn fit(line: &str, columns: usize) -> String {
+    if line.starts_with('\x1b') {
+        return line.to_string();
+    }
+    line.chars().take(columns).collect()
+}
+
+impl ProgressObserver for Dashboard {
+    fn on_started(&self, url: &Url, total_size: u64) {
+        self.report.on_started(url, total_size);
+        if let Some(map) = self.map.get() {
+            map.on_started(url, total_size);
+        }
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        if let Some(index) = state.current {
+            state.downloads[index].total_size = Some(total_size);
+        }
+    }
+
+    fn on_request(&self, offset: u64, len: u64) {
+        self.report.on_request(offset, len);
+        if let Some(map) = self.map.get() {
+            map.on_request(offset, len);
+        }
+    }
+
+    fn on_chunk(&self, offset: u64, len: usize, downloaded: u64, total_size: u64) {
+        self.report.on_chunk(offset, len, downloaded, total_size);
+        if let Some(map) = self.map.get() {
+            map.on_chunk(offset, len, downloaded, total_size);
+        }
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        if let Some(index) = state.current {
+            state.downloads[index].downloaded = downloaded;
+        }
+    }
+
+    fn on_retry(&self, offset: u64, attempt: usize, reason: &str) {
+        self.report.on_retry(offset, attempt, reason);
+        if let Some(map) = self.map.get() {
+            map.on_retry(offset, attempt, reason);
+        }
+        let mut state = self.state.lock().expect("no thread panics holding the dashboard");
+        if let Some(index) = state.current {
+            state.downloads[index].retries += 1;
+        }
+    }
+
+    fn on_finished(&self, result: Result<&Summary, &std::io::Error>) {
+        self.report.on_finished(result);
+        if let Some(map) = self.map.get() {
+            map.on_finished(result);
+        }
+    }
+}
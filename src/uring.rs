@@ -0,0 +1,434 @@
+// A minimal io_uring on Linux, made with the raw system calls: reads from
+// sockets and writes to files in buffers registered once with the kernel,
+// each operation tagged with a number that comes back with its completion.
+// Elsewhere Ring::new fails with Unsupported and nothing else can be reached.
+
+use std::fs::File;
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_int, c_long, c_void};
+
+    unsafe extern "C" {
+        pub fn syscall(number: c_long, ...) -> c_long;
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+
+    // The same on every architecture
+    pub const IO_URING_SETUP: c_long = 425;
+    pub const IO_URING_ENTER: c_long = 426;
+    pub const IO_URING_REGISTER: c_long = 427;
+
+    pub const IORING_OFF_SQ_RING: i64 = 0;
+    pub const IORING_OFF_CQ_RING: i64 = 0x8000000;
+    pub const IORING_OFF_SQES: i64 = 0x10000000;
+    pub const IORING_ENTER_GETEVENTS: u32 = 1;
+    pub const IORING_REGISTER_BUFFERS: u32 = 0;
+
+    pub const IORING_OP_READ_FIXED: u8 = 4;
+    pub const IORING_OP_WRITE_FIXED: u8 = 5;
+    pub const IORING_OP_TIMEOUT: u8 = 11;
+    pub const IORING_OP_TIMEOUT_REMOVE: u8 = 12;
+    pub const IORING_OP_LINK_TIMEOUT: u8 = 15;
+    // The next entry only starts once this one has finished
+    pub const IOSQE_IO_LINK: u8 = 1 << 2;
+
+    pub const PROT_READ_WRITE: c_int = 0x1 | 0x2;
+    pub const MAP_SHARED_POPULATE: c_int = 0x01 | 0x8000;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct SqringOffsets {
+        pub head: u32,
+        pub tail: u32,
+        pub ring_mask: u32,
+        pub ring_entries: u32,
+        pub flags: u32,
+        pub dropped: u32,
+        pub array: u32,
+        pub resv1: u32,
+        pub user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct CqringOffsets {
+        pub head: u32,
+        pub tail: u32,
+        pub ring_mask: u32,
+        pub ring_entries: u32,
+        pub overflow: u32,
+        pub cqes: u32,
+        pub flags: u32,
+        pub resv1: u32,
+        pub user_addr: u64,
+    }
+
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Params {
+        pub sq_entries: u32,
+        pub cq_entries: u32,
+        pub flags: u32,
+        pub sq_thread_cpu: u32,
+        pub sq_thread_idle: u32,
+        pub features: u32,
+        pub wq_fd: u32,
+        pub resv: [u32; 3],
+        pub sq_off: SqringOffsets,
+        pub cq_off: CqringOffsets,
+    }
+
+    // struct io_uring_sqe, with the unions as the fields used here
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct Sqe {
+        pub opcode: u8,
+        pub flags: u8,
+        pub ioprio: u16,
+        pub fd: i32,
+        pub off: u64,
+        pub addr: u64,
+        pub len: u32,
+        pub op_flags: u32,
+        pub user_data: u64,
+        pub buf_index: u16,
+        pub personality: u16,
+        pub splice_fd_in: i32,
+        pub addr3: u64,
+        pub pad: u64,
+    }
+
+    #[repr(C)]
+    pub struct Cqe {
+        pub user_data: u64,
+        pub res: i32,
+        pub flags: u32,
+    }
+
+    #[repr(C)]
+    pub struct Iovec {
+        pub base: *mut c_void,
+        pub len: usize,
+    }
+}
+
+// struct __kernel_timespec, for the timeouts
+#[repr(C)]
+pub(crate) struct Timespec {
+    secs: i64,
+    nanos: i64,
+}
+
+impl From<Duration> for Timespec {
+    fn from(duration: Duration) -> Timespec {
+        Timespec { secs: duration.as_secs().min(i64::MAX as u64) as i64, nanos: i64::from(duration.subsec_nanos()) }
+    }
+}
+
+// What an operation ended with: its tag, and the byte count or the error
+pub(crate) struct Completion {
+    pub(crate) tag: u64,
+    pub(crate) result: std::io::Result<usize>,
+}
+
+// Whether an operation ended because its linked timeout fired first, which
+// cancels it with ECANCELED
+pub(crate) fn timed_out(error: &std::io::Error) -> bool {
+    error.raw_os_error() == Some(125)
+}
+
+// Shared memory the kernel maps for a ring, unmapped on drop
+#[cfg(target_os = "linux")]
+struct Mapping {
+    addr: *mut u8,
+    len: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl Mapping {
+    fn new(fd: std::ffi::c_int, len: usize, offset: i64) -> std::io::Result<Mapping> {
+        // SAFETY: a fresh shared mapping of the ring's memory, at an address
+        // the system picks
+        let addr = unsafe {
+            sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ_WRITE, sys::MAP_SHARED_POPULATE, fd, offset)
+        };
+        if addr == sys::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Mapping { addr: addr.cast(), len })
+    }
+
+    // The u32 at the offset, which the kernel reads or writes concurrently
+    fn atomic(&self, offset: u32) -> &std::sync::atomic::AtomicU32 {
+        // SAFETY: the kernel's offsets are in range and 4-byte aligned
+        unsafe { &*self.addr.add(offset as usize).cast() }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was made by Mapping::new and is used no more
+        unsafe { sys::munmap(self.addr.cast(), self.len) };
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) struct Ring {
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    params: sys::Params,
+    // Entries queued since the last submit
+    queued: u32,
+    // Closed last, after the mappings
+    fd: std::os::fd::OwnedFd,
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct Ring(std::convert::Infallible);
+
+#[cfg(target_os = "linux")]
+impl Ring {
+    // A ring with room for at least this many operations at once
+    pub(crate) fn new(entries: u32) -> std::io::Result<Ring> {
+        use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+        let mut params = sys::Params::default();
+        // SAFETY: params is the struct io_uring_params the call fills in
+        let fd = unsafe { sys::syscall(sys::IO_URING_SETUP, entries, &raw mut params) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: the descriptor was just returned and nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as std::ffi::c_int) };
+        let raw = fd.as_raw_fd();
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<sys::Cqe>();
+        let sqes_len = params.sq_entries as usize * size_of::<sys::Sqe>();
+        Ok(Ring {
+            sq: Mapping::new(raw, sq_len, sys::IORING_OFF_SQ_RING)?,
+            cq: Mapping::new(raw, cq_len, sys::IORING_OFF_CQ_RING)?,
+            sqes: Mapping::new(raw, sqes_len, sys::IORING_OFF_SQES)?,
+            params,
+            queued: 0,
+            fd,
+        })
+    }
+
+    // Hand the buffers to the kernel once, so fixed reads and writes can use
+    // them by index without mapping them each time.
+    //
+    // SAFETY: the buffers must stay where they are, and not be touched while
+    // an operation on them is in flight, until the ring is dropped
+    pub(crate) unsafe fn register_buffers(&self, buffers: &mut [Box<[u8]>]) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        let iovecs: Vec<sys::Iovec> = buffers.iter_mut()
+            .map(|buffer| sys::Iovec { base: buffer.as_mut_ptr().cast(), len: buffer.len() })
+            .collect();
+        // SAFETY: the iovecs describe live buffers and are read during the call
+        let result = unsafe {
+            sys::syscall(sys::IO_URING_REGISTER, self.fd.as_raw_fd(), sys::IORING_REGISTER_BUFFERS, iovecs.as_ptr(), iovecs.len() as u32)
+        };
+        if result < 0 { Err(std::io::Error::last_os_error()) } else { Ok(()) }
+    }
+
+    // Queue a read from the connection into registered buffer index, at most
+    // len bytes from start. With a timeout the read is cancelled if nothing
+    // arrives in time, and the timeout's completion comes back tagged as well.
+    //
+    // SAFETY: the range must be inside the registered buffer, and the
+    // timeout must stay in place until submit has returned
+    pub(crate) unsafe fn read_fixed(
+        &mut self,
+        conn: &TcpStream,
+        buffer: &mut [u8],
+        index: u16,
+        tag: u64,
+        timeout: Option<(&Timespec, u64)>,
+    ) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        self.push(sys::Sqe {
+            opcode: sys::IORING_OP_READ_FIXED,
+            flags: if timeout.is_some() { sys::IOSQE_IO_LINK } else { 0 },
+            fd: conn.as_raw_fd(),
+            addr: buffer.as_mut_ptr() as u64,
+            len: buffer.len() as u32,
+            buf_index: index,
+            user_data: tag,
+            ..sys::Sqe::default()
+        })?;
+        if let Some((timeout, tag)) = timeout {
+            self.push(sys::Sqe {
+                opcode: sys::IORING_OP_LINK_TIMEOUT,
+                addr: (&raw const *timeout) as u64,
+                len: 1,
+                user_data: tag,
+                ..sys::Sqe::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    // Queue a write of the part of registered buffer index to the file at
+    // the offset
+    //
+    // SAFETY: the part must be inside the registered buffer
+    pub(crate) unsafe fn write_fixed(&mut self, file: &File, data: &[u8], index: u16, offset: u64, tag: u64) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        self.push(sys::Sqe {
+            opcode: sys::IORING_OP_WRITE_FIXED,
+            fd: file.as_raw_fd(),
+            off: offset,
+            addr: data.as_ptr() as u64,
+            len: data.len() as u32,
+            buf_index: index,
+            user_data: tag,
+            ..sys::Sqe::default()
+        })
+    }
+
+    // Queue a timer that completes once the time has passed
+    //
+    // SAFETY: the time must stay in place until submit has returned
+    pub(crate) unsafe fn timeout(&mut self, after: &Timespec, tag: u64) -> std::io::Result<()> {
+        self.push(sys::Sqe {
+            opcode: sys::IORING_OP_TIMEOUT,
+            addr: (&raw const *after) as u64,
+            len: 1,
+            user_data: tag,
+            ..sys::Sqe::default()
+        })
+    }
+
+    // Queue the removal of the timer queued with the target tag, which then
+    // completes at once with ECANCELED
+    pub(crate) fn remove_timeout(&mut self, target: u64, tag: u64) -> std::io::Result<()> {
+        self.push(sys::Sqe {
+            opcode: sys::IORING_OP_TIMEOUT_REMOVE,
+            addr: target,
+            user_data: tag,
+            ..sys::Sqe::default()
+        })
+    }
+
+    fn push(&mut self, sqe: sys::Sqe) -> std::io::Result<()> {
+        use std::sync::atomic::Ordering;
+
+        let head = self.sq.atomic(self.params.sq_off.head).load(Ordering::Acquire);
+        let tail = self.sq.atomic(self.params.sq_off.tail).load(Ordering::Relaxed);
+        if tail.wrapping_sub(head) >= self.params.sq_entries {
+            return Err(std::io::Error::other("io_uring submission queue is full"));
+        }
+        let mask = self.sq.atomic(self.params.sq_off.ring_mask).load(Ordering::Relaxed);
+        let index = tail & mask;
+        // SAFETY: index is within the entries and the array, which only the
+        // kernel reads, and only once the tail has moved past them
+        unsafe {
+            self.sqes.addr.cast::<sys::Sqe>().add(index as usize).write(sqe);
+            self.sq.addr.add(self.params.sq_off.array as usize).cast::<u32>().add(index as usize).write(index);
+        }
+        self.sq.atomic(self.params.sq_off.tail).store(tail.wrapping_add(1), Ordering::Release);
+        self.queued += 1;
+        Ok(())
+    }
+
+    // Submit what was queued and wait until at least wait operations have
+    // completed
+    pub(crate) fn submit_and_wait(&mut self, wait: u32) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        loop {
+            // SAFETY: no pointers are passed besides the null signal mask
+            let result = unsafe {
+                sys::syscall(
+                    sys::IO_URING_ENTER, self.fd.as_raw_fd(), self.queued, wait, sys::IORING_ENTER_GETEVENTS,
+                    std::ptr::null::<std::ffi::c_void>(), 0usize,
+                )
+            };
+            if result >= 0 {
+                self.queued -= (result as u32).min(self.queued);
+                return Ok(());
+            }
+            let error = std::io::Error::last_os_error();
+            if error.kind() != std::io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    // Every completion that has come in since last time
+    pub(crate) fn completions(&mut self) -> Vec<Completion> {
+        use std::sync::atomic::Ordering;
+
+        let head = self.cq.atomic(self.params.cq_off.head).load(Ordering::Relaxed);
+        let tail = self.cq.atomic(self.params.cq_off.tail).load(Ordering::Acquire);
+        let mask = self.cq.atomic(self.params.cq_off.ring_mask).load(Ordering::Relaxed);
+        let completions = (0..tail.wrapping_sub(head))
+            .map(|i| {
+                // SAFETY: the entries between head and tail are the kernel's
+                // finished ones, in range once masked
+                let cqe = unsafe {
+                    self.cq.addr.add(self.params.cq_off.cqes as usize)
+                        .cast::<sys::Cqe>()
+                        .add((head.wrapping_add(i) & mask) as usize)
+                        .read()
+                };
+                let result = if cqe.res < 0 { Err(std::io::Error::from_raw_os_error(-cqe.res)) } else { Ok(cqe.res as usize) };
+                Completion { tag: cqe.user_data, result }
+            })
+            .collect();
+        self.cq.atomic(self.params.cq_off.head).store(tail, Ordering::Release);
+        completions
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Ring {
+    pub(crate) fn new(_entries: u32) -> std::io::Result<Ring> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "io_uring is only available on Linux"))
+    }
+
+    pub(crate) unsafe fn register_buffers(&self, _buffers: &mut [Box<[u8]>]) -> std::io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) unsafe fn read_fixed(
+        &mut self,
+        _conn: &TcpStream,
+        _buffer: &mut [u8],
+        _index: u16,
+        _tag: u64,
+        _timeout: Option<(&Timespec, u64)>,
+    ) -> std::io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) unsafe fn write_fixed(&mut self, _file: &File, _data: &[u8], _index: u16, _offset: u64, _tag: u64) -> std::io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) unsafe fn timeout(&mut self, _after: &Timespec, _tag: u64) -> std::io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) fn remove_timeout(&mut self, _target: u64, _tag: u64) -> std::io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) fn submit_and_wait(&mut self, _wait: u32) -> std::io::Result<()> {
+        match self.0 {}
+    }
+
+    pub(crate) fn completions(&mut self) -> Vec<Completion> {
+        match self.0 {}
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt;
+
+// How the error for a URL that isn't http:// starts, https:// included as
+// there is no TLS
+pub const UNSUPPORTED_SCHEME: &str = "Unsupported URL scheme";
+
+// A parsed http:// URL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    // Host name or address, without the brackets around IPv6 literals
+    pub host: String,
+    pub port: u16,
+    // Path and query, always starting with '/'
+    pub path: String,
+}
+
+impl Url {
+    // Parse an absolute URL. A missing scheme is taken to mean http://
+    pub fn parse(input: &str) -> Result<Url, String> {
+        let rest = match input.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) => return Err(format!("{}: {}", UNSUPPORTED_SCHEME, scheme)),
+            None => input,
+        };
+
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let path = if path.starts_with('?') { format!("/{}", path) } else { path.to_string() };
+        // Fragments are never sent to the server
+        let path = path.split('#').next().unwrap_or("/").to_string();
+
+        // Credentials in the authority are not supported, only host and port
+        if authority.contains('@') {
+            return Err(format!("Credentials in URLs are not supported: {}", input));
+        }
+
+        let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+            let (host, after) = v6.split_once(']').ok_or(format!("Invalid IPv6 host in URL: {}", input))?;
+            (host, after.strip_prefix(':'))
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(format!("Missing host in URL: {}", input));
+        }
+        let port = match port {
+            Some(port) => port.parse::<u16>().map_err(|_| format!("Invalid port in URL: {}", input))?,
+            None => 80,
+        };
+
+        Ok(Url { host: host.to_string(), port, path })
+    }
+
+    // Value for the Host header: the host, plus the port when it isn't 80
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        if self.port == 80 { host } else { format!("{}:{}", host, self.port) }
+    }
+
+    // The host and port, the port even when it is 80, as CONNECT wants them
+    pub fn authority_with_port(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        format!("{}:{}", host, self.port)
+    }
+
+    // The path without the query string, split into its segments
+    pub fn segments(&self) -> Vec<&str> {
+        let path = self.path.split('?').next().unwrap_or("");
+        path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    // Last path segment, None when the path ends in '/'
+    pub fn file_name(&self) -> Option<&str> {
+        let path = self.path.split('?').next().unwrap_or("");
+        if path.ends_with('/') { None } else { self.segments().last().copied() }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority(), self.path)
+    }
+}
+
+// Percent-encode everything but the unreserved characters
+pub fn percent_encode(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Undo percent-encoding, leaving malformed escapes as they are
+pub fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::path::Path;
+
+use crate::http::{self, ResponseHead};
+use crate::integrity::{self, Algorithm, ExpectedDigest, Hasher};
+use crate::md5;
+use crate::url::Url;
+use crate::xattr;
+
+// Re-hash a local file and compare it against everything the server says
+// about the URL (size, Repr-Digest/Digest, Content-MD5, ETag), without
+// downloading the body. Provenance recorded by --xattr and digests given on
+// the command line are checked too. Returns whether every check passed.
+pub fn verify(file: &Path, url: &Url, expected: &[ExpectedDigest], settings: &http::Settings) -> std::io::Result<bool> {
+    let local = File::open(file)?;
+
+    // Fall back to a GET probe for servers that don't implement HEAD
+    let head = match http::head(url, settings)? {
+        head if head.status == 405 || head.status == 501 => http::probe(url, settings)?,
+        head => head,
+    };
+    if !(200..300).contains(&head.status) {
+        return Err(std::io::Error::other(format!("Server answered {} for {}", head.status, url)));
+    }
+
+    // One pass over the file, in every algorithm that something is to be
+    // compared in
+    let advertised = advertised_digests(&head);
+    let mut algorithms = vec![Algorithm::Sha256];
+    let named = advertised.iter().filter_map(|(_, name, _)| Algorithm::from_name(&name.replace('-', "")));
+    for algorithm in named.chain(expected.iter().map(|digest| digest.algorithm)) {
+        if !algorithms.contains(&algorithm) {
+            algorithms.push(algorithm);
+        }
+    }
+    let mut hashers: Vec<Hasher> = algorithms.into_iter().map(Hasher::new).collect();
+    let mut md5 = md5::Md5::new();
+    let size = crate::read_pieces(local, |piece| {
+        hashers.iter_mut().for_each(|hasher| hasher.update(piece));
+        md5.update(piece);
+        Ok(())
+    })?;
+    let digests: Vec<(Algorithm, Vec<u8>)> = hashers.into_iter().map(|hasher| (hasher.algorithm(), hasher.finish())).collect();
+    let digest = |algorithm: Algorithm| digests.iter().find(|(hashed, _)| *hashed == algorithm).map(|(_, digest)| digest);
+    let sha256 = digest(Algorithm::Sha256).expect("SHA-256 is always hashed");
+    let md5 = md5.finish();
+    let mut report = Report::default();
+
+    if let Some(length) = head.content_length() {
+        report.check("Size", length == size, format!("local {} bytes, remote {}", size, length));
+    }
+
+    for (header, name, advertised) in advertised {
+        let local = match name.as_str() {
+            "md5" => md5.to_vec(),
+            name => match Algorithm::from_name(&name.replace('-', "")).and_then(digest) {
+                Some(digest) => digest.clone(),
+                None => continue,
+            },
+        };
+        report.check(header, local == advertised, format!("{} {}", name, integrity::encode_base64(&advertised)));
+    }
+
+    if let Some(content_md5) = head.header("Content-MD5").and_then(integrity::decode_base64) {
+        report.check("Content-MD5", content_md5 == md5, integrity::encode_base64(&content_md5));
+    }
+
+    if let Some(etag) = head.header("ETag") {
+        let recorded = xattr::get(file, xattr::ETAG)?;
+        // S3 and others use the MD5 of single-part uploads as the ETag
+        let etag_md5 = integrity::decode_hex(etag.trim_matches('"')).filter(|bytes| bytes.len() == 16);
+
+        match (recorded, etag_md5) {
+            (Some(recorded), _) => report.check(
+                "ETag",
+                recorded == etag.as_bytes(),
+                format!("recorded {}, remote {}", String::from_utf8_lossy(&recorded), etag),
+            ),
+            (None, Some(etag_md5)) if etag_md5 == md5 => report.check("ETag", true, format!("{} is the MD5", etag)),
+            _ => println!("{:<14} SKIPPED ({} is not comparable)", "ETag", etag),
+        }
+    }
+
+    if let Some(recorded) = xattr::get(file, xattr::SHA256)? {
+        let recorded = String::from_utf8_lossy(&recorded);
+        let local: String = sha256.iter().map(|b| format!("{:02x}", b)).collect();
+        report.check("Recorded hash", recorded == local, format!("sha256 {}", recorded));
+    }
+
+    if !expected.is_empty() {
+        let result = integrity::check_digests(expected, &digests);
+        let detail = match &result {
+            Ok(checked) => checked.iter().map(|a| a.name()).collect::<Vec<_>>().join(", "),
+            Err(e) => e.to_string(),
+        };
+        report.check("Expected", result.is_ok(), detail);
+    }
+
+    if report.checks == 0 {
+        println!("Nothing to compare {} against", file.display());
+        return Ok(false);
+    }
+
+    println!("{} of {} checks passed", report.checks - report.failures, report.checks);
+    Ok(report.failures == 0)
+}
+
+#[derive(Default)]
+struct Report {
+    checks: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, name: &str, ok: bool, detail: String) {
+        self.checks += 1;
+        if !ok {
+            self.failures += 1;
+        }
+        println!("{:<14} {} ({})", name, if ok { "OK" } else { "MISMATCH" }, detail);
+    }
+}
+
+// Digests from Repr-Digest (RFC 9530, "sha-256=:<base64>:") and the older
+// Digest header (RFC 3230, "SHA-256=<base64>"), as (header, algorithm, bytes)
+fn advertised_digests(head: &ResponseHead) -> Vec<(&'static str, String, Vec<u8>)> {
+    let mut digests = Vec::new();
+
+    for header in ["Repr-Digest", "Digest"] {
+        let Some(value) = head.header(header) else { continue };
+
+        for item in value.split(',') {
+            let Some((name, encoded)) = item.trim().split_once('=') else { continue };
+            let encoded = encoded.trim().trim_matches(':');
+            if let Some(bytes) = integrity::decode_base64(encoded) {
+                digests.push((header, name.trim().to_ascii_lowercase(), bytes));
+            }
+        }
+    }
+
+    digests
+}
@@ -0,0 +1,224 @@
+// WebAssembly entry points, for running the chunking and verification in a
+// browser. The page does the requests itself with the Fetch API (see
+// wasm/http_client.js) and the engine here tells it which ranges to ask for
+// and what to make of what came back. Build with
+//
+//     cargo rustc --lib --release --target wasm32-unknown-unknown --crate-type cdylib
+//
+// Strings and bodies are passed through memory from shc_alloc; functions
+// that can fail put a message where shc_error_ptr/shc_error_len point. The
+// safety requirements are in the comment above each function.
+#![allow(clippy::missing_safety_doc)]
+
+use sha2::{Digest, Sha256};
+
+use crate::engine::{CHUNK_SIZE, Chunk, Scheduler, Step};
+use crate::integrity::{self, ExpectedDigest};
+
+pub struct WasmDownload {
+    scheduler: Scheduler,
+    chunk: Option<Chunk>,
+    data: Vec<u8>,
+    expected: Vec<ExpectedDigest>,
+    sha256: String,
+    error: String,
+}
+
+// What shc_on_response and shc_on_error tell the page to do next
+const KEEP_READING: i32 = 0;
+const CHUNK_DONE: i32 = 1;
+const RETRY: i32 = 2;
+const FAILED: i32 = -1;
+
+#[unsafe(no_mangle)]
+pub extern "C" fn shc_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+// # Safety
+// ptr and len must come from one call to shc_alloc.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_free(ptr: *mut u8, len: usize) {
+    drop(unsafe { Vec::from_raw_parts(ptr, 0, len) });
+}
+
+// Start a download of total_size bytes, the Content-Length of a HEAD or GET.
+// expected_sha256 is hex and may be empty. Returns null if it isn't valid.
+//
+// # Safety
+// expected_sha256 must point to len readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_start(total_size: usize, expected_sha256: *const u8, len: usize) -> *mut WasmDownload {
+    let expected = match len {
+        0 => Vec::new(),
+        _ => {
+            let hex = unsafe { std::slice::from_raw_parts(expected_sha256, len) };
+            match integrity::parse_hex_sha256(&String::from_utf8_lossy(hex)) {
+                Ok(digest) => vec![digest],
+                Err(_) => return std::ptr::null_mut(),
+            }
+        }
+    };
+
+    Box::into_raw(Box::new(WasmDownload {
+        scheduler: Scheduler::new(total_size as u64),
+        chunk: None,
+        data: vec![0; total_size],
+        expected,
+        sha256: String::new(),
+        error: String::new(),
+    }))
+}
+
+// Pick the range to fetch next. Returns 1 with the range available from
+// shc_range_start/shc_range_end (inclusive), or 0 once everything is in.
+//
+// # Safety
+// download must come from shc_start and not have been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_next_range(download: *mut WasmDownload) -> i32 {
+    let download = unsafe { &mut *download };
+    if download.chunk.as_ref().is_none_or(Chunk::is_done) {
+        download.chunk = download.scheduler.claim(CHUNK_SIZE as u64);
+    }
+    download.chunk.is_some() as i32
+}
+
+// # Safety
+// As for shc_next_range, after it returned 1.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_range_start(download: *const WasmDownload) -> usize {
+    unsafe { &*download }.chunk.as_ref().map_or(0, |chunk| chunk.range().0 as usize)
+}
+
+// # Safety
+// As for shc_next_range, after it returned 1.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_range_end(download: *const WasmDownload) -> usize {
+    unsafe { &*download }.chunk.as_ref().map_or(0, |chunk| chunk.range().1 as usize)
+}
+
+// Hand over the next piece of the response body for the current range. Bodies
+// can be passed as they stream in. Returns 0 to keep reading, 1 when the
+// range is complete (stop reading and call shc_next_range), 2 to request the
+// range again or -1 if the download has failed.
+//
+// # Safety
+// download as for shc_next_range; body must point to len readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_on_response(download: *mut WasmDownload, body: *const u8, len: usize) -> i32 {
+    let download = unsafe { &mut *download };
+    let body = unsafe { std::slice::from_raw_parts(body, len) };
+    let Some(chunk) = download.chunk.as_mut() else {
+        return CHUNK_DONE;
+    };
+    let step = chunk.on_response(&download.scheduler, body);
+    let done = chunk.is_done();
+    match download.apply(step) {
+        KEEP_READING if done => CHUNK_DONE,
+        code => code,
+    }
+}
+
+// The request for the current range failed without a response. Returns 2
+// to try again or -1 if the download has failed.
+//
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_on_error(download: *mut WasmDownload) -> i32 {
+    let download = unsafe { &mut *download };
+    let Some(chunk) = download.chunk.as_mut() else {
+        return FAILED;
+    };
+    let step = chunk.on_error(std::io::Error::new(std::io::ErrorKind::ConnectionAborted, "Fetch failed"));
+    download.apply(step)
+}
+
+// Check the assembled body once shc_next_range has returned 0. Returns 1 if
+// it matches the expected hash (or none was given) and 0 if not.
+//
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_finish(download: *mut WasmDownload) -> i32 {
+    let download = unsafe { &mut *download };
+    download.sha256 = format!("{:x}", Sha256::digest(&download.data));
+    if download.expected.is_empty() {
+        return 1;
+    }
+    match integrity::verify(&download.expected, &download.data) {
+        Ok(_) => 1,
+        Err(e) => {
+            download.error = e.to_string();
+            0
+        }
+    }
+}
+
+// The body, valid until shc_free_download
+//
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_data_ptr(download: *const WasmDownload) -> *const u8 {
+    unsafe { &*download }.data.as_ptr()
+}
+
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_data_len(download: *const WasmDownload) -> usize {
+    unsafe { &*download }.data.len()
+}
+
+// The hex SHA-256 from shc_finish, 64 bytes
+//
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_sha256_ptr(download: *const WasmDownload) -> *const u8 {
+    unsafe { &*download }.sha256.as_ptr()
+}
+
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_error_ptr(download: *const WasmDownload) -> *const u8 {
+    unsafe { &*download }.error.as_ptr()
+}
+
+// # Safety
+// As for shc_next_range.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_error_len(download: *const WasmDownload) -> usize {
+    unsafe { &*download }.error.len()
+}
+
+// # Safety
+// download must come from shc_start and not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn shc_free_download(download: *mut WasmDownload) {
+    drop(unsafe { Box::from_raw(download) });
+}
+
+impl WasmDownload {
+    fn apply(&mut self, step: Step) -> i32 {
+        match step {
+            Step::Write { offset, data } => {
+                // Offsets are within the body, which is in memory
+                let offset = offset as usize;
+                self.data[offset..offset + data.len()].copy_from_slice(data);
+                KEEP_READING
+            }
+            Step::Retry { .. } => RETRY,
+            Step::Fail(e) => {
+                self.error = e.to_string();
+                FAILED
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Reporting each transfer to --notify-webhook: a JSON POST as it ends,
+// whatever became of it. The payload has a "text" line as well, which is
+// what Slack-style incoming webhooks show. A webhook that can't be reached
+// or refuses the report gets a warning; the download's own result stands.
+
+use std::time::Duration;
+
+use http_client::{http, json};
+
+use crate::url::Url;
+use crate::writeout::Transfer;
+
+// How long the webhook gets when no --timeout is set
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// POST what became of the transfer, one of saved, piped, skipped or failed
+pub fn post(webhook: &Url, transfer: &Transfer, status: &str, settings: &http::Settings) -> std::io::Result<()> {
+    // The webhook gets none of the headers or credentials meant for the
+    // download, and isn't cut short by --max-time or counted in its timings
+    let settings = http::Settings {
+        timeout: Some(settings.timeout.unwrap_or(DEFAULT_TIMEOUT)),
+        http2: false,
+        low_speed: None,
+        cancel: None,
+        har: None,
+        headers: Vec::new(),
+        timing: None,
+        cookies: None,
+        sigv4: None,
+        refresh: None,
+        oauth2: None,
+        authorizations: Vec::new(),
+        netrc: None,
+        ..settings.clone()
+    };
+    let headers = [("Content-Type".to_string(), "application/json".to_string())];
+    let (head, _) = http::post(webhook, &headers, payload(transfer, status).as_bytes(), &settings)?;
+    if !(200..300).contains(&head.status) {
+        return Err(std::io::Error::other(format!("{} answered {}", webhook, head.status)));
+    }
+    Ok(())
+}
+
+fn payload(transfer: &Transfer, status: &str) -> String {
+    let optional = |text: Option<&str>| text.map_or("null".to_string(), json::quote);
+    let file = transfer.path.as_ref().map(|path| path.display().to_string());
+    let seconds = transfer.elapsed.as_secs_f64();
+    let text = match (&transfer.error, &file) {
+        (Some(failure), _) => format!("Download of {} failed after {:.1} s: {}", transfer.url, seconds, failure.message),
+        (None, Some(file)) => format!("Downloaded {} ({} bytes) to {} in {:.1} s", transfer.url, transfer.size_download, file, seconds),
+        (None, None) => format!("Download of {} {} after {:.1} s", transfer.url, status, seconds),
+    };
+    format!(
+        "{{\"text\":{},\"url\":{},\"file\":{},\"bytes\":{},\"hash\":{},\"status\":{},\"http_code\":{},\"duration_ms\":{:.3},\"error\":{}}}",
+        json::quote(&text),
+        json::quote(&transfer.url),
+        optional(file.as_deref()),
+        transfer.size_download,
+        optional(transfer.sha256.as_deref()),
+        json::quote(status),
+        transfer.status.map_or("null".to_string(), |status| status.to_string()),
+        seconds * 1000.0,
+        transfer.error.as_ref().map_or("null".to_string(), |failure| failure.to_json(&transfer.url)),
+    )
+}
@@ -0,0 +1,189 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use http_client::json;
+use http_client::timing::{self, Phases};
+
+use crate::report;
+
+// A --write-out template such as "%{http_code} %{size_download}\n", printed
+// to stdout after each transfer.
+//
+// Variables:
+//   %{url}                 the URL as given
+//   %{http_code}           status of the server's first response, 000 if there was none
+//   %{content_type}        Content-Type of that response
+//   %{size_download}       body bytes received in this run
+//   %{time_total}          seconds the transfer took
+//   %{time_namelookup}     seconds until the host name was resolved, for the first connection
+//   %{time_connect}        seconds until it was connected
+//   %{time_appconnect}     seconds until the TLS handshake was done, 0 without TLS
+//   %{time_starttransfer}  seconds until its first response byte
+//   %{num_connects}        connections made
+//   %{speed_download}      average bytes per second
+//   %{sha256}              hex SHA-256 of the body, once verified
+//   %{filename_effective}  where the body was saved
+//   %{errormsg}            why the transfer failed
+// \n, \t and \\ are escapes and %% is a literal %.
+pub struct WriteOut {
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Literal(String),
+    Variable(Variable),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    Url,
+    HttpCode,
+    ContentType,
+    SizeDownload,
+    TimeTotal,
+    TimeNamelookup,
+    TimeConnect,
+    TimeAppconnect,
+    TimeStarttransfer,
+    NumConnects,
+    SpeedDownload,
+    Sha256,
+    FilenameEffective,
+    ErrorMessage,
+}
+
+const VARIABLES: &[(&str, Variable)] = &[
+    ("url", Variable::Url),
+    ("http_code", Variable::HttpCode),
+    ("content_type", Variable::ContentType),
+    ("size_download", Variable::SizeDownload),
+    ("time_total", Variable::TimeTotal),
+    ("time_namelookup", Variable::TimeNamelookup),
+    ("time_connect", Variable::TimeConnect),
+    ("time_appconnect", Variable::TimeAppconnect),
+    ("time_starttransfer", Variable::TimeStarttransfer),
+    ("num_connects", Variable::NumConnects),
+    ("speed_download", Variable::SpeedDownload),
+    ("sha256", Variable::Sha256),
+    ("filename_effective", Variable::FilenameEffective),
+    ("errormsg", Variable::ErrorMessage),
+];
+
+// What a transfer did, filled in as it goes
+#[derive(Default)]
+pub struct Transfer {
+    pub url: String,
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    pub size_download: u64,
+    pub elapsed: Duration,
+    // Phases of every connection made, oldest first
+    pub connections: Vec<Phases>,
+    pub sha256: Option<String>,
+    pub path: Option<PathBuf>,
+    pub error: Option<report::Failure>,
+    // What the download was doing when it failed, or last did
+    pub phase: Option<report::Phase>,
+}
+
+impl WriteOut {
+    pub fn parse(template: &str) -> Result<WriteOut, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('n') => literal.push('\n'),
+                    Some('t') => literal.push('\t'),
+                    Some('r') => literal.push('\r'),
+                    Some('\\') => literal.push('\\'),
+                    Some(other) => {
+                        literal.push('\\');
+                        literal.push(other);
+                    }
+                    None => literal.push('\\'),
+                },
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    literal.push('%');
+                }
+                '%' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let (_, variable) = VARIABLES.iter()
+                        .find(|(known, _)| *known == name)
+                        .ok_or_else(|| format!("Unknown --write-out variable %{{{}}}", name))?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Variable(*variable));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(WriteOut { parts })
+    }
+
+    pub fn render(&self, transfer: &Transfer) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => text.push_str(literal),
+                Part::Variable(variable) => text.push_str(&value(*variable, transfer)),
+            }
+        }
+        text
+    }
+}
+
+fn value(variable: Variable, transfer: &Transfer) -> String {
+    let seconds = transfer.elapsed.as_secs_f64();
+    let first = transfer.connections.first().copied().unwrap_or_default();
+    let secs = |duration: Duration| format!("{:.6}", duration.as_secs_f64());
+    match variable {
+        Variable::Url => transfer.url.clone(),
+        Variable::HttpCode => format!("{:03}", transfer.status.unwrap_or(0)),
+        Variable::ContentType => transfer.content_type.clone().unwrap_or_default(),
+        Variable::SizeDownload => transfer.size_download.to_string(),
+        Variable::TimeTotal => format!("{:.6}", seconds),
+        Variable::TimeNamelookup => secs(first.dns),
+        Variable::TimeConnect => secs(first.dns + first.connect),
+        Variable::TimeAppconnect if first.tls.is_zero() => secs(Duration::ZERO),
+        Variable::TimeAppconnect => secs(first.dns + first.connect + first.tls),
+        Variable::TimeStarttransfer => secs(first.first_byte()),
+        Variable::NumConnects => transfer.connections.len().to_string(),
+        Variable::SpeedDownload if seconds > 0.0 => format!("{:.0}", transfer.size_download as f64 / seconds),
+        Variable::SpeedDownload => "0".to_string(),
+        Variable::Sha256 => transfer.sha256.clone().unwrap_or_default(),
+        Variable::FilenameEffective => transfer.path.as_ref().map(|path| path.display().to_string()).unwrap_or_default(),
+        Variable::ErrorMessage => transfer.error.as_ref().map(|failure| failure.message.clone()).unwrap_or_default(),
+    }
+}
+
+impl Transfer {
+    // A one-line JSON summary for --json, timings in milliseconds
+    pub fn to_json(&self) -> String {
+        let optional = |text: Option<&str>| text.map_or("null".to_string(), json::quote);
+        let connections: Vec<String> = self.connections.iter().map(Phases::to_json).collect();
+        format!(
+            "{{\"url\":{},\"http_code\":{},\"content_type\":{},\"size_download\":{},\"time_total_ms\":{:.3},\
+             \"sha256\":{},\"filename_effective\":{},\"error\":{},\
+             \"timings\":{{\"aggregate\":{},\"connections\":[{}]}}}}",
+            json::quote(&self.url),
+            self.status.map_or("null".to_string(), |status| status.to_string()),
+            optional(self.content_type.as_deref()),
+            self.size_download,
+            self.elapsed.as_secs_f64() * 1000.0,
+            optional(self.sha256.as_deref()),
+            optional(self.path.as_ref().map(|path| path.to_string_lossy()).as_deref()),
+            self.error.as_ref().map_or("null".to_string(), |failure| failure.to_json(&self.url)),
+            timing::aggregate(&self.connections).to_json(),
+            connections.join(","),
+        )
+    }
+}
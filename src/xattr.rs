@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use crate::url::Url;
+
+// Attribute names the provenance of a download is recorded under. The URL
+// uses the freedesktop.org name that curl and wget write as well.
+pub const ORIGIN_URL: &str = "user.xdg.origin.url";
+pub const ETAG: &str = "user.http_client.etag";
+pub const SHA256: &str = "user.http_client.sha256";
+
+// Record where a downloaded file came from in its extended attributes
+pub fn record_provenance(path: &Path, url: &Url, etag: Option<&str>, sha256: &str) -> std::io::Result<()> {
+    set(path, ORIGIN_URL, url.to_string().as_bytes())?;
+    if let Some(etag) = etag {
+        set(path, ETAG, etag.as_bytes())?;
+    }
+    set(path, SHA256, sha256.as_bytes())
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::ffi::{c_char, c_int, c_void};
+
+    #[cfg(target_os = "linux")]
+    unsafe extern "C" {
+        pub fn setxattr(path: *const c_char, name: *const c_char, value: *const c_void, size: usize, flags: c_int) -> c_int;
+        pub fn getxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize;
+    }
+
+    #[cfg(target_os = "macos")]
+    unsafe extern "C" {
+        pub fn setxattr(path: *const c_char, name: *const c_char, value: *const c_void, size: usize, position: u32, options: c_int) -> c_int;
+        pub fn getxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize, position: u32, options: c_int) -> isize;
+    }
+
+    // errno values for an attribute that isn't set, and a filesystem without them
+    #[cfg(target_os = "linux")]
+    pub const MISSING: [i32; 2] = [61, 95];
+    #[cfg(target_os = "macos")]
+    pub const MISSING: [i32; 2] = [93, 45];
+}
+
+// Set an extended attribute on a file, replacing any previous value
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn set(path: &Path, name: &str, value: &[u8]) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+    let c_name = CString::new(name).map_err(std::io::Error::other)?;
+
+    // SAFETY: both strings are NUL terminated and value outlives the call
+    #[cfg(target_os = "linux")]
+    let result = unsafe { sys::setxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+    #[cfg(target_os = "macos")]
+    let result = unsafe { sys::setxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_ptr().cast(), value.len(), 0, 0) };
+
+    if result == 0 { Ok(()) } else { Err(std::io::Error::last_os_error()) }
+}
+
+// Read an extended attribute, None when the file doesn't have it (or the
+// filesystem doesn't support them)
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn get(path: &Path, name: &str) -> std::io::Result<Option<Vec<u8>>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).map_err(std::io::Error::other)?;
+    let c_name = CString::new(name).map_err(std::io::Error::other)?;
+    let mut value = vec![0u8; 4096];
+
+    // SAFETY: both strings are NUL terminated and value has the given size
+    #[cfg(target_os = "linux")]
+    let len = unsafe { sys::getxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr().cast(), value.len()) };
+    #[cfg(target_os = "macos")]
+    let len = unsafe { sys::getxattr(c_path.as_ptr(), c_name.as_ptr(), value.as_mut_ptr().cast(), value.len(), 0, 0) };
+
+    if len < 0 {
+        let error = std::io::Error::last_os_error();
+        let missing = error.raw_os_error().is_some_and(|code| sys::MISSING.contains(&code));
+        return if missing { Ok(None) } else { Err(error) };
+    }
+    value.truncate(len as usize);
+    Ok(Some(value))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn get(_path: &Path, _name: &str) -> std::io::Result<Option<Vec<u8>>> {
+    Ok(None)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn set(_path: &Path, _name: &str, _value: &[u8]) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "extended attributes are not supported on this platform"))
+}
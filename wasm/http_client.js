@@ -0,0 +1,75 @@
+// Browser side of the WebAssembly build: does the requests with fetch() and
+// lets the module decide what to request and whether the result is good.
+//
+//     const { instance } = await WebAssembly.instantiateStreaming(fetch("http_client.wasm"));
+//     const { bytes, sha256 } = await download(instance, "https://example.com/file.iso", "9f86d0...");
+
+const KEEP_READING = 0, CHUNK_DONE = 1, RETRY = 2, FAILED = -1;
+
+export async function download(instance, url, expectedSha256 = "") {
+  const wasm = instance.exports;
+  const text = (ptr, len) => new TextDecoder().decode(new Uint8Array(wasm.memory.buffer, ptr, len));
+
+  const head = await fetch(url, { method: "HEAD" });
+  const totalSize = Number(head.headers.get("Content-Length"));
+  if (!head.ok || Number.isNaN(totalSize)) {
+    throw new Error(`No usable Content-Length for ${url}`);
+  }
+
+  const hex = new TextEncoder().encode(expectedSha256);
+  const hexPtr = wasm.shc_alloc(hex.length);
+  new Uint8Array(wasm.memory.buffer, hexPtr, hex.length).set(hex);
+  const dl = wasm.shc_start(totalSize, hexPtr, hex.length);
+  wasm.shc_free(hexPtr, hex.length);
+  if (dl === 0) {
+    throw new Error(`Invalid SHA-256: ${expectedSha256}`);
+  }
+
+  // Copy a piece of body into the module and hand it over
+  const feed = (bytes) => {
+    const ptr = wasm.shc_alloc(bytes.length);
+    new Uint8Array(wasm.memory.buffer, ptr, bytes.length).set(bytes);
+    const code = wasm.shc_on_response(dl, ptr, bytes.length);
+    wasm.shc_free(ptr, bytes.length);
+    return code;
+  };
+
+  try {
+    while (wasm.shc_next_range(dl)) {
+      const range = `bytes=${wasm.shc_range_start(dl)}-${wasm.shc_range_end(dl)}`;
+      let code;
+      try {
+        const response = await fetch(url, { headers: { Range: range } });
+        if (response.status === 206 && response.body) {
+          // Stream the partial content in as it arrives
+          const reader = response.body.getReader();
+          code = KEEP_READING;
+          while (code === KEEP_READING) {
+            const { done, value } = await reader.read();
+            if (done) break;
+            code = feed(value);
+          }
+          await reader.cancel();
+        } else {
+          // The server ignored the range; the module can use a complete body
+          code = feed(new Uint8Array(await response.arrayBuffer()));
+        }
+      } catch (e) {
+        code = wasm.shc_on_error(dl);
+      }
+      if (code === FAILED) {
+        throw new Error(text(wasm.shc_error_ptr(dl), wasm.shc_error_len(dl)));
+      }
+    }
+
+    const verified = wasm.shc_finish(dl);
+    const sha256 = text(wasm.shc_sha256_ptr(dl), 64);
+    if (!verified) {
+      throw new Error(text(wasm.shc_error_ptr(dl), wasm.shc_error_len(dl)));
+    }
+    const bytes = new Uint8Array(wasm.memory.buffer, wasm.shc_data_ptr(dl), wasm.shc_data_len(dl)).slice();
+    return { bytes, sha256 };
+  } finally {
+    wasm.shc_free_download(dl);
+  }
+}